@@ -21,6 +21,9 @@ impl std::error::Error for CodecError {}
 mod opus;
 
 #[cfg(feature = "ffi-opus")]
-pub use opus::{opus_encode_chunk, OpusEncodeError, OpusEncoderWrapper};
+pub use opus::{
+    opus_encode_chunk, OpusBandwidth, OpusEncodeError, OpusEncoderWrapper,
+    OpusMultistreamEncoderWrapper, OpusSignal, OpusVbrMode,
+};
 #[cfg(feature = "ffi-opus")]
 pub use opus::{OpusDecodeError, OpusDecoderWrapper};
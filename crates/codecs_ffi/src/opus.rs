@@ -12,10 +12,34 @@ pub struct OpusDecoder {
     _private: [u8; 0],
 }
 
+#[repr(C)]
+pub struct OpusMSEncoder {
+    _private: [u8; 0],
+}
+
 const OPUS_OK: c_int = 0;
 const OPUS_APPLICATION_AUDIO: c_int = 2049;
+const OPUS_AUTO: c_int = -1000;
 const OPUS_SET_BITRATE_REQUEST: c_int = 4002;
+const OPUS_SET_VBR_REQUEST: c_int = 4006;
+const OPUS_SET_BANDWIDTH_REQUEST: c_int = 4008;
+const OPUS_SET_COMPLEXITY_REQUEST: c_int = 4010;
+const OPUS_SET_INBAND_FEC_REQUEST: c_int = 4012;
+const OPUS_SET_PACKET_LOSS_PERC_REQUEST: c_int = 4014;
+const OPUS_SET_VBR_CONSTRAINT_REQUEST: c_int = 4020;
+const OPUS_SET_SIGNAL_REQUEST: c_int = 4024;
+const OPUS_GET_LOOKAHEAD_REQUEST: c_int = 4027;
+const OPUS_SIGNAL_VOICE: c_int = 3001;
+const OPUS_SIGNAL_MUSIC: c_int = 3002;
+const OPUS_BANDWIDTH_NARROWBAND: c_int = 1101;
+const OPUS_BANDWIDTH_MEDIUMBAND: c_int = 1102;
+const OPUS_BANDWIDTH_WIDEBAND: c_int = 1103;
+const OPUS_BANDWIDTH_SUPERWIDEBAND: c_int = 1104;
+const OPUS_BANDWIDTH_FULLBAND: c_int = 1105;
 const OPUS_MAX_FRAME_SIZE: usize = 5760;
+/// Vorbis channel order, the only multichannel family libopus can pick
+/// stream layouts for.
+const OPUS_MAPPING_FAMILY_VORBIS: c_int = 1;
 
 extern "C" {
     fn opus_encoder_create(
@@ -33,6 +57,25 @@ extern "C" {
         max_data_bytes: c_int,
     ) -> c_int;
     fn opus_encoder_ctl(st: *mut OpusEncoder, request: c_int, ...) -> c_int;
+    fn opus_multistream_surround_encoder_create(
+        fs: c_int,
+        channels: c_int,
+        mapping_family: c_int,
+        streams: *mut c_int,
+        coupled_streams: *mut c_int,
+        mapping: *mut u8,
+        application: c_int,
+        error: *mut c_int,
+    ) -> *mut OpusMSEncoder;
+    fn opus_multistream_encoder_destroy(st: *mut OpusMSEncoder);
+    fn opus_multistream_encode(
+        st: *mut OpusMSEncoder,
+        pcm: *const i16,
+        frame_size: c_int,
+        data: *mut u8,
+        max_data_bytes: c_int,
+    ) -> c_int;
+    fn opus_multistream_encoder_ctl(st: *mut OpusMSEncoder, request: c_int, ...) -> c_int;
     fn opus_decoder_create(fs: c_int, channels: c_int, error: *mut c_int) -> *mut OpusDecoder;
     fn opus_decoder_destroy(st: *mut OpusDecoder);
     fn opus_decode(
@@ -68,6 +111,66 @@ impl std::fmt::Display for OpusEncodeError {
 
 impl std::error::Error for OpusEncodeError {}
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OpusVbrMode {
+    Cbr,
+    Vbr,
+    /// VBR that keeps each frame close to the target size.
+    ConstrainedVbr,
+}
+
+impl OpusVbrMode {
+    /// (OPUS_SET_VBR, OPUS_SET_VBR_CONSTRAINT) values.
+    fn ctl_values(self) -> (c_int, c_int) {
+        match self {
+            OpusVbrMode::Cbr => (0, 0),
+            OpusVbrMode::Vbr => (1, 0),
+            OpusVbrMode::ConstrainedVbr => (1, 1),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OpusSignal {
+    Auto,
+    Voice,
+    Music,
+}
+
+impl OpusSignal {
+    fn ctl_value(self) -> c_int {
+        match self {
+            OpusSignal::Auto => OPUS_AUTO,
+            OpusSignal::Voice => OPUS_SIGNAL_VOICE,
+            OpusSignal::Music => OPUS_SIGNAL_MUSIC,
+        }
+    }
+}
+
+/// Audio bandwidth to code; `Auto` lets the encoder pick from the bitrate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OpusBandwidth {
+    Auto,
+    Narrowband,
+    Mediumband,
+    Wideband,
+    Superwideband,
+    Fullband,
+}
+
+impl OpusBandwidth {
+    fn ctl_value(self) -> c_int {
+        match self {
+            OpusBandwidth::Auto => OPUS_AUTO,
+            OpusBandwidth::Narrowband => OPUS_BANDWIDTH_NARROWBAND,
+            OpusBandwidth::Mediumband => OPUS_BANDWIDTH_MEDIUMBAND,
+            OpusBandwidth::Wideband => OPUS_BANDWIDTH_WIDEBAND,
+            OpusBandwidth::Superwideband => OPUS_BANDWIDTH_SUPERWIDEBAND,
+            OpusBandwidth::Fullband => OPUS_BANDWIDTH_FULLBAND,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum OpusDecodeError {
     DecoderUnavailable,
@@ -130,15 +233,71 @@ impl OpusEncoderWrapper {
         })
     }
 
-    pub fn set_bitrate(&mut self, bitrate_bps: u32) -> Result<(), OpusEncodeError> {
-        let ctl_err =
-            unsafe { opus_encoder_ctl(self.encoder, OPUS_SET_BITRATE_REQUEST, bitrate_bps as c_int) };
+    fn set_ctl(&mut self, request: c_int, value: c_int) -> Result<(), OpusEncodeError> {
+        let ctl_err = unsafe { opus_encoder_ctl(self.encoder, request, value) };
         if ctl_err != OPUS_OK {
             return Err(OpusEncodeError::EncodeFailed);
         }
         Ok(())
     }
 
+    pub fn set_bitrate(&mut self, bitrate_bps: u32) -> Result<(), OpusEncodeError> {
+        self.set_ctl(OPUS_SET_BITRATE_REQUEST, bitrate_bps as c_int)
+    }
+
+    /// 0 (fastest) to 10 (best quality).
+    pub fn set_complexity(&mut self, complexity: u8) -> Result<(), OpusEncodeError> {
+        if complexity > 10 {
+            return Err(OpusEncodeError::InvalidInput);
+        }
+        self.set_ctl(OPUS_SET_COMPLEXITY_REQUEST, complexity as c_int)
+    }
+
+    pub fn set_vbr_mode(&mut self, mode: OpusVbrMode) -> Result<(), OpusEncodeError> {
+        let (vbr, constraint) = mode.ctl_values();
+        self.set_ctl(OPUS_SET_VBR_REQUEST, vbr)?;
+        self.set_ctl(OPUS_SET_VBR_CONSTRAINT_REQUEST, constraint)
+    }
+
+    /// In-band FEC only takes effect in the SILK and hybrid modes, and only
+    /// once a non-zero packet loss percentage is set.
+    pub fn set_inband_fec(&mut self, enabled: bool) -> Result<(), OpusEncodeError> {
+        self.set_ctl(OPUS_SET_INBAND_FEC_REQUEST, enabled as c_int)
+    }
+
+    /// Expected packet loss, 0-100.
+    pub fn set_packet_loss_perc(&mut self, percent: u8) -> Result<(), OpusEncodeError> {
+        if percent > 100 {
+            return Err(OpusEncodeError::InvalidInput);
+        }
+        self.set_ctl(OPUS_SET_PACKET_LOSS_PERC_REQUEST, percent as c_int)
+    }
+
+    pub fn set_signal(&mut self, signal: OpusSignal) -> Result<(), OpusEncodeError> {
+        self.set_ctl(OPUS_SET_SIGNAL_REQUEST, signal.ctl_value())
+    }
+
+    pub fn set_bandwidth(&mut self, bandwidth: OpusBandwidth) -> Result<(), OpusEncodeError> {
+        self.set_ctl(OPUS_SET_BANDWIDTH_REQUEST, bandwidth.ctl_value())
+    }
+
+    /// Samples of algorithmic delay the encoder adds; this is the Opus
+    /// pre-skip a decoder has to drop.
+    pub fn lookahead(&mut self) -> Result<u32, OpusEncodeError> {
+        let mut value: c_int = 0;
+        let ctl_err = unsafe {
+            opus_encoder_ctl(
+                self.encoder,
+                OPUS_GET_LOOKAHEAD_REQUEST,
+                &mut value as *mut c_int,
+            )
+        };
+        if ctl_err != OPUS_OK || value < 0 {
+            return Err(OpusEncodeError::EncodeFailed);
+        }
+        Ok(value as u32)
+    }
+
     pub fn encode(&mut self, pcm: &[i16], frame_size: usize) -> Result<Vec<u8>, OpusEncodeError> {
         if pcm.len() < frame_size * self.channels as usize {
             return Err(OpusEncodeError::InvalidInput);
@@ -177,6 +336,175 @@ impl Drop for OpusEncoderWrapper {
     }
 }
 
+/// Surround encoder using channel mapping family 1. Input is interleaved in
+/// Vorbis channel order.
+pub struct OpusMultistreamEncoderWrapper {
+    encoder: *mut OpusMSEncoder,
+    sample_rate: u32,
+    channels: u8,
+    streams: u8,
+    coupled_streams: u8,
+    mapping: Vec<u8>,
+}
+
+unsafe impl Send for OpusMultistreamEncoderWrapper {}
+unsafe impl Sync for OpusMultistreamEncoderWrapper {}
+
+impl OpusMultistreamEncoderWrapper {
+    pub fn new(sample_rate: u32, channels: u8, bitrate_bps: u32) -> Result<Self, OpusEncodeError> {
+        if !matches!(sample_rate, 8000 | 12000 | 16000 | 24000 | 48000) {
+            return Err(OpusEncodeError::UnsupportedRate);
+        }
+        if !(1..=8).contains(&channels) {
+            return Err(OpusEncodeError::InvalidInput);
+        }
+        let mut err = 0;
+        let mut streams: c_int = 0;
+        let mut coupled: c_int = 0;
+        let mut mapping = vec![0u8; channels as usize];
+        let encoder = unsafe {
+            opus_multistream_surround_encoder_create(
+                sample_rate as c_int,
+                channels as c_int,
+                OPUS_MAPPING_FAMILY_VORBIS,
+                &mut streams as *mut c_int,
+                &mut coupled as *mut c_int,
+                mapping.as_mut_ptr(),
+                OPUS_APPLICATION_AUDIO,
+                &mut err as *mut c_int,
+            )
+        };
+        if encoder.is_null() || err != OPUS_OK {
+            return Err(OpusEncodeError::EncoderInitFailed);
+        }
+        let ctl_err = unsafe {
+            opus_multistream_encoder_ctl(encoder, OPUS_SET_BITRATE_REQUEST, bitrate_bps as c_int)
+        };
+        if ctl_err != OPUS_OK {
+            unsafe { opus_multistream_encoder_destroy(encoder) };
+            return Err(OpusEncodeError::EncoderInitFailed);
+        }
+        Ok(Self {
+            encoder,
+            sample_rate,
+            channels,
+            streams: streams as u8,
+            coupled_streams: coupled as u8,
+            mapping,
+        })
+    }
+
+    /// Multistream ctls apply to every stream.
+    fn set_ctl(&mut self, request: c_int, value: c_int) -> Result<(), OpusEncodeError> {
+        let ctl_err = unsafe { opus_multistream_encoder_ctl(self.encoder, request, value) };
+        if ctl_err != OPUS_OK {
+            return Err(OpusEncodeError::EncodeFailed);
+        }
+        Ok(())
+    }
+
+    pub fn set_bitrate(&mut self, bitrate_bps: u32) -> Result<(), OpusEncodeError> {
+        self.set_ctl(OPUS_SET_BITRATE_REQUEST, bitrate_bps as c_int)
+    }
+
+    pub fn set_complexity(&mut self, complexity: u8) -> Result<(), OpusEncodeError> {
+        if complexity > 10 {
+            return Err(OpusEncodeError::InvalidInput);
+        }
+        self.set_ctl(OPUS_SET_COMPLEXITY_REQUEST, complexity as c_int)
+    }
+
+    pub fn set_vbr_mode(&mut self, mode: OpusVbrMode) -> Result<(), OpusEncodeError> {
+        let (vbr, constraint) = mode.ctl_values();
+        self.set_ctl(OPUS_SET_VBR_REQUEST, vbr)?;
+        self.set_ctl(OPUS_SET_VBR_CONSTRAINT_REQUEST, constraint)
+    }
+
+    pub fn set_inband_fec(&mut self, enabled: bool) -> Result<(), OpusEncodeError> {
+        self.set_ctl(OPUS_SET_INBAND_FEC_REQUEST, enabled as c_int)
+    }
+
+    pub fn set_packet_loss_perc(&mut self, percent: u8) -> Result<(), OpusEncodeError> {
+        if percent > 100 {
+            return Err(OpusEncodeError::InvalidInput);
+        }
+        self.set_ctl(OPUS_SET_PACKET_LOSS_PERC_REQUEST, percent as c_int)
+    }
+
+    pub fn set_signal(&mut self, signal: OpusSignal) -> Result<(), OpusEncodeError> {
+        self.set_ctl(OPUS_SET_SIGNAL_REQUEST, signal.ctl_value())
+    }
+
+    pub fn set_bandwidth(&mut self, bandwidth: OpusBandwidth) -> Result<(), OpusEncodeError> {
+        self.set_ctl(OPUS_SET_BANDWIDTH_REQUEST, bandwidth.ctl_value())
+    }
+
+    pub fn lookahead(&mut self) -> Result<u32, OpusEncodeError> {
+        let mut value: c_int = 0;
+        let ctl_err = unsafe {
+            opus_multistream_encoder_ctl(
+                self.encoder,
+                OPUS_GET_LOOKAHEAD_REQUEST,
+                &mut value as *mut c_int,
+            )
+        };
+        if ctl_err != OPUS_OK || value < 0 {
+            return Err(OpusEncodeError::EncodeFailed);
+        }
+        Ok(value as u32)
+    }
+
+    pub fn encode(&mut self, pcm: &[i16], frame_size: usize) -> Result<Vec<u8>, OpusEncodeError> {
+        if pcm.len() < frame_size * self.channels as usize {
+            return Err(OpusEncodeError::InvalidInput);
+        }
+        let mut out = vec![0u8; 4000 * self.streams.max(1) as usize];
+        let encoded = unsafe {
+            opus_multistream_encode(
+                self.encoder,
+                pcm.as_ptr(),
+                frame_size as c_int,
+                out.as_mut_ptr(),
+                out.len() as c_int,
+            )
+        };
+        if encoded < 0 {
+            return Err(OpusEncodeError::EncodeFailed);
+        }
+        out.truncate(encoded as usize);
+        Ok(out)
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn channels(&self) -> u8 {
+        self.channels
+    }
+
+    pub fn streams(&self) -> u8 {
+        self.streams
+    }
+
+    pub fn coupled_streams(&self) -> u8 {
+        self.coupled_streams
+    }
+
+    /// Output channel to coded channel table, as written in OpusHead.
+    pub fn mapping(&self) -> &[u8] {
+        &self.mapping
+    }
+}
+
+impl Drop for OpusMultistreamEncoderWrapper {
+    fn drop(&mut self) {
+        if !self.encoder.is_null() {
+            unsafe { opus_multistream_encoder_destroy(self.encoder) };
+        }
+    }
+}
+
 pub struct OpusDecoderWrapper {
     decoder: *mut OpusDecoder,
     sample_rate: u32,
@@ -29,6 +29,39 @@ int32_t opus_encoder_ctl(void* st, int32_t request, ...) {
     return -1;
 }
 
+void* opus_multistream_surround_encoder_create(int32_t fs, int32_t channels, int32_t mapping_family, int32_t* streams, int32_t* coupled_streams, unsigned char* mapping, int32_t application, int32_t* error) {
+    (void)fs;
+    (void)channels;
+    (void)mapping_family;
+    (void)streams;
+    (void)coupled_streams;
+    (void)mapping;
+    (void)application;
+    if (error) {
+        *error = -1;
+    }
+    return 0;
+}
+
+void opus_multistream_encoder_destroy(void* st) {
+    (void)st;
+}
+
+int32_t opus_multistream_encode(void* st, const int16_t* pcm, int32_t frame_size, unsigned char* data, int32_t max_data_bytes) {
+    (void)st;
+    (void)pcm;
+    (void)frame_size;
+    (void)data;
+    (void)max_data_bytes;
+    return -1;
+}
+
+int32_t opus_multistream_encoder_ctl(void* st, int32_t request, ...) {
+    (void)st;
+    (void)request;
+    return -1;
+}
+
 void* opus_decoder_create(int32_t fs, int32_t channels, int32_t* error) {
     (void)fs;
     (void)channels;
@@ -13,6 +13,12 @@ pub struct Artist {
     pub logo_ref: Option<String>,
     #[serde(default)]
     pub banner_ref: Option<String>,
+    #[serde(default)]
+    pub sort_name: Option<String>,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    #[serde(default)]
+    pub musicbrainz_id: Option<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -27,6 +33,104 @@ pub struct Album {
     pub genres: Vec<String>,
     #[serde(default)]
     pub summary: Option<String>,
+    #[serde(default)]
+    pub sort_title: Option<String>,
+    #[serde(default)]
+    pub release_date: Option<String>,
+    #[serde(default)]
+    pub album_type: Option<AlbumType>,
+    #[serde(default)]
+    pub musicbrainz_release_id: Option<String>,
+    #[serde(default)]
+    pub musicbrainz_release_group_id: Option<String>,
+    #[serde(default)]
+    pub original_date: Option<String>,
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default)]
+    pub catalog_number: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlbumType {
+    Album,
+    Ep,
+    Single,
+    Live,
+    Compilation,
+    Soundtrack,
+    Remix,
+    Other,
+}
+
+impl AlbumType {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "album" | "lp" => Some(Self::Album),
+            "ep" => Some(Self::Ep),
+            "single" => Some(Self::Single),
+            "live" => Some(Self::Live),
+            "compilation" => Some(Self::Compilation),
+            "soundtrack" => Some(Self::Soundtrack),
+            "remix" => Some(Self::Remix),
+            "other" => Some(Self::Other),
+            _ => None,
+        }
+    }
+
+    /// Parses a RELEASETYPE / MusicBrainz type list such as "album; live".
+    /// Secondary types describe the release better than the primary one, so
+    /// they win when present.
+    pub fn from_release_types(value: &str) -> Option<Self> {
+        let mut primary = None;
+        for part in value.split(&[';', '/', ',', '\0'][..]) {
+            let part = part.trim().to_ascii_lowercase();
+            let kind = match part.as_str() {
+                "" => continue,
+                "broadcast" | "audiobook" | "spokenword" | "spoken word" | "interview" => {
+                    Some(Self::Other)
+                }
+                "dj-mix" | "mixtape" => Some(Self::Compilation),
+                other => Self::parse(other),
+            };
+            match kind {
+                Some(Self::Album) | Some(Self::Ep) | Some(Self::Single) | Some(Self::Other) => {
+                    primary = primary.or(kind);
+                }
+                Some(secondary) => return Some(secondary),
+                None => {}
+            }
+        }
+        primary
+    }
+
+    /// Position used when a discography is grouped by type.
+    pub fn group_order(&self) -> u8 {
+        match self {
+            Self::Album => 0,
+            Self::Ep => 1,
+            Self::Single => 2,
+            Self::Live => 3,
+            Self::Compilation => 4,
+            Self::Soundtrack => 5,
+            Self::Remix => 6,
+            Self::Other => 7,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Album => "album",
+            Self::Ep => "ep",
+            Self::Single => "single",
+            Self::Live => "live",
+            Self::Compilation => "compilation",
+            Self::Soundtrack => "soundtrack",
+            Self::Remix => "remix",
+            Self::Other => "other",
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -46,6 +150,31 @@ pub struct Track {
     pub file_size: u64,
     #[serde(default)]
     pub genres: Vec<String>,
+    #[serde(default)]
+    pub musicbrainz_recording_id: Option<String>,
+    #[serde(default)]
+    pub composers: Vec<String>,
+    #[serde(default)]
+    pub conductor: Option<String>,
+    #[serde(default)]
+    pub performers: Vec<String>,
+    #[serde(default)]
+    pub work: Option<String>,
+    #[serde(default)]
+    pub movement: Option<String>,
+    #[serde(default)]
+    pub movement_no: Option<u16>,
+    #[serde(default)]
+    pub movement_total: Option<u16>,
+    /// Leading decoded samples (at the source rate) the encoder added.
+    #[serde(default)]
+    pub encoder_delay: Option<u32>,
+    /// Trailing decoded samples (at the source rate) the encoder added.
+    #[serde(default)]
+    pub encoder_padding: Option<u32>,
+    /// Exact per-channel sample count once delay and padding are dropped.
+    #[serde(default)]
+    pub total_samples: Option<u64>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -75,6 +204,18 @@ pub struct SeekIndex {
     pub hint: String,
 }
 
+/// Per-bucket sample peaks across all channels, in -1.0..=1.0.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Waveform {
+    pub duration_ms: u32,
+    /// Size and modification time of the file the peaks came from; a
+    /// mismatch in either means it changed.
+    pub source_size: u64,
+    pub source_modified_ns: u64,
+    pub min: Vec<f32>,
+    pub max: Vec<f32>,
+}
+
 pub fn stable_id(input: &str) -> String {
     blake3::hash(input.as_bytes()).to_hex().to_string()
 }
@@ -105,7 +246,7 @@ fn path_to_slash_string(path: &Path) -> String {
 
 #[cfg(test)]
 mod tests {
-    use super::stable_id;
+    use super::{stable_id, AlbumType};
 
     #[test]
     fn stable_id_is_deterministic() {
@@ -114,4 +255,16 @@ mod tests {
         assert_eq!(first, second);
         assert_ne!(first, stable_id("Artist/Album/Track2.mp3"));
     }
+
+    #[test]
+    fn release_types_prefer_secondary() {
+        assert_eq!(AlbumType::from_release_types("album"), Some(AlbumType::Album));
+        assert_eq!(AlbumType::from_release_types("album; live"), Some(AlbumType::Live));
+        assert_eq!(
+            AlbumType::from_release_types("Album/Compilation"),
+            Some(AlbumType::Compilation)
+        );
+        assert_eq!(AlbumType::from_release_types("EP"), Some(AlbumType::Ep));
+        assert_eq!(AlbumType::from_release_types("nonsense"), None);
+    }
 }
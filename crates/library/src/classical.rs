@@ -0,0 +1,344 @@
+//! Composer and work indexes for classical tags.
+//!
+//! Composers are derived from track credits rather than folder layout, so the
+//! index is rebuilt from the stored tracks after every scan, the same way the
+//! genre index is.
+
+use std::collections::HashMap;
+
+use common::{stable_id, Track};
+use redb::{ReadableTable, TableDefinition, WriteTransaction};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    clear_table, decode_value, encode_value, prefix_key, split_key_last, LibraryError,
+    ALBUMS_BY_NAME_TABLE, KEY_SEP, TRACKS_BY_NAME_TABLE, TRACKS_TABLE,
+};
+
+pub(crate) const COMPOSERS_TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("composers");
+pub(crate) const COMPOSERS_BY_NAME_TABLE: TableDefinition<&str, &[u8]> =
+    TableDefinition::new("composers_by_name");
+pub(crate) const COMPOSER_ALBUMS_TABLE: TableDefinition<&str, &[u8]> =
+    TableDefinition::new("composer_albums");
+pub(crate) const COMPOSER_TRACKS_TABLE: TableDefinition<&str, &[u8]> =
+    TableDefinition::new("composer_tracks");
+pub(crate) const CREDIT_TRACKS_TABLE: TableDefinition<&str, &[u8]> =
+    TableDefinition::new("credit_tracks");
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ComposerInfo {
+    pub id: String,
+    pub name: String,
+    pub album_count: usize,
+    pub track_count: usize,
+}
+
+/// Consecutive album tracks that belong to the same work.
+#[derive(Clone, Debug)]
+pub struct WorkGroup {
+    pub work: Option<String>,
+    pub composers: Vec<String>,
+    pub tracks: Vec<Track>,
+}
+
+/// Composer ids are namespaced so a composer who is also an album artist
+/// doesn't collide with the artist entry.
+pub fn composer_id(name: &str) -> String {
+    stable_id(&format!("composer{}{}", KEY_SEP, name.trim().to_lowercase()))
+}
+
+/// Groups tracks (already in album order) by work. Movements only group when
+/// they are adjacent, so a work split across the album shows up twice rather
+/// than reordering tracks.
+pub fn group_by_work(tracks: Vec<Track>) -> Vec<WorkGroup> {
+    let mut groups: Vec<WorkGroup> = Vec::new();
+    for track in tracks {
+        let work = track.work.clone();
+        let same = groups.last().is_some_and(|group| {
+            work.is_some()
+                && work_key(group.work.as_deref()) == work_key(work.as_deref())
+                && group.composers.first() == track.composers.first()
+        });
+        if same {
+            if let Some(group) = groups.last_mut() {
+                group.tracks.push(track);
+            }
+            continue;
+        }
+        groups.push(WorkGroup {
+            work,
+            composers: track.composers.clone(),
+            tracks: vec![track],
+        });
+    }
+    groups
+}
+
+fn work_key(work: Option<&str>) -> String {
+    work.map(|value| value.trim().to_lowercase()).unwrap_or_default()
+}
+
+#[derive(Default)]
+struct ComposerDraft {
+    name: String,
+    albums: Vec<String>,
+    tracks: usize,
+}
+
+/// Composer track rows are keyed by work first, so browsing a composer lists
+/// each work's movements together. Conductors and performers aren't browsable,
+/// but get track rows keyed by name so search can find them.
+pub(crate) fn write_composer_index(txn: &WriteTransaction) -> Result<usize, LibraryError> {
+    clear_table(txn, COMPOSERS_TABLE)?;
+    clear_table(txn, COMPOSERS_BY_NAME_TABLE)?;
+    clear_table(txn, COMPOSER_ALBUMS_TABLE)?;
+    clear_table(txn, COMPOSER_TRACKS_TABLE)?;
+    clear_table(txn, CREDIT_TRACKS_TABLE)?;
+
+    let mut drafts: HashMap<String, ComposerDraft> = HashMap::new();
+    {
+        let albums_by_name = txn.open_table(ALBUMS_BY_NAME_TABLE)?;
+        let mut album_keys: HashMap<String, String> = HashMap::new();
+        for entry in albums_by_name.iter()? {
+            let entry = entry?;
+            let key = entry.0.value();
+            let (_, album_id) = split_key_last(key)?;
+            album_keys.insert(album_id.to_string(), key.to_string());
+        }
+
+        let tracks_by_name = txn.open_table(TRACKS_BY_NAME_TABLE)?;
+        let tracks = txn.open_table(TRACKS_TABLE)?;
+        let mut composer_albums = txn.open_table(COMPOSER_ALBUMS_TABLE)?;
+        let mut composer_tracks = txn.open_table(COMPOSER_TRACKS_TABLE)?;
+        let mut credit_tracks = txn.open_table(CREDIT_TRACKS_TABLE)?;
+
+        for entry in tracks_by_name.iter()? {
+            let entry = entry?;
+            let key = entry.0.value();
+            let (_, track_id) = split_key_last(key)?;
+            let track: Track = match tracks.get(track_id)? {
+                Some(value) => decode_value(value.value())?,
+                None => continue,
+            };
+            for name in track.conductor.iter().chain(&track.performers) {
+                let row = format!("{}{}", prefix_key(&name.trim().to_lowercase()), key);
+                credit_tracks.insert(row.as_str(), track_id.as_bytes())?;
+            }
+            for name in &track.composers {
+                let id = composer_id(name);
+                let draft = drafts.entry(id.clone()).or_default();
+                if draft.name.is_empty() {
+                    draft.name = name.trim().to_string();
+                }
+                draft.tracks += 1;
+
+                let row = format!(
+                    "{}{}{}{}",
+                    prefix_key(&id),
+                    work_key(track.work.as_deref()),
+                    KEY_SEP,
+                    key
+                );
+                composer_tracks.insert(row.as_str(), track_id.as_bytes())?;
+
+                if !draft.albums.contains(&track.album_id) {
+                    draft.albums.push(track.album_id.clone());
+                    if let Some(album_key) = album_keys.get(&track.album_id) {
+                        let row = format!("{}{}", prefix_key(&id), album_key);
+                        composer_albums.insert(row.as_str(), track.album_id.as_bytes())?;
+                    }
+                }
+            }
+        }
+    }
+
+    let count = drafts.len();
+    let mut composers = txn.open_table(COMPOSERS_TABLE)?;
+    let mut composers_by_name = txn.open_table(COMPOSERS_BY_NAME_TABLE)?;
+    for (id, draft) in drafts {
+        let info = ComposerInfo {
+            id: id.clone(),
+            album_count: draft.albums.len(),
+            track_count: draft.tracks,
+            name: draft.name,
+        };
+        let bytes = encode_value(&info)?;
+        composers.insert(id.as_str(), bytes.as_slice())?;
+        let name_key = format!("{}{}", prefix_key(&info.name.to_lowercase()), id);
+        composers_by_name.insert(name_key.as_str(), id.as_bytes())?;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use common::Codec;
+
+    use super::*;
+    use crate::{track_name_key, GenreMap, Library};
+
+    fn track(id: &str, no: u16, work: Option<&str>, composers: &[&str]) -> Track {
+        Track {
+            id: id.to_string(),
+            album_id: "album".to_string(),
+            artist_id: "artist".to_string(),
+            title: format!("Track {}", no),
+            track_no: Some(no),
+            disc_no: Some(1),
+            duration_ms: 1000,
+            codec: Codec::Flac,
+            sample_rate: None,
+            channels: None,
+            bitrate: None,
+            file_relpath: format!("{}.flac", id),
+            file_size: 0,
+            genres: Vec::new(),
+            musicbrainz_recording_id: None,
+            composers: composers.iter().map(|name| name.to_string()).collect(),
+            conductor: None,
+            performers: Vec::new(),
+            work: work.map(str::to_string),
+            movement: None,
+            movement_no: None,
+            movement_total: None,
+            encoder_delay: None,
+            encoder_padding: None,
+            total_samples: None,
+        }
+    }
+
+    /// A library holding just `tracks`, with the composer index built.
+    fn library(name: &str, tracks: &[Track]) -> (PathBuf, Library) {
+        let path = std::env::temp_dir().join(format!(
+            "phonolite-classical-{}-{}.redb",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let db = Library::open_db(&path).unwrap();
+        let txn = db.begin_write().unwrap();
+        {
+            let mut albums_by_name = txn.open_table(ALBUMS_BY_NAME_TABLE).unwrap();
+            let album_key = format!("{}album", prefix_key("album"));
+            albums_by_name
+                .insert(album_key.as_str(), b"album".as_slice())
+                .unwrap();
+            let mut by_name = txn.open_table(TRACKS_BY_NAME_TABLE).unwrap();
+            let mut table = txn.open_table(TRACKS_TABLE).unwrap();
+            for track in tracks {
+                let key = track_name_key(
+                    "artist",
+                    "album",
+                    track.disc_no,
+                    track.track_no,
+                    &track.title,
+                    &track.id,
+                );
+                by_name.insert(key.as_str(), track.id.as_bytes()).unwrap();
+                let bytes = encode_value(track).unwrap();
+                table.insert(track.id.as_str(), bytes.as_slice()).unwrap();
+            }
+        }
+        write_composer_index(&txn).unwrap();
+        txn.commit().unwrap();
+        let library = Library {
+            root: PathBuf::new(),
+            db,
+            genres: Arc::new(GenreMap::builtin()),
+        };
+        (path, library)
+    }
+
+    fn ids(tracks: &[Track]) -> Vec<&str> {
+        tracks.iter().map(|track| track.id.as_str()).collect()
+    }
+
+    #[test]
+    fn composers_are_indexed_by_work() {
+        let tracks = [
+            track("a", 1, Some("Symphony No. 5"), &["Ludwig van Beethoven"]),
+            track("b", 2, Some("Symphony No. 5"), &[" ludwig van beethoven "]),
+            track("c", 3, Some("Coriolan Overture"), &["Ludwig van Beethoven"]),
+            track("d", 4, None, &["Franz Schubert", "Ludwig van Beethoven"]),
+        ];
+        let (path, library) = library("index", &tracks);
+
+        let (composers, total) = library.list_composers(None, 10, 0).unwrap();
+        assert_eq!(total, 2);
+        let beethoven = composer_id("Ludwig van Beethoven");
+        let info = library.get_composer(&beethoven).unwrap().unwrap();
+        assert_eq!(info.name, "Ludwig van Beethoven");
+        assert_eq!((info.album_count, info.track_count), (1, 4));
+        assert!(composers
+            .iter()
+            .any(|composer| composer.name == "Franz Schubert"));
+
+        // Works sort by title, with untitled tracks first.
+        let (listed, _) = library.list_composer_tracks(&beethoven, 10, 0).unwrap();
+        assert_eq!(ids(&listed), vec!["d", "c", "a", "b"]);
+        let read = library.db.begin_read().unwrap();
+        let albums = read.open_table(COMPOSER_ALBUMS_TABLE).unwrap();
+        let album_row = format!("{}{}album", prefix_key(&beethoven), prefix_key("album"));
+        assert!(albums.get(album_row.as_str()).unwrap().is_some());
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn adjacent_movements_group_by_work() {
+        let tracks = vec![
+            track("a", 1, Some("Sonata No. 14"), &["Beethoven"]),
+            track("b", 2, Some("sonata no. 14 "), &["Beethoven"]),
+            track("c", 3, None, &["Beethoven"]),
+            track("d", 4, None, &["Beethoven"]),
+            track("e", 5, Some("Sonata No. 14"), &["Beethoven"]),
+            track("f", 6, Some("Sonata No. 14"), &["Czerny"]),
+        ];
+        let groups = group_by_work(tracks);
+        let shape: Vec<(Option<&str>, Vec<&str>)> = groups
+            .iter()
+            .map(|group| (group.work.as_deref(), ids(&group.tracks)))
+            .collect();
+        assert_eq!(
+            shape,
+            vec![
+                (Some("Sonata No. 14"), vec!["a", "b"]),
+                (None, vec!["c"]),
+                (None, vec!["d"]),
+                (Some("Sonata No. 14"), vec!["e"]),
+                (Some("Sonata No. 14"), vec!["f"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn search_finds_works_and_credits() {
+        let mut first = track("a", 1, Some("Goldberg Variations"), &["Bach"]);
+        first.performers = vec!["Glenn Gould".to_string()];
+        let mut second = track("b", 2, Some("The Rite of Spring"), &["Stravinsky"]);
+        second.conductor = Some("Pierre Boulez".to_string());
+        second.performers = vec!["Cleveland Orchestra".to_string()];
+        let (path, library) = library("search", &[first, second]);
+
+        assert_eq!(
+            ids(&library.search_work_tracks("goldberg", 10).unwrap()),
+            vec!["a"]
+        );
+        assert_eq!(
+            ids(&library.search_credit_tracks("gould", 10).unwrap()),
+            vec!["a"]
+        );
+        assert_eq!(
+            ids(&library.search_credit_tracks("Boulez", 10).unwrap()),
+            vec!["b"]
+        );
+        assert_eq!(
+            ids(&library.search_credit_tracks("cleveland", 10).unwrap()),
+            vec!["b"]
+        );
+        assert!(library.search_credit_tracks("bach", 10).unwrap().is_empty());
+        let _ = std::fs::remove_file(path);
+    }
+}
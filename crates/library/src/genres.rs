@@ -0,0 +1,541 @@
+//! Genre normalization and the persisted genre index.
+//!
+//! Raw tag values go through the multilingual label cleanup, then the alias
+//! table, so "Hip Hop", "hiphop" and "Hip-Hop" land in one bucket. An optional
+//! mapping file adds aliases and parent links; browsing a parent genre also
+//! lists everything filed under its children.
+
+use std::collections::{HashMap, HashSet};
+
+use common::{stable_id, Album, Artist, Track};
+use redb::{Database, ReadableTable, TableDefinition, TableError, WriteTransaction};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    clear_table, decode_value, encode_value, prefix_key, split_key_last, LibraryError,
+    ALBUMS_BY_NAME_TABLE, ALBUMS_TABLE, ARTISTS_BY_NAME_TABLE, ARTISTS_TABLE, KEY_SEP,
+    META_TABLE, TRACKS_BY_NAME_TABLE, TRACKS_TABLE,
+};
+
+pub(crate) const GENRES_TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("genres");
+pub(crate) const GENRES_BY_NAME_TABLE: TableDefinition<&str, &[u8]> =
+    TableDefinition::new("genres_by_name");
+pub(crate) const GENRE_ALBUMS_TABLE: TableDefinition<&str, &[u8]> =
+    TableDefinition::new("genre_albums");
+pub(crate) const GENRE_ARTISTS_TABLE: TableDefinition<&str, &[u8]> =
+    TableDefinition::new("genre_artists");
+pub(crate) const GENRE_TRACKS_TABLE: TableDefinition<&str, &[u8]> =
+    TableDefinition::new("genre_tracks");
+
+pub(crate) const META_GENRE_MAP_KEY: &str = "genre_map";
+
+const BUILTIN_ALIASES: &[(&str, &[&str])] = &[
+    ("Hip-Hop", &["hip hop", "hiphop", "hip-hop/rap", "hip hop/rap"]),
+    ("R&B", &["rnb", "r and b", "r 'n' b", "r'n'b", "rhythm and blues", "rhythm & blues"]),
+    ("Drum & Bass", &["drum and bass", "drum n bass", "drum'n'bass", "dnb", "d&b"]),
+    ("Rock & Roll", &["rock and roll", "rock n roll", "rock'n'roll"]),
+    ("Lo-Fi", &["lofi", "lo fi"]),
+    ("Synth-pop", &["synthpop", "synth pop"]),
+    ("Post-Rock", &["post rock", "postrock"]),
+    ("Post-Punk", &["post punk", "postpunk"]),
+    ("Trip-Hop", &["trip hop", "triphop"]),
+    ("K-Pop", &["kpop", "k pop"]),
+    ("J-Pop", &["jpop", "j pop"]),
+    ("Singer-Songwriter", &["singer songwriter", "singer/songwriter"]),
+    ("Electronic", &["electronic music"]),
+];
+
+const BUILTIN_PARENTS: &[(&str, &str)] = &[
+    ("Contemporary classical", "Classical"),
+    ("Chamber music", "Classical"),
+    ("Drum & Bass", "Electronic"),
+    ("House", "Electronic"),
+    ("Techno", "Electronic"),
+    ("Trance", "Electronic"),
+    ("Dubstep", "Electronic"),
+    ("Trap", "Hip-Hop"),
+];
+
+/// On-disk mapping file (`genres.yaml` next to the config by default).
+/// An empty parent detaches a genre from its built-in parent.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GenreMapFile {
+    pub aliases: HashMap<String, Vec<String>>,
+    pub parents: HashMap<String, String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct GenreMap {
+    aliases: HashMap<String, String>,
+    parents: HashMap<String, String>,
+}
+
+impl Default for GenreMap {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl GenreMap {
+    pub fn builtin() -> Self {
+        let mut map = Self {
+            aliases: HashMap::new(),
+            parents: HashMap::new(),
+        };
+        for (label, aliases) in BUILTIN_ALIASES {
+            map.add_aliases(label, aliases.iter().copied());
+        }
+        for (child, parent) in BUILTIN_PARENTS {
+            map.parents.insert(genre_key(child), parent.to_string());
+        }
+        map
+    }
+
+    pub fn from_file(file: &GenreMapFile) -> Self {
+        let mut map = Self::builtin();
+        for (label, aliases) in &file.aliases {
+            map.add_aliases(label, aliases.iter().map(String::as_str));
+        }
+        for (child, parent) in &file.parents {
+            let key = genre_key(child);
+            let parent = parent.trim();
+            if parent.is_empty() {
+                map.parents.remove(&key);
+            } else {
+                map.parents.insert(key, map.canonical(parent));
+            }
+        }
+        map
+    }
+
+    fn add_aliases<'a>(&mut self, label: &str, aliases: impl Iterator<Item = &'a str>) {
+        let label = label.trim();
+        if label.is_empty() {
+            return;
+        }
+        self.aliases.insert(genre_key(label), label.to_string());
+        for alias in aliases {
+            let key = genre_key(alias);
+            if !key.is_empty() {
+                self.aliases.insert(key, label.to_string());
+            }
+        }
+    }
+
+    /// Display label for a raw genre value.
+    pub fn canonical(&self, value: &str) -> String {
+        let label = normalize_genre_label(value.trim());
+        match self.aliases.get(&genre_key(&label)) {
+            Some(mapped) => mapped.clone(),
+            None => label,
+        }
+    }
+
+    pub fn parent(&self, label: &str) -> Option<&str> {
+        self.parents.get(&genre_key(label)).map(String::as_str)
+    }
+
+    /// The genre itself followed by its parents, stopping at cycles.
+    pub fn lineage(&self, label: &str) -> Vec<String> {
+        let mut out = vec![label.to_string()];
+        let mut seen: HashSet<String> = HashSet::new();
+        seen.insert(genre_key(label));
+        let mut current = label.to_string();
+        while let Some(parent) = self.parent(&current) {
+            if !seen.insert(genre_key(parent)) {
+                break;
+            }
+            out.push(parent.to_string());
+            current = parent.to_string();
+        }
+        out
+    }
+
+    /// Changes whenever aliases or parents change, so a stale index can be
+    /// rebuilt without a full rescan.
+    pub fn fingerprint(&self) -> String {
+        let mut aliases: Vec<_> = self.aliases.iter().collect();
+        aliases.sort();
+        let mut parents: Vec<_> = self.parents.iter().collect();
+        parents.sort();
+        let mut out = String::new();
+        for (key, value) in aliases.into_iter().chain(parents) {
+            out.push_str(key);
+            out.push(KEY_SEP);
+            out.push_str(value);
+            out.push('\n');
+        }
+        stable_id(&out)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GenreInfo {
+    pub id: String,
+    pub name: String,
+    pub parent_id: Option<String>,
+    pub album_count: usize,
+    pub artist_count: usize,
+    pub track_count: usize,
+}
+
+/// Folded comparison key: lowercase, accents stripped, punctuation and
+/// whitespace removed.
+pub fn genre_key(value: &str) -> String {
+    let lower = value.trim().to_lowercase();
+    let probe = normalize_genre_probe(&lower);
+    let folded: String = probe.chars().filter(|ch| !ch.is_whitespace()).collect();
+    if folded.is_empty() {
+        lower
+    } else {
+        folded
+    }
+}
+
+pub fn genre_id(label: &str) -> String {
+    stable_id(&genre_key(label))
+}
+
+pub(crate) fn merge_genres(map: &GenreMap, target: &mut Vec<String>, incoming: &[String]) {
+    if incoming.is_empty() {
+        return;
+    }
+    let mut seen: HashSet<String> = target.iter().map(|genre| genre_key(genre)).collect();
+    for genre in incoming {
+        if genre.trim().is_empty() {
+            continue;
+        }
+        let label = map.canonical(genre);
+        if label.is_empty() {
+            continue;
+        }
+        if seen.insert(genre_key(&label)) {
+            target.push(label);
+        }
+    }
+}
+
+pub(crate) fn normalize_genres(map: &GenreMap, values: &[String]) -> Vec<String> {
+    let mut out = Vec::new();
+    merge_genres(map, &mut out, values);
+    out
+}
+
+pub(crate) fn read_genre_fingerprint(db: &Database) -> Result<Option<String>, LibraryError> {
+    let read_txn = db.begin_read()?;
+    let table = match read_txn.open_table(META_TABLE) {
+        Ok(table) => table,
+        Err(TableError::TableDoesNotExist(_)) => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let fingerprint = match table.get(META_GENRE_MAP_KEY)? {
+        Some(value) => Some(decode_value(value.value())?),
+        None => None,
+    };
+    Ok(fingerprint)
+}
+
+#[derive(Default)]
+struct GenreDraft {
+    name: String,
+    albums: usize,
+    artists: usize,
+    tracks: usize,
+}
+
+/// Rebuilds the genre tables from the stored artists, albums and tracks.
+/// Entities are filed under each genre's whole lineage, so parent counts
+/// include their children.
+pub(crate) fn write_genre_index(
+    txn: &WriteTransaction,
+    map: &GenreMap,
+) -> Result<usize, LibraryError> {
+    clear_table(txn, GENRES_TABLE)?;
+    clear_table(txn, GENRES_BY_NAME_TABLE)?;
+    clear_table(txn, GENRE_ALBUMS_TABLE)?;
+    clear_table(txn, GENRE_ARTISTS_TABLE)?;
+    clear_table(txn, GENRE_TRACKS_TABLE)?;
+
+    let mut drafts: HashMap<String, GenreDraft> = HashMap::new();
+    let mut album_genres: HashMap<String, Vec<String>> = HashMap::new();
+    {
+        let albums_by_name = txn.open_table(ALBUMS_BY_NAME_TABLE)?;
+        let albums = txn.open_table(ALBUMS_TABLE)?;
+        let artists_by_name = txn.open_table(ARTISTS_BY_NAME_TABLE)?;
+        let artists = txn.open_table(ARTISTS_TABLE)?;
+        let tracks_by_name = txn.open_table(TRACKS_BY_NAME_TABLE)?;
+        let tracks = txn.open_table(TRACKS_TABLE)?;
+        let mut genre_albums = txn.open_table(GENRE_ALBUMS_TABLE)?;
+        let mut genre_artists = txn.open_table(GENRE_ARTISTS_TABLE)?;
+        let mut genre_tracks = txn.open_table(GENRE_TRACKS_TABLE)?;
+
+        for entry in albums_by_name.iter()? {
+            let entry = entry?;
+            let key = entry.0.value();
+            let (_, album_id) = split_key_last(key)?;
+            let album: Album = match albums.get(album_id)? {
+                Some(value) => decode_value(value.value())?,
+                None => continue,
+            };
+            let ids = file_under(map, &mut drafts, &album.genres, |draft| draft.albums += 1);
+            for id in &ids {
+                let row = format!("{}{}", prefix_key(id), key);
+                genre_albums.insert(row.as_str(), album_id.as_bytes())?;
+            }
+            album_genres.insert(album.id, album.genres);
+        }
+
+        for entry in artists_by_name.iter()? {
+            let entry = entry?;
+            let key = entry.0.value();
+            let (_, artist_id) = split_key_last(key)?;
+            let artist: Artist = match artists.get(artist_id)? {
+                Some(value) => decode_value(value.value())?,
+                None => continue,
+            };
+            let ids = file_under(map, &mut drafts, &artist.genres, |draft| draft.artists += 1);
+            for id in &ids {
+                let row = format!("{}{}", prefix_key(id), key);
+                genre_artists.insert(row.as_str(), artist_id.as_bytes())?;
+            }
+        }
+
+        for entry in tracks_by_name.iter()? {
+            let entry = entry?;
+            let key = entry.0.value();
+            let (_, track_id) = split_key_last(key)?;
+            let track: Track = match tracks.get(track_id)? {
+                Some(value) => decode_value(value.value())?,
+                None => continue,
+            };
+            // Untagged tracks inherit their album's genres.
+            let genres = if track.genres.is_empty() {
+                album_genres
+                    .get(&track.album_id)
+                    .map(Vec::as_slice)
+                    .unwrap_or(&[])
+            } else {
+                track.genres.as_slice()
+            };
+            let ids = file_under(map, &mut drafts, genres, |draft| draft.tracks += 1);
+            for id in &ids {
+                let row = format!("{}{}", prefix_key(id), key);
+                genre_tracks.insert(row.as_str(), track_id.as_bytes())?;
+            }
+        }
+    }
+
+    let count = drafts.len();
+    {
+        let mut genres = txn.open_table(GENRES_TABLE)?;
+        let mut genres_by_name = txn.open_table(GENRES_BY_NAME_TABLE)?;
+        for (id, draft) in drafts {
+            let info = GenreInfo {
+                parent_id: map.parent(&draft.name).map(genre_id),
+                id: id.clone(),
+                name: draft.name,
+                album_count: draft.albums,
+                artist_count: draft.artists,
+                track_count: draft.tracks,
+            };
+            let bytes = encode_value(&info)?;
+            genres.insert(id.as_str(), bytes.as_slice())?;
+            let name_key = format!("{}{}", prefix_key(&info.name.to_lowercase()), id);
+            genres_by_name.insert(name_key.as_str(), id.as_bytes())?;
+        }
+        let mut meta = txn.open_table(META_TABLE)?;
+        let fingerprint = encode_value(&map.fingerprint())?;
+        meta.insert(META_GENRE_MAP_KEY, fingerprint.as_slice())?;
+    }
+    Ok(count)
+}
+
+fn file_under(
+    map: &GenreMap,
+    drafts: &mut HashMap<String, GenreDraft>,
+    genres: &[String],
+    mut bump: impl FnMut(&mut GenreDraft),
+) -> Vec<String> {
+    let mut ids = Vec::new();
+    for genre in genres {
+        for label in map.lineage(&map.canonical(genre)) {
+            let id = genre_id(&label);
+            if ids.contains(&id) {
+                continue;
+            }
+            let draft = drafts.entry(id.clone()).or_default();
+            if draft.name.is_empty() {
+                draft.name = label;
+            }
+            bump(draft);
+            ids.push(id);
+        }
+    }
+    ids
+}
+
+fn normalize_genre_label(value: &str) -> String {
+    let lower = value.trim().to_lowercase();
+    let normalized = normalize_genre_probe(&lower);
+    let exact = match lower.as_str() {
+        "musique classique" => "Classical".to_string(),
+        "musique de chambre" => "Chamber music".to_string(),
+        "musique de film" => "Soundtrack".to_string(),
+        "musiques de film" => "Soundtrack".to_string(),
+        "musique électronique" => "Electronic".to_string(),
+        "musique electronique" => "Electronic".to_string(),
+        "musique instrumentale" => "Instrumental".to_string(),
+        "musique ambient" => "Ambient".to_string(),
+        "musique ambiante" => "Ambient".to_string(),
+        "piano solo" => "Solo piano".to_string(),
+        "classique" => "Classical".to_string(),
+        "musica clasica" => "Classical".to_string(),
+        "música clásica" => "Classical".to_string(),
+        "musica classica" => "Classical".to_string(),
+        "música clásica contemporánea" => "Contemporary classical".to_string(),
+        "musica contemporanea" => "Contemporary classical".to_string(),
+        "musica de camara" => "Chamber music".to_string(),
+        "música de cámara" => "Chamber music".to_string(),
+        "musica de pelicula" => "Soundtrack".to_string(),
+        "música de película" => "Soundtrack".to_string(),
+        "banda sonora" => "Soundtrack".to_string(),
+        "colonna sonora" => "Soundtrack".to_string(),
+        "musica elettronica" => "Electronic".to_string(),
+        "musica elettronica sperimentale" => "Electronic".to_string(),
+        "musica strumentale" => "Instrumental".to_string(),
+        "musica ambient" => "Ambient".to_string(),
+        "música instrumental" => "Instrumental".to_string(),
+        "música electrónica" => "Electronic".to_string(),
+        "música electronica" => "Electronic".to_string(),
+        "klassik" => "Classical".to_string(),
+        "klassische musik" => "Classical".to_string(),
+        "filmmusik" => "Soundtrack".to_string(),
+        "elektronische musik" => "Electronic".to_string(),
+        "instrumentalmusik" => "Instrumental".to_string(),
+        "kammermusik" => "Chamber music".to_string(),
+        "zeitgenössische klassische musik" => "Contemporary classical".to_string(),
+        "zeitgenossische klassische musik" => "Contemporary classical".to_string(),
+        _ => String::new(),
+    };
+    if !exact.is_empty() {
+        return exact;
+    }
+    match match_genre_probe(&normalized) {
+        Some(mapped) => mapped,
+        None => value.trim().to_string(),
+    }
+}
+
+fn match_genre_probe(value: &str) -> Option<String> {
+    let rules: [(&str, &[&str]); 6] = [
+        ("Classical", &["classical", "klassik", "musica clasica", "musique classique"]),
+        ("Contemporary classical", &["contemporary classical", "zeitgenossisch", "contemporanea"]),
+        ("Chamber music", &["chamber music", "musique de chambre", "musica de camara", "kammermusik"]),
+        ("Soundtrack", &["soundtrack", "musique de film", "banda sonora", "colonna sonora", "filmmusik"]),
+        ("Electronic", &["electronic", "electronique", "electronica", "elektronisch"]),
+        ("Instrumental", &["instrumental", "instrumentale", "strumentale", "instrumentalmusik"]),
+    ];
+
+    for (label, terms) in rules {
+        for term in terms {
+            if value.contains(term) {
+                return Some(label.to_string());
+            }
+        }
+    }
+    if value.contains("ambient") {
+        return Some("Ambient".to_string());
+    }
+    if value.contains("solo piano") || value.contains("piano solo") {
+        return Some("Solo piano".to_string());
+    }
+    None
+}
+
+fn normalize_genre_probe(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut last_space = false;
+    for ch in value.chars() {
+        let mapped = match ch {
+            'à' | 'á' | 'â' | 'ä' | 'ã' | 'å' => 'a',
+            'ç' => 'c',
+            'è' | 'é' | 'ê' | 'ë' => 'e',
+            'ì' | 'í' | 'î' | 'ï' => 'i',
+            'ñ' => 'n',
+            'ò' | 'ó' | 'ô' | 'ö' | 'õ' => 'o',
+            'ù' | 'ú' | 'û' | 'ü' => 'u',
+            'ý' | 'ÿ' => 'y',
+            'œ' => {
+                out.push('o');
+                'e'
+            }
+            'æ' => {
+                out.push('a');
+                'e'
+            }
+            _ => ch,
+        };
+        if mapped.is_ascii_alphanumeric() {
+            out.push(mapped);
+            last_space = false;
+        } else if !last_space {
+            out.push(' ');
+            last_space = true;
+        }
+    }
+    out.trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aliases_and_parents_resolve() {
+        let mut file = GenreMapFile::default();
+        file.aliases
+            .insert("Drum & Bass".to_string(), vec!["jungle".to_string()]);
+        file.parents.insert("Trap".to_string(), String::new());
+        let map = GenreMap::from_file(&file);
+
+        assert_eq!(map.canonical("hip hop"), "Hip-Hop");
+        assert_eq!(map.canonical("Jungle"), "Drum & Bass");
+        assert_eq!(map.lineage("Drum & Bass"), vec!["Drum & Bass", "Electronic"]);
+        assert_eq!(map.parent("Trap"), None);
+        assert_eq!(genre_id("Hip Hop"), genre_id("hip-hop"));
+    }
+
+    fn labels(values: &[&str]) -> Vec<String> {
+        let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+        normalize_genres(&GenreMap::builtin(), &values)
+    }
+
+    #[test]
+    fn genres_fold_case_separators_and_duplicates() {
+        assert_eq!(
+            labels(&["hip hop", "Hip-Hop", "hiphop", "HIP_HOP"]),
+            vec!["Hip-Hop"]
+        );
+        assert_eq!(labels(&["", "  ", "Jazz"]), vec!["Jazz"]);
+        assert_eq!(
+            labels(&["Musique électronique", "electronic", "Ambient"]),
+            vec!["Electronic", "Ambient"]
+        );
+        // Genres without an alias keep their first spelling.
+        assert_eq!(labels(&["Zeuhl", " ZEUHL ", "zeuhl"]), vec!["Zeuhl"]);
+        assert_eq!(genre_key("Drum & Bass"), genre_key("drum  &  bass"));
+    }
+
+    #[test]
+    fn merging_keeps_existing_genres_first() {
+        let map = GenreMap::builtin();
+        let mut genres = vec!["Jazz".to_string()];
+        merge_genres(
+            &map,
+            &mut genres,
+            &["jazz".to_string(), "Hip Hop".to_string()],
+        );
+        assert_eq!(genres, vec!["Jazz", "Hip-Hop"]);
+    }
+}
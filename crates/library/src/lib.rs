@@ -5,8 +5,11 @@ use std::sync::Arc;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use bincode;
-use common::{relpath_from, stable_id, Album, Artist, Codec, CoverRef, SeekIndex, SeekPoint, Track};
-use metadata::{read_tags, MetadataError, TagInfo};
+use common::{
+    relpath_from, stable_id, Album, AlbumType, Artist, Codec, CoverRef, SeekIndex, SeekPoint,
+    Track, Waveform,
+};
+use metadata::{parse_date, read_tags, GaplessInfo, MetadataError, TagInfo};
 use redb::{
     CommitError, Database, DatabaseError, ReadableTable, StorageError, TableDefinition, TableError,
     TransactionError, WriteTransaction,
@@ -15,7 +18,21 @@ use serde::{Deserialize, Serialize};
 use tracing::{info, warn};
 use walkdir::WalkDir;
 
-const INDEX_VERSION: u32 = 7;
+mod classical;
+mod genres;
+
+use genres::{
+    merge_genres, normalize_genres, read_genre_fingerprint, write_genre_index, GENRES_BY_NAME_TABLE,
+    GENRES_TABLE, GENRE_ALBUMS_TABLE, GENRE_ARTISTS_TABLE, GENRE_TRACKS_TABLE,
+};
+use classical::{
+    write_composer_index, COMPOSERS_BY_NAME_TABLE, COMPOSERS_TABLE, COMPOSER_ALBUMS_TABLE,
+    COMPOSER_TRACKS_TABLE, CREDIT_TRACKS_TABLE,
+};
+pub use classical::{composer_id, group_by_work, ComposerInfo, WorkGroup};
+pub use genres::{genre_id, genre_key, GenreInfo, GenreMap, GenreMapFile};
+
+const INDEX_VERSION: u32 = 14;
 const SEEK_STEP_MS: u32 = 5000;
 const KEY_SEP: char = '\x1f';
 
@@ -31,6 +48,7 @@ const ALBUM_TRACKS_TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("a
 const TRACK_EMBEDDED_COVER_TABLE: TableDefinition<&str, &[u8]> =
     TableDefinition::new("track_embedded_cover");
 const SEEK_TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("seek");
+const WAVEFORMS_TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("waveforms");
 const EXTERNAL_ATTEMPTS_TABLE: TableDefinition<&str, &[u8]> =
     TableDefinition::new("external_attempts");
 const TAG_ERRORS_TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("tag_errors");
@@ -44,6 +62,7 @@ const META_STATS_KEY: &str = "stats";
 pub struct Library {
     root: PathBuf,
     db: Arc<Database>,
+    genres: Arc<GenreMap>,
 }
 
 impl Library {
@@ -55,12 +74,14 @@ impl Library {
         let library = Self {
             root,
             db: Arc::new(db),
+            genres: Arc::new(GenreMap::builtin()),
         };
 
         let mut scanned = false;
         match read_version(&library.db)? {
             Some(version) if version == INDEX_VERSION => {
                 info!("Loaded index from {:?}", db_path);
+                library.refresh_genre_index()?;
             }
             Some(version) => {
                 warn!("Index version mismatch ({}); rescanning", version);
@@ -80,21 +101,28 @@ impl Library {
     pub fn load_or_scan_with_db(
         root: PathBuf,
         db: Arc<Database>,
+        genres: GenreMap,
+        progress: &dyn Fn(ScanProgress),
     ) -> Result<(Self, bool), LibraryError> {
-        let library = Self { root, db };
+        let library = Self {
+            root,
+            db,
+            genres: Arc::new(genres),
+        };
         let mut scanned = false;
         match read_version(&library.db)? {
             Some(version) if version == INDEX_VERSION => {
                 info!("Loaded index from existing database");
+                library.refresh_genre_index()?;
             }
             Some(version) => {
                 warn!("Index version mismatch ({}); rescanning", version);
-                library.rescan()?;
+                library.rescan_with_progress(progress)?;
                 scanned = true;
             }
             None => {
                 warn!("Index missing; scanning");
-                library.rescan()?;
+                library.rescan_with_progress(progress)?;
                 scanned = true;
             }
         }
@@ -107,11 +135,19 @@ impl Library {
     }
 
     pub fn rescan(&self) -> Result<LibraryStats, LibraryError> {
-        scan_library(&self.root, &self.db)
+        self.rescan_with_progress(&|_| {})
+    }
+
+    /// A full rescan that reports before each album folder.
+    pub fn rescan_with_progress(
+        &self,
+        progress: &dyn Fn(ScanProgress),
+    ) -> Result<LibraryStats, LibraryError> {
+        scan_library(&self.root, &self.db, &self.genres, progress)
     }
 
     pub fn incremental_scan(&self) -> Result<LibraryStats, LibraryError> {
-        scan_library_incremental(&self.root, &self.db)
+        scan_library_incremental(&self.root, &self.db, &self.genres)
     }
 
     pub fn stats(&self) -> Result<LibraryStats, LibraryError> {
@@ -399,6 +435,31 @@ impl Library {
         Ok(seek)
     }
 
+    pub fn get_waveform(&self, track_id: &str) -> Result<Option<Waveform>, LibraryError> {
+        let read_txn = self.db.begin_read()?;
+        let table = match read_txn.open_table(WAVEFORMS_TABLE) {
+            Ok(table) => table,
+            Err(TableError::TableDoesNotExist(_)) => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let waveform = match table.get(track_id)? {
+            Some(value) => Some(decode_value(value.value())?),
+            None => None,
+        };
+        Ok(waveform)
+    }
+
+    pub fn put_waveform(&self, track_id: &str, waveform: &Waveform) -> Result<(), LibraryError> {
+        let write_txn = self.db.begin_write()?;
+        {
+            let mut table = write_txn.open_table(WAVEFORMS_TABLE)?;
+            let bytes = encode_value(waveform)?;
+            table.insert(track_id, bytes.as_slice())?;
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
     pub fn track_has_embedded_cover(&self, track_id: &str) -> Result<bool, LibraryError> {
         let read_txn = self.db.begin_read()?;
         let table = read_txn.open_table(TRACK_EMBEDDED_COVER_TABLE)?;
@@ -442,14 +503,14 @@ impl Library {
             if !genres.is_empty() {
                 if replace {
                     let mut normalized = Vec::new();
-                    merge_genres(&mut normalized, genres);
+                    merge_genres(&self.genres, &mut normalized, genres);
                     if artist.genres != normalized {
                         artist.genres = normalized;
                         updated = true;
                     }
                 } else {
                     let before = artist.genres.len();
-                    merge_genres(&mut artist.genres, genres);
+                    merge_genres(&self.genres, &mut artist.genres, genres);
                     if artist.genres.len() != before {
                         updated = true;
                     }
@@ -486,6 +547,7 @@ impl Library {
         album_id: &str,
         summary: Option<String>,
         genres: &[String],
+        album_type: Option<AlbumType>,
     ) -> Result<bool, LibraryError> {
         let write_txn = self.db.begin_write()?;
         let updated = {
@@ -509,11 +571,15 @@ impl Library {
             }
             if !genres.is_empty() {
                 let before = album.genres.len();
-                merge_genres(&mut album.genres, genres);
+                merge_genres(&self.genres, &mut album.genres, genres);
                 if album.genres.len() != before {
                     updated = true;
                 }
             }
+            if album.album_type.is_none() && album_type.is_some() {
+                album.album_type = album_type;
+                updated = true;
+            }
 
             if updated {
                 let album_bytes = encode_value(&album)?;
@@ -571,6 +637,391 @@ impl Library {
         Ok(())
     }
 
+    pub fn album_sidecar_path(&self, album_id: &str) -> Result<Option<PathBuf>, LibraryError> {
+        let album = match self.get_album(album_id)? {
+            Some(album) => album,
+            None => return Ok(None),
+        };
+        Ok(Some(
+            self.root
+                .join(&album.folder_relpath)
+                .join(ALBUM_SIDECAR_FILE),
+        ))
+    }
+
+    /// artist.json lives in the parent of the album folders; the first album
+    /// decides which folder that is.
+    pub fn artist_sidecar_path(&self, artist_id: &str) -> Result<Option<PathBuf>, LibraryError> {
+        let album = match self.list_artist_albums(artist_id)?.into_iter().next() {
+            Some(album) => album,
+            None => return Ok(None),
+        };
+        let album_dir = self.root.join(&album.folder_relpath);
+        Ok(album_dir
+            .parent()
+            .map(|parent| parent.join(ARTIST_SIDECAR_FILE)))
+    }
+
+    pub fn read_album_sidecar(&self, album_id: &str) -> Result<Option<AlbumSidecar>, LibraryError> {
+        let path = match self.album_sidecar_path(album_id)? {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        Ok(Some(read_album_sidecar(&path).unwrap_or_default()))
+    }
+
+    pub fn read_artist_sidecar(
+        &self,
+        artist_id: &str,
+    ) -> Result<Option<ArtistSidecar>, LibraryError> {
+        let path = match self.artist_sidecar_path(artist_id)? {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        Ok(Some(read_artist_sidecar(&path).unwrap_or_default()))
+    }
+
+    /// Writes album.json next to the album's files. Changes take effect on the
+    /// next full rescan.
+    pub fn write_album_sidecar(
+        &self,
+        album_id: &str,
+        sidecar: AlbumSidecar,
+    ) -> Result<bool, LibraryError> {
+        let path = match self.album_sidecar_path(album_id)? {
+            Some(path) => path,
+            None => return Ok(false),
+        };
+        let sidecar = sidecar.cleaned();
+        if let (Some(cover), Some(album_dir)) = (sidecar.cover.as_deref(), path.parent()) {
+            if sidecar_cover_ref(&self.root, album_dir, cover).is_none() {
+                return Err(LibraryError::Sidecar(format!("invalid cover path: {}", cover)));
+            }
+        }
+        write_sidecar(&path, &sidecar, ALBUM_SIDECAR_KEYS)?;
+        Ok(true)
+    }
+
+    pub fn write_artist_sidecar(
+        &self,
+        artist_id: &str,
+        sidecar: ArtistSidecar,
+    ) -> Result<bool, LibraryError> {
+        let path = match self.artist_sidecar_path(artist_id)? {
+            Some(path) => path,
+            None => return Ok(false),
+        };
+        write_sidecar(&path, &sidecar.cleaned(), ARTIST_SIDECAR_KEYS)?;
+        Ok(true)
+    }
+
+    pub fn genre_map(&self) -> &GenreMap {
+        &self.genres
+    }
+
+    /// The same index read through another genre map, so a rescan applies
+    /// an edited mapping file.
+    pub fn with_genre_map(&self, genres: GenreMap) -> Self {
+        Self {
+            root: self.root.clone(),
+            db: Arc::clone(&self.db),
+            genres: Arc::new(genres),
+        }
+    }
+
+    pub fn canonical_genre(&self, value: &str) -> String {
+        self.genres.canonical(value)
+    }
+
+    /// Recomputes genre ids, counts and membership from the stored entities.
+    /// Enrichment only touches artist and album rows, so callers run this
+    /// once a sweep has finished.
+    pub fn rebuild_genre_index(&self) -> Result<usize, LibraryError> {
+        let write_txn = self.db.begin_write()?;
+        let count = write_genre_index(&write_txn, &self.genres)?;
+        write_txn.commit()?;
+        Ok(count)
+    }
+
+    fn refresh_genre_index(&self) -> Result<(), LibraryError> {
+        let stored = read_genre_fingerprint(&self.db)?;
+        if stored.as_deref() != Some(self.genres.fingerprint().as_str()) {
+            info!("Genre map changed; rebuilding genre index");
+            self.rebuild_genre_index()?;
+        }
+        Ok(())
+    }
+
+    pub fn list_genres(
+        &self,
+        search: Option<&str>,
+        limit: usize,
+        offset: usize,
+    ) -> Result<(Vec<GenreInfo>, usize), LibraryError> {
+        let search = search
+            .map(|value| value.trim())
+            .filter(|value| !value.is_empty())
+            .map(genre_key);
+
+        let read_txn = self.db.begin_read()?;
+        let name_table = read_txn.open_table(GENRES_BY_NAME_TABLE)?;
+        let genre_table = read_txn.open_table(GENRES_TABLE)?;
+
+        let mut total = 0usize;
+        let mut items = Vec::new();
+
+        for entry in name_table.iter()? {
+            let entry = entry?;
+            let key = entry.0.value();
+            let (name_lower, genre_id) = split_key_last(key)?;
+            if let Some(search) = &search {
+                if !genre_key(name_lower).contains(search.as_str()) {
+                    continue;
+                }
+            }
+
+            total += 1;
+            if total <= offset {
+                continue;
+            }
+            if items.len() >= limit {
+                continue;
+            }
+
+            if let Some(value) = genre_table.get(genre_id)? {
+                let genre: GenreInfo = decode_value(value.value())?;
+                items.push(genre);
+            }
+        }
+
+        Ok((items, total))
+    }
+
+    pub fn get_genre(&self, genre_id: &str) -> Result<Option<GenreInfo>, LibraryError> {
+        let read_txn = self.db.begin_read()?;
+        let genre_table = read_txn.open_table(GENRES_TABLE)?;
+        let genre = match genre_table.get(genre_id)? {
+            Some(value) => Some(decode_value(value.value())?),
+            None => None,
+        };
+        Ok(genre)
+    }
+
+    /// Albums filed under the genre or any of its sub-genres, in album name
+    /// order.
+    pub fn list_genre_albums(
+        &self,
+        genre_id: &str,
+        limit: usize,
+        offset: usize,
+    ) -> Result<(Vec<Album>, usize), LibraryError> {
+        self.list_index_range(GENRE_ALBUMS_TABLE, ALBUMS_TABLE, genre_id, limit, offset)
+    }
+
+    pub fn list_genre_artists(
+        &self,
+        genre_id: &str,
+        limit: usize,
+        offset: usize,
+    ) -> Result<(Vec<Artist>, usize), LibraryError> {
+        self.list_index_range(GENRE_ARTISTS_TABLE, ARTISTS_TABLE, genre_id, limit, offset)
+    }
+
+    /// Tracks without their own genre tags count under their album's genres.
+    pub fn list_genre_tracks(
+        &self,
+        genre_id: &str,
+        limit: usize,
+        offset: usize,
+    ) -> Result<(Vec<Track>, usize), LibraryError> {
+        self.list_index_range(GENRE_TRACKS_TABLE, TRACKS_TABLE, genre_id, limit, offset)
+    }
+
+    pub fn list_composers(
+        &self,
+        search: Option<&str>,
+        limit: usize,
+        offset: usize,
+    ) -> Result<(Vec<ComposerInfo>, usize), LibraryError> {
+        let search = search
+            .map(|value| value.trim())
+            .filter(|value| !value.is_empty())
+            .map(|value| value.to_lowercase());
+
+        let read_txn = self.db.begin_read()?;
+        let name_table = read_txn.open_table(COMPOSERS_BY_NAME_TABLE)?;
+        let composer_table = read_txn.open_table(COMPOSERS_TABLE)?;
+
+        let mut total = 0usize;
+        let mut items = Vec::new();
+
+        for entry in name_table.iter()? {
+            let entry = entry?;
+            let key = entry.0.value();
+            let (name_lower, composer_id) = split_key_last(key)?;
+            if let Some(search) = &search {
+                if !name_lower.contains(search) {
+                    continue;
+                }
+            }
+
+            total += 1;
+            if total <= offset {
+                continue;
+            }
+            if items.len() >= limit {
+                continue;
+            }
+
+            if let Some(value) = composer_table.get(composer_id)? {
+                let composer: ComposerInfo = decode_value(value.value())?;
+                items.push(composer);
+            }
+        }
+
+        Ok((items, total))
+    }
+
+    pub fn get_composer(&self, composer_id: &str) -> Result<Option<ComposerInfo>, LibraryError> {
+        let read_txn = self.db.begin_read()?;
+        let composer_table = read_txn.open_table(COMPOSERS_TABLE)?;
+        let composer = match composer_table.get(composer_id)? {
+            Some(value) => Some(decode_value(value.value())?),
+            None => None,
+        };
+        Ok(composer)
+    }
+
+    pub fn list_composer_albums(
+        &self,
+        composer_id: &str,
+        limit: usize,
+        offset: usize,
+    ) -> Result<(Vec<Album>, usize), LibraryError> {
+        self.list_index_range(COMPOSER_ALBUMS_TABLE, ALBUMS_TABLE, composer_id, limit, offset)
+    }
+
+    /// Ordered by work, then by album and track position.
+    pub fn list_composer_tracks(
+        &self,
+        composer_id: &str,
+        limit: usize,
+        offset: usize,
+    ) -> Result<(Vec<Track>, usize), LibraryError> {
+        self.list_index_range(COMPOSER_TRACKS_TABLE, TRACKS_TABLE, composer_id, limit, offset)
+    }
+
+    /// Tracks whose work title contains `search`. Only tracks with a composer
+    /// credit are covered, which is where work tags show up in practice.
+    pub fn search_work_tracks(
+        &self,
+        search: &str,
+        limit: usize,
+    ) -> Result<Vec<Track>, LibraryError> {
+        let search = search.trim().to_lowercase();
+        if search.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let read_txn = self.db.begin_read()?;
+        let index_table = read_txn.open_table(COMPOSER_TRACKS_TABLE)?;
+        let track_table = read_txn.open_table(TRACKS_TABLE)?;
+
+        let mut seen = HashSet::new();
+        let mut items = Vec::new();
+        for entry in index_table.iter()? {
+            if items.len() >= limit {
+                break;
+            }
+            let entry = entry?;
+            let key = entry.0.value();
+            let work = key.split(KEY_SEP).nth(1).unwrap_or("");
+            if !work.contains(search.as_str()) {
+                continue;
+            }
+            let (_, track_id) = split_key_last(key)?;
+            if !seen.insert(track_id.to_string()) {
+                continue;
+            }
+            if let Some(value) = track_table.get(track_id)? {
+                items.push(decode_value(value.value())?);
+            }
+        }
+        Ok(items)
+    }
+
+    /// Tracks with a conductor or performer whose name contains `search`.
+    pub fn search_credit_tracks(
+        &self,
+        search: &str,
+        limit: usize,
+    ) -> Result<Vec<Track>, LibraryError> {
+        let search = search.trim().to_lowercase();
+        if search.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let read_txn = self.db.begin_read()?;
+        let index_table = read_txn.open_table(CREDIT_TRACKS_TABLE)?;
+        let track_table = read_txn.open_table(TRACKS_TABLE)?;
+
+        let mut seen = HashSet::new();
+        let mut items = Vec::new();
+        for entry in index_table.iter()? {
+            if items.len() >= limit {
+                break;
+            }
+            let entry = entry?;
+            let key = entry.0.value();
+            let name = key.split(KEY_SEP).next().unwrap_or("");
+            if !name.contains(search.as_str()) {
+                continue;
+            }
+            let (_, track_id) = split_key_last(key)?;
+            if !seen.insert(track_id.to_string()) {
+                continue;
+            }
+            if let Some(value) = track_table.get(track_id)? {
+                items.push(decode_value(value.value())?);
+            }
+        }
+        Ok(items)
+    }
+
+    fn list_index_range<T: for<'de> Deserialize<'de>>(
+        &self,
+        index: TableDefinition<&str, &[u8]>,
+        items_table: TableDefinition<&str, &[u8]>,
+        genre_id: &str,
+        limit: usize,
+        offset: usize,
+    ) -> Result<(Vec<T>, usize), LibraryError> {
+        let read_txn = self.db.begin_read()?;
+        let index_table = read_txn.open_table(index)?;
+        let item_table = read_txn.open_table(items_table)?;
+
+        let prefix = prefix_key(genre_id);
+        let mut end = prefix.clone();
+        end.push('\u{10ffff}');
+
+        let mut total = 0usize;
+        let mut items = Vec::new();
+
+        for entry in index_table.range(prefix.as_str()..end.as_str())? {
+            let entry = entry?;
+            total += 1;
+            if total <= offset || items.len() >= limit {
+                continue;
+            }
+            let (_, item_id) = split_key_last(entry.0.value())?;
+            if let Some(value) = item_table.get(item_id)? {
+                items.push(decode_value(value.value())?);
+            }
+        }
+
+        Ok((items, total))
+    }
+
     pub fn root(&self) -> &Path {
         &self.root
     }
@@ -587,6 +1038,13 @@ pub struct LibraryStats {
     pub tracks: usize,
 }
 
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct ScanProgress {
+    pub folders_done: usize,
+    pub folders_total: usize,
+    pub tracks: usize,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct TagErrorInfo {
     pub album_id: String,
@@ -619,6 +1077,7 @@ pub enum LibraryError {
     Bincode(Box<bincode::ErrorKind>),
     KeyParse(String),
     VersionMismatch(u32),
+    Sidecar(String),
 }
 
 impl std::fmt::Display for LibraryError {
@@ -632,6 +1091,7 @@ impl std::fmt::Display for LibraryError {
             LibraryError::VersionMismatch(version) => {
                 write!(f, "index version mismatch: {}", version)
             }
+            LibraryError::Sidecar(value) => write!(f, "sidecar error: {}", value),
         }
     }
 }
@@ -741,9 +1201,15 @@ fn read_stats(db: &Database) -> Result<LibraryStats, LibraryError> {
     Ok(stats)
 }
 
-fn scan_library(root: &Path, db: &Database) -> Result<LibraryStats, LibraryError> {
+fn scan_library(
+    root: &Path,
+    db: &Database,
+    genre_map: &GenreMap,
+    progress: &dyn Fn(ScanProgress),
+) -> Result<LibraryStats, LibraryError> {
     let album_dirs = collect_album_dirs(root);
-    info!("Found {} album folders", album_dirs.len());
+    let folders_total = album_dirs.len();
+    info!("Found {} album folders", folders_total);
 
     let write_txn = db.begin_write()?;
 
@@ -758,6 +1224,7 @@ fn scan_library(root: &Path, db: &Database) -> Result<LibraryStats, LibraryError
     clear_table(&write_txn, ALBUM_TRACKS_TABLE)?;
     clear_table(&write_txn, TRACK_EMBEDDED_COVER_TABLE)?;
     clear_table(&write_txn, SEEK_TABLE)?;
+    clear_table(&write_txn, WAVEFORMS_TABLE)?;
     clear_table(&write_txn, EXTERNAL_ATTEMPTS_TABLE)?;
     clear_table(&write_txn, TAG_ERRORS_TABLE)?;
     clear_table(&write_txn, TAG_ERROR_FILES_TABLE)?;
@@ -780,9 +1247,14 @@ fn scan_library(root: &Path, db: &Database) -> Result<LibraryStats, LibraryError
         let mut artist_count = 0usize;
         let mut album_count = 0usize;
         let mut track_count = 0usize;
-        let mut artist_sidecar_cache: HashMap<PathBuf, Option<SidecarInfo>> = HashMap::new();
+        let mut artist_sidecar_cache: HashMap<PathBuf, Option<ArtistSidecar>> = HashMap::new();
 
-        for album_dir in album_dirs {
+        for (folders_done, album_dir) in album_dirs.into_iter().enumerate() {
+            progress(ScanProgress {
+                folders_done,
+                folders_total,
+                tracks: track_count,
+            });
             let files = audio_files_in_dir(&album_dir);
             if files.is_empty() {
                 continue;
@@ -805,10 +1277,10 @@ fn scan_library(root: &Path, db: &Database) -> Result<LibraryStats, LibraryError
                 .map(|s| s.to_string_lossy().to_string())
                 .unwrap_or_else(|| "Unknown Artist".to_string());
 
-            let album_sidecar = read_sidecar_info(&album_dir.join("album.json"));
-            let artist_sidecar = album_dir
-                .parent()
-                .and_then(|parent| load_sidecar_info(&mut artist_sidecar_cache, parent.join("artist.json")));
+            let album_sidecar = read_album_sidecar(&album_dir.join(ALBUM_SIDECAR_FILE));
+            let artist_sidecar = album_dir.parent().and_then(|parent| {
+                load_artist_sidecar(&mut artist_sidecar_cache, parent.join(ARTIST_SIDECAR_FILE))
+            });
 
             let mut album_title: Option<String> = None;
             let mut album_artist: Option<String> = None;
@@ -816,15 +1288,32 @@ fn scan_library(root: &Path, db: &Database) -> Result<LibraryStats, LibraryError
             let mut album_cover: Option<CoverRef> = None;
             let mut album_summary: Option<String> = None;
             let mut album_genres: Vec<String> = Vec::new();
+            let mut album_artist_mbid: Option<String> = None;
+            let mut album_release_mbid: Option<String> = None;
+            let mut album_release_group_mbid: Option<String> = None;
+            let mut album_release_date: Option<String> = None;
+            let mut album_original_date: Option<String> = None;
+            let mut album_type: Option<AlbumType> = None;
+            let mut album_label: Option<String> = None;
+            let mut album_catalog_number: Option<String> = None;
         let mut track_drafts = Vec::new();
         let mut album_tag_error = false;
         let mut tag_error_files: Vec<TagErrorFile> = Vec::new();
 
             if let Some(info) = &album_sidecar {
-                if info.summary.as_ref().is_some() {
-                    album_summary = info.summary.clone();
-                }
-                merge_genres(&mut album_genres, &info.genres);
+                album_title = info.title.clone();
+                album_year = info.year;
+                album_release_date = info.release_date.clone();
+                album_original_date = info.original_date.clone();
+                album_type = info.album_type;
+                album_label = info.label.clone();
+                album_catalog_number = info.catalog_number.clone();
+                album_summary = info.summary.clone();
+                album_cover = info
+                    .cover
+                    .as_deref()
+                    .and_then(|cover| sidecar_cover_ref(root, &album_dir, cover));
+                merge_genres(genre_map, &mut album_genres, &info.genres);
             }
 
             for file in files {
@@ -860,11 +1349,42 @@ fn scan_library(root: &Path, db: &Database) -> Result<LibraryStats, LibraryError
                 if album_year.is_none() {
                     album_year = tag.year;
                 }
+                if album_artist_mbid.is_none() {
+                    album_artist_mbid = if tag.album_artist.is_some() {
+                        tag.musicbrainz_album_artist_id.clone()
+                    } else {
+                        tag.musicbrainz_artist_id.clone()
+                    };
+                }
+                if album_release_mbid.is_none() {
+                    album_release_mbid = tag.musicbrainz_release_id.clone();
+                }
+                if album_release_group_mbid.is_none() {
+                    album_release_group_mbid = tag.musicbrainz_release_group_id.clone();
+                }
+                if album_release_date.is_none() {
+                    album_release_date = tag.release_date.clone();
+                }
+                if album_original_date.is_none() {
+                    album_original_date = tag.original_date.clone();
+                }
+                if album_type.is_none() {
+                    album_type = tag
+                        .release_type
+                        .as_deref()
+                        .and_then(AlbumType::from_release_types);
+                }
+                if album_label.is_none() {
+                    album_label = tag.label.clone();
+                }
+                if album_catalog_number.is_none() {
+                    album_catalog_number = tag.catalog_number.clone();
+                }
                 if album_summary.is_none() {
                     album_summary = tag.summary.clone();
                 }
                 if !tag.genres.is_empty() {
-                    merge_genres(&mut album_genres, &tag.genres);
+                    merge_genres(genre_map, &mut album_genres, &tag.genres);
                 }
 
                 let title = tag.title.clone().unwrap_or_else(|| file_stem(&file));
@@ -904,7 +1424,16 @@ fn scan_library(root: &Path, db: &Database) -> Result<LibraryStats, LibraryError
                     channels: tag.channels,
                     bitrate: tag.bitrate,
                     file_size,
-                    genres: tag.genres,
+                    genres: normalize_genres(genre_map, &tag.genres),
+                    musicbrainz_recording_id: tag.musicbrainz_recording_id,
+                    composers: tag.composers,
+                    conductor: tag.conductor,
+                    performers: tag.performers,
+                    work: tag.work,
+                    movement: tag.movement,
+                    movement_no: tag.movement_no,
+                    movement_total: tag.movement_total,
+                    gapless: tag.gapless,
                 });
             }
 
@@ -917,10 +1446,10 @@ fn scan_library(root: &Path, db: &Database) -> Result<LibraryStats, LibraryError
             }
 
             let album_title = album_title.unwrap_or(folder_title);
-            let album_artist = album_artist
-                .unwrap_or(fallback_artist)
-                .trim()
-                .to_string();
+            let album_artist = match artist_sidecar.as_ref().and_then(|info| info.name.clone()) {
+                Some(name) => name,
+                None => album_artist.unwrap_or(fallback_artist).trim().to_string(),
+            };
             let artist_id = stable_id(album_artist.trim());
             let album_id = stable_id(&folder_relpath);
 
@@ -931,19 +1460,30 @@ fn scan_library(root: &Path, db: &Database) -> Result<LibraryStats, LibraryError
             }
 
             let mut artist_genres = Vec::new();
-            merge_genres(&mut artist_genres, &album_genres);
+            merge_genres(genre_map, &mut artist_genres, &album_genres);
             if let Some(info) = &artist_sidecar {
-                merge_genres(&mut artist_genres, &info.genres);
+                merge_genres(genre_map, &mut artist_genres, &info.genres);
             }
             let mut artist_summary = artist_sidecar
                 .as_ref()
                 .and_then(|info| info.summary.clone());
             let mut artist_logo = None;
             let mut artist_banner = None;
+            let mut artist_sort_name = artist_sidecar
+                .as_ref()
+                .and_then(|info| info.sort_name.clone());
+            let mut artist_aliases = artist_sidecar
+                .as_ref()
+                .map(|info| info.aliases.clone())
+                .unwrap_or_default();
+            let mut artist_musicbrainz_id = artist_sidecar
+                .as_ref()
+                .and_then(|info| info.musicbrainz_id.clone())
+                .or(album_artist_mbid);
 
             if let Some(value) = artists_table.get(artist_id.as_str())? {
                 let existing: Artist = decode_value(value.value())?;
-                merge_genres(&mut artist_genres, &existing.genres);
+                merge_genres(genre_map, &mut artist_genres, &existing.genres);
                 if artist_summary.is_none() {
                     artist_summary = existing.summary;
                 }
@@ -953,6 +1493,13 @@ fn scan_library(root: &Path, db: &Database) -> Result<LibraryStats, LibraryError
                 if artist_banner.is_none() {
                     artist_banner = existing.banner_ref;
                 }
+                if artist_sort_name.is_none() {
+                    artist_sort_name = existing.sort_name;
+                }
+                if artist_musicbrainz_id.is_none() {
+                    artist_musicbrainz_id = existing.musicbrainz_id;
+                }
+                merge_aliases(&mut artist_aliases, &existing.aliases);
             }
 
             let artist = Artist {
@@ -962,6 +1509,9 @@ fn scan_library(root: &Path, db: &Database) -> Result<LibraryStats, LibraryError
                 summary: artist_summary,
                 logo_ref: artist_logo,
                 banner_ref: artist_banner,
+                sort_name: artist_sort_name,
+                aliases: artist_aliases,
+                musicbrainz_id: artist_musicbrainz_id,
             };
             let artist_bytes = encode_value(&artist)?;
             let prev = artists_table.insert(artist_id.as_str(), artist_bytes.as_slice())?;
@@ -969,7 +1519,7 @@ fn scan_library(root: &Path, db: &Database) -> Result<LibraryStats, LibraryError
                 artist_count += 1;
             }
 
-            let artist_name_key = artist_name_key(&artist.name, &artist.id);
+            let artist_name_key = artist_name_key(&artist);
             artists_by_name_table.insert(artist_name_key.as_str(), artist.id.as_bytes())?;
 
             let album = Album {
@@ -981,6 +1531,22 @@ fn scan_library(root: &Path, db: &Database) -> Result<LibraryStats, LibraryError
                 cover_ref: album_cover,
                 genres: album_genres,
                 summary: album_summary,
+                sort_title: album_sidecar
+                    .as_ref()
+                    .and_then(|info| info.sort_title.clone()),
+                release_date: album_release_date,
+                album_type,
+                musicbrainz_release_id: album_sidecar
+                    .as_ref()
+                    .and_then(|info| info.musicbrainz_release_id.clone())
+                    .or(album_release_mbid),
+                musicbrainz_release_group_id: album_sidecar
+                    .as_ref()
+                    .and_then(|info| info.musicbrainz_release_group_id.clone())
+                    .or(album_release_group_mbid),
+                original_date: album_original_date,
+                label: album_label,
+                catalog_number: album_catalog_number,
             };
 
             let album_bytes = encode_value(&album)?;
@@ -989,7 +1555,7 @@ fn scan_library(root: &Path, db: &Database) -> Result<LibraryStats, LibraryError
                 album_count += 1;
             }
 
-            let album_name_key = album_name_key(&artist.name, &album);
+            let album_name_key = album_name_key(&artist, &album);
             albums_by_name_table.insert(album_name_key.as_str(), album.id.as_bytes())?;
 
             let album_index_key = album_index_key(&artist_id, &album);
@@ -1041,6 +1607,17 @@ fn scan_library(root: &Path, db: &Database) -> Result<LibraryStats, LibraryError
                     file_relpath: draft.relpath,
                     file_size: draft.file_size,
                     genres: draft.genres,
+                    musicbrainz_recording_id: draft.musicbrainz_recording_id,
+                    composers: draft.composers,
+                    conductor: draft.conductor,
+                    performers: draft.performers,
+                    work: draft.work,
+                    movement: draft.movement,
+                    movement_no: draft.movement_no,
+                    movement_total: draft.movement_total,
+                    encoder_delay: draft.gapless.map(|gapless| gapless.encoder_delay),
+                    encoder_padding: draft.gapless.map(|gapless| gapless.encoder_padding),
+                    total_samples: draft.gapless.and_then(|gapless| gapless.total_samples),
                 };
 
                 let track_bytes = encode_value(&track)?;
@@ -1078,11 +1655,17 @@ fn scan_library(root: &Path, db: &Database) -> Result<LibraryStats, LibraryError
         stats
     };
 
+    write_genre_index(&write_txn, genre_map)?;
+    write_composer_index(&write_txn)?;
     write_txn.commit()?;
     Ok(stats)
 }
 
-fn scan_library_incremental(root: &Path, db: &Database) -> Result<LibraryStats, LibraryError> {
+fn scan_library_incremental(
+    root: &Path,
+    db: &Database,
+    genre_map: &GenreMap,
+) -> Result<LibraryStats, LibraryError> {
     let album_dirs = collect_album_dirs(root);
     info!("Found {} album folders", album_dirs.len());
 
@@ -1107,7 +1690,7 @@ fn scan_library_incremental(root: &Path, db: &Database) -> Result<LibraryStats,
         let mut artist_count = running_stats.artists;
         let mut album_count = running_stats.albums;
         let mut track_count = running_stats.tracks;
-        let mut artist_sidecar_cache: HashMap<PathBuf, Option<SidecarInfo>> = HashMap::new();
+        let mut artist_sidecar_cache: HashMap<PathBuf, Option<ArtistSidecar>> = HashMap::new();
 
         for album_dir in album_dirs {
             let files = audio_files_in_dir(&album_dir);
@@ -1137,10 +1720,10 @@ fn scan_library_incremental(root: &Path, db: &Database) -> Result<LibraryStats,
                 .map(|s| s.to_string_lossy().to_string())
                 .unwrap_or_else(|| "Unknown Artist".to_string());
 
-            let album_sidecar = read_sidecar_info(&album_dir.join("album.json"));
-            let artist_sidecar = album_dir
-                .parent()
-                .and_then(|parent| load_sidecar_info(&mut artist_sidecar_cache, parent.join("artist.json")));
+            let album_sidecar = read_album_sidecar(&album_dir.join(ALBUM_SIDECAR_FILE));
+            let artist_sidecar = album_dir.parent().and_then(|parent| {
+                load_artist_sidecar(&mut artist_sidecar_cache, parent.join(ARTIST_SIDECAR_FILE))
+            });
 
             let mut album_title: Option<String> = None;
             let mut album_artist: Option<String> = None;
@@ -1148,15 +1731,32 @@ fn scan_library_incremental(root: &Path, db: &Database) -> Result<LibraryStats,
             let mut album_cover: Option<CoverRef> = None;
             let mut album_summary: Option<String> = None;
             let mut album_genres: Vec<String> = Vec::new();
+            let mut album_artist_mbid: Option<String> = None;
+            let mut album_release_mbid: Option<String> = None;
+            let mut album_release_group_mbid: Option<String> = None;
+            let mut album_release_date: Option<String> = None;
+            let mut album_original_date: Option<String> = None;
+            let mut album_type: Option<AlbumType> = None;
+            let mut album_label: Option<String> = None;
+            let mut album_catalog_number: Option<String> = None;
             let mut track_drafts = Vec::new();
             let mut album_tag_error = false;
             let mut tag_error_files: Vec<TagErrorFile> = Vec::new();
 
             if let Some(info) = &album_sidecar {
-                if info.summary.as_ref().is_some() {
-                    album_summary = info.summary.clone();
-                }
-                merge_genres(&mut album_genres, &info.genres);
+                album_title = info.title.clone();
+                album_year = info.year;
+                album_release_date = info.release_date.clone();
+                album_original_date = info.original_date.clone();
+                album_type = info.album_type;
+                album_label = info.label.clone();
+                album_catalog_number = info.catalog_number.clone();
+                album_summary = info.summary.clone();
+                album_cover = info
+                    .cover
+                    .as_deref()
+                    .and_then(|cover| sidecar_cover_ref(root, &album_dir, cover));
+                merge_genres(genre_map, &mut album_genres, &info.genres);
             }
 
             for file in files {
@@ -1192,11 +1792,42 @@ fn scan_library_incremental(root: &Path, db: &Database) -> Result<LibraryStats,
                 if album_year.is_none() {
                     album_year = tag.year;
                 }
+                if album_artist_mbid.is_none() {
+                    album_artist_mbid = if tag.album_artist.is_some() {
+                        tag.musicbrainz_album_artist_id.clone()
+                    } else {
+                        tag.musicbrainz_artist_id.clone()
+                    };
+                }
+                if album_release_mbid.is_none() {
+                    album_release_mbid = tag.musicbrainz_release_id.clone();
+                }
+                if album_release_group_mbid.is_none() {
+                    album_release_group_mbid = tag.musicbrainz_release_group_id.clone();
+                }
+                if album_release_date.is_none() {
+                    album_release_date = tag.release_date.clone();
+                }
+                if album_original_date.is_none() {
+                    album_original_date = tag.original_date.clone();
+                }
+                if album_type.is_none() {
+                    album_type = tag
+                        .release_type
+                        .as_deref()
+                        .and_then(AlbumType::from_release_types);
+                }
+                if album_label.is_none() {
+                    album_label = tag.label.clone();
+                }
+                if album_catalog_number.is_none() {
+                    album_catalog_number = tag.catalog_number.clone();
+                }
                 if album_summary.is_none() {
                     album_summary = tag.summary.clone();
                 }
                 if !tag.genres.is_empty() {
-                    merge_genres(&mut album_genres, &tag.genres);
+                    merge_genres(genre_map, &mut album_genres, &tag.genres);
                 }
 
                 let title = tag.title.clone().unwrap_or_else(|| file_stem(&file));
@@ -1236,7 +1867,16 @@ fn scan_library_incremental(root: &Path, db: &Database) -> Result<LibraryStats,
                     channels: tag.channels,
                     bitrate: tag.bitrate,
                     file_size,
-                    genres: tag.genres,
+                    genres: normalize_genres(genre_map, &tag.genres),
+                    musicbrainz_recording_id: tag.musicbrainz_recording_id,
+                    composers: tag.composers,
+                    conductor: tag.conductor,
+                    performers: tag.performers,
+                    work: tag.work,
+                    movement: tag.movement,
+                    movement_no: tag.movement_no,
+                    movement_total: tag.movement_total,
+                    gapless: tag.gapless,
                 });
             }
 
@@ -1249,10 +1889,10 @@ fn scan_library_incremental(root: &Path, db: &Database) -> Result<LibraryStats,
             }
 
             let album_title = album_title.unwrap_or(folder_title);
-            let album_artist = album_artist
-                .unwrap_or(fallback_artist)
-                .trim()
-                .to_string();
+            let album_artist = match artist_sidecar.as_ref().and_then(|info| info.name.clone()) {
+                Some(name) => name,
+                None => album_artist.unwrap_or(fallback_artist).trim().to_string(),
+            };
             let artist_id = stable_id(album_artist.trim());
 
             if album_cover.is_none() {
@@ -1262,19 +1902,30 @@ fn scan_library_incremental(root: &Path, db: &Database) -> Result<LibraryStats,
             }
 
             let mut artist_genres = Vec::new();
-            merge_genres(&mut artist_genres, &album_genres);
+            merge_genres(genre_map, &mut artist_genres, &album_genres);
             if let Some(info) = &artist_sidecar {
-                merge_genres(&mut artist_genres, &info.genres);
+                merge_genres(genre_map, &mut artist_genres, &info.genres);
             }
             let mut artist_summary = artist_sidecar
                 .as_ref()
                 .and_then(|info| info.summary.clone());
             let mut artist_logo = None;
             let mut artist_banner = None;
+            let mut artist_sort_name = artist_sidecar
+                .as_ref()
+                .and_then(|info| info.sort_name.clone());
+            let mut artist_aliases = artist_sidecar
+                .as_ref()
+                .map(|info| info.aliases.clone())
+                .unwrap_or_default();
+            let mut artist_musicbrainz_id = artist_sidecar
+                .as_ref()
+                .and_then(|info| info.musicbrainz_id.clone())
+                .or(album_artist_mbid);
 
             if let Some(value) = artists_table.get(artist_id.as_str())? {
                 let existing: Artist = decode_value(value.value())?;
-                merge_genres(&mut artist_genres, &existing.genres);
+                merge_genres(genre_map, &mut artist_genres, &existing.genres);
                 if artist_summary.is_none() {
                     artist_summary = existing.summary;
                 }
@@ -1284,6 +1935,13 @@ fn scan_library_incremental(root: &Path, db: &Database) -> Result<LibraryStats,
                 if artist_banner.is_none() {
                     artist_banner = existing.banner_ref;
                 }
+                if artist_sort_name.is_none() {
+                    artist_sort_name = existing.sort_name;
+                }
+                if artist_musicbrainz_id.is_none() {
+                    artist_musicbrainz_id = existing.musicbrainz_id;
+                }
+                merge_aliases(&mut artist_aliases, &existing.aliases);
             }
 
             let artist = Artist {
@@ -1293,6 +1951,9 @@ fn scan_library_incremental(root: &Path, db: &Database) -> Result<LibraryStats,
                 summary: artist_summary,
                 logo_ref: artist_logo,
                 banner_ref: artist_banner,
+                sort_name: artist_sort_name,
+                aliases: artist_aliases,
+                musicbrainz_id: artist_musicbrainz_id,
             };
             let artist_bytes = encode_value(&artist)?;
             let prev = artists_table.insert(artist_id.as_str(), artist_bytes.as_slice())?;
@@ -1300,7 +1961,7 @@ fn scan_library_incremental(root: &Path, db: &Database) -> Result<LibraryStats,
                 artist_count += 1;
             }
 
-            let artist_name_key = artist_name_key(&artist.name, &artist.id);
+            let artist_name_key = artist_name_key(&artist);
             artists_by_name_table.insert(artist_name_key.as_str(), artist.id.as_bytes())?;
 
             let album = Album {
@@ -1312,6 +1973,22 @@ fn scan_library_incremental(root: &Path, db: &Database) -> Result<LibraryStats,
                 cover_ref: album_cover,
                 genres: album_genres,
                 summary: album_summary,
+                sort_title: album_sidecar
+                    .as_ref()
+                    .and_then(|info| info.sort_title.clone()),
+                release_date: album_release_date,
+                album_type,
+                musicbrainz_release_id: album_sidecar
+                    .as_ref()
+                    .and_then(|info| info.musicbrainz_release_id.clone())
+                    .or(album_release_mbid),
+                musicbrainz_release_group_id: album_sidecar
+                    .as_ref()
+                    .and_then(|info| info.musicbrainz_release_group_id.clone())
+                    .or(album_release_group_mbid),
+                original_date: album_original_date,
+                label: album_label,
+                catalog_number: album_catalog_number,
             };
 
             let album_bytes = encode_value(&album)?;
@@ -1320,7 +1997,7 @@ fn scan_library_incremental(root: &Path, db: &Database) -> Result<LibraryStats,
                 album_count += 1;
             }
 
-            let album_name_key = album_name_key(&artist.name, &album);
+            let album_name_key = album_name_key(&artist, &album);
             albums_by_name_table.insert(album_name_key.as_str(), album.id.as_bytes())?;
 
             let album_index_key = album_index_key(&artist_id, &album);
@@ -1372,6 +2049,17 @@ fn scan_library_incremental(root: &Path, db: &Database) -> Result<LibraryStats,
                     file_relpath: draft.relpath,
                     file_size: draft.file_size,
                     genres: draft.genres,
+                    musicbrainz_recording_id: draft.musicbrainz_recording_id,
+                    composers: draft.composers,
+                    conductor: draft.conductor,
+                    performers: draft.performers,
+                    work: draft.work,
+                    movement: draft.movement,
+                    movement_no: draft.movement_no,
+                    movement_total: draft.movement_total,
+                    encoder_delay: draft.gapless.map(|gapless| gapless.encoder_delay),
+                    encoder_padding: draft.gapless.map(|gapless| gapless.encoder_padding),
+                    total_samples: draft.gapless.and_then(|gapless| gapless.total_samples),
                 };
 
                 let track_bytes = encode_value(&track)?;
@@ -1409,6 +2097,8 @@ fn scan_library_incremental(root: &Path, db: &Database) -> Result<LibraryStats,
         running_stats
     };
 
+    write_genre_index(&write_txn, genre_map)?;
+    write_composer_index(&write_txn)?;
     write_txn.commit()?;
     Ok(stats)
 }
@@ -1440,142 +2130,6 @@ fn bool_bytes(value: bool) -> &'static [u8] {
     }
 }
 
-fn merge_genres(target: &mut Vec<String>, incoming: &[String]) {
-    if incoming.is_empty() {
-        return;
-    }
-    let mut seen: HashSet<String> = target
-        .iter()
-        .map(|genre| normalize_genre_key(genre))
-        .collect();
-    for genre in incoming {
-        let trimmed = normalize_genre_label(genre.trim());
-        if trimmed.is_empty() {
-            continue;
-        }
-        let key = normalize_genre_key(&trimmed);
-        if seen.insert(key) {
-            target.push(trimmed);
-        }
-    }
-}
-
-fn normalize_genre_label(value: &str) -> String {
-    let lower = value.trim().to_lowercase();
-    let normalized = normalize_genre_probe(&lower);
-    let exact = match lower.as_str() {
-        "musique classique" => "Classical".to_string(),
-        "musique de chambre" => "Chamber music".to_string(),
-        "musique de film" => "Soundtrack".to_string(),
-        "musiques de film" => "Soundtrack".to_string(),
-        "musique électronique" => "Electronic".to_string(),
-        "musique electronique" => "Electronic".to_string(),
-        "musique instrumentale" => "Instrumental".to_string(),
-        "musique ambient" => "Ambient".to_string(),
-        "musique ambiante" => "Ambient".to_string(),
-        "piano solo" => "Solo piano".to_string(),
-        "classique" => "Classical".to_string(),
-        "musica clasica" => "Classical".to_string(),
-        "música clásica" => "Classical".to_string(),
-        "musica classica" => "Classical".to_string(),
-        "música clásica contemporánea" => "Contemporary classical".to_string(),
-        "musica contemporanea" => "Contemporary classical".to_string(),
-        "musica de camara" => "Chamber music".to_string(),
-        "música de cámara" => "Chamber music".to_string(),
-        "musica de pelicula" => "Soundtrack".to_string(),
-        "música de película" => "Soundtrack".to_string(),
-        "banda sonora" => "Soundtrack".to_string(),
-        "colonna sonora" => "Soundtrack".to_string(),
-        "musica elettronica" => "Electronic".to_string(),
-        "musica elettronica sperimentale" => "Electronic".to_string(),
-        "musica strumentale" => "Instrumental".to_string(),
-        "musica ambient" => "Ambient".to_string(),
-        "música instrumental" => "Instrumental".to_string(),
-        "música electrónica" => "Electronic".to_string(),
-        "música electronica" => "Electronic".to_string(),
-        "klassik" => "Classical".to_string(),
-        "klassische musik" => "Classical".to_string(),
-        "filmmusik" => "Soundtrack".to_string(),
-        "elektronische musik" => "Electronic".to_string(),
-        "instrumentalmusik" => "Instrumental".to_string(),
-        "kammermusik" => "Chamber music".to_string(),
-        "zeitgenössische klassische musik" => "Contemporary classical".to_string(),
-        "zeitgenossische klassische musik" => "Contemporary classical".to_string(),
-        _ => String::new(),
-    };
-    if !exact.is_empty() {
-        return exact;
-    }
-    match match_genre_probe(&normalized) {
-        Some(mapped) => mapped,
-        None => value.trim().to_string(),
-    }
-}
-
-fn match_genre_probe(value: &str) -> Option<String> {
-    let rules: [(&str, &[&str]); 6] = [
-        ("Classical", &["classical", "klassik", "musica clasica", "musique classique"]),
-        ("Contemporary classical", &["contemporary classical", "zeitgenossisch", "contemporanea"]),
-        ("Chamber music", &["chamber music", "musique de chambre", "musica de camara", "kammermusik"]),
-        ("Soundtrack", &["soundtrack", "musique de film", "banda sonora", "colonna sonora", "filmmusik"]),
-        ("Electronic", &["electronic", "electronique", "electronica", "elektronisch"]),
-        ("Instrumental", &["instrumental", "instrumentale", "strumentale", "instrumentalmusik"]),
-    ];
-
-    for (label, terms) in rules {
-        for term in terms {
-            if value.contains(term) {
-                return Some(label.to_string());
-            }
-        }
-    }
-    if value.contains("ambient") {
-        return Some("Ambient".to_string());
-    }
-    if value.contains("solo piano") || value.contains("piano solo") {
-        return Some("Solo piano".to_string());
-    }
-    None
-}
-
-fn normalize_genre_probe(value: &str) -> String {
-    let mut out = String::with_capacity(value.len());
-    let mut last_space = false;
-    for ch in value.chars() {
-        let mapped = match ch {
-            'à' | 'á' | 'â' | 'ä' | 'ã' | 'å' => 'a',
-            'ç' => 'c',
-            'è' | 'é' | 'ê' | 'ë' => 'e',
-            'ì' | 'í' | 'î' | 'ï' => 'i',
-            'ñ' => 'n',
-            'ò' | 'ó' | 'ô' | 'ö' | 'õ' => 'o',
-            'ù' | 'ú' | 'û' | 'ü' => 'u',
-            'ý' | 'ÿ' => 'y',
-            'œ' => {
-                out.push('o');
-                'e'
-            }
-            'æ' => {
-                out.push('a');
-                'e'
-            }
-            _ => ch,
-        };
-        if mapped.is_ascii_alphanumeric() {
-            out.push(mapped);
-            last_space = false;
-        } else if !last_space {
-            out.push(' ');
-            last_space = true;
-        }
-    }
-    out.trim().to_string()
-}
-
-fn normalize_genre_key(value: &str) -> String {
-    value.trim().to_lowercase()
-}
-
 fn clean_summary(summary: String) -> Option<String> {
     let trimmed = summary.trim();
     if trimmed.is_empty() {
@@ -1585,40 +2139,89 @@ fn clean_summary(summary: String) -> Option<String> {
     }
 }
 
-fn artist_name_key(name: &str, artist_id: &str) -> String {
+// Name keys sort by the sort label but also carry the display name and
+// aliases, so substring search still matches what users actually see.
+fn artist_name_key(artist: &Artist) -> String {
+    let name = artist.name.trim().to_lowercase();
+    let sort = sort_label(&artist.name, artist.sort_name.as_deref());
     let mut out = String::new();
-    out.push_str(name.trim().to_lowercase().as_str());
+    out.push_str(&sort);
+    if sort != name {
+        out.push(KEY_SEP);
+        out.push_str(&name);
+    }
+    for alias in &artist.aliases {
+        out.push(KEY_SEP);
+        out.push_str(alias.trim().to_lowercase().as_str());
+    }
     out.push(KEY_SEP);
-    out.push_str(artist_id);
+    out.push_str(&artist.id);
     out
 }
 
-fn album_name_key(artist_name: &str, album: &Album) -> String {
+fn album_name_key(artist: &Artist, album: &Album) -> String {
     let year = album.year.unwrap_or(9999).clamp(-9999, 9999);
+    let title = album.title.trim().to_lowercase();
+    let sort = sort_label(&album.title, album.sort_title.as_deref());
     let mut out = String::new();
-    out.push_str(artist_name.trim().to_lowercase().as_str());
+    out.push_str(&sort_label(&artist.name, artist.sort_name.as_deref()));
+    out.push(KEY_SEP);
+    out.push_str(artist.name.trim().to_lowercase().as_str());
     out.push(KEY_SEP);
     out.push_str(&format!("{:04}", year.max(0)));
     out.push(KEY_SEP);
-    out.push_str(&album.title.to_lowercase());
+    out.push_str(&sort);
+    if sort != title {
+        out.push(KEY_SEP);
+        out.push_str(&title);
+    }
     out.push(KEY_SEP);
     out.push_str(&album.id);
     out
 }
 
+// Discographies are ordered by first release, so reissues sit where the
+// original came out.
 fn album_index_key(artist_id: &str, album: &Album) -> String {
-    let year = album.year.unwrap_or(9999).clamp(-9999, 9999);
     let mut out = String::new();
     out.push_str(artist_id);
     out.push(KEY_SEP);
-    out.push_str(&format!("{:04}", year.max(0)));
+    out.push_str(&album_chronology_key(album));
     out.push(KEY_SEP);
-    out.push_str(&album.title.to_lowercase());
+    out.push_str(&sort_label(&album.title, album.sort_title.as_deref()));
     out.push(KEY_SEP);
     out.push_str(&album.id);
     out
 }
 
+/// Sortable `YYYY-MM-DD` built from the original date, the release date or
+/// the year, in that order. Missing parts sort first within their year.
+pub fn album_chronology_key(album: &Album) -> String {
+    let date = album
+        .original_date
+        .as_deref()
+        .or(album.release_date.as_deref());
+    let (year, rest) = match date.and_then(|value| Some((year_from_date(value)?, value))) {
+        Some((year, value)) => (year, &value[4..]),
+        None => (album.year.unwrap_or(9999), ""),
+    };
+    let mut out = format!("{:04}", year.clamp(0, 9999));
+    let mut parts = rest.split('-').filter(|part| !part.is_empty());
+    for _ in 0..2 {
+        out.push('-');
+        out.push_str(&format!("{:0>2}", parts.next().unwrap_or("00")));
+    }
+    out
+}
+
+fn sort_label(name: &str, sort: Option<&str>) -> String {
+    sort.map(str::trim)
+        .filter(|value| !value.is_empty())
+        .unwrap_or(name)
+        .trim()
+        .to_lowercase()
+}
+
 fn album_track_key(album_id: &str, order: usize, track_id: &str) -> String {
     let mut out = String::new();
     out.push_str(album_id);
@@ -1690,6 +2293,15 @@ struct TrackDraft {
     bitrate: Option<u32>,
     file_size: u64,
     genres: Vec<String>,
+    musicbrainz_recording_id: Option<String>,
+    composers: Vec<String>,
+    conductor: Option<String>,
+    performers: Vec<String>,
+    work: Option<String>,
+    movement: Option<String>,
+    movement_no: Option<u16>,
+    movement_total: Option<u16>,
+    gapless: Option<GaplessInfo>,
 }
 
 fn collect_album_dirs(root: &Path) -> Vec<PathBuf> {
@@ -1864,41 +2476,262 @@ fn split_year_suffix(input: &str, open: char, close: char) -> Option<(&str, i32)
     Some((title, year))
 }
 
-#[derive(Debug, Deserialize, Default, Clone)]
+pub const ALBUM_SIDECAR_FILE: &str = "album.json";
+pub const ARTIST_SIDECAR_FILE: &str = "artist.json";
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 #[serde(default)]
-struct SidecarInfo {
-    summary: Option<String>,
-    genres: Vec<String>,
+pub struct AlbumSidecar {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort_title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub year: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub release_date: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original_date: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub album_type: Option<AlbumType>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub catalog_number: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cover: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub musicbrainz_release_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub musicbrainz_release_group_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub genres: Vec<String>,
+}
+
+impl AlbumSidecar {
+    fn cleaned(mut self) -> Self {
+        self.title = clean_opt(self.title);
+        self.sort_title = clean_opt(self.sort_title);
+        self.release_date = clean_opt(self.release_date).and_then(|value| parse_date(&value));
+        self.original_date = clean_opt(self.original_date).and_then(|value| parse_date(&value));
+        self.label = clean_opt(self.label);
+        self.catalog_number = clean_opt(self.catalog_number);
+        self.cover = clean_opt(self.cover);
+        self.musicbrainz_release_id = clean_opt(self.musicbrainz_release_id);
+        self.musicbrainz_release_group_id = clean_opt(self.musicbrainz_release_group_id);
+        self.summary = clean_opt(self.summary);
+        self.genres.retain(|genre| !genre.trim().is_empty());
+        if self.year.is_none() {
+            self.year = self.release_date.as_deref().and_then(year_from_date);
+        }
+        self
+    }
+
+    fn is_empty(&self) -> bool {
+        self.title.is_none()
+            && self.sort_title.is_none()
+            && self.year.is_none()
+            && self.release_date.is_none()
+            && self.original_date.is_none()
+            && self.album_type.is_none()
+            && self.label.is_none()
+            && self.catalog_number.is_none()
+            && self.cover.is_none()
+            && self.musicbrainz_release_id.is_none()
+            && self.musicbrainz_release_group_id.is_none()
+            && self.summary.is_none()
+            && self.genres.is_empty()
+    }
 }
 
-fn read_sidecar_info(path: &Path) -> Option<SidecarInfo> {
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ArtistSidecar {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort_name: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub musicbrainz_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub genres: Vec<String>,
+}
+
+impl ArtistSidecar {
+    fn cleaned(mut self) -> Self {
+        self.name = clean_opt(self.name);
+        self.sort_name = clean_opt(self.sort_name);
+        self.musicbrainz_id = clean_opt(self.musicbrainz_id);
+        self.summary = clean_opt(self.summary);
+        self.genres.retain(|genre| !genre.trim().is_empty());
+        let aliases = std::mem::take(&mut self.aliases);
+        merge_aliases(&mut self.aliases, &aliases);
+        self
+    }
+
+    fn is_empty(&self) -> bool {
+        self.name.is_none()
+            && self.sort_name.is_none()
+            && self.aliases.is_empty()
+            && self.musicbrainz_id.is_none()
+            && self.summary.is_none()
+            && self.genres.is_empty()
+    }
+}
+
+fn clean_opt(value: Option<String>) -> Option<String> {
+    value.and_then(clean_summary)
+}
+
+fn year_from_date(value: &str) -> Option<i32> {
+    let head: String = value.trim().chars().take(4).collect();
+    if head.len() == 4 && head.chars().all(|c| c.is_ascii_digit()) {
+        head.parse().ok()
+    } else {
+        None
+    }
+}
+
+fn merge_aliases(target: &mut Vec<String>, incoming: &[String]) {
+    for alias in incoming {
+        let trimmed = alias.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        if !target
+            .iter()
+            .any(|existing| existing.eq_ignore_ascii_case(trimmed))
+        {
+            target.push(trimmed.to_string());
+        }
+    }
+}
+
+fn read_album_sidecar(path: &Path) -> Option<AlbumSidecar> {
     let data = fs::read(path).ok()?;
-    let mut info: SidecarInfo = serde_json::from_slice(&data).ok()?;
-    if let Some(summary) = &info.summary {
-        if summary.trim().is_empty() {
-            info.summary = None;
+    let info: AlbumSidecar = match serde_json::from_slice(&data) {
+        Ok(info) => info,
+        Err(err) => {
+            warn!("Ignoring malformed sidecar {:?}: {}", path, err);
+            return None;
         }
+    };
+    let info = info.cleaned();
+    if info.is_empty() {
+        None
+    } else {
+        Some(info)
     }
-    info.genres.retain(|genre| !genre.trim().is_empty());
-    if info.summary.is_none() && info.genres.is_empty() {
+}
+
+fn read_artist_sidecar(path: &Path) -> Option<ArtistSidecar> {
+    let data = fs::read(path).ok()?;
+    let info: ArtistSidecar = match serde_json::from_slice(&data) {
+        Ok(info) => info,
+        Err(err) => {
+            warn!("Ignoring malformed sidecar {:?}: {}", path, err);
+            return None;
+        }
+    };
+    let info = info.cleaned();
+    if info.is_empty() {
         None
     } else {
         Some(info)
     }
 }
 
-fn load_sidecar_info(
-    cache: &mut HashMap<PathBuf, Option<SidecarInfo>>,
+fn load_artist_sidecar(
+    cache: &mut HashMap<PathBuf, Option<ArtistSidecar>>,
     path: PathBuf,
-) -> Option<SidecarInfo> {
+) -> Option<ArtistSidecar> {
     if let Some(cached) = cache.get(&path) {
         return cached.clone();
     }
-    let info = read_sidecar_info(&path);
+    let info = read_artist_sidecar(&path);
     cache.insert(path, info.clone());
     info
 }
 
+/// Writes `value` over the JSON object at `path`, keeping keys this server
+/// does not know about so hand-maintained sidecars are not clobbered.
+fn write_sidecar<T: Serialize>(
+    path: &Path,
+    value: &T,
+    known_keys: &[&str],
+) -> Result<(), LibraryError> {
+    let mut object = match fs::read(path) {
+        Ok(data) => match serde_json::from_slice::<serde_json::Value>(&data) {
+            Ok(serde_json::Value::Object(map)) => map,
+            _ => serde_json::Map::new(),
+        },
+        Err(_) => serde_json::Map::new(),
+    };
+    let incoming = match serde_json::to_value(value) {
+        Ok(serde_json::Value::Object(map)) => map,
+        Ok(_) => serde_json::Map::new(),
+        Err(err) => return Err(LibraryError::Sidecar(err.to_string())),
+    };
+    for key in known_keys {
+        object.remove(*key);
+    }
+    object.extend(incoming);
+    let bytes = serde_json::to_vec_pretty(&serde_json::Value::Object(object))
+        .map_err(|err| LibraryError::Sidecar(err.to_string()))?;
+    fs::write(path, bytes)?;
+    Ok(())
+}
+
+const ALBUM_SIDECAR_KEYS: &[&str] = &[
+    "title",
+    "sort_title",
+    "year",
+    "release_date",
+    "original_date",
+    "album_type",
+    "label",
+    "catalog_number",
+    "cover",
+    "musicbrainz_release_id",
+    "musicbrainz_release_group_id",
+    "summary",
+    "genres",
+];
+
+const ARTIST_SIDECAR_KEYS: &[&str] = &[
+    "name",
+    "sort_name",
+    "aliases",
+    "musicbrainz_id",
+    "summary",
+    "genres",
+];
+
+fn sidecar_cover_ref(root: &Path, album_dir: &Path, cover: &str) -> Option<CoverRef> {
+    let relative = Path::new(cover);
+    if relative.is_absolute()
+        || relative
+            .components()
+            .any(|part| matches!(part, std::path::Component::ParentDir))
+    {
+        warn!("Ignoring sidecar cover outside album folder: {}", cover);
+        return None;
+    }
+    let path = album_dir.join(relative);
+    if !path.is_file() {
+        warn!("Sidecar cover not found: {:?}", path);
+        return None;
+    }
+    let relpath = relpath_from(root, &path)?;
+    Some(CoverRef::File { relpath })
+}
+
 fn find_folder_cover(root: &Path, album_dir: &Path) -> Option<String> {
     const COVERS: &[&str] = &[
         "cover.jpg",
@@ -0,0 +1,322 @@
+//! Encoder delay/padding and exact sample counts, read straight from the
+//! file headers since lofty doesn't expose them.
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// MP3 decoders emit 528 + 1 samples of their own delay ahead of the
+/// encoder delay stored in the LAME tag.
+const MP3_DECODER_DELAY: u32 = 529;
+const HEADER_SCAN_BYTES: u64 = 64 * 1024;
+/// Comment frames longer than this can't be an `iTunSMPB` and are skipped.
+const MAX_COMMENT_FRAME_BYTES: u64 = 4096;
+/// Frames past this far into a tag aren't looked at.
+const MAX_TAG_SCAN_BYTES: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct GaplessInfo {
+    /// Leading decoded samples (per channel) to drop.
+    pub encoder_delay: u32,
+    /// Trailing decoded samples (per channel) to drop.
+    pub encoder_padding: u32,
+    /// Valid samples per channel once delay and padding are removed.
+    pub total_samples: Option<u64>,
+}
+
+pub fn read_gapless(path: &Path) -> Option<GaplessInfo> {
+    read_from(&mut File::open(path).ok()?)
+}
+
+#[cfg(test)]
+pub(crate) fn parse_gapless(data: &[u8]) -> Option<GaplessInfo> {
+    read_from(&mut std::io::Cursor::new(data))
+}
+
+/// Reads only the ID3v2 comment frames, then seeks past the tag to the
+/// first audio bytes, so large cover art is never loaded.
+fn read_from<R: Read + Seek>(reader: &mut R) -> Option<GaplessInfo> {
+    let mut header = [0u8; 10];
+    reader.read_exact(&mut header).ok()?;
+    let tag_len = id3v2_len(&header);
+    let comments = if tag_len > 0 {
+        id3v2_comments(reader, &header)
+    } else {
+        Vec::new()
+    };
+    reader.seek(SeekFrom::Start(tag_len as u64)).ok()?;
+    let mut body = Vec::new();
+    reader.take(HEADER_SCAN_BYTES).read_to_end(&mut body).ok()?;
+    if body.starts_with(b"fLaC") {
+        return flac_stream_info(&body);
+    }
+    lame_info(&body).or_else(|| itunes_smpb(&comments))
+}
+
+fn id3v2_len(data: &[u8]) -> usize {
+    if data.len() < 10 || &data[..3] != b"ID3" {
+        return 0;
+    }
+    let footer = if data[5] & 0x10 != 0 { 10 } else { 0 };
+    10 + syncsafe(&data[6..10]) as usize + footer
+}
+
+fn syncsafe(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(0, |acc, byte| (acc << 7) | (*byte as u64 & 0x7F))
+}
+
+/// Contents of the tag's comment (`COMM`/`TXXX`) frames, where iTunes keeps
+/// `iTunSMPB`. Every other frame is seeked over.
+fn id3v2_comments<R: Read + Seek>(reader: &mut R, header: &[u8; 10]) -> Vec<u8> {
+    let version = header[3];
+    let (frame_header_len, wanted): (u64, [&[u8]; 2]) = match version {
+        2 => (6, [b"COM", b"TXX"]),
+        3 | 4 => (10, [b"COMM", b"TXXX"]),
+        _ => return Vec::new(),
+    };
+    let frames_end = (10 + syncsafe(&header[6..10])).min(MAX_TAG_SCAN_BYTES);
+    let mut pos = 10;
+    let mut out = Vec::new();
+    if header[5] & 0x40 != 0 {
+        // Extended header: v2.4 counts its own size field, v2.3 doesn't.
+        let mut size = [0u8; 4];
+        if reader.read_exact(&mut size).is_err() {
+            return out;
+        }
+        pos += match version {
+            4 => syncsafe(&size),
+            _ => 4 + u32::from_be_bytes(size) as u64,
+        };
+    }
+    let mut frame = [0u8; 10];
+    while pos + frame_header_len <= frames_end {
+        let frame = &mut frame[..frame_header_len as usize];
+        if reader.seek(SeekFrom::Start(pos)).is_err() || reader.read_exact(frame).is_err() {
+            break;
+        }
+        if frame[0] == 0 {
+            // Padding.
+            break;
+        }
+        let (id, size) = match version {
+            2 => (
+                &frame[..3],
+                u32::from_be_bytes([0, frame[3], frame[4], frame[5]]) as u64,
+            ),
+            3 => (
+                &frame[..4],
+                u32::from_be_bytes(frame[4..8].try_into().unwrap()) as u64,
+            ),
+            _ => (&frame[..4], syncsafe(&frame[4..8])),
+        };
+        if wanted.contains(&id) && size <= MAX_COMMENT_FRAME_BYTES {
+            let start = out.len();
+            if reader.by_ref().take(size).read_to_end(&mut out).is_err() {
+                out.truncate(start);
+                break;
+            }
+        }
+        pos += frame_header_len + size;
+    }
+    out
+}
+
+fn flac_stream_info(data: &[u8]) -> Option<GaplessInfo> {
+    // "fLaC", 4-byte block header, then STREAMINFO with the 36-bit sample
+    // count in the low bits of bytes 10..18.
+    let info = data.get(8..8 + 18)?;
+    if data[4] & 0x7F != 0 {
+        return None;
+    }
+    let packed = u64::from_be_bytes(info[10..18].try_into().ok()?);
+    let total = packed & 0xF_FFFF_FFFF;
+    if total == 0 {
+        return None;
+    }
+    Some(GaplessInfo {
+        encoder_delay: 0,
+        encoder_padding: 0,
+        total_samples: Some(total),
+    })
+}
+
+fn lame_info(data: &[u8]) -> Option<GaplessInfo> {
+    let start = data
+        .windows(2)
+        .position(|pair| pair[0] == 0xFF && pair[1] & 0xE0 == 0xE0)?;
+    let frame = &data[start..];
+    let header = frame.get(..4)?;
+    let version = (header[1] >> 3) & 0x03;
+    let layer = (header[1] >> 1) & 0x03;
+    if layer != 1 || version == 1 {
+        return None;
+    }
+    let mpeg1 = version == 3;
+    let mono = (header[3] >> 6) & 0x03 == 3;
+    let side_info = match (mpeg1, mono) {
+        (true, true) => 17,
+        (true, false) => 32,
+        (false, true) => 9,
+        (false, false) => 17,
+    };
+    let xing = frame.get(4 + side_info..)?;
+    if !(xing.starts_with(b"Xing") || xing.starts_with(b"Info")) {
+        return None;
+    }
+    let flags = u32::from_be_bytes(xing.get(4..8)?.try_into().ok()?);
+    let mut offset = 8;
+    let mut frames = None;
+    if flags & 0x1 != 0 {
+        frames = Some(u32::from_be_bytes(
+            xing.get(offset..offset + 4)?.try_into().ok()?,
+        ));
+        offset += 4;
+    }
+    if flags & 0x2 != 0 {
+        offset += 4;
+    }
+    if flags & 0x4 != 0 {
+        offset += 100;
+    }
+    if flags & 0x8 != 0 {
+        offset += 4;
+    }
+    let lame = xing.get(offset..offset + 24)?;
+    if !(lame.starts_with(b"LAME") || lame.starts_with(b"Lavc") || lame.starts_with(b"Lavf")) {
+        return None;
+    }
+    let packed = &lame[21..24];
+    let delay = ((packed[0] as u32) << 4) | ((packed[1] as u32) >> 4);
+    let padding = (((packed[1] & 0x0F) as u32) << 8) | packed[2] as u32;
+    let samples_per_frame: u64 = if mpeg1 { 1152 } else { 576 };
+    let total_samples = frames.and_then(|frames| {
+        (frames as u64 * samples_per_frame).checked_sub(delay as u64 + padding as u64)
+    });
+    Some(GaplessInfo {
+        encoder_delay: delay + MP3_DECODER_DELAY,
+        encoder_padding: padding.saturating_sub(MP3_DECODER_DELAY),
+        total_samples,
+    })
+}
+
+/// iTunes writes `iTunSMPB` into MP3s as an ID3v2 comment.
+fn itunes_smpb(comments: &[u8]) -> Option<GaplessInfo> {
+    let marker = comments
+        .windows(8)
+        .position(|window| window == b"iTunSMPB")?;
+    parse_itunes_smpb(&comments[marker + 8..])
+}
+
+/// An `iTunSMPB` value is hex words: reserved, delay, padding, total
+/// samples. The delay already covers the decoder delay.
+pub(crate) fn parse_itunes_smpb(value: &[u8]) -> Option<GaplessInfo> {
+    let text: String = value
+        .iter()
+        .take(128)
+        .skip_while(|byte| !byte.is_ascii_hexdigit())
+        .take_while(|byte| byte.is_ascii_hexdigit() || **byte == b' ')
+        .map(|byte| *byte as char)
+        .collect();
+    let words: Vec<u64> = text
+        .split_whitespace()
+        .filter_map(|word| u64::from_str_radix(word, 16).ok())
+        .collect();
+    if words.len() < 4 {
+        return None;
+    }
+    Some(GaplessInfo {
+        encoder_delay: u32::try_from(words[1]).ok()?,
+        encoder_padding: u32::try_from(words[2]).ok()?,
+        total_samples: Some(words[3]).filter(|total| *total > 0),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_lame_delay_and_padding() {
+        // MPEG-1 layer III stereo frame with an Info tag carrying a frame
+        // count and a LAME tag (delay 576, padding 1200).
+        let mut frame = vec![0xFF, 0xFB, 0x90, 0x00];
+        frame.extend_from_slice(&[0u8; 32]);
+        frame.extend_from_slice(b"Info");
+        frame.extend_from_slice(&1u32.to_be_bytes());
+        frame.extend_from_slice(&100u32.to_be_bytes());
+        let mut lame = b"LAME3.100".to_vec();
+        lame.extend_from_slice(&[0u8; 12]);
+        lame.extend_from_slice(&[0x24, 0x04, 0xB0]);
+        frame.extend_from_slice(&lame);
+
+        let info = parse_gapless(&frame).unwrap();
+        assert_eq!(info.encoder_delay, 576 + MP3_DECODER_DELAY);
+        assert_eq!(info.encoder_padding, 1200 - MP3_DECODER_DELAY);
+        assert_eq!(info.total_samples, Some(100 * 1152 - 576 - 1200));
+    }
+
+    #[test]
+    fn reads_flac_total_samples() {
+        let mut data = b"fLaC".to_vec();
+        data.extend_from_slice(&[0x80, 0x00, 0x00, 0x22]);
+        let mut info = [0u8; 18];
+        let packed: u64 = (44_100u64 << 44) | (1 << 41) | (15 << 36) | 1_234_567;
+        info[10..18].copy_from_slice(&packed.to_be_bytes());
+        data.extend_from_slice(&info);
+
+        let gapless = parse_gapless(&data).unwrap();
+        assert_eq!(gapless.total_samples, Some(1_234_567));
+        assert_eq!(gapless.encoder_delay, 0);
+    }
+
+    fn id3v2_frame(id: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut frame = id.to_vec();
+        frame.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&[0, 0]);
+        frame.extend_from_slice(body);
+        frame
+    }
+
+    fn id3v2_tag(version: u8, flags: u8, frames: &[u8]) -> Vec<u8> {
+        let size = frames.len() as u32;
+        let mut tag = vec![b'I', b'D', b'3', version, 0, flags];
+        tag.extend(
+            (0..4)
+                .rev()
+                .map(|shift| ((size >> (shift * 7)) & 0x7F) as u8),
+        );
+        tag.extend_from_slice(frames);
+        tag
+    }
+
+    #[test]
+    fn reads_itunes_smpb_past_other_frames() {
+        let mut frames = id3v2_frame(b"APIC", &[0xFF; 8000]);
+        let mut comment = b"\0eng".to_vec();
+        comment.extend_from_slice(b"iTunSMPB\0");
+        comment.extend_from_slice(b" 00000000 00000840 000001CA 00000000000A1234");
+        frames.extend(id3v2_frame(b"COMM", &comment));
+        frames.extend_from_slice(&[0u8; 64]);
+        let data = id3v2_tag(3, 0, &frames);
+
+        let info = parse_gapless(&data).unwrap();
+        assert_eq!(info.encoder_delay, 0x840);
+        assert_eq!(info.encoder_padding, 0x1CA);
+        assert_eq!(info.total_samples, Some(0xA1234));
+    }
+
+    #[test]
+    fn skips_the_id3v2_footer() {
+        let mut data = id3v2_tag(4, 0x10, &[0u8; 16]);
+        data.extend_from_slice(b"3DI\x04\x00\x10\x00\x00\x00\x10");
+        data.extend_from_slice(b"fLaC");
+        data.extend_from_slice(&[0x80, 0x00, 0x00, 0x22]);
+        let mut info = [0u8; 18];
+        info[10..18].copy_from_slice(&42u64.to_be_bytes());
+        data.extend_from_slice(&info);
+
+        assert_eq!(parse_gapless(&data).unwrap().total_samples, Some(42));
+    }
+}
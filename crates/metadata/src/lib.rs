@@ -3,6 +3,12 @@ use std::path::Path;
 use lofty::error::LoftyError;
 use lofty::picture::{Picture, PictureType};
 use lofty::prelude::{AudioFile, ItemKey, TaggedFileExt};
+use lofty::tag::TagType;
+
+mod gapless;
+
+use gapless::parse_itunes_smpb;
+pub use gapless::{read_gapless, GaplessInfo};
 
 #[derive(Debug, Default, Clone)]
 pub struct TagInfo {
@@ -20,6 +26,24 @@ pub struct TagInfo {
     pub bitrate: Option<u32>,
     pub has_embedded_cover: bool,
     pub genres: Vec<String>,
+    pub musicbrainz_artist_id: Option<String>,
+    pub musicbrainz_album_artist_id: Option<String>,
+    pub musicbrainz_release_id: Option<String>,
+    pub musicbrainz_release_group_id: Option<String>,
+    pub musicbrainz_recording_id: Option<String>,
+    pub release_date: Option<String>,
+    pub original_date: Option<String>,
+    pub release_type: Option<String>,
+    pub label: Option<String>,
+    pub catalog_number: Option<String>,
+    pub composers: Vec<String>,
+    pub conductor: Option<String>,
+    pub performers: Vec<String>,
+    pub work: Option<String>,
+    pub movement: Option<String>,
+    pub movement_no: Option<u16>,
+    pub movement_total: Option<u16>,
+    pub gapless: Option<GaplessInfo>,
 }
 
 #[derive(Debug, Clone)]
@@ -62,6 +86,18 @@ pub fn read_tags(path: &Path) -> Result<TagInfo, MetadataError> {
     info.channels = properties.channels();
     info.bitrate = properties.audio_bitrate().or(properties.overall_bitrate());
 
+    info.gapless = tagged_file
+        .tag(TagType::Mp4Ilst)
+        .and_then(mp4_gapless)
+        .or_else(|| read_gapless(path));
+    if let (Some(total), Some(rate)) = (
+        info.gapless.and_then(|gapless| gapless.total_samples),
+        info.sample_rate.filter(|rate| *rate > 0),
+    ) {
+        let exact_ms = total.saturating_mul(1000) / u64::from(rate);
+        info.duration_ms = Some(exact_ms.min(u64::from(u32::MAX)) as u32);
+    }
+
     if let Some(tag) = tagged_file.primary_tag().or_else(|| tagged_file.first_tag()) {
         info.title = tag.get_string(&ItemKey::TrackTitle).map(|v| v.to_string());
         info.album = tag.get_string(&ItemKey::AlbumTitle).map(|v| v.to_string());
@@ -81,11 +117,98 @@ pub fn read_tags(path: &Path) -> Result<TagInfo, MetadataError> {
         }
         info.summary = tag.get_string(&ItemKey::Comment).map(|s| s.to_string());
         info.has_embedded_cover = !tag.pictures().is_empty();
+        info.musicbrainz_artist_id = tag
+            .get_string(&ItemKey::MusicBrainzArtistId)
+            .and_then(parse_mbid);
+        info.musicbrainz_album_artist_id = tag
+            .get_string(&ItemKey::MusicBrainzReleaseArtistId)
+            .and_then(parse_mbid);
+        info.musicbrainz_release_id = tag
+            .get_string(&ItemKey::MusicBrainzReleaseId)
+            .and_then(parse_mbid);
+        info.musicbrainz_release_group_id = tag
+            .get_string(&ItemKey::MusicBrainzReleaseGroupId)
+            .and_then(parse_mbid);
+        info.musicbrainz_recording_id = tag
+            .get_string(&ItemKey::MusicBrainzRecordingId)
+            .and_then(parse_mbid);
+        info.release_date = [ItemKey::ReleaseDate, ItemKey::RecordingDate, ItemKey::Year]
+            .iter()
+            .find_map(|key| tag.get_string(key).and_then(parse_date));
+        info.original_date = tag
+            .get_string(&ItemKey::OriginalReleaseDate)
+            .or_else(|| first_unknown(tag, ORIGINAL_YEAR_KEYS))
+            .and_then(parse_date);
+        if info.year.is_none() {
+            info.year = info.release_date.as_deref().and_then(parse_year);
+        }
+        info.release_type = first_unknown(tag, RELEASE_TYPE_KEYS).and_then(clean_value);
+        info.label = tag.get_string(&ItemKey::Label).and_then(clean_value);
+        info.catalog_number = tag.get_string(&ItemKey::CatalogNumber).and_then(clean_value);
+        info.composers = parse_people(tag.get_strings(&ItemKey::Composer));
+        info.conductor = tag.get_string(&ItemKey::Conductor).and_then(clean_value);
+        info.performers = read_performers(tag);
+        info.movement = tag.get_string(&ItemKey::Movement).and_then(clean_value);
+        let movement_no = tag.get_string(&ItemKey::MovementNumber);
+        info.movement_no = movement_no.and_then(parse_u16);
+        info.movement_total = tag
+            .get_string(&ItemKey::MovementTotal)
+            .and_then(parse_u16)
+            .or_else(|| movement_no.and_then(parse_total));
+        // Older iTunes files carry the work in the grouping field; only trust
+        // it when the file also has movement tags.
+        info.work = tag.get_string(&ItemKey::Work).and_then(clean_value).or_else(|| {
+            if info.movement.is_some() || info.movement_no.is_some() {
+                tag.get_string(&ItemKey::ContentGroup).and_then(clean_value)
+            } else {
+                None
+            }
+        });
     }
 
     Ok(info)
 }
 
+// lofty has no dedicated keys for these, so they surface under the raw
+// Vorbis, ID3 TXXX or MP4 freeform names depending on the container.
+const RELEASE_TYPE_KEYS: &[&str] = &[
+    "RELEASETYPE",
+    "MUSICBRAINZ_ALBUMTYPE",
+    "MusicBrainz Album Type",
+    "----:com.apple.iTunes:MusicBrainz Album Type",
+    "----:com.apple.iTunes:RELEASETYPE",
+];
+
+const ORIGINAL_YEAR_KEYS: &[&str] = &[
+    "ORIGINALYEAR",
+    "originalyear",
+    "----:com.apple.iTunes:ORIGINALYEAR",
+];
+
+// ID3v2.4 musician and involved-people credit frames, which lofty leaves
+// under their frame ids.
+const CREDIT_LIST_KEYS: &[&str] = &["TMCL", "TIPL"];
+
+// MP4 files keep iTunes gapless info in a freeform atom rather than the
+// ID3v2 comment `read_gapless` looks for.
+const ITUNES_SMPB_KEYS: &[&str] = &["----:com.apple.iTunes:iTunSMPB", "iTunSMPB"];
+
+fn first_unknown<'a>(tag: &'a lofty::tag::Tag, keys: &[&str]) -> Option<&'a str> {
+    keys.iter()
+        .find_map(|key| tag.get_string(&ItemKey::Unknown(key.to_string())))
+}
+
+fn read_performers(tag: &lofty::tag::Tag) -> Vec<String> {
+    let credits = CREDIT_LIST_KEYS
+        .iter()
+        .filter_map(|key| tag.get_string(&ItemKey::Unknown(key.to_string())));
+    parse_people(tag.get_strings(&ItemKey::Performer).chain(credit_names(credits)))
+}
+
+fn mp4_gapless(tag: &lofty::tag::Tag) -> Option<GaplessInfo> {
+    first_unknown(tag, ITUNES_SMPB_KEYS).and_then(|value| parse_itunes_smpb(value.as_bytes()))
+}
+
 pub fn read_cover(path: &Path) -> Result<Option<CoverArt>, MetadataError> {
     let tagged_file = lofty::read_from_path(path)?;
     let tag = match tagged_file.primary_tag().or_else(|| tagged_file.first_tag()) {
@@ -108,6 +231,10 @@ fn parse_u16(text: &str) -> Option<u16> {
     head.parse().ok()
 }
 
+fn parse_total(text: &str) -> Option<u16> {
+    text.split('/').nth(1)?.trim().parse().ok()
+}
+
 fn parse_year(text: &str) -> Option<i32> {
     let mut digits = String::new();
     for ch in text.chars() {
@@ -127,6 +254,56 @@ fn parse_year(text: &str) -> Option<i32> {
     }
 }
 
+/// Normalizes tag dates to `YYYY`, `YYYY-MM` or `YYYY-MM-DD`, dropping any
+/// time component and accepting `/` or `.` separators.
+pub fn parse_date(text: &str) -> Option<String> {
+    let mut parts = text
+        .trim()
+        .split(|ch: char| ch == '-' || ch == '/' || ch == '.' || ch == 'T' || ch == ' ');
+    let year = parts.next()?.trim();
+    if year.len() != 4 || !year.chars().all(|ch| ch.is_ascii_digit()) {
+        return parse_year(text).map(|year| format!("{:04}", year));
+    }
+    let mut out = year.to_string();
+    for (max, part) in [12u32, 31u32].into_iter().zip(parts) {
+        match part.trim().parse::<u32>() {
+            Ok(value) if (1..=max).contains(&value) && part.len() <= 2 => {
+                out.push_str(&format!("-{:02}", value));
+            }
+            _ => break,
+        }
+    }
+    Some(out)
+}
+
+fn clean_value(text: &str) -> Option<String> {
+    let trimmed = text.trim();
+    if trimmed.is_empty() {
+        None
+    } else {
+        Some(trimmed.to_string())
+    }
+}
+
+/// Multi-artist tags hold several ids separated by `/` or `;`; the first one
+/// belongs to the primary artist.
+pub fn parse_mbid(text: &str) -> Option<String> {
+    let head = text
+        .split(&['/', ';', ',', '\0'][..])
+        .map(str::trim)
+        .find(|part| !part.is_empty())?;
+    let valid = head.len() == 36
+        && head.char_indices().all(|(idx, ch)| match idx {
+            8 | 13 | 18 | 23 => ch == '-',
+            _ => ch.is_ascii_hexdigit(),
+        });
+    if valid {
+        Some(head.to_ascii_lowercase())
+    } else {
+        None
+    }
+}
+
 fn parse_genres(text: &str) -> Vec<String> {
     let mut out = Vec::new();
     for part in text.split(&[';', ',', '/', '|', '\0'][..]) {
@@ -145,6 +322,27 @@ fn parse_genres(text: &str) -> Vec<String> {
     out
 }
 
+/// Credits may be multi-valued or packed into one value; `/` is left alone
+/// because it shows up inside names.
+fn parse_people<'a>(values: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let mut out: Vec<String> = Vec::new();
+    for value in values {
+        for part in value.split(&[';', '\0'][..]) {
+            let trimmed = part.trim();
+            if !trimmed.is_empty() && !out.iter().any(|name| name == trimmed) {
+                out.push(trimmed.to_string());
+            }
+        }
+    }
+    out
+}
+
+/// TMCL/TIPL credits pack `role\0name` pairs into one value; only the
+/// names are kept.
+fn credit_names<'a>(values: impl Iterator<Item = &'a str>) -> impl Iterator<Item = &'a str> {
+    values.flat_map(|value| value.split('\0').skip(1).step_by(2))
+}
+
 fn pick_picture(pictures: &[Picture]) -> Option<&Picture> {
     for picture in pictures {
         if picture.pic_type() == PictureType::CoverFront {
@@ -163,3 +361,63 @@ fn guess_mime(bytes: &[u8]) -> Option<String> {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn credit_pairs_keep_only_names() {
+        let mut tag = lofty::tag::Tag::new(TagType::VorbisComments);
+        tag.insert_text(
+            ItemKey::Unknown("TMCL".to_string()),
+            "piano\0Martha Argerich\0violin\0Gidon Kremer".to_string(),
+        );
+        tag.insert_text(ItemKey::Performer, "Mischa Maisky; Gidon Kremer".to_string());
+        assert_eq!(
+            read_performers(&tag),
+            vec!["Mischa Maisky", "Gidon Kremer", "Martha Argerich"]
+        );
+        assert_eq!(
+            parse_people(["Bach\0Busoni"].into_iter()),
+            vec!["Bach", "Busoni"]
+        );
+    }
+
+    #[test]
+    fn performer_lists_keep_every_name() {
+        let mut tag = lofty::tag::Tag::new(TagType::VorbisComments);
+        tag.insert_text(
+            ItemKey::Performer,
+            "Martha Argerich\0Gidon Kremer\0Mischa Maisky".to_string(),
+        );
+        assert_eq!(
+            read_performers(&tag),
+            vec!["Martha Argerich", "Gidon Kremer", "Mischa Maisky"]
+        );
+    }
+
+    #[test]
+    fn genre_values_split_on_list_separators() {
+        assert_eq!(
+            parse_genres("Rock; Pop,Jazz / Blues|Soul\0Funk"),
+            vec!["Rock", "Pop", "Jazz", "Blues", "Soul", "Funk"]
+        );
+        assert_eq!(parse_genres(" ;Drum & Bass; "), vec!["Drum & Bass"]);
+        assert_eq!(parse_genres("Hip-Hop"), vec!["Hip-Hop"]);
+    }
+
+    #[test]
+    fn reads_itunes_smpb_from_mp4_freeform_atom() {
+        let mut tag = lofty::tag::Tag::new(TagType::Mp4Ilst);
+        tag.insert_text(
+            ItemKey::Unknown("----:com.apple.iTunes:iTunSMPB".to_string()),
+            " 00000000 00000840 000001CA 00000000000A1234".to_string(),
+        );
+        let info = mp4_gapless(&tag).unwrap();
+        assert_eq!(info.encoder_delay, 0x840);
+        assert_eq!(info.encoder_padding, 0x1CA);
+        assert_eq!(info.total_samples, Some(0xA1234));
+        assert_eq!(mp4_gapless(&lofty::tag::Tag::new(TagType::Mp4Ilst)), None);
+    }
+}
@@ -0,0 +1,434 @@
+//! The async API. `connect` starts a task that owns the connection; the
+//! returned `Client` is a cheap handle for sending requests to it.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use quic_wire::AudioUnit;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::connection::{
+    ClientMessage, Command, Driver, Target, Transport, Waiter, PROTOCOL_VERSION,
+};
+
+const CLIENT_NAME: &str = concat!("phonolite_quic/", env!("CARGO_PKG_VERSION"));
+
+pub struct ConnectOptions {
+    pub host: String,
+    pub port: u16,
+    /// Sent with `hello`, so `connect` also waits for `auth_ok`.
+    pub token: Option<String>,
+    /// Optional protocol features to ask for. `binary_framing` is handled
+    /// here; the others change what the server sends and are left to the
+    /// caller.
+    pub capabilities: Vec<String>,
+    pub client_name: String,
+    pub verify_peer: bool,
+    /// What to do when an established connection drops. `None` lets it
+    /// close.
+    pub reconnect: Option<Reconnect>,
+}
+
+impl ConnectOptions {
+    pub fn new(host: impl Into<String>, port: u16) -> Self {
+        Self {
+            host: host.into(),
+            port,
+            token: None,
+            capabilities: vec!["binary_framing".to_string()],
+            client_name: CLIENT_NAME.to_string(),
+            verify_peer: false,
+            reconnect: Some(Reconnect::default()),
+        }
+    }
+}
+
+/// Reconnection with exponential backoff. The client re-sends `hello` and
+/// `auth`, then asks the server to resume the session where the delivered
+/// audio left off. If the server can't, it opens the track again with the
+/// latest queue and seeks.
+///
+/// Nothing is retried when the connection was closed locally, when the
+/// server closed it on purpose (for instance because the session was
+/// resumed on another connection), or when the first `hello` failed.
+#[derive(Clone, Debug)]
+pub struct Reconnect {
+    /// Attempts per outage before the connection closes.
+    pub attempts: u32,
+    /// The wait before the first attempt, doubled for each one after.
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for Reconnect {
+    fn default() -> Self {
+        Self {
+            attempts: 8,
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(15),
+        }
+    }
+}
+
+impl Reconnect {
+    /// The wait before `attempt`, counting from 1, without jitter.
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        let doublings = attempt.saturating_sub(1).min(16);
+        self.initial_delay
+            .saturating_mul(1 << doublings)
+            .min(self.max_delay)
+    }
+}
+
+/// The fields of an `open` request. `Open::new` leaves everything but the
+/// track to the server's defaults.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Open {
+    pub track_id: String,
+    pub mode: Option<String>,
+    pub quality: Option<String>,
+    pub frame_ms: Option<u32>,
+    pub queue: Option<Vec<String>>,
+    pub gapless: Option<bool>,
+    pub crossfade_ms: Option<u32>,
+    pub crossfade_curve: Option<String>,
+    pub surround: Option<bool>,
+    pub speed: Option<f32>,
+}
+
+impl Open {
+    pub fn new(track_id: impl Into<String>) -> Self {
+        Self {
+            track_id: track_id.into(),
+            ..Self::default()
+        }
+    }
+}
+
+/// One unit of the active track's audio. Audio for other tracks is held
+/// back until they are opened.
+#[derive(Clone, Debug)]
+pub struct AudioFrame {
+    pub track_id: String,
+    /// The QUIC stream, which `track_boundary` and `stream_end` refer to.
+    pub stream_id: u64,
+    pub unit: AudioUnit,
+    /// Set on the first unit after a reconnect: the track continues here,
+    /// on a new stream that starts with a header.
+    pub resumed: bool,
+    /// Set on the first unit of the stream a `seek` started.
+    pub seek: bool,
+}
+
+/// Server messages, plus the connection's own notices. `hello_ok`,
+/// `stream` and `pong` are handled internally and not passed on.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    AuthOk {
+        resume_token: String,
+    },
+    OpenOk {
+        track_id: String,
+    },
+    SessionResumed {
+        track_id: Option<String>,
+        position_ms: u32,
+        queue: Vec<String>,
+        paused: bool,
+    },
+    TrackBoundary {
+        stream_id: u64,
+        track_id: String,
+        sample_offset: u64,
+        crossfade_samples: u64,
+    },
+    StreamEnd {
+        stream_id: u64,
+        track_id: String,
+        pre_skip: u16,
+        valid_samples: u64,
+    },
+    TrackStarted {
+        track_id: String,
+        position_ms: u32,
+        duration_ms: u32,
+    },
+    TrackEnded {
+        track_id: String,
+        position_ms: u32,
+        reason: String,
+    },
+    QueueExhausted,
+    Subscribed {
+        topics: Vec<String>,
+        seq: u64,
+        epoch: u64,
+    },
+    /// A change event from a subscribed topic.
+    #[serde(rename = "event")]
+    Change {
+        seq: u64,
+        topic: String,
+        kind: String,
+        at: u64,
+        data: Value,
+    },
+    ResyncRequired {
+        epoch: u64,
+    },
+    Error {
+        code: Option<String>,
+        message: String,
+    },
+    /// A message this client doesn't know.
+    #[serde(skip)]
+    Other(Value),
+    /// A transport problem the connection survived.
+    #[serde(skip)]
+    TransportError(String),
+    /// A human-readable transport statistics line, every few seconds.
+    #[serde(skip)]
+    Stats(String),
+    /// The connection dropped; `attempt` starts after `delay_ms`. Requests
+    /// made meanwhile are held until the session is restored.
+    #[serde(skip)]
+    Reconnecting {
+        attempt: u32,
+        delay_ms: u64,
+        reason: String,
+    },
+    /// The session is back. `resumed` says the server resumed it; otherwise
+    /// the track was opened again and sought to the last delivered
+    /// position. The active track's audio continues on a new stream,
+    /// starting with a header.
+    #[serde(skip)]
+    Reconnected {
+        attempt: u32,
+        resumed: bool,
+    },
+    /// The last event; both receivers close after it.
+    #[serde(skip)]
+    Closed {
+        reason: String,
+    },
+}
+
+pub struct Connection {
+    pub client: Client,
+    pub audio: mpsc::UnboundedReceiver<AudioFrame>,
+    pub events: mpsc::UnboundedReceiver<Event>,
+    /// From `auth_ok` when `ConnectOptions::token` was set.
+    pub resume_token: Option<String>,
+}
+
+/// Requests that only need sending return once they are queued; `auth`
+/// and `open` wait for the server's answer. Every answer is also delivered
+/// as an `Event`. Errors are reported as strings, and the connection closes
+/// once every `Client` is dropped.
+#[derive(Clone)]
+pub struct Client {
+    commands: mpsc::UnboundedSender<Command>,
+    protocol: u32,
+    capabilities: Arc<[String]>,
+}
+
+/// Opens a QUIC connection and sends `hello`, and `auth` if there is a
+/// token. Resolves once the server has answered both. Must be called
+/// within a tokio runtime, which then runs the connection.
+pub async fn connect(options: ConnectOptions) -> Result<Connection, String> {
+    let target = Target {
+        host: options.host,
+        port: options.port,
+        verify_peer: options.verify_peer,
+    };
+    let transport = Transport::open(&target, None).await?;
+
+    let (commands, commands_rx) = mpsc::unbounded_channel();
+    let (audio_tx, audio) = mpsc::unbounded_channel();
+    let (events_tx, events) = mpsc::unbounded_channel();
+    let (hello_tx, hello_rx) = oneshot::channel();
+    let _ = commands.send(Command::Send(ClientMessage::Hello {
+        protocol: PROTOCOL_VERSION,
+        client: options.client_name,
+        capabilities: options.capabilities,
+    }));
+    let auth = options.token.map(|token| {
+        let (reply, answer) = oneshot::channel();
+        let _ = commands.send(Command::Request(
+            ClientMessage::Auth { token },
+            Waiter::Auth(reply),
+        ));
+        answer
+    });
+    tokio::spawn(
+        Driver {
+            target,
+            reconnect: options.reconnect,
+            commands: commands_rx,
+            hello: Some(hello_tx),
+            audio: audio_tx,
+            events: events_tx,
+        }
+        .run(transport),
+    );
+
+    let hello = wait(hello_rx).await?;
+    let resume_token = match auth {
+        Some(answer) => Some(wait(answer).await?),
+        None => None,
+    };
+    Ok(Connection {
+        client: Client {
+            commands,
+            protocol: hello.protocol,
+            capabilities: hello.capabilities.into(),
+        },
+        audio,
+        events,
+        resume_token,
+    })
+}
+
+async fn wait<T>(answer: oneshot::Receiver<Result<T, String>>) -> Result<T, String> {
+    answer
+        .await
+        .map_err(|_| "QUIC connection closed".to_string())?
+}
+
+impl Client {
+    /// The protocol version `hello_ok` settled on.
+    pub fn protocol(&self) -> u32 {
+        self.protocol
+    }
+
+    /// The capabilities the server agreed to.
+    pub fn capabilities(&self) -> &[String] {
+        &self.capabilities
+    }
+
+    pub fn has_capability(&self, name: &str) -> bool {
+        self.capabilities.iter().any(|value| value == name)
+    }
+
+    /// Returns the resume token from `auth_ok`.
+    pub async fn auth(&self, token: &str) -> Result<String, String> {
+        let (reply, answer) = oneshot::channel();
+        self.command(Command::Request(
+            ClientMessage::Auth {
+                token: token.to_string(),
+            },
+            Waiter::Auth(reply),
+        ))?;
+        wait(answer).await
+    }
+
+    /// Makes `open.track_id` the active track. Its audio arrives on
+    /// `Connection::audio`, starting with a header.
+    pub async fn open(&self, open: Open) -> Result<(), String> {
+        let (reply, answer) = oneshot::channel();
+        self.command(Command::Request(
+            ClientMessage::Open(open),
+            Waiter::Open(reply),
+        ))?;
+        wait(answer).await
+    }
+
+    pub fn queue(&self, track_ids: Vec<String>) -> Result<(), String> {
+        self.send(ClientMessage::Queue { track_ids })
+    }
+
+    pub fn advance(&self) -> Result<(), String> {
+        self.send(ClientMessage::Advance)
+    }
+
+    /// The server starts the track again on a new stream; its first unit,
+    /// a header, has `AudioFrame::seek` set.
+    pub fn seek(&self, track_id: &str, position_ms: u32, speed: Option<f32>) -> Result<(), String> {
+        self.send(ClientMessage::Seek {
+            track_id: track_id.to_string(),
+            position_ms,
+            speed,
+        })
+    }
+
+    pub fn pause(&self) -> Result<(), String> {
+        self.send(ClientMessage::Pause)
+    }
+
+    pub fn resume(&self) -> Result<(), String> {
+        self.send(ClientMessage::Resume)
+    }
+
+    pub fn stop(&self) -> Result<(), String> {
+        self.send(ClientMessage::Stop)
+    }
+
+    pub fn position(&self, track_id: &str, position_ms: u32) -> Result<(), String> {
+        self.send(ClientMessage::Position {
+            track_id: track_id.to_string(),
+            position_ms,
+        })
+    }
+
+    /// Reports the local buffer so the server can adapt the bitrate.
+    pub fn buffer(&self, buffer_ms: u32, target_ms: Option<u32>) -> Result<(), String> {
+        self.send(ClientMessage::Buffer {
+            buffer_ms,
+            target_ms,
+        })
+    }
+
+    /// `since` is the last `(seq, epoch)` seen, to replay missed events.
+    pub fn subscribe(&self, topics: Vec<String>, since: Option<(u64, u64)>) -> Result<(), String> {
+        self.send(ClientMessage::Subscribe {
+            topics,
+            since: since.map(|(seq, _)| seq),
+            epoch: since.map(|(_, epoch)| epoch),
+        })
+    }
+
+    pub fn unsubscribe(&self, topics: Vec<String>) -> Result<(), String> {
+        self.send(ClientMessage::Unsubscribe { topics })
+    }
+
+    /// Replaces `ConnectOptions::reconnect` for the rest of the connection.
+    pub fn set_reconnect(&self, reconnect: Option<Reconnect>) -> Result<(), String> {
+        self.command(Command::Reconnect(reconnect))
+    }
+
+    /// Closes the connection for every handle.
+    pub fn close(&self) {
+        let _ = self.commands.send(Command::Close);
+    }
+
+    pub(crate) fn send(&self, message: ClientMessage) -> Result<(), String> {
+        self.command(Command::Send(message))
+    }
+
+    fn command(&self, command: Command) -> Result<(), String> {
+        self.commands
+            .send(command)
+            .map_err(|_| "QUIC connection closed".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reconnect_delay_doubles_up_to_the_limit() {
+        let reconnect = Reconnect {
+            attempts: 8,
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(3),
+        };
+        let delays: Vec<u128> = (1..=5)
+            .map(|attempt| reconnect.delay(attempt).as_millis())
+            .collect();
+        assert_eq!(delays, vec![500, 1000, 2000, 3000, 3000]);
+    }
+}
@@ -0,0 +1,1559 @@
+//! The task that owns the QUIC connection. It multiplexes commands from
+//! `Client` handles onto the control stream and splits the audio streams
+//! into units for the active track. When the connection drops it opens a
+//! new one and restores the session there.
+
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use bytes::Bytes;
+use quic_wire::{
+    encode_control, AudioFraming, AudioReader, AudioUnit, ControlFraming, ControlReader,
+};
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::net::UdpSocket;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{interval_at, sleep, sleep_until, Instant};
+
+use crate::client::{AudioFrame, Event, Open, Reconnect};
+
+const ALPN_QUIC: &[&[u8]] = &[b"phonolite-quic"];
+const MAX_QUIC_DATAGRAM: usize = 1350;
+pub(crate) const PROTOCOL_VERSION: u32 = 2;
+const MAX_UDP_SIZE: usize = 65535;
+const MAX_PREFETCH_BYTES: usize = 12 * 1024 * 1024;
+const CONTROL_STREAM_ID: u64 = 0;
+const PING_INTERVAL: Duration = Duration::from_millis(500);
+const ACK_ELICIT_INTERVAL: Duration = Duration::from_millis(200);
+const STATS_INTERVAL: Duration = Duration::from_secs(5);
+const RECONNECT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+const HEADER_FRAME_MS_OFFSET: usize = 17;
+/// The sample rate `track_boundary` offsets count in.
+const NOTICE_RATE: u64 = 48_000;
+
+#[derive(Clone, Serialize)]
+#[serde(tag = "type")]
+pub(crate) enum ClientMessage {
+    #[serde(rename = "hello")]
+    Hello {
+        protocol: u32,
+        client: String,
+        capabilities: Vec<String>,
+    },
+    #[serde(rename = "auth")]
+    Auth { token: String },
+    #[serde(rename = "resume_session")]
+    ResumeSession {
+        token: String,
+        position_ms: Option<u32>,
+    },
+    #[serde(rename = "open")]
+    Open(Open),
+    #[serde(rename = "queue")]
+    Queue { track_ids: Vec<String> },
+    #[serde(rename = "advance")]
+    Advance,
+    #[serde(rename = "buffer")]
+    Buffer {
+        buffer_ms: u32,
+        target_ms: Option<u32>,
+    },
+    #[serde(rename = "seek")]
+    Seek {
+        track_id: String,
+        position_ms: u32,
+        speed: Option<f32>,
+    },
+    #[serde(rename = "pause")]
+    Pause,
+    #[serde(rename = "resume")]
+    Resume,
+    #[serde(rename = "stop")]
+    Stop,
+    #[serde(rename = "position")]
+    Position { track_id: String, position_ms: u32 },
+    #[serde(rename = "subscribe")]
+    Subscribe {
+        topics: Vec<String>,
+        since: Option<u64>,
+        epoch: Option<u64>,
+    },
+    #[serde(rename = "unsubscribe")]
+    Unsubscribe { topics: Vec<String> },
+    #[serde(rename = "ping")]
+    Ping { ts: Option<i64> },
+}
+
+/// What `hello_ok` agreed to.
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct HelloOk {
+    pub protocol: u32,
+    pub capabilities: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct StreamAnnounce {
+    track_id: String,
+    stream_id: u64,
+    role: Option<String>,
+}
+
+/// A caller waiting for the answer to a request.
+pub(crate) enum Waiter {
+    Auth(oneshot::Sender<Result<String, String>>),
+    Open(oneshot::Sender<Result<(), String>>),
+}
+
+impl Waiter {
+    fn fail(self, message: String) {
+        match self {
+            Self::Auth(reply) => {
+                let _ = reply.send(Err(message));
+            }
+            Self::Open(reply) => {
+                let _ = reply.send(Err(message));
+            }
+        }
+    }
+}
+
+pub(crate) enum Command {
+    Send(ClientMessage),
+    Request(ClientMessage, Waiter),
+    Reconnect(Option<Reconnect>),
+    Close,
+}
+
+/// Where to connect, kept for reconnecting.
+pub(crate) struct Target {
+    pub host: String,
+    pub port: u16,
+    pub verify_peer: bool,
+}
+
+/// One QUIC connection and its socket.
+pub(crate) struct Transport {
+    conn: quiche::Connection,
+    socket: UdpSocket,
+    local_addr: SocketAddr,
+}
+
+impl Transport {
+    /// `ticket` is the TLS session of an earlier connection. With it the
+    /// handshake resumes and `hello` and `auth` go out as early data.
+    pub(crate) async fn open(target: &Target, ticket: Option<&[u8]>) -> Result<Self, String> {
+        let addrs = tokio::net::lookup_host((target.host.as_str(), target.port))
+            .await
+            .map_err(|err| format!("invalid server addr: {}", err))?
+            .collect::<Vec<_>>();
+        let server_addr = addrs
+            .iter()
+            .find(|addr| addr.is_ipv4())
+            .copied()
+            .or_else(|| addrs.first().copied())
+            .ok_or_else(|| "invalid server addr: no resolved addresses".to_string())?;
+        let bind_addr: SocketAddr = if server_addr.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(bind_addr)
+            .await
+            .map_err(|err| format!("{}", err))?;
+        socket
+            .connect(server_addr)
+            .await
+            .map_err(|err| format!("socket connect: {}", err))?;
+        let local_addr = socket.local_addr().map_err(|err| format!("{}", err))?;
+
+        let mut config = build_config(target.verify_peer)?;
+        let mut scid = [0u8; 16];
+        rand::rng().fill_bytes(&mut scid);
+        let scid = quiche::ConnectionId::from_ref(&scid);
+        let server_name = target.verify_peer.then_some(target.host.as_str());
+        let mut conn = quiche::connect(server_name, &scid, local_addr, server_addr, &mut config)
+            .map_err(|err| format!("connect error: {:?}", err))?;
+        if let Some(ticket) = ticket {
+            // A ticket the server no longer accepts only costs the early data.
+            let _ = conn.set_session(ticket);
+        }
+        Ok(Self {
+            conn,
+            socket,
+            local_addr,
+        })
+    }
+}
+
+fn build_config(verify_peer: bool) -> Result<quiche::Config, String> {
+    let mut config =
+        quiche::Config::new(quiche::PROTOCOL_VERSION).map_err(|e| format!("{:?}", e))?;
+    config
+        .set_application_protos(ALPN_QUIC)
+        .map_err(|err| format!("alpn error: {:?}", err))?;
+    config.verify_peer(verify_peer);
+    config.set_max_idle_timeout(30_000);
+    config.set_max_recv_udp_payload_size(MAX_QUIC_DATAGRAM);
+    config.set_max_send_udp_payload_size(MAX_QUIC_DATAGRAM);
+    config.set_initial_max_data(20_000_000);
+    config.set_initial_max_stream_data_bidi_local(10_000_000);
+    config.set_initial_max_stream_data_bidi_remote(10_000_000);
+    config.set_initial_max_stream_data_uni(10_000_000);
+    config.set_initial_max_streams_bidi(16);
+    config.set_initial_max_streams_uni(32);
+    config.set_disable_active_migration(true);
+    config.enable_early_data();
+    Ok(config)
+}
+
+pub(crate) struct Driver {
+    pub target: Target,
+    pub reconnect: Option<Reconnect>,
+    pub commands: mpsc::UnboundedReceiver<Command>,
+    pub hello: Option<oneshot::Sender<Result<HelloOk, String>>>,
+    pub audio: mpsc::UnboundedSender<AudioFrame>,
+    pub events: mpsc::UnboundedSender<Event>,
+}
+
+/// How a connection ended.
+struct Ended {
+    reason: String,
+    /// False when either side closed it on purpose.
+    retry: bool,
+}
+
+impl Driver {
+    pub(crate) async fn run(mut self, mut transport: Transport) {
+        let mut session = Session::new(self.hello.take());
+        let mut attempt = 0;
+        loop {
+            let ended = self.drive(&mut session, &mut transport).await;
+            let retry = ended.retry && session.connected;
+            let mut reason = ended.reason;
+            let ticket = transport.conn.session().map(<[u8]>::to_vec);
+            // A connection that drops before the session is restored is
+            // part of the same outage.
+            if !session.restoring() {
+                attempt = 0;
+            }
+            session.lost(&reason);
+            transport = loop {
+                attempt += 1;
+                let policy = match self.reconnect.clone() {
+                    Some(policy) if retry && attempt <= policy.attempts => policy,
+                    Some(_) if retry => {
+                        reason = format!(
+                            "{} (gave up after {} reconnect attempts)",
+                            reason,
+                            attempt - 1
+                        );
+                        session.finish(&self.events, reason);
+                        return;
+                    }
+                    _ => {
+                        session.finish(&self.events, reason);
+                        return;
+                    }
+                };
+                let jitter = rand::rng().random_range(1.0..1.25);
+                let delay = policy.delay(attempt).mul_f64(jitter);
+                let _ = self.events.send(Event::Reconnecting {
+                    attempt,
+                    delay_ms: delay.as_millis() as u64,
+                    reason: reason.clone(),
+                });
+                if !self.backoff(delay, &mut session).await {
+                    session.finish(&self.events, "client closed".to_string());
+                    return;
+                }
+                match Transport::open(&self.target, ticket.as_deref()).await {
+                    Ok(transport) => break transport,
+                    Err(err) => reason = err,
+                }
+            };
+            session.reconnect(attempt);
+        }
+    }
+
+    /// Runs one connection until it closes.
+    async fn drive(&mut self, session: &mut Session, transport: &mut Transport) -> Ended {
+        let Transport {
+            conn,
+            socket,
+            local_addr,
+        } = transport;
+        let local_addr = *local_addr;
+        let mut recv_buf = vec![0u8; MAX_UDP_SIZE];
+        let mut send_buf = vec![0u8; MAX_UDP_SIZE];
+        let mut ping = interval_at(Instant::now() + PING_INTERVAL, PING_INTERVAL);
+        let mut ack_elicit = interval_at(Instant::now() + ACK_ELICIT_INTERVAL, ACK_ELICIT_INTERVAL);
+        let mut stats = interval_at(Instant::now() + STATS_INTERVAL, STATS_INTERVAL);
+        // Waiting out the idle timeout would stall every attempt while the
+        // server is down.
+        let handshake_deadline = session
+            .restoring()
+            .then(|| Instant::now() + RECONNECT_HANDSHAKE_TIMEOUT);
+        if let Ok(peer) = socket.peer_addr() {
+            let _ = self.events.send(Event::Stats(format!(
+                "QUIC client socket local={} server={}",
+                local_addr, peer
+            )));
+        }
+
+        let mut closing = false;
+        let mut rejected = None;
+        loop {
+            if let Some(reason) = session.rejected.take() {
+                closing = true;
+                let _ = conn.close(true, 0, b"session rejected");
+                rejected = Some(reason);
+            }
+            session.flush_control(conn);
+            flush_conn(conn, socket, &mut send_buf, &self.events).await;
+            if conn.is_closed() {
+                break;
+            }
+            let timeout = conn.timeout();
+            tokio::select! {
+                command = self.commands.recv(), if !closing => {
+                    if !self.apply(command, session) {
+                        closing = true;
+                        let _ = conn.close(true, 0, b"client closed");
+                    }
+                }
+                received = socket.recv_from(&mut recv_buf) => {
+                    let mut received = received;
+                    loop {
+                        match received {
+                            Ok((len, from)) => {
+                                let info = quiche::RecvInfo { from, to: local_addr };
+                                if let Err(err) = conn.recv(&mut recv_buf[..len], info) {
+                                    if err != quiche::Error::Done {
+                                        let _ = self.events.send(Event::TransportError(format!(
+                                            "QUIC recv error: {:?}",
+                                            err
+                                        )));
+                                    }
+                                }
+                            }
+                            Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => break,
+                            Err(err) => {
+                                return Ended {
+                                    reason: format!("udp recv error: {}", err),
+                                    retry: !closing,
+                                };
+                            }
+                        }
+                        received = socket.try_recv_from(&mut recv_buf);
+                    }
+                    session.read_streams(conn, &self.audio, &self.events);
+                }
+                _ = sleep(timeout.unwrap_or_default()), if timeout.is_some() => conn.on_timeout(),
+                _ = sleep_until(handshake_deadline.unwrap_or_else(Instant::now)),
+                    if handshake_deadline.is_some() && !conn.is_established() =>
+                {
+                    return Ended {
+                        reason: "QUIC handshake timed out".to_string(),
+                        retry: true,
+                    };
+                }
+                _ = ping.tick() => {
+                    let ts = std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .ok()
+                        .map(|v| v.as_millis() as i64);
+                    session.write(ClientMessage::Ping { ts }, None);
+                }
+                _ = ack_elicit.tick() => {
+                    let _ = conn.send_ack_eliciting();
+                }
+                _ = stats.tick() => {
+                    let _ = self.events.send(Event::Stats(stats_line(conn)));
+                }
+            }
+        }
+
+        // The server closes with an application error only on purpose, for
+        // instance when the session was resumed elsewhere.
+        let closed_by_server = conn.peer_error().is_some_and(|err| err.is_app);
+        let reason = rejected
+            .or_else(|| connection_close_detail(conn))
+            .unwrap_or_else(|| "QUIC connection closed".to_string());
+        Ended {
+            reason,
+            retry: !closing && !closed_by_server,
+        }
+    }
+
+    /// Returns false once the connection should close.
+    fn apply(&mut self, command: Option<Command>, session: &mut Session) -> bool {
+        match command {
+            Some(Command::Send(message)) => session.send(message, None),
+            Some(Command::Request(message, waiter)) => session.send(message, Some(waiter)),
+            Some(Command::Reconnect(reconnect)) => self.reconnect = reconnect,
+            Some(Command::Close) | None => return false,
+        }
+        true
+    }
+
+    /// Waits before a reconnect attempt. Returns false if the client closed
+    /// meanwhile.
+    async fn backoff(&mut self, delay: Duration, session: &mut Session) -> bool {
+        let deadline = sleep(delay);
+        tokio::pin!(deadline);
+        loop {
+            tokio::select! {
+                _ = &mut deadline => return true,
+                command = self.commands.recv() => {
+                    if !self.apply(command, session) {
+                        return false;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The answers a reconnect waits for, in the order the server sends them.
+#[derive(Clone, Copy, PartialEq)]
+enum Expect {
+    Hello,
+    Auth,
+    Resume,
+    Open,
+}
+
+/// A reconnect in progress. Requests made meanwhile wait in `deferred`
+/// until the server has answered the ones that restore the session.
+#[derive(Default)]
+struct Restore {
+    attempt: u32,
+    expect: VecDeque<Expect>,
+    resumed: bool,
+    deferred: Vec<(ClientMessage, Option<Waiter>)>,
+}
+
+struct Session {
+    pending_control: VecDeque<Bytes>,
+    control_offset: usize,
+    control_reader: ControlReader,
+    control_framing: ControlFraming,
+    hello: Option<oneshot::Sender<Result<HelloOk, String>>>,
+    /// Audio streams are left unread until `hello_ok` says how they're framed.
+    hello_done: bool,
+    /// Set by the first `hello_ok`; a connection that never got one isn't
+    /// worth reconnecting.
+    connected: bool,
+    waiters: VecDeque<Waiter>,
+    router: AudioRouter,
+    // What a reconnect sends again.
+    hello_message: Option<ClientMessage>,
+    token: Option<String>,
+    resume_token: Option<String>,
+    playback: Playback,
+    topics: BTreeSet<String>,
+    /// `(seq, epoch)` of the last change event.
+    since: Option<(u64, u64)>,
+    restore: Option<Restore>,
+    /// Set when the server refuses a reconnect's `hello` or `auth`.
+    rejected: Option<String>,
+}
+
+impl Session {
+    fn new(hello: Option<oneshot::Sender<Result<HelloOk, String>>>) -> Self {
+        Self {
+            pending_control: VecDeque::new(),
+            control_offset: 0,
+            control_reader: ControlReader::default(),
+            control_framing: ControlFraming::Json,
+            hello,
+            hello_done: false,
+            connected: false,
+            waiters: VecDeque::new(),
+            router: AudioRouter::default(),
+            hello_message: None,
+            token: None,
+            resume_token: None,
+            playback: Playback::default(),
+            topics: BTreeSet::new(),
+            since: None,
+            restore: None,
+            rejected: None,
+        }
+    }
+
+    fn restoring(&self) -> bool {
+        self.restore.is_some()
+    }
+
+    fn send(&mut self, message: ClientMessage, waiter: Option<Waiter>) {
+        if let Some(restore) = self.restore.as_mut() {
+            restore.deferred.push((message, waiter));
+            return;
+        }
+        self.write(message, waiter);
+    }
+
+    fn write(&mut self, message: ClientMessage, waiter: Option<Waiter>) {
+        self.observe(&message);
+        match encode_control(self.control_framing, &message) {
+            Ok(payload) => self.pending_control.push_back(Bytes::from(payload)),
+            Err(err) => {
+                if let Some(waiter) = waiter {
+                    waiter.fail(err);
+                }
+                return;
+            }
+        }
+        if let Some(waiter) = waiter {
+            self.waiters.push_back(waiter);
+        }
+    }
+
+    /// Keeps what a reconnect needs from an outgoing request.
+    fn observe(&mut self, message: &ClientMessage) {
+        match message {
+            ClientMessage::Hello { .. } if self.hello_message.is_none() => {
+                self.hello_message = Some(message.clone());
+            }
+            ClientMessage::Auth { token } => self.token = Some(token.clone()),
+            ClientMessage::Open(open) => {
+                self.router.open(&open.track_id);
+                self.playback.open(open);
+            }
+            ClientMessage::Queue { track_ids } => self.playback.queue = Some(track_ids.clone()),
+            ClientMessage::Seek {
+                track_id,
+                position_ms,
+                speed,
+            } => self.playback.seek(track_id, *position_ms, *speed),
+            ClientMessage::Pause => self.playback.paused = true,
+            ClientMessage::Resume => self.playback.paused = false,
+            ClientMessage::Stop => self.playback.stop(),
+            ClientMessage::Subscribe {
+                topics,
+                since,
+                epoch,
+            } => {
+                self.topics.extend(topics.iter().cloned());
+                if let (Some(seq), Some(epoch)) = (since, epoch) {
+                    self.since = Some((*seq, *epoch));
+                }
+            }
+            ClientMessage::Unsubscribe { topics } => {
+                for topic in topics {
+                    self.topics.remove(topic);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Keeps what a reconnect needs from an incoming event.
+    fn note(&mut self, event: &Event, frames: &mut Vec<AudioFrame>) {
+        match event {
+            Event::AuthOk { resume_token } => self.resume_token = Some(resume_token.clone()),
+            Event::SessionResumed {
+                track_id, paused, ..
+            } => {
+                self.playback.paused = *paused;
+                if let Some(track_id) = track_id {
+                    self.router.activate(track_id, frames);
+                }
+            }
+            Event::TrackBoundary {
+                stream_id,
+                track_id,
+                sample_offset,
+                ..
+            } => self.playback.boundary(*stream_id, track_id, *sample_offset),
+            Event::Subscribed { seq, epoch, .. } => self.since = Some((*seq, *epoch)),
+            Event::Change { seq, .. } => {
+                if let Some(since) = self.since.as_mut() {
+                    since.0 = *seq;
+                }
+            }
+            Event::ResyncRequired { .. } => self.since = None,
+            _ => {}
+        }
+    }
+
+    fn flush_control(&mut self, conn: &mut quiche::Connection) {
+        while let Some(front) = self.pending_control.front() {
+            let data = &front[self.control_offset..];
+            match conn.stream_send(CONTROL_STREAM_ID, data, false) {
+                Ok(sent) if sent == data.len() => {
+                    self.pending_control.pop_front();
+                    self.control_offset = 0;
+                }
+                Ok(sent) => {
+                    self.control_offset += sent;
+                    break;
+                }
+                Err(_) => break,
+            }
+        }
+    }
+
+    fn read_streams(
+        &mut self,
+        conn: &mut quiche::Connection,
+        audio: &mpsc::UnboundedSender<AudioFrame>,
+        events: &mpsc::UnboundedSender<Event>,
+    ) {
+        let mut buf = vec![0u8; MAX_UDP_SIZE];
+        let mut readable: Vec<u64> = conn.readable().collect();
+        // The control stream goes first so a `hello_ok` in the same flight
+        // unlocks the audio streams.
+        readable.sort_by_key(|stream_id| *stream_id != CONTROL_STREAM_ID);
+        let mut frames = Vec::new();
+        for stream_id in readable {
+            if stream_id != CONTROL_STREAM_ID && !self.hello_done {
+                continue;
+            }
+            loop {
+                match conn.stream_recv(stream_id, &mut buf) {
+                    Ok((len, fin)) => {
+                        if stream_id == CONTROL_STREAM_ID {
+                            self.handle_control(&buf[..len], &mut frames, events);
+                        } else {
+                            if let Err(err) = self.router.push(stream_id, &buf[..len], &mut frames)
+                            {
+                                let _ = events.send(Event::TransportError(err));
+                                let _ = conn.stream_shutdown(stream_id, quiche::Shutdown::Read, 0);
+                            }
+                            if fin {
+                                self.router.finish(stream_id);
+                            }
+                        }
+                    }
+                    Err(quiche::Error::Done) => break,
+                    Err(err) => {
+                        let _ = events.send(Event::TransportError(format!(
+                            "QUIC stream recv error: {:?}",
+                            err
+                        )));
+                        break;
+                    }
+                }
+            }
+        }
+        for mut frame in frames {
+            self.playback.deliver(&mut frame);
+            let _ = audio.send(frame);
+        }
+    }
+
+    fn handle_control(
+        &mut self,
+        data: &[u8],
+        frames: &mut Vec<AudioFrame>,
+        events: &mpsc::UnboundedSender<Event>,
+    ) {
+        let messages: Vec<Value> = self.control_reader.push(data);
+        for message in messages {
+            match message.get("type").and_then(Value::as_str) {
+                Some("hello_ok") => {
+                    let Ok(hello) = serde_json::from_value::<HelloOk>(message) else {
+                        continue;
+                    };
+                    if hello
+                        .capabilities
+                        .iter()
+                        .any(|name| name == "binary_framing")
+                    {
+                        self.control_framing = ControlFraming::Binary;
+                        self.router.framing = AudioFraming::Binary;
+                    }
+                    self.hello_done = true;
+                    self.connected = true;
+                    if let Some(reply) = self.hello.take() {
+                        let _ = reply.send(Ok(hello));
+                    }
+                    self.answered(Expect::Hello);
+                }
+                Some("stream") => {
+                    if let Ok(stream) = serde_json::from_value::<StreamAnnounce>(message) {
+                        let start = (stream.role.as_deref() == Some("active"))
+                            .then(|| self.playback.announced(&stream.track_id, stream.stream_id))
+                            .flatten();
+                        if start == Some(Start::Discard) {
+                            self.router.discard(stream.stream_id);
+                        }
+                        self.router
+                            .announce(&stream.track_id, stream.stream_id, frames);
+                    }
+                }
+                Some("pong") => {}
+                _ => {
+                    let event = serde_json::from_value::<Event>(message.clone())
+                        .unwrap_or(Event::Other(message));
+                    self.note(&event, frames);
+                    match self.restore_answer(&event) {
+                        None => {
+                            self.answer_waiter(&event);
+                            let _ = events.send(event);
+                        }
+                        Some(true) => {
+                            let _ = events.send(event);
+                        }
+                        Some(false) => {}
+                    }
+                }
+            }
+            self.finish_restore(events);
+        }
+    }
+
+    /// The server answers requests in order, so an `error` belongs to the
+    /// oldest outstanding one.
+    fn answer_waiter(&mut self, event: &Event) {
+        match event {
+            Event::AuthOk { resume_token } => {
+                if let Some(index) = self
+                    .waiters
+                    .iter()
+                    .position(|waiter| matches!(waiter, Waiter::Auth(_)))
+                {
+                    if let Some(Waiter::Auth(reply)) = self.waiters.remove(index) {
+                        let _ = reply.send(Ok(resume_token.clone()));
+                    }
+                }
+            }
+            Event::OpenOk { .. } => {
+                if let Some(index) = self
+                    .waiters
+                    .iter()
+                    .position(|waiter| matches!(waiter, Waiter::Open(_)))
+                {
+                    if let Some(Waiter::Open(reply)) = self.waiters.remove(index) {
+                        let _ = reply.send(Ok(()));
+                    }
+                }
+            }
+            Event::Error { message, .. } => {
+                if let Some(reply) = self.hello.take() {
+                    let _ = reply.send(Err(message.clone()));
+                } else if let Some(waiter) = self.waiters.pop_front() {
+                    waiter.fail(message.clone());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Fails the requests the dropped connection was answering and holds
+    /// new ones until a reconnect restores the session.
+    fn lost(&mut self, reason: &str) {
+        for waiter in self.waiters.drain(..) {
+            waiter.fail(format!("connection lost: {}", reason));
+        }
+        self.restore.get_or_insert_with(Restore::default);
+        // Stream ids start over on the next connection.
+        self.playback.stream_id = None;
+        self.playback.requested.clear();
+        self.playback.starts.clear();
+    }
+
+    /// Starts over on a new connection: `hello` and `auth` again, then
+    /// `resume_session` with the last resume token, or the track opened
+    /// again if there is no token.
+    fn reconnect(&mut self, attempt: u32) {
+        self.pending_control.clear();
+        self.control_offset = 0;
+        self.control_reader = ControlReader::default();
+        self.control_framing = ControlFraming::Json;
+        self.hello_done = false;
+        self.router = AudioRouter {
+            active_track: self.playback.track_id.clone(),
+            ..AudioRouter::default()
+        };
+        let restore = self.restore.get_or_insert_with(Restore::default);
+        restore.attempt = attempt;
+        restore.resumed = false;
+        restore.expect.clear();
+
+        if let Some(hello) = self.hello_message.clone() {
+            self.write(hello, None);
+            self.expect(Expect::Hello);
+        }
+        if let Some(token) = self.token.clone() {
+            self.write(ClientMessage::Auth { token }, None);
+            self.expect(Expect::Auth);
+        }
+        match (self.resume_token.clone(), self.playback.position()) {
+            (Some(token), Some((_, position_ms))) => {
+                self.write(
+                    ClientMessage::ResumeSession {
+                        token,
+                        position_ms: Some(position_ms),
+                    },
+                    None,
+                );
+                self.expect(Expect::Resume);
+                // The server picks the track from its snapshot.
+                self.playback.request(None, Start::Resume(position_ms));
+            }
+            _ => self.reopen(),
+        }
+    }
+
+    /// Opens the track again with the latest queue and seeks to where the
+    /// delivered audio stopped, for when the server couldn't resume.
+    fn reopen(&mut self) {
+        let Some((track_id, position_ms)) = self.playback.position() else {
+            if let Some(track_ids) = self.playback.queue.clone() {
+                self.write(ClientMessage::Queue { track_ids }, None);
+            }
+            return;
+        };
+        let paused = self.playback.paused;
+        let speed = self.playback.speed();
+        let mut open = self.playback.open.clone().unwrap_or_default();
+        open.track_id = track_id.clone();
+        open.queue = self.playback.queue.clone();
+        if speed != 1.0 {
+            open.speed = Some(speed);
+        }
+        self.write(ClientMessage::Open(open), None);
+        self.expect(Expect::Open);
+        if position_ms > 0 {
+            self.write(
+                ClientMessage::Seek {
+                    track_id: track_id.clone(),
+                    position_ms,
+                    speed: None,
+                },
+                None,
+            );
+        }
+        // Both streams are announced in order, and only the second one
+        // plays.
+        self.playback.requested.clear();
+        if position_ms > 0 {
+            self.playback
+                .request(Some(track_id.clone()), Start::Discard);
+        }
+        self.playback
+            .request(Some(track_id), Start::Resume(position_ms));
+        if paused {
+            self.write(ClientMessage::Pause, None);
+        }
+    }
+
+    fn expect(&mut self, expect: Expect) {
+        if let Some(restore) = self.restore.as_mut() {
+            restore.expect.push_back(expect);
+        }
+    }
+
+    fn answered(&mut self, expect: Expect) {
+        if let Some(restore) = self.restore.as_mut() {
+            if restore.expect.front() == Some(&expect) {
+                restore.expect.pop_front();
+            }
+        }
+    }
+
+    /// Matches `event` against the answers a reconnect waits for. `None`
+    /// if it isn't one; otherwise whether to pass it on. Failed restore
+    /// requests are the client's business, apart from a rejected `hello` or
+    /// `auth`, which ends the connection.
+    fn restore_answer(&mut self, event: &Event) -> Option<bool> {
+        let expect = *self.restore.as_ref()?.expect.front()?;
+        let forward = match (expect, event) {
+            (Expect::Hello | Expect::Auth, Event::Error { message, .. }) => {
+                self.rejected = Some(format!("reconnect rejected: {}", message));
+                false
+            }
+            (Expect::Auth, Event::AuthOk { .. }) => true,
+            (Expect::Resume, Event::SessionResumed { .. }) => {
+                if let Some(restore) = self.restore.as_mut() {
+                    restore.resumed = true;
+                }
+                true
+            }
+            (Expect::Resume, Event::Error { .. }) => {
+                self.reopen();
+                false
+            }
+            (Expect::Open, Event::OpenOk { .. } | Event::Error { .. }) => true,
+            _ => return None,
+        };
+        self.answered(expect);
+        Some(forward)
+    }
+
+    /// Once the session is restored, subscribes again, reports the
+    /// reconnect and sends the requests that were held back.
+    fn finish_restore(&mut self, events: &mpsc::UnboundedSender<Event>) {
+        if !self
+            .restore
+            .as_ref()
+            .is_some_and(|restore| restore.expect.is_empty())
+        {
+            return;
+        }
+        let Some(restore) = self.restore.take() else {
+            return;
+        };
+        if !self.topics.is_empty() {
+            let (since, epoch) = self.since.unzip();
+            self.write(
+                ClientMessage::Subscribe {
+                    topics: self.topics.iter().cloned().collect(),
+                    since,
+                    epoch,
+                },
+                None,
+            );
+        }
+        let _ = events.send(Event::Reconnected {
+            attempt: restore.attempt,
+            resumed: restore.resumed,
+        });
+        for (message, waiter) in restore.deferred {
+            self.write(message, waiter);
+        }
+    }
+
+    fn finish(&mut self, events: &mpsc::UnboundedSender<Event>, reason: String) {
+        if let Some(reply) = self.hello.take() {
+            let _ = reply.send(Err(reason.clone()));
+        }
+        for waiter in self.waiters.drain(..) {
+            waiter.fail(reason.clone());
+        }
+        if let Some(restore) = self.restore.take() {
+            for waiter in restore
+                .deferred
+                .into_iter()
+                .filter_map(|(_, waiter)| waiter)
+            {
+                waiter.fail(reason.clone());
+            }
+        }
+        let _ = events.send(Event::Closed { reason });
+    }
+}
+
+/// How far into its track the delivered audio has got, so a reconnect can
+/// pick up from there.
+#[derive(Default)]
+struct Playback {
+    /// The last `open`, reused when the track has to be opened again.
+    open: Option<Open>,
+    queue: Option<Vec<String>>,
+    paused: bool,
+    track_id: Option<String>,
+    stream_id: Option<u64>,
+    /// Where in the track the stream, or its last reset, started.
+    base_ms: u32,
+    /// Audio delivered since then, and the point in it where the current
+    /// track began.
+    stream_ms: u64,
+    track_start_ms: u64,
+    frame_ms: u32,
+    /// 0 until an `open` or `seek` sets it.
+    speed: f32,
+    /// `(offset_ms, track_id)` from `track_boundary` on the current stream.
+    boundaries: Vec<(u64, String)>,
+    ended: bool,
+    /// Active streams asked for but not announced yet, in request order.
+    /// A track of `None` matches any.
+    requested: VecDeque<(Option<String>, Start)>,
+    /// Announced streams that haven't delivered audio yet.
+    starts: HashMap<u64, Start>,
+}
+
+/// Where a stream the client asked for begins. The server answers `seek`
+/// and `resume_session` with a new stream rather than a reset.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Start {
+    Seek(u32),
+    /// Picks the track up after a reconnect.
+    Resume(u32),
+    /// The `open` of a track reopened mid-way, superseded by its seek.
+    Discard,
+}
+
+impl Playback {
+    fn open(&mut self, open: &Open) {
+        if open.queue.is_some() {
+            self.queue = open.queue.clone();
+        }
+        self.open = Some(open.clone());
+        self.paused = false;
+        self.track_id = Some(open.track_id.clone());
+        self.stream_id = None;
+        self.speed = open.speed.unwrap_or(1.0);
+        self.requested.clear();
+        self.starts.clear();
+        self.restart(0);
+    }
+
+    fn seek(&mut self, track_id: &str, position_ms: u32, speed: Option<f32>) {
+        self.request(Some(track_id.to_string()), Start::Seek(position_ms));
+        if let Some(speed) = speed {
+            self.speed = speed;
+        }
+    }
+
+    fn stop(&mut self) {
+        self.track_id = None;
+        self.stream_id = None;
+        self.paused = false;
+        self.requested.clear();
+    }
+
+    fn request(&mut self, track_id: Option<String>, start: Start) {
+        self.requested.push_back((track_id, start));
+    }
+
+    /// Matches an announced active stream with the request that started
+    /// it. Streams nobody asked for, such as the next track's after
+    /// `advance`, start at 0.
+    fn announced(&mut self, track_id: &str, stream_id: u64) -> Option<Start> {
+        let (track, _) = self.requested.front()?;
+        if track.as_deref().is_some_and(|track| track != track_id) {
+            return None;
+        }
+        let (_, start) = self.requested.pop_front()?;
+        self.starts.insert(stream_id, start);
+        Some(start)
+    }
+
+    fn boundary(&mut self, stream_id: u64, track_id: &str, sample_offset: u64) {
+        if self.stream_id == Some(stream_id) {
+            self.boundaries
+                .push((sample_offset * 1000 / NOTICE_RATE, track_id.to_string()));
+        }
+    }
+
+    /// Counts a frame on its way to the caller.
+    fn deliver(&mut self, frame: &mut AudioFrame) {
+        if self.stream_id != Some(frame.stream_id) {
+            self.stream_id = Some(frame.stream_id);
+            self.track_id = Some(frame.track_id.clone());
+            let start = self.starts.remove(&frame.stream_id);
+            let base_ms = match start {
+                Some(Start::Seek(position_ms) | Start::Resume(position_ms)) => position_ms,
+                _ => 0,
+            };
+            self.restart(base_ms);
+            frame.resumed = matches!(start, Some(Start::Resume(_)));
+            frame.seek = matches!(start, Some(Start::Seek(_)));
+        }
+        match &frame.unit {
+            AudioUnit::Header(header) => {
+                if let Some(frame_ms) = header.get(HEADER_FRAME_MS_OFFSET) {
+                    self.frame_ms = *frame_ms as u32;
+                }
+            }
+            AudioUnit::Packet(_) => {
+                self.stream_ms += self.frame_ms as u64;
+                while let Some(index) = self
+                    .boundaries
+                    .iter()
+                    .position(|(offset, _)| *offset <= self.stream_ms)
+                {
+                    let (offset, track_id) = self.boundaries.remove(index);
+                    self.track_id = Some(track_id);
+                    self.base_ms = 0;
+                    self.track_start_ms = offset;
+                }
+            }
+            AudioUnit::Reset => self.restart(0),
+            AudioUnit::End => self.ended = true,
+        }
+    }
+
+    fn restart(&mut self, base_ms: u32) {
+        self.base_ms = base_ms;
+        self.stream_ms = 0;
+        self.track_start_ms = 0;
+        self.boundaries.clear();
+        self.ended = false;
+    }
+
+    fn speed(&self) -> f32 {
+        if self.speed > 0.0 {
+            self.speed
+        } else {
+            1.0
+        }
+    }
+
+    /// The track and position to pick up from, unless nothing is playing.
+    fn position(&self) -> Option<(String, u32)> {
+        if self.ended {
+            return None;
+        }
+        let track_id = self.track_id.clone()?;
+        let played =
+            self.stream_ms.saturating_sub(self.track_start_ms) as f64 * self.speed() as f64;
+        Some((track_id, self.base_ms.saturating_add(played as u32)))
+    }
+}
+
+#[derive(Default)]
+struct Buffered {
+    units: VecDeque<AudioUnit>,
+    bytes: usize,
+}
+
+impl Buffered {
+    fn push(&mut self, unit: AudioUnit) {
+        if self.bytes > MAX_PREFETCH_BYTES {
+            return;
+        }
+        self.bytes += match &unit {
+            AudioUnit::Header(data) | AudioUnit::Packet(data) => data.len(),
+            AudioUnit::End | AudioUnit::Reset => 0,
+        };
+        self.units.push_back(unit);
+    }
+}
+
+/// Passes the active track's audio through and holds on to the rest until
+/// that track is opened.
+#[derive(Default)]
+struct AudioRouter {
+    framing: AudioFraming,
+    readers: HashMap<u64, AudioReader>,
+    active_track: Option<String>,
+    active_stream: Option<u64>,
+    track_streams: HashMap<String, u64>,
+    prefetch: HashMap<String, Buffered>,
+    /// Audio on streams the control stream hasn't announced yet.
+    pending: HashMap<u64, Buffered>,
+    /// Streams whose audio is thrown away.
+    discarded: HashSet<u64>,
+}
+
+impl AudioRouter {
+    fn open(&mut self, track_id: &str) {
+        self.active_track = Some(track_id.to_string());
+        self.active_stream = None;
+        self.track_streams.clear();
+        self.prefetch.clear();
+        self.pending.clear();
+        self.discarded.clear();
+    }
+
+    fn discard(&mut self, stream_id: u64) {
+        self.discarded.insert(stream_id);
+        self.pending.remove(&stream_id);
+    }
+
+    /// Makes `track_id` active without dropping what is buffered, for the
+    /// track a resumed session continues with.
+    fn activate(&mut self, track_id: &str, out: &mut Vec<AudioFrame>) {
+        self.active_track = Some(track_id.to_string());
+        self.active_stream = None;
+        if let Some(stream_id) = self.track_streams.get(track_id).copied() {
+            self.announce(track_id, stream_id, out);
+        }
+    }
+
+    fn announce(&mut self, track_id: &str, stream_id: u64, out: &mut Vec<AudioFrame>) {
+        if self.discarded.contains(&stream_id) {
+            return;
+        }
+        self.track_streams.insert(track_id.to_string(), stream_id);
+        let active = self.active_track.as_deref() == Some(track_id);
+        if active {
+            self.active_stream = Some(stream_id);
+            if let Some(buffered) = self.prefetch.remove(track_id) {
+                out.extend(buffered.units.into_iter().map(|unit| AudioFrame {
+                    track_id: track_id.to_string(),
+                    stream_id,
+                    unit,
+                    resumed: false,
+                    seek: false,
+                }));
+            }
+        }
+        let Some(buffered) = self.pending.remove(&stream_id) else {
+            return;
+        };
+        if active {
+            out.extend(buffered.units.into_iter().map(|unit| AudioFrame {
+                track_id: track_id.to_string(),
+                stream_id,
+                unit,
+                resumed: false,
+                seek: false,
+            }));
+        } else {
+            let target = self.prefetch.entry(track_id.to_string()).or_default();
+            for unit in buffered.units {
+                target.push(unit);
+            }
+        }
+    }
+
+    fn push(
+        &mut self,
+        stream_id: u64,
+        data: &[u8],
+        out: &mut Vec<AudioFrame>,
+    ) -> Result<(), String> {
+        if self.discarded.contains(&stream_id) {
+            return Ok(());
+        }
+        let framing = self.framing;
+        let units = self
+            .readers
+            .entry(stream_id)
+            .or_insert_with(|| AudioReader::new(framing))
+            .push(data)?;
+        let track_id = self
+            .track_streams
+            .iter()
+            .find_map(|(track, id)| (*id == stream_id).then(|| track.clone()));
+        for unit in units {
+            match &track_id {
+                Some(track_id) if Some(stream_id) == self.active_stream => out.push(AudioFrame {
+                    track_id: track_id.clone(),
+                    stream_id,
+                    unit,
+                    resumed: false,
+                    seek: false,
+                }),
+                Some(track_id) => self
+                    .prefetch
+                    .entry(track_id.clone())
+                    .or_default()
+                    .push(unit),
+                None => self.pending.entry(stream_id).or_default().push(unit),
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, stream_id: u64) {
+        self.readers.remove(&stream_id);
+    }
+}
+
+async fn flush_conn(
+    conn: &mut quiche::Connection,
+    socket: &UdpSocket,
+    out: &mut [u8],
+    events: &mpsc::UnboundedSender<Event>,
+) {
+    loop {
+        match conn.send(out) {
+            Ok((len, send_info)) => {
+                if let Err(err) = socket.send_to(&out[..len], send_info.to).await {
+                    let _ = events.send(Event::TransportError(format!("udp send error: {}", err)));
+                    break;
+                }
+            }
+            Err(quiche::Error::Done) => break,
+            Err(err) => {
+                let _ = events.send(Event::TransportError(format!("QUIC send error: {:?}", err)));
+                break;
+            }
+        }
+    }
+}
+
+fn connection_close_detail(conn: &quiche::Connection) -> Option<String> {
+    let mut parts = Vec::new();
+    if conn.is_timed_out() {
+        parts.push("timed_out=true".to_string());
+    }
+    if let Some(err) = conn.peer_error() {
+        parts.push(format!(
+            "peer_error(code={}, app={}, reason={})",
+            err.error_code,
+            err.is_app,
+            String::from_utf8_lossy(&err.reason)
+        ));
+    }
+    if let Some(err) = conn.local_error() {
+        parts.push(format!(
+            "local_error(code={}, app={}, reason={})",
+            err.error_code,
+            err.is_app,
+            String::from_utf8_lossy(&err.reason)
+        ));
+    }
+    if parts.is_empty() {
+        None
+    } else {
+        Some(format!("QUIC connection closed: {}", parts.join(", ")))
+    }
+}
+
+fn stats_line(conn: &quiche::Connection) -> String {
+    let stats = conn.stats();
+    let path = conn.path_stats().next();
+    format!(
+        "QUIC client stats: sent_pkts={} recv_pkts={} sent_bytes={} recv_bytes={} established={} timed_out={} path={:?}",
+        stats.sent,
+        stats.recv,
+        stats.sent_bytes,
+        stats.recv_bytes,
+        conn.is_established(),
+        conn.is_timed_out(),
+        path,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> Vec<u8> {
+        let mut header = b"OPUSR01\0\x01\0".to_vec();
+        header.extend_from_slice(&12u16.to_le_bytes());
+        header
+    }
+
+    fn sent(session: &mut Session) -> Vec<Value> {
+        session
+            .pending_control
+            .drain(..)
+            .flat_map(|payload| ControlReader::default().push::<Value>(&payload))
+            .collect()
+    }
+
+    fn types(messages: &[Value]) -> Vec<&str> {
+        messages
+            .iter()
+            .filter_map(|message| message["type"].as_str())
+            .collect()
+    }
+
+    fn wire(units: &[AudioUnit]) -> Vec<u8> {
+        units
+            .iter()
+            .flat_map(|unit| unit.encode(AudioFraming::Binary).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn holds_prefetched_audio_until_its_track_is_opened() {
+        let mut router = AudioRouter {
+            framing: AudioFraming::Binary,
+            ..AudioRouter::default()
+        };
+        let mut out = Vec::new();
+        router.open("a");
+        // Audio for the next track arrives before its stream is announced.
+        router
+            .push(7, &wire(&[AudioUnit::Header(header())]), &mut out)
+            .unwrap();
+        router.announce("b", 7, &mut out);
+        router.announce("a", 3, &mut out);
+        router
+            .push(
+                3,
+                &wire(&[AudioUnit::Header(header()), AudioUnit::End]),
+                &mut out,
+            )
+            .unwrap();
+        router
+            .push(7, &wire(&[AudioUnit::Packet(vec![1, 2])]), &mut out)
+            .unwrap();
+        assert_eq!(out.len(), 2);
+        assert!(out.iter().all(|frame| frame.track_id == "a"));
+
+        out.clear();
+        router.active_track = Some("b".to_string());
+        router.announce("b", 7, &mut out);
+        let units: Vec<AudioUnit> = out.into_iter().map(|frame| frame.unit).collect();
+        assert_eq!(
+            units,
+            vec![AudioUnit::Header(header()), AudioUnit::Packet(vec![1, 2])]
+        );
+    }
+
+    fn control(
+        session: &mut Session,
+        events: &mpsc::UnboundedSender<Event>,
+        lines: &str,
+    ) -> Vec<AudioFrame> {
+        let mut frames = Vec::new();
+        session.handle_control(lines.as_bytes(), &mut frames, events);
+        for frame in &mut frames {
+            session.playback.deliver(frame);
+        }
+        frames
+    }
+
+    fn receive(session: &mut Session, stream_id: u64, units: &[AudioUnit]) -> Vec<AudioFrame> {
+        let mut frames = Vec::new();
+        session
+            .router
+            .push(stream_id, &wire(units), &mut frames)
+            .unwrap();
+        for frame in &mut frames {
+            session.playback.deliver(frame);
+        }
+        frames
+    }
+
+    /// A stream header followed by `packets` 20 ms packets.
+    fn audio(packets: usize) -> Vec<AudioUnit> {
+        let mut header = header();
+        header.resize(HEADER_FRAME_MS_OFFSET + 1, 0);
+        header[HEADER_FRAME_MS_OFFSET] = 20;
+        let mut units = vec![AudioUnit::Header(header)];
+        units.extend(vec![AudioUnit::Packet(vec![0]); packets]);
+        units
+    }
+
+    #[test]
+    fn tracks_the_position_onto_the_stream_a_seek_starts() {
+        let (events, _events_rx) = mpsc::unbounded_channel();
+        let mut session = Session::new(None);
+        session.router.framing = AudioFraming::Binary;
+        session.write(ClientMessage::Open(Open::new("a")), None);
+        control(
+            &mut session,
+            &events,
+            "{\"type\":\"stream\",\"track_id\":\"a\",\"stream_id\":3,\"role\":\"active\"}\n",
+        );
+        receive(&mut session, 3, &audio(10));
+        assert_eq!(session.playback.position(), Some(("a".to_string(), 200)));
+
+        session.write(
+            ClientMessage::Seek {
+                track_id: "a".to_string(),
+                position_ms: 60_000,
+                speed: None,
+            },
+            None,
+        );
+        // Audio already on its way still counts from the old position.
+        receive(&mut session, 3, &[AudioUnit::Packet(vec![0])]);
+        assert_eq!(session.playback.position(), Some(("a".to_string(), 220)));
+        control(
+            &mut session,
+            &events,
+            "{\"type\":\"stream\",\"track_id\":\"a\",\"stream_id\":7,\"role\":\"active\"}\n",
+        );
+        assert!(receive(&mut session, 3, &[AudioUnit::Packet(vec![0])]).is_empty());
+        assert_eq!(receive(&mut session, 7, &audio(5)).len(), 6);
+        assert_eq!(session.playback.position(), Some(("a".to_string(), 60_100)));
+    }
+
+    #[test]
+    fn restores_the_session_where_the_delivered_audio_stopped() {
+        let (events, mut events_rx) = mpsc::unbounded_channel();
+        let mut session = Session::new(None);
+        session.router.framing = AudioFraming::Binary;
+        session.write(
+            ClientMessage::Hello {
+                protocol: PROTOCOL_VERSION,
+                client: "test".to_string(),
+                capabilities: Vec::new(),
+            },
+            None,
+        );
+        session.write(
+            ClientMessage::Auth {
+                token: "token".to_string(),
+            },
+            None,
+        );
+        control(
+            &mut session,
+            &events,
+            "{\"type\":\"auth_ok\",\"resume_token\":\"first\"}\n",
+        );
+        session.write(
+            ClientMessage::Open(Open {
+                queue: Some(vec!["a".to_string(), "b".to_string()]),
+                ..Open::new("a")
+            }),
+            None,
+        );
+        control(
+            &mut session,
+            &events,
+            "{\"type\":\"stream\",\"track_id\":\"a\",\"stream_id\":3,\"role\":\"active\"}\n",
+        );
+        sent(&mut session);
+        receive(&mut session, 3, &audio(10));
+        // Gapless: "b" starts 400 ms into the stream.
+        session.note(
+            &Event::TrackBoundary {
+                stream_id: 3,
+                track_id: "b".to_string(),
+                sample_offset: 19_200,
+                crossfade_samples: 0,
+            },
+            &mut Vec::new(),
+        );
+        receive(&mut session, 3, &vec![AudioUnit::Packet(vec![0]); 15]);
+        assert_eq!(session.playback.position(), Some(("b".to_string(), 100)));
+
+        session.lost("timed out");
+        session.send(ClientMessage::Pause, None);
+        session.reconnect(1);
+        let restore = sent(&mut session);
+        assert_eq!(types(&restore), vec!["hello", "auth", "resume_session"]);
+        assert_eq!(restore[2]["token"], "first");
+        assert_eq!(restore[2]["position_ms"], 100);
+
+        // The token expired, so the track is opened again.
+        control(
+            &mut session,
+            &events,
+            concat!(
+                "{\"type\":\"hello_ok\",\"protocol\":2,\"capabilities\":[\"binary_framing\"]}\n",
+                "{\"type\":\"auth_ok\",\"resume_token\":\"second\"}\n",
+                "{\"type\":\"error\",\"code\":\"resume_failed\",\"message\":\"expired\"}\n",
+            ),
+        );
+        let reopen = sent(&mut session);
+        assert_eq!(types(&reopen), vec!["open", "seek"]);
+        assert_eq!(reopen[0]["track_id"], "b");
+        assert_eq!(reopen[0]["queue"], serde_json::json!(["a", "b"]));
+        assert_eq!(reopen[1]["position_ms"], 100);
+        // Stream ids start over on the new connection.
+        control(
+            &mut session,
+            &events,
+            concat!(
+                "{\"type\":\"stream\",\"track_id\":\"b\",\"stream_id\":3,\"role\":\"active\"}\n",
+                "{\"type\":\"open_ok\",\"track_id\":\"b\"}\n",
+                "{\"type\":\"stream\",\"track_id\":\"b\",\"stream_id\":7,\"role\":\"active\"}\n",
+            ),
+        );
+        // The pause made while disconnected goes out after the restore.
+        assert_eq!(types(&sent(&mut session)), vec!["pause"]);
+        let mut seen = Vec::new();
+        while let Ok(event) = events_rx.try_recv() {
+            seen.push(event);
+        }
+        assert!(matches!(
+            seen.as_slice(),
+            [
+                Event::AuthOk { .. },
+                Event::AuthOk { .. },
+                Event::OpenOk { .. },
+                Event::Reconnected {
+                    attempt: 1,
+                    resumed: false
+                },
+            ]
+        ));
+
+        // The open's stream starts at 0 and is dropped; the seek's carries
+        // on from where the audio stopped.
+        assert!(receive(&mut session, 3, &audio(1)).is_empty());
+        let delivered = receive(&mut session, 7, &audio(1));
+        assert_eq!(delivered.len(), 2);
+        assert!(delivered[0].resumed && !delivered[1].resumed);
+        assert_eq!(session.playback.position(), Some(("b".to_string(), 120)));
+    }
+}
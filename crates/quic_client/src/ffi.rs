@@ -0,0 +1,696 @@
+//! The C ABI. Each handle runs the async client on its own thread and
+//! hands audio back in the legacy stream layout: the `OPUSR01` header,
+//! then each packet behind a little-endian u16 length, with an empty read
+//! (return value 0) when the connection ends or the server reports an
+//! error.
+//!
+//! With the `pcm` feature, handles from `phonolite_quic_connect_pcm` decode
+//! instead, and `phonolite_quic_read_pcm` returns interleaved 16-bit
+//! samples. Its `PhonolitePcmInfo` describes the samples just read; `flags`
+//! is set on the first read after a change:
+//!
+//! - `1` a new track starts with these samples
+//! - `2` a seek reset the stream; drop buffered audio
+//! - `4` the stream ended (with 0 samples)
+//!
+//! A read of 0 samples without flags means the connection ended or the
+//! server reported an error, as with `phonolite_quic_read`.
+//!
+//! A dropped connection is replaced as described on `Reconnect`, which
+//! `phonolite_quic_set_reconnect` adjusts. The PCM output just carries on;
+//! the byte layout sees a seek marker and a new header, as after a seek.
+//! `phonolite_quic_poll_stats` reports each attempt and its outcome as
+//! `QUIC reconnecting ...` and `QUIC reconnected ...` lines, ahead of the
+//! periodic statistics.
+
+use std::collections::VecDeque;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use quic_wire::{AudioFraming, AudioUnit};
+use tokio::sync::mpsc as async_mpsc;
+
+use crate::client::{connect, AudioFrame, ConnectOptions, Event, Open, Reconnect};
+use crate::connection::ClientMessage;
+#[cfg(feature = "pcm")]
+use crate::pcm::{Pcm, PcmDecoder};
+
+/// Reconnect notices waiting for `phonolite_quic_poll_stats` beyond this
+/// many are dropped, oldest first.
+const MAX_STATS_NOTICES: usize = 32;
+
+#[cfg(feature = "pcm")]
+pub const PHONOLITE_PCM_TRACK_START: u32 = 1;
+#[cfg(feature = "pcm")]
+pub const PHONOLITE_PCM_RESET: u32 = 2;
+#[cfg(feature = "pcm")]
+pub const PHONOLITE_PCM_END: u32 = 4;
+
+#[repr(C)]
+pub struct QuicHandle {
+    inner: Arc<ClientHandle>,
+}
+
+struct ClientHandle {
+    tx: async_mpsc::UnboundedSender<ControlCommand>,
+    rx: Mutex<mpsc::Receiver<Vec<u8>>>,
+    #[cfg(feature = "pcm")]
+    pcm: Mutex<PcmReader>,
+    last_error: Arc<Mutex<Option<String>>>,
+    last_stats: Arc<Mutex<StatsLines>>,
+}
+
+/// Lines for `phonolite_quic_poll_stats`. Notices queue up so none is
+/// missed; of the periodic statistics only the latest is kept.
+#[derive(Default)]
+struct StatsLines {
+    notices: VecDeque<String>,
+    latest: Option<String>,
+}
+
+#[cfg(feature = "pcm")]
+#[repr(C)]
+pub struct PhonolitePcmInfo {
+    pub sample_rate: u32,
+    pub channels: u32,
+    pub flags: u32,
+}
+
+/// Decoded audio on its way to `phonolite_quic_read_pcm`. No samples and
+/// no flags marks the end of the connection.
+#[cfg(feature = "pcm")]
+struct PcmChunk {
+    track_id: String,
+    sample_rate: u32,
+    channels: u8,
+    flags: u32,
+    samples: Vec<i16>,
+}
+
+#[cfg(feature = "pcm")]
+struct PcmReader {
+    rx: mpsc::Receiver<PcmChunk>,
+    /// A chunk larger than the caller's buffer, and how much of it was read.
+    current: Option<(PcmChunk, usize)>,
+    track_id: String,
+}
+
+/// Where a handle's audio goes.
+enum Output {
+    Bytes(mpsc::Sender<Vec<u8>>),
+    #[cfg(feature = "pcm")]
+    Pcm {
+        tx: mpsc::Sender<PcmChunk>,
+        decoder: PcmDecoder,
+        /// Flags for the next chunk.
+        flags: u32,
+    },
+}
+
+enum ControlCommand {
+    Open(Open),
+    Buffer {
+        buffer_ms: u32,
+        target_ms: Option<u32>,
+    },
+    Advance,
+    Reconnect(Option<Reconnect>),
+    Close,
+}
+
+#[no_mangle]
+pub extern "C" fn phonolite_quic_connect(
+    host: *const c_char,
+    port: u16,
+    token: *const c_char,
+) -> *mut QuicHandle {
+    let host = unsafe { cstr_to_string(host) };
+    let token = unsafe { cstr_to_string(token) };
+    start_client(host, port, token, false)
+}
+
+/// Like `phonolite_quic_connect`, but audio is read with
+/// `phonolite_quic_read_pcm`.
+#[cfg(feature = "pcm")]
+#[no_mangle]
+pub extern "C" fn phonolite_quic_connect_pcm(
+    host: *const c_char,
+    port: u16,
+    token: *const c_char,
+) -> *mut QuicHandle {
+    let host = unsafe { cstr_to_string(host) };
+    let token = unsafe { cstr_to_string(token) };
+    start_client(host, port, token, true)
+}
+
+fn start_client(host: String, port: u16, token: String, pcm: bool) -> *mut QuicHandle {
+    if host.is_empty() || token.is_empty() {
+        return std::ptr::null_mut();
+    }
+    let mut options = ConnectOptions::new(host, port);
+    options.token = Some(token);
+
+    let (tx_cmd, rx_cmd) = async_mpsc::unbounded_channel::<ControlCommand>();
+    let (tx_bytes, rx_bytes) = mpsc::channel::<Vec<u8>>();
+    #[cfg(feature = "pcm")]
+    let (tx_pcm, rx_pcm) = mpsc::channel::<PcmChunk>();
+    let last_error = Arc::new(Mutex::new(None));
+    let last_stats = Arc::new(Mutex::new(StatsLines::default()));
+    let handle = Arc::new(ClientHandle {
+        tx: tx_cmd,
+        rx: Mutex::new(rx_bytes),
+        #[cfg(feature = "pcm")]
+        pcm: Mutex::new(PcmReader {
+            rx: rx_pcm,
+            current: None,
+            track_id: String::new(),
+        }),
+        last_error: Arc::clone(&last_error),
+        last_stats: Arc::clone(&last_stats),
+    });
+
+    // The unused channel's sender is dropped, so reading it gives -4.
+    let output = match pcm {
+        #[cfg(feature = "pcm")]
+        true => {
+            // Gapless notices carry the trim and track boundary positions.
+            options.capabilities.push("gapless".to_string());
+            Output::Pcm {
+                tx: tx_pcm,
+                decoder: PcmDecoder::new(),
+                flags: 0,
+            }
+        }
+        _ => Output::Bytes(tx_bytes),
+    };
+
+    thread::spawn(move || {
+        let runtime = match tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+        {
+            Ok(runtime) => runtime,
+            Err(err) => {
+                set_last_error(&last_error, format!("runtime error: {}", err));
+                output.end();
+                return;
+            }
+        };
+        runtime.block_on(run_client(options, rx_cmd, output, last_error, last_stats));
+    });
+
+    let boxed = Box::new(QuicHandle { inner: handle });
+    Box::into_raw(boxed)
+}
+
+#[no_mangle]
+pub extern "C" fn phonolite_quic_open_track(
+    handle: *mut QuicHandle,
+    track_id: *const c_char,
+    mode: *const c_char,
+    quality: *const c_char,
+    frame_ms: u32,
+    queue_json: *const c_char,
+) -> c_int {
+    let Some(handle) = (unsafe { handle.as_ref() }) else {
+        return -1;
+    };
+    let track_id = unsafe { cstr_to_string(track_id) };
+    if track_id.is_empty() {
+        return -2;
+    }
+    let mode = unsafe { cstr_to_optional_string(mode) };
+    let quality = unsafe { cstr_to_optional_string(quality) };
+    let queue = unsafe { cstr_to_optional_string(queue_json) }
+        .and_then(|raw| serde_json::from_str::<Vec<String>>(&raw).ok())
+        .filter(|queue| !queue.is_empty());
+
+    let open = Open {
+        mode,
+        quality,
+        frame_ms: Some(frame_ms),
+        queue,
+        ..Open::new(track_id)
+    };
+    if handle.inner.tx.send(ControlCommand::Open(open)).is_err() {
+        return -3;
+    }
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn phonolite_quic_send_buffer(
+    handle: *mut QuicHandle,
+    buffer_ms: u32,
+    target_ms: u32,
+) -> c_int {
+    let Some(handle) = (unsafe { handle.as_ref() }) else {
+        return -1;
+    };
+    let target = if target_ms == 0 {
+        None
+    } else {
+        Some(target_ms)
+    };
+    if handle
+        .inner
+        .tx
+        .send(ControlCommand::Buffer {
+            buffer_ms,
+            target_ms: target,
+        })
+        .is_err()
+    {
+        return -2;
+    }
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn phonolite_quic_advance(handle: *mut QuicHandle) -> c_int {
+    let Some(handle) = (unsafe { handle.as_ref() }) else {
+        return -1;
+    };
+    if handle.inner.tx.send(ControlCommand::Advance).is_err() {
+        return -2;
+    }
+    0
+}
+
+/// Sets how many times, and how patiently, a dropped connection is
+/// replaced. `max_attempts` 0 turns reconnecting off; 0 delays keep the
+/// defaults.
+#[no_mangle]
+pub extern "C" fn phonolite_quic_set_reconnect(
+    handle: *mut QuicHandle,
+    max_attempts: u32,
+    initial_delay_ms: u32,
+    max_delay_ms: u32,
+) -> c_int {
+    let Some(handle) = (unsafe { handle.as_ref() }) else {
+        return -1;
+    };
+    let reconnect = (max_attempts > 0).then(|| {
+        let defaults = Reconnect::default();
+        let delay = |ms: u32, default| {
+            if ms == 0 {
+                default
+            } else {
+                Duration::from_millis(ms as u64)
+            }
+        };
+        Reconnect {
+            attempts: max_attempts,
+            initial_delay: delay(initial_delay_ms, defaults.initial_delay),
+            max_delay: delay(max_delay_ms, defaults.max_delay),
+        }
+    });
+    if handle
+        .inner
+        .tx
+        .send(ControlCommand::Reconnect(reconnect))
+        .is_err()
+    {
+        return -2;
+    }
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn phonolite_quic_read(
+    handle: *mut QuicHandle,
+    buffer: *mut u8,
+    buffer_len: usize,
+) -> c_int {
+    let Some(handle) = (unsafe { handle.as_ref() }) else {
+        return -1;
+    };
+    if buffer.is_null() || buffer_len == 0 {
+        return -2;
+    }
+    let rx = match handle.inner.rx.lock() {
+        Ok(value) => value,
+        Err(_) => return -3,
+    };
+    match rx.try_recv() {
+        Ok(chunk) => {
+            if chunk.is_empty() {
+                return 0;
+            }
+            let len = chunk.len().min(buffer_len);
+            unsafe {
+                std::ptr::copy_nonoverlapping(chunk.as_ptr(), buffer, len);
+            }
+            len as c_int
+        }
+        Err(mpsc::TryRecvError::Empty) => -5,
+        Err(mpsc::TryRecvError::Disconnected) => -4,
+    }
+}
+
+/// Reads decoded audio into `buffer`, which holds `buffer_len` samples.
+/// Returns the number of samples read, whole frames only, or the negative
+/// codes of `phonolite_quic_read`. `info` may be null.
+#[cfg(feature = "pcm")]
+#[no_mangle]
+pub extern "C" fn phonolite_quic_read_pcm(
+    handle: *mut QuicHandle,
+    buffer: *mut i16,
+    buffer_len: usize,
+    info: *mut PhonolitePcmInfo,
+) -> c_int {
+    let Some(handle) = (unsafe { handle.as_ref() }) else {
+        return -1;
+    };
+    if buffer.is_null() || buffer_len == 0 {
+        return -2;
+    }
+    let mut reader = match handle.inner.pcm.lock() {
+        Ok(value) => value,
+        Err(_) => return -3,
+    };
+    let reader = &mut *reader;
+    let (chunk, offset) = match reader.current.take() {
+        Some(current) => current,
+        None => match reader.rx.try_recv() {
+            Ok(chunk) => (chunk, 0),
+            Err(mpsc::TryRecvError::Empty) => return -5,
+            Err(mpsc::TryRecvError::Disconnected) => return -4,
+        },
+    };
+    if let Some(info) = unsafe { info.as_mut() } {
+        info.sample_rate = chunk.sample_rate;
+        info.channels = chunk.channels as u32;
+        info.flags = if offset == 0 { chunk.flags } else { 0 };
+    }
+    // End markers carry no track.
+    if !chunk.track_id.is_empty() && chunk.track_id != reader.track_id {
+        reader.track_id = chunk.track_id.clone();
+    }
+    let channels = (chunk.channels as usize).max(1);
+    let len = (chunk.samples.len() - offset).min(buffer_len / channels * channels);
+    if len == 0 && !chunk.samples.is_empty() {
+        // Too small for one frame; keep the chunk for a bigger buffer.
+        reader.current = Some((chunk, offset));
+        return -2;
+    }
+    unsafe {
+        std::ptr::copy_nonoverlapping(chunk.samples[offset..].as_ptr(), buffer, len);
+    }
+    if offset + len < chunk.samples.len() {
+        reader.current = Some((chunk, offset + len));
+    }
+    len as c_int
+}
+
+/// The track of the samples last returned by `phonolite_quic_read_pcm`.
+/// Free the result with `phonolite_quic_free_string`.
+#[cfg(feature = "pcm")]
+#[no_mangle]
+pub extern "C" fn phonolite_quic_pcm_track_id(handle: *mut QuicHandle) -> *mut c_char {
+    let Some(handle) = (unsafe { handle.as_ref() }) else {
+        return std::ptr::null_mut();
+    };
+    let track_id = match handle.inner.pcm.lock() {
+        Ok(reader) => reader.track_id.clone(),
+        Err(_) => String::new(),
+    };
+    let cstring = CString::new(track_id).unwrap_or_else(|_| CString::new("").unwrap());
+    cstring.into_raw()
+}
+
+#[no_mangle]
+pub extern "C" fn phonolite_quic_last_error(handle: *mut QuicHandle) -> *mut c_char {
+    let Some(handle) = (unsafe { handle.as_ref() }) else {
+        return std::ptr::null_mut();
+    };
+    let msg = match handle.inner.last_error.lock() {
+        Ok(value) => value.clone().unwrap_or_default(),
+        Err(_) => String::new(),
+    };
+    let cstring = CString::new(msg).unwrap_or_else(|_| CString::new("").unwrap());
+    cstring.into_raw()
+}
+
+#[no_mangle]
+pub extern "C" fn phonolite_quic_poll_stats(handle: *mut QuicHandle) -> *mut c_char {
+    let Some(handle) = (unsafe { handle.as_ref() }) else {
+        return std::ptr::null_mut();
+    };
+    let msg = match handle.inner.last_stats.lock() {
+        Ok(mut lines) => {
+            let lines = &mut *lines;
+            lines
+                .notices
+                .pop_front()
+                .or_else(|| lines.latest.take())
+                .unwrap_or_default()
+        }
+        Err(_) => String::new(),
+    };
+    if msg.is_empty() {
+        return std::ptr::null_mut();
+    }
+    let cstring = CString::new(msg).unwrap_or_else(|_| CString::new("").unwrap());
+    cstring.into_raw()
+}
+
+#[no_mangle]
+pub extern "C" fn phonolite_quic_free_string(ptr: *mut c_char) {
+    if ptr.is_null() {
+        return;
+    }
+    unsafe {
+        let _ = CString::from_raw(ptr);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn phonolite_quic_close(handle: *mut QuicHandle) {
+    if handle.is_null() {
+        return;
+    }
+    unsafe {
+        let boxed = Box::from_raw(handle);
+        let _ = boxed.inner.tx.send(ControlCommand::Close);
+    }
+}
+
+unsafe fn cstr_to_string(ptr: *const c_char) -> String {
+    if ptr.is_null() {
+        return String::new();
+    }
+    CStr::from_ptr(ptr).to_string_lossy().trim().to_string()
+}
+
+unsafe fn cstr_to_optional_string(ptr: *const c_char) -> Option<String> {
+    if ptr.is_null() {
+        return None;
+    }
+    let value = CStr::from_ptr(ptr).to_string_lossy().trim().to_string();
+    if value.is_empty() {
+        None
+    } else {
+        Some(value)
+    }
+}
+
+/// Forwards C calls to the client, and audio and errors back, until the
+/// connection ends or the handle is closed.
+async fn run_client(
+    options: ConnectOptions,
+    mut rx_cmd: async_mpsc::UnboundedReceiver<ControlCommand>,
+    mut output: Output,
+    last_error: Arc<Mutex<Option<String>>>,
+    last_stats: Arc<Mutex<StatsLines>>,
+) {
+    let mut connection = match connect(options).await {
+        Ok(connection) => connection,
+        Err(err) => {
+            set_last_error_if_empty(&last_error, err);
+            output.end();
+            return;
+        }
+    };
+    let client = connection.client.clone();
+    loop {
+        tokio::select! {
+            command = rx_cmd.recv() => {
+                let sent = match command {
+                    Some(ControlCommand::Open(open)) => client.send(ClientMessage::Open(open)),
+                    Some(ControlCommand::Buffer { buffer_ms, target_ms }) => {
+                        client.buffer(buffer_ms, target_ms)
+                    }
+                    Some(ControlCommand::Advance) => client.advance(),
+                    Some(ControlCommand::Reconnect(reconnect)) => client.set_reconnect(reconnect),
+                    Some(ControlCommand::Close) | None => {
+                        client.close();
+                        output.end();
+                        // Dropping the runtime early would cut off the
+                        // CONNECTION_CLOSE frame.
+                        while let Some(event) = connection.events.recv().await {
+                            if matches!(event, Event::Closed { .. }) {
+                                break;
+                            }
+                        }
+                        return;
+                    }
+                };
+                if let Err(err) = sent {
+                    set_last_error_if_empty(&last_error, err);
+                }
+            }
+            Some(frame) = connection.audio.recv() => {
+                if let Err(err) = output.audio(frame) {
+                    set_last_error_if_empty(&last_error, err);
+                }
+            }
+            event = connection.events.recv() => match event {
+                Some(Event::Error { message, .. }) => {
+                    set_last_error_if_empty(&last_error, message);
+                    output.end();
+                }
+                Some(Event::TransportError(message)) => set_last_error_if_empty(&last_error, message),
+                Some(Event::Stats(line)) => {
+                    if let Ok(mut lines) = last_stats.lock() {
+                        lines.latest = Some(line);
+                    }
+                }
+                Some(Event::Reconnecting { attempt, delay_ms, reason }) => push_notice(
+                    &last_stats,
+                    format!(
+                        "QUIC reconnecting attempt={} delay_ms={} reason={}",
+                        attempt, delay_ms, reason
+                    ),
+                ),
+                Some(Event::Reconnected { attempt, resumed }) => push_notice(
+                    &last_stats,
+                    format!("QUIC reconnected attempt={} resumed={}", attempt, resumed),
+                ),
+                Some(Event::Closed { reason }) => {
+                    // Audio sent before the close is still queued.
+                    while let Ok(frame) = connection.audio.try_recv() {
+                        if let Err(err) = output.audio(frame) {
+                            set_last_error_if_empty(&last_error, err);
+                        }
+                    }
+                    set_last_error(&last_error, reason);
+                    output.end();
+                    return;
+                }
+                Some(event) => output.event(&event),
+                None => return,
+            },
+        }
+    }
+}
+
+impl Output {
+    fn audio(&mut self, frame: AudioFrame) -> Result<(), String> {
+        match self {
+            Output::Bytes(tx) => {
+                if frame.resumed || frame.seek {
+                    // Tells the reader a header follows.
+                    let _ = tx.send(AudioUnit::Reset.encode(AudioFraming::Legacy)?);
+                }
+                let _ = tx.send(frame.unit.encode(AudioFraming::Legacy)?);
+            }
+            #[cfg(feature = "pcm")]
+            Output::Pcm { decoder, .. } => {
+                let pcm = decoder.push_audio(&frame)?;
+                self.send_pcm(pcm);
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg_attr(not(feature = "pcm"), allow(unused_variables))]
+    fn event(&mut self, event: &Event) {
+        #[cfg(feature = "pcm")]
+        if let Output::Pcm { decoder, .. } = self {
+            let pcm = decoder.push_event(event);
+            self.send_pcm(pcm);
+        }
+    }
+
+    /// Makes the next read return 0.
+    fn end(&self) {
+        match self {
+            Output::Bytes(tx) => {
+                let _ = tx.send(Vec::new());
+            }
+            #[cfg(feature = "pcm")]
+            Output::Pcm { tx, .. } => {
+                let _ = tx.send(PcmChunk {
+                    track_id: String::new(),
+                    sample_rate: 0,
+                    channels: 0,
+                    flags: 0,
+                    samples: Vec::new(),
+                });
+            }
+        }
+    }
+
+    #[cfg(feature = "pcm")]
+    fn send_pcm(&mut self, pcm: Vec<Pcm>) {
+        let Output::Pcm { tx, flags, .. } = self else {
+            return;
+        };
+        for item in pcm {
+            match item {
+                Pcm::Samples {
+                    track_id,
+                    sample_rate,
+                    channels,
+                    samples,
+                } => {
+                    let _ = tx.send(PcmChunk {
+                        track_id,
+                        sample_rate,
+                        channels,
+                        flags: std::mem::take(flags),
+                        samples,
+                    });
+                }
+                Pcm::TrackStart { .. } => *flags |= PHONOLITE_PCM_TRACK_START,
+                Pcm::Reset => *flags |= PHONOLITE_PCM_RESET,
+                Pcm::End => {
+                    let _ = tx.send(PcmChunk {
+                        track_id: String::new(),
+                        sample_rate: 0,
+                        channels: 0,
+                        flags: std::mem::take(flags) | PHONOLITE_PCM_END,
+                        samples: Vec::new(),
+                    });
+                }
+            }
+        }
+    }
+}
+
+fn push_notice(target: &Arc<Mutex<StatsLines>>, line: String) {
+    if let Ok(mut lines) = target.lock() {
+        if lines.notices.len() == MAX_STATS_NOTICES {
+            lines.notices.pop_front();
+        }
+        lines.notices.push_back(line);
+    }
+}
+
+fn set_last_error_if_empty(target: &Arc<Mutex<Option<String>>>, message: String) {
+    if let Ok(mut guard) = target.lock() {
+        if guard.is_none() {
+            *guard = Some(message);
+        }
+    }
+}
+
+fn set_last_error(target: &Arc<Mutex<Option<String>>>, message: String) {
+    if let Ok(mut guard) = target.lock() {
+        *guard = Some(message);
+    }
+}
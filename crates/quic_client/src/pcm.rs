@@ -0,0 +1,347 @@
+//! Opus decoding for clients that want PCM rather than packets.
+//!
+//! `PcmDecoder` takes the audio units and the gapless notices of one
+//! connection. It drops the encoder pre-skip and the padding after
+//! `stream_end`, and splits its output where `track_boundary` says the next
+//! track begins. Positions in those notices count 48 kHz samples per channel
+//! after the pre-skip; audio decoded before a notice arrives isn't trimmed
+//! retroactively.
+
+use std::collections::HashMap;
+
+use codecs_ffi::OpusDecoderWrapper;
+use quic_wire::AudioUnit;
+
+use crate::client::{AudioFrame, Event};
+
+const HEADER_MAGIC: &[u8] = b"OPUSR01\0";
+/// Version 2 headers describe a multistream (surround) stream.
+const HEADER_VERSION_STEREO: u8 = 1;
+const HEADER_SAMPLE_RATE_OFFSET: usize = 12;
+const HEADER_CHANNELS_OFFSET: usize = 16;
+const HEADER_PRE_SKIP_OFFSET: usize = 26;
+const NOTICE_RATE: u64 = 48_000;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pcm {
+    /// Interleaved 16-bit samples.
+    Samples {
+        track_id: String,
+        sample_rate: u32,
+        channels: u8,
+        samples: Vec<i16>,
+    },
+    /// The samples that follow belong to `track_id`: a new stream or a
+    /// gapless boundary within one.
+    TrackStart { track_id: String },
+    /// A seek restarted the stream; drop anything buffered.
+    Reset,
+    /// The stream has no more audio.
+    End,
+}
+
+#[derive(Default)]
+struct StreamNotices {
+    /// `(sample_offset, track_id)`, in the order they were sent.
+    boundaries: Vec<(u64, String)>,
+    valid_samples: Option<u64>,
+}
+
+#[derive(Default)]
+pub struct PcmDecoder {
+    decoder: Option<OpusDecoderWrapper>,
+    stream_id: Option<u64>,
+    track_id: String,
+    sample_rate: u32,
+    channels: u8,
+    /// Samples per channel still to drop from the start of the stream.
+    skip: u64,
+    /// Samples per channel emitted since the pre-skip.
+    position: u64,
+    notices: HashMap<u64, StreamNotices>,
+}
+
+impl PcmDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push_audio(&mut self, frame: &AudioFrame) -> Result<Vec<Pcm>, String> {
+        let mut out = Vec::new();
+        if self.stream_id != Some(frame.stream_id) || frame.resumed {
+            if let Some(previous) = self.stream_id.take() {
+                self.notices.remove(&previous);
+            }
+            self.decoder = None;
+            self.stream_id = Some(frame.stream_id);
+            let same_track = frame.track_id == self.track_id;
+            self.track_id = frame.track_id.clone();
+            if same_track && frame.seek {
+                // The server restarts a sought track on a new stream.
+                out.push(Pcm::Reset);
+            } else if !(same_track && frame.resumed) {
+                // After a reconnect the same track carries on seamlessly.
+                out.push(Pcm::TrackStart {
+                    track_id: frame.track_id.clone(),
+                });
+            }
+        }
+        match &frame.unit {
+            AudioUnit::Header(header) => {
+                self.start(header)?;
+                let sample_rate = self.sample_rate;
+                let channels = self.channels;
+                self.decoder = Some(
+                    OpusDecoderWrapper::new(sample_rate, channels)
+                        .map_err(|err| err.to_string())?,
+                );
+            }
+            AudioUnit::Packet(packet) => {
+                let Some(decoder) = self.decoder.as_mut() else {
+                    return Err("opus packet before stream header".to_string());
+                };
+                let pcm = decoder.decode(packet).map_err(|err| err.to_string())?;
+                self.emit(pcm, &mut out);
+            }
+            AudioUnit::Reset => {
+                // The restarted transcode sends its own notices.
+                self.notices.remove(&frame.stream_id);
+                self.decoder = None;
+                out.push(Pcm::Reset);
+            }
+            AudioUnit::End => {
+                self.notices.remove(&frame.stream_id);
+                self.decoder = None;
+                out.push(Pcm::End);
+            }
+        }
+        Ok(out)
+    }
+
+    /// Takes `track_boundary` and `stream_end`; other events are ignored.
+    /// A boundary the output has already passed starts the track at once.
+    pub fn push_event(&mut self, event: &Event) -> Vec<Pcm> {
+        match event {
+            Event::TrackBoundary {
+                stream_id,
+                track_id,
+                sample_offset,
+                ..
+            } => {
+                if self.stream_id == Some(*stream_id)
+                    && self.to_output_rate(*sample_offset) <= self.position
+                {
+                    self.track_id = track_id.clone();
+                    return vec![Pcm::TrackStart {
+                        track_id: track_id.clone(),
+                    }];
+                }
+                self.notices
+                    .entry(*stream_id)
+                    .or_default()
+                    .boundaries
+                    .push((*sample_offset, track_id.clone()));
+            }
+            Event::StreamEnd {
+                stream_id,
+                valid_samples,
+                ..
+            } => {
+                self.notices.entry(*stream_id).or_default().valid_samples = Some(*valid_samples);
+            }
+            _ => {}
+        }
+        Vec::new()
+    }
+
+    fn start(&mut self, header: &[u8]) -> Result<(), String> {
+        if header.len() < HEADER_PRE_SKIP_OFFSET + 2 || !header.starts_with(HEADER_MAGIC) {
+            return Err("invalid opus stream header".to_string());
+        }
+        if header[HEADER_MAGIC.len()] != HEADER_VERSION_STEREO {
+            return Err("surround streams can't be decoded to PCM".to_string());
+        }
+        let field = |offset: usize, len: usize| &header[offset..offset + len];
+        self.sample_rate =
+            u32::from_le_bytes(field(HEADER_SAMPLE_RATE_OFFSET, 4).try_into().unwrap());
+        self.channels = header[HEADER_CHANNELS_OFFSET];
+        let pre_skip = u16::from_le_bytes(field(HEADER_PRE_SKIP_OFFSET, 2).try_into().unwrap());
+        self.skip = self.to_output_rate(pre_skip as u64);
+        self.position = 0;
+        Ok(())
+    }
+
+    /// Trims and splits one decoded packet.
+    fn emit(&mut self, mut pcm: Vec<i16>, out: &mut Vec<Pcm>) {
+        let channels = self.channels.max(1) as usize;
+        let frames = (pcm.len() / channels) as u64;
+        let skipped = self.skip.min(frames);
+        self.skip -= skipped;
+        pcm.drain(..skipped as usize * channels);
+
+        let notices = self.stream_id.and_then(|id| self.notices.get_mut(&id));
+        let (mut boundaries, valid) = match notices {
+            Some(notices) => (
+                std::mem::take(&mut notices.boundaries),
+                notices.valid_samples,
+            ),
+            None => (Vec::new(), None),
+        };
+        if let Some(valid) = valid {
+            let allowed = self.to_output_rate(valid).saturating_sub(self.position);
+            pcm.truncate(allowed.min(pcm.len() as u64 / channels as u64) as usize * channels);
+        }
+
+        let mut rest = pcm;
+        boundaries.sort_by_key(|(offset, _)| *offset);
+        let mut later = Vec::new();
+        for (offset, track_id) in boundaries {
+            let at = self.to_output_rate(offset).saturating_sub(self.position);
+            let available = (rest.len() / channels) as u64;
+            if at > available {
+                later.push((offset, track_id));
+                continue;
+            }
+            let tail = rest.split_off(at as usize * channels);
+            self.push_samples(rest, out);
+            self.track_id = track_id.clone();
+            out.push(Pcm::TrackStart { track_id });
+            rest = tail;
+        }
+        self.push_samples(rest, out);
+        if let Some(id) = self.stream_id {
+            if !later.is_empty() {
+                self.notices.entry(id).or_default().boundaries = later;
+            }
+        }
+    }
+
+    fn push_samples(&mut self, samples: Vec<i16>, out: &mut Vec<Pcm>) {
+        if samples.is_empty() {
+            return;
+        }
+        self.position += (samples.len() / self.channels.max(1) as usize) as u64;
+        out.push(Pcm::Samples {
+            track_id: self.track_id.clone(),
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+            samples,
+        });
+    }
+
+    fn to_output_rate(&self, samples: u64) -> u64 {
+        if self.sample_rate == 0 {
+            return samples;
+        }
+        samples * self.sample_rate as u64 / NOTICE_RATE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(pre_skip: u16) -> Vec<u8> {
+        let mut header = b"OPUSR01\0\x01\0".to_vec();
+        header.extend_from_slice(&30u16.to_le_bytes());
+        header.extend_from_slice(&48_000u32.to_le_bytes());
+        header.extend_from_slice(&[2, 20]);
+        header.extend_from_slice(&[0; 8]);
+        header.extend_from_slice(&pre_skip.to_le_bytes());
+        header.extend_from_slice(&[0; 2]);
+        header
+    }
+
+    fn frames(pcm: &[Pcm]) -> Vec<(String, usize)> {
+        pcm.iter()
+            .map(|item| match item {
+                Pcm::Samples {
+                    track_id, samples, ..
+                } => (track_id.clone(), samples.len() / 2),
+                Pcm::TrackStart { track_id } => (format!("start {}", track_id), 0),
+                other => (format!("{:?}", other), 0),
+            })
+            .collect()
+    }
+
+    fn first_unit(stream_id: u64, track_id: &str, seek: bool, resumed: bool) -> AudioFrame {
+        // `End` needs no Opus decoder.
+        AudioFrame {
+            track_id: track_id.to_string(),
+            stream_id,
+            unit: AudioUnit::End,
+            resumed,
+            seek,
+        }
+    }
+
+    #[test]
+    fn tells_seeks_and_reconnects_from_new_tracks() {
+        let mut decoder = PcmDecoder::new();
+        let mut starts = |frame: AudioFrame| {
+            let mut out = decoder.push_audio(&frame).unwrap();
+            assert_eq!(out.pop(), Some(Pcm::End));
+            out
+        };
+        let track_start = |track_id: &str| {
+            vec![Pcm::TrackStart {
+                track_id: track_id.to_string(),
+            }]
+        };
+        assert_eq!(starts(first_unit(3, "a", false, false)), track_start("a"));
+        assert_eq!(starts(first_unit(7, "a", true, false)), vec![Pcm::Reset]);
+        assert_eq!(starts(first_unit(7, "a", false, true)), Vec::new());
+        assert_eq!(starts(first_unit(11, "b", false, false)), track_start("b"));
+        // A seek into another track starts that track.
+        assert_eq!(starts(first_unit(15, "c", true, false)), track_start("c"));
+    }
+
+    #[test]
+    fn trims_pre_skip_and_padding_and_splits_at_boundaries() {
+        let mut decoder = PcmDecoder::new();
+        decoder.stream_id = Some(5);
+        decoder.track_id = "a".to_string();
+        decoder.start(&header(312)).unwrap();
+        assert!(decoder.start(&b"OPUSR01\0\x02"[..]).is_err());
+        decoder.push_event(&Event::TrackBoundary {
+            stream_id: 5,
+            track_id: "b".to_string(),
+            sample_offset: 1000,
+            crossfade_samples: 0,
+        });
+        decoder.push_event(&Event::StreamEnd {
+            stream_id: 5,
+            track_id: "b".to_string(),
+            pre_skip: 312,
+            valid_samples: 1500,
+        });
+
+        let mut out = Vec::new();
+        for _ in 0..3 {
+            decoder.emit(vec![0; 960 * 2], &mut out);
+        }
+        assert_eq!(
+            frames(&out),
+            vec![
+                ("a".to_string(), 648),
+                ("a".to_string(), 352),
+                ("start b".to_string(), 0),
+                ("b".to_string(), 500),
+            ]
+        );
+
+        // A boundary the output already passed applies at once.
+        let late = decoder.push_event(&Event::TrackBoundary {
+            stream_id: 5,
+            track_id: "c".to_string(),
+            sample_offset: 1200,
+            crossfade_samples: 0,
+        });
+        assert_eq!(
+            late,
+            vec![Pcm::TrackStart {
+                track_id: "c".to_string()
+            }]
+        );
+    }
+}
@@ -0,0 +1,204 @@
+//! Audio stream framing. Each stream starts with the `OPUSR01` header
+//! (built by the server's transcoder) followed by Opus packets.
+//!
+//! Legacy framing sends the header as is (its length is at bytes 10..12),
+//! then each packet as a little-endian u16 length and the packet. A zero
+//! length ends the stream and `0xFFFF` resets it. Binary framing wraps each
+//! of those in its own frame.
+
+use crate::frame::{encode_frame, FrameReader};
+use crate::{
+    FRAME_AUDIO_END, FRAME_AUDIO_HEADER, FRAME_AUDIO_PACKET, FRAME_AUDIO_RESET,
+    MAX_AUDIO_PAYLOAD,
+};
+
+const LEGACY_END: u16 = 0;
+const LEGACY_RESET: u16 = 0xFFFF;
+/// Header bytes needed to read its length.
+const HEADER_LEN_END: usize = 12;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AudioFraming {
+    #[default]
+    Legacy,
+    Binary,
+}
+
+impl AudioFraming {
+    pub fn header(self, header: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Legacy => header.to_vec(),
+            Self::Binary => frame(FRAME_AUDIO_HEADER, header),
+        }
+    }
+
+    pub fn packet(self, packet: &[u8]) -> Result<Vec<u8>, String> {
+        if packet.len() >= LEGACY_RESET as usize {
+            return Err("opus frame too large".to_string());
+        }
+        Ok(match self {
+            Self::Legacy => {
+                let mut out = Vec::with_capacity(2 + packet.len());
+                out.extend_from_slice(&(packet.len() as u16).to_le_bytes());
+                out.extend_from_slice(packet);
+                out
+            }
+            Self::Binary => frame(FRAME_AUDIO_PACKET, packet),
+        })
+    }
+
+    pub fn end(self) -> Vec<u8> {
+        match self {
+            Self::Legacy => LEGACY_END.to_le_bytes().to_vec(),
+            Self::Binary => frame(FRAME_AUDIO_END, &[]),
+        }
+    }
+
+    pub fn reset(self) -> Vec<u8> {
+        match self {
+            Self::Legacy => LEGACY_RESET.to_le_bytes().to_vec(),
+            Self::Binary => frame(FRAME_AUDIO_RESET, &[]),
+        }
+    }
+}
+
+fn frame(kind: u64, payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(payload.len() + 4);
+    encode_frame(kind, payload, &mut out);
+    out
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AudioUnit {
+    Header(Vec<u8>),
+    Packet(Vec<u8>),
+    End,
+    /// Drop buffered audio; the next unit is a new header.
+    Reset,
+}
+
+impl AudioUnit {
+    /// The unit in `framing`, for passing a stream on in another framing.
+    pub fn encode(&self, framing: AudioFraming) -> Result<Vec<u8>, String> {
+        match self {
+            Self::Header(header) => Ok(framing.header(header)),
+            Self::Packet(packet) => framing.packet(packet),
+            Self::End => Ok(framing.end()),
+            Self::Reset => Ok(framing.reset()),
+        }
+    }
+}
+
+/// Splits one audio stream into units.
+pub struct AudioReader {
+    framing: AudioFraming,
+    frames: FrameReader,
+    expect_header: bool,
+}
+
+impl AudioReader {
+    pub fn new(framing: AudioFraming) -> Self {
+        Self {
+            framing,
+            frames: FrameReader::new(MAX_AUDIO_PAYLOAD),
+            expect_header: true,
+        }
+    }
+
+    pub fn push(&mut self, data: &[u8]) -> Result<Vec<AudioUnit>, String> {
+        self.frames.push(data);
+        let mut out = Vec::new();
+        loop {
+            let unit = match self.framing {
+                AudioFraming::Legacy => self.next_legacy(),
+                AudioFraming::Binary => self.next_binary()?,
+            };
+            let Some(unit) = unit else { break };
+            self.expect_header = unit == AudioUnit::Reset;
+            out.push(unit);
+        }
+        Ok(out)
+    }
+
+    fn next_legacy(&mut self) -> Option<AudioUnit> {
+        let buffered = self.frames.buffered();
+        if self.expect_header {
+            let len = u16::from_le_bytes(buffered.get(10..HEADER_LEN_END)?.try_into().ok()?);
+            let len = (len as usize).max(HEADER_LEN_END);
+            if buffered.len() < len {
+                return None;
+            }
+            return Some(AudioUnit::Header(self.frames.take(len)));
+        }
+        let len = u16::from_le_bytes(buffered.get(..2)?.try_into().ok()?);
+        match len {
+            LEGACY_END => {
+                self.frames.take(2);
+                Some(AudioUnit::End)
+            }
+            LEGACY_RESET => {
+                self.frames.take(2);
+                Some(AudioUnit::Reset)
+            }
+            len => {
+                if buffered.len() < 2 + len as usize {
+                    return None;
+                }
+                let mut packet = self.frames.take(2 + len as usize);
+                packet.drain(..2);
+                Some(AudioUnit::Packet(packet))
+            }
+        }
+    }
+
+    fn next_binary(&mut self) -> Result<Option<AudioUnit>, String> {
+        while let Some(frame) = self.frames.next_frame()? {
+            let unit = match frame.kind {
+                FRAME_AUDIO_HEADER => AudioUnit::Header(frame.payload),
+                FRAME_AUDIO_PACKET => AudioUnit::Packet(frame.payload),
+                FRAME_AUDIO_END => AudioUnit::End,
+                FRAME_AUDIO_RESET => AudioUnit::Reset,
+                _ => continue,
+            };
+            return Ok(Some(unit));
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> Vec<u8> {
+        let mut header = b"OPUSR01\0\x01\0".to_vec();
+        header.extend_from_slice(&16u16.to_le_bytes());
+        header.extend_from_slice(&[9; 4]);
+        header
+    }
+
+    #[test]
+    fn both_framings_carry_the_same_units() {
+        let units = vec![
+            AudioUnit::Header(header()),
+            AudioUnit::Packet(vec![1, 2, 3]),
+            AudioUnit::Reset,
+            AudioUnit::Header(header()),
+            AudioUnit::Packet(vec![4; 200]),
+            AudioUnit::End,
+        ];
+        for framing in [AudioFraming::Legacy, AudioFraming::Binary] {
+            let wire: Vec<u8> = units
+                .iter()
+                .flat_map(|unit| unit.encode(framing).unwrap())
+                .collect();
+            let mut reader = AudioReader::new(framing);
+            let mut decoded = Vec::new();
+            for chunk in wire.chunks(5) {
+                decoded.extend(reader.push(chunk).unwrap());
+            }
+            assert_eq!(decoded, units, "{:?}", framing);
+        }
+        assert!(AudioFraming::Legacy.packet(&[0; 0xFFFF]).is_err());
+    }
+}
@@ -0,0 +1,239 @@
+//! The CBOR subset control messages need: maps with text keys, arrays,
+//! text, integers, floats, booleans and null, all with definite lengths.
+//! Byte strings decode as arrays of numbers and tags are ignored, so
+//! generic CBOR encoders can talk to the server too.
+
+use serde_json::{Map, Number, Value};
+
+const MAX_DEPTH: usize = 32;
+
+const MAJOR_UNSIGNED: u8 = 0;
+const MAJOR_NEGATIVE: u8 = 1;
+const MAJOR_BYTES: u8 = 2;
+const MAJOR_TEXT: u8 = 3;
+const MAJOR_ARRAY: u8 = 4;
+const MAJOR_MAP: u8 = 5;
+const MAJOR_TAG: u8 = 6;
+const MAJOR_SIMPLE: u8 = 7;
+
+pub fn encode(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Null => out.push(0xf6),
+        Value::Bool(false) => out.push(0xf4),
+        Value::Bool(true) => out.push(0xf5),
+        Value::Number(number) => {
+            if let Some(value) = number.as_u64() {
+                encode_head(MAJOR_UNSIGNED, value, out);
+            } else if let Some(value) = number.as_i64() {
+                encode_head(MAJOR_NEGATIVE, !(value as u64), out);
+            } else {
+                out.push(0xfb);
+                out.extend_from_slice(&number.as_f64().unwrap_or(0.0).to_be_bytes());
+            }
+        }
+        Value::String(text) => {
+            encode_head(MAJOR_TEXT, text.len() as u64, out);
+            out.extend_from_slice(text.as_bytes());
+        }
+        Value::Array(items) => {
+            encode_head(MAJOR_ARRAY, items.len() as u64, out);
+            for item in items {
+                encode(item, out);
+            }
+        }
+        Value::Object(map) => {
+            encode_head(MAJOR_MAP, map.len() as u64, out);
+            for (key, item) in map {
+                encode_head(MAJOR_TEXT, key.len() as u64, out);
+                out.extend_from_slice(key.as_bytes());
+                encode(item, out);
+            }
+        }
+    }
+}
+
+fn encode_head(major: u8, value: u64, out: &mut Vec<u8>) {
+    let major = major << 5;
+    if value < 24 {
+        out.push(major | value as u8);
+    } else if value <= u8::MAX as u64 {
+        out.extend_from_slice(&[major | 24, value as u8]);
+    } else if value <= u16::MAX as u64 {
+        out.push(major | 25);
+        out.extend_from_slice(&(value as u16).to_be_bytes());
+    } else if value <= u32::MAX as u64 {
+        out.push(major | 26);
+        out.extend_from_slice(&(value as u32).to_be_bytes());
+    } else {
+        out.push(major | 27);
+        out.extend_from_slice(&value.to_be_bytes());
+    }
+}
+
+/// Decodes exactly one item filling `data`.
+pub fn decode(data: &[u8]) -> Result<Value, String> {
+    let mut decoder = Decoder { data, offset: 0 };
+    let value = decoder.item(0)?;
+    if decoder.offset != data.len() {
+        return Err("trailing bytes after CBOR item".to_string());
+    }
+    Ok(value)
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl Decoder<'_> {
+    fn bytes(&mut self, len: usize) -> Result<&[u8], String> {
+        let end = self
+            .offset
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| "truncated CBOR item".to_string())?;
+        let bytes = &self.data[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    /// The major type and argument of the next item.
+    fn head(&mut self) -> Result<(u8, u8, u64), String> {
+        let initial = self.bytes(1)?[0];
+        let info = initial & 0x1f;
+        let value = match info {
+            0..=23 => info as u64,
+            24 => self.bytes(1)?[0] as u64,
+            25 => u16::from_be_bytes(self.bytes(2)?.try_into().unwrap()) as u64,
+            26 => u32::from_be_bytes(self.bytes(4)?.try_into().unwrap()) as u64,
+            27 => u64::from_be_bytes(self.bytes(8)?.try_into().unwrap()),
+            _ => return Err("indefinite-length CBOR is not supported".to_string()),
+        };
+        Ok((initial >> 5, info, value))
+    }
+
+    /// A length that can't claim more items than bytes remain.
+    fn length(&self, len: u64) -> Result<usize, String> {
+        if len > (self.data.len() - self.offset) as u64 {
+            return Err("truncated CBOR item".to_string());
+        }
+        Ok(len as usize)
+    }
+
+    fn item(&mut self, depth: usize) -> Result<Value, String> {
+        if depth > MAX_DEPTH {
+            return Err("CBOR nested too deeply".to_string());
+        }
+        let (major, info, value) = self.head()?;
+        match major {
+            MAJOR_UNSIGNED => Ok(Value::from(value)),
+            MAJOR_NEGATIVE => {
+                let value = i64::try_from(value)
+                    .map_err(|_| "CBOR integer out of range".to_string())?;
+                Ok(Value::from(-1 - value))
+            }
+            MAJOR_BYTES => {
+                let len = self.length(value)?;
+                Ok(Value::Array(
+                    self.bytes(len)?.iter().map(|byte| Value::from(*byte)).collect(),
+                ))
+            }
+            MAJOR_TEXT => {
+                let len = self.length(value)?;
+                let text = std::str::from_utf8(self.bytes(len)?)
+                    .map_err(|_| "CBOR text is not UTF-8".to_string())?;
+                Ok(Value::String(text.to_string()))
+            }
+            MAJOR_ARRAY => {
+                let len = self.length(value)?;
+                let mut items = Vec::with_capacity(len);
+                for _ in 0..len {
+                    items.push(self.item(depth + 1)?);
+                }
+                Ok(Value::Array(items))
+            }
+            MAJOR_MAP => {
+                let len = self.length(value)?;
+                let mut map = Map::new();
+                for _ in 0..len {
+                    let Value::String(key) = self.item(depth + 1)? else {
+                        return Err("CBOR map keys must be text".to_string());
+                    };
+                    let item = self.item(depth + 1)?;
+                    map.insert(key, item);
+                }
+                Ok(Value::Object(map))
+            }
+            MAJOR_TAG => self.item(depth + 1),
+            MAJOR_SIMPLE => match info {
+                20 => Ok(Value::Bool(false)),
+                21 => Ok(Value::Bool(true)),
+                // null and undefined
+                22 | 23 => Ok(Value::Null),
+                25 => Ok(float(half_to_f64(value as u16))),
+                26 => Ok(float(f32::from_bits(value as u32) as f64)),
+                27 => Ok(float(f64::from_bits(value))),
+                _ => Err(format!("unsupported CBOR simple value {}", value)),
+            },
+            _ => unreachable!("major type is three bits"),
+        }
+    }
+}
+
+/// JSON has no NaN or infinity; those become null.
+fn float(value: f64) -> Value {
+    Number::from_f64(value).map_or(Value::Null, Value::Number)
+}
+
+fn half_to_f64(half: u16) -> f64 {
+    let exponent = (half >> 10) & 0x1f;
+    let mantissa = (half & 0x3ff) as f64;
+    let magnitude = match exponent {
+        0 => mantissa * 2f64.powi(-24),
+        31 if mantissa == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        _ => (mantissa + 1024.0) * 2f64.powi(exponent as i32 - 25),
+    };
+    if half & 0x8000 != 0 {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn round_trips_control_messages() {
+        let message = json!({
+            "type": "open",
+            "track_id": "a1b2",
+            "frame_ms": 20,
+            "speed": 1.25,
+            "seek_ms": -1,
+            "big": 4_294_967_296u64,
+            "queue": ["a", "b"],
+            "gapless": true,
+            "crossfade_ms": null,
+        });
+        let mut out = Vec::new();
+        encode(&message, &mut out);
+        assert_eq!(decode(&out).unwrap(), message);
+        assert!(decode(&out[..out.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn decodes_rfc_8949_examples() {
+        assert_eq!(decode(&[0x18, 0x64]).unwrap(), json!(100));
+        assert_eq!(decode(&[0x38, 0x63]).unwrap(), json!(-100));
+        assert_eq!(decode(&[0xf9, 0x3e, 0x00]).unwrap(), json!(1.5));
+        assert_eq!(decode(&[0xfa, 0x47, 0xc3, 0x50, 0x00]).unwrap(), json!(100000.0));
+        assert_eq!(decode(&[0x62, 0x22, 0x5c]).unwrap(), json!("\"\\"));
+        assert_eq!(decode(&[0xc1, 0x1a, 0x51, 0x4b, 0x67, 0xb0]).unwrap(), json!(1363896240));
+        // An array claiming more items than there are bytes.
+        assert!(decode(&[0x9b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]).is_err());
+    }
+}
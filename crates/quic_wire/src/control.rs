@@ -0,0 +1,132 @@
+//! Control stream messages in either framing.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::frame::{encode_frame, FrameReader};
+use crate::{cbor, FRAME_CONTROL, MAX_CONTROL_MESSAGE};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ControlFraming {
+    /// One JSON object per line.
+    #[default]
+    Json,
+    /// One `FRAME_CONTROL` frame per message, CBOR inside.
+    Binary,
+}
+
+pub fn encode_control<T: Serialize>(framing: ControlFraming, message: &T) -> Result<Vec<u8>, String> {
+    match framing {
+        ControlFraming::Json => {
+            let mut out = serde_json::to_vec(message).map_err(|err| err.to_string())?;
+            out.push(b'\n');
+            Ok(out)
+        }
+        ControlFraming::Binary => {
+            let value = serde_json::to_value(message).map_err(|err| err.to_string())?;
+            let mut payload = Vec::new();
+            cbor::encode(&value, &mut payload);
+            if payload.len() > MAX_CONTROL_MESSAGE {
+                return Err(format!("control message of {} bytes is too large", payload.len()));
+            }
+            let mut out = Vec::with_capacity(payload.len() + 4);
+            encode_frame(FRAME_CONTROL, &payload, &mut out);
+            Ok(out)
+        }
+    }
+}
+
+/// Splits the control stream into messages. Each message may use either
+/// framing: one starting with `{` or whitespace is a JSON line, anything
+/// else a frame. That lets a peer switch framing between any two messages.
+pub struct ControlReader {
+    frames: FrameReader,
+}
+
+impl Default for ControlReader {
+    fn default() -> Self {
+        Self {
+            frames: FrameReader::new(MAX_CONTROL_MESSAGE),
+        }
+    }
+}
+
+impl ControlReader {
+    /// Messages that don't decode as `T` are skipped, as are lines and
+    /// frames over `MAX_CONTROL_MESSAGE`.
+    pub fn push<T: DeserializeOwned>(&mut self, data: &[u8]) -> Vec<T> {
+        self.frames.push(data);
+        let mut out = Vec::new();
+        while let Some(first) = self.frames.buffered().first().copied() {
+            if matches!(first, b'{' | b' ' | b'\t' | b'\r' | b'\n') {
+                let Some(pos) = self.frames.buffered().iter().position(|b| *b == b'\n') else {
+                    if self.frames.buffered().len() > MAX_CONTROL_MESSAGE {
+                        self.frames.clear();
+                    }
+                    break;
+                };
+                let line = self.frames.take(pos + 1);
+                if line.len() > MAX_CONTROL_MESSAGE + 1 {
+                    continue;
+                }
+                let text = line.trim_ascii();
+                if text.is_empty() {
+                    continue;
+                }
+                if let Ok(message) = serde_json::from_slice::<T>(text) {
+                    out.push(message);
+                }
+                continue;
+            }
+            match self.frames.next_frame() {
+                Ok(Some(frame)) if frame.kind == FRAME_CONTROL => {
+                    let message = cbor::decode(&frame.payload)
+                        .and_then(|value| serde_json::from_value::<T>(value).map_err(|err| err.to_string()));
+                    if let Ok(message) = message {
+                        out.push(message);
+                    }
+                }
+                Ok(Some(_)) => {}
+                Ok(None) => break,
+                Err(_) => break,
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(tag = "type")]
+    enum Message {
+        #[serde(rename = "ping")]
+        Ping { ts: Option<i64> },
+        #[serde(rename = "auth")]
+        Auth { token: String },
+    }
+
+    #[test]
+    fn reads_mixed_framings() {
+        let mut wire = encode_control(ControlFraming::Json, &Message::Ping { ts: Some(5) }).unwrap();
+        wire.extend_from_slice(b"{\"type\":\"unknown\"}\n\n");
+        let auth = Message::Auth {
+            token: "secret".to_string(),
+        };
+        wire.extend(encode_control(ControlFraming::Binary, &auth).unwrap());
+        wire.extend(encode_control(ControlFraming::Json, &Message::Ping { ts: None }).unwrap());
+
+        let mut reader = ControlReader::default();
+        let (head, tail) = wire.split_at(7);
+        let mut messages: Vec<Message> = reader.push(head);
+        assert!(messages.is_empty());
+        messages.extend(reader.push::<Message>(tail));
+        assert_eq!(
+            messages,
+            vec![Message::Ping { ts: Some(5) }, auth, Message::Ping { ts: None }]
+        );
+    }
+}
@@ -0,0 +1,102 @@
+//! The type-length-value envelope used by binary framing.
+
+use crate::varint;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub kind: u64,
+    pub payload: Vec<u8>,
+}
+
+pub fn encode_frame(kind: u64, payload: &[u8], out: &mut Vec<u8>) {
+    varint::encode(kind, out);
+    varint::encode(payload.len() as u64, out);
+    out.extend_from_slice(payload);
+}
+
+/// Reassembles frames from stream reads of any size.
+pub struct FrameReader {
+    buffer: Vec<u8>,
+    max_payload: usize,
+}
+
+impl FrameReader {
+    pub fn new(max_payload: usize) -> Self {
+        Self {
+            buffer: Vec::new(),
+            max_payload,
+        }
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Bytes received but not yet returned as a frame.
+    pub fn buffered(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// The next complete frame. A frame over the size limit is an error;
+    /// the stream can't be resynchronised after one.
+    pub fn next_frame(&mut self) -> Result<Option<Frame>, String> {
+        let Some((kind, kind_len)) = varint::decode(&self.buffer) else {
+            return Ok(None);
+        };
+        let Some((len, len_len)) = varint::decode(&self.buffer[kind_len..]) else {
+            return Ok(None);
+        };
+        if len > self.max_payload as u64 {
+            self.buffer.clear();
+            return Err(format!(
+                "frame of {} bytes exceeds {}",
+                len, self.max_payload
+            ));
+        }
+        let start = kind_len + len_len;
+        let end = start + len as usize;
+        if self.buffer.len() < end {
+            return Ok(None);
+        }
+        let payload = self.buffer[start..end].to_vec();
+        self.buffer.drain(..end);
+        Ok(Some(Frame { kind, payload }))
+    }
+
+    /// Takes up to `len` buffered bytes that aren't framed.
+    pub(crate) fn take(&mut self, len: usize) -> Vec<u8> {
+        let len = len.min(self.buffer.len());
+        self.buffer.drain(..len).collect()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.buffer.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reassembles_frames_split_across_reads() {
+        let mut wire = Vec::new();
+        encode_frame(0x11, &[7; 300], &mut wire);
+        encode_frame(0x12, &[], &mut wire);
+        let mut reader = FrameReader::new(1024);
+        let mut frames = Vec::new();
+        for byte in &wire {
+            reader.push(std::slice::from_ref(byte));
+            while let Some(frame) = reader.next_frame().unwrap() {
+                frames.push(frame);
+            }
+        }
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].payload.len(), 300);
+        assert_eq!(frames[1].kind, 0x12);
+
+        let mut reader = FrameReader::new(100);
+        reader.push(&wire);
+        assert!(reader.next_frame().is_err());
+    }
+}
@@ -0,0 +1,43 @@
+//! Wire format of the phonolite QUIC protocol, shared by the server and
+//! `phonolite_quic` so the two can't drift.
+//!
+//! Connections start with newline-delimited JSON on the control stream and
+//! u16-length-prefixed Opus packets on audio streams. When both sides agree
+//! on the `binary_framing` capability in `hello`, the server switches to
+//! frames right after `hello_ok` and clients may switch whenever they like:
+//!
+//! ```text
+//! frame = type:varint length:varint payload:length bytes
+//! ```
+//!
+//! Varints are QUIC's (RFC 9000 §16): the top two bits of the first byte
+//! give the size, 1, 2, 4 or 8 bytes, big-endian. Frames of unknown type
+//! are skipped. Control payloads are CBOR (RFC 8949, definite lengths only)
+//! holding the same maps as the JSON messages.
+
+pub mod audio;
+pub mod cbor;
+pub mod control;
+pub mod frame;
+pub mod varint;
+
+pub use audio::{AudioFraming, AudioReader, AudioUnit};
+pub use control::{encode_control, ControlFraming, ControlReader};
+pub use frame::{encode_frame, Frame, FrameReader};
+
+/// Control stream frame carrying one CBOR-encoded message.
+pub const FRAME_CONTROL: u64 = 0x01;
+/// Audio stream frame carrying the `OPUSR01` stream header.
+pub const FRAME_AUDIO_HEADER: u64 = 0x10;
+/// Audio stream frame carrying one Opus packet.
+pub const FRAME_AUDIO_PACKET: u64 = 0x11;
+/// The stream's audio is complete.
+pub const FRAME_AUDIO_END: u64 = 0x12;
+/// Discard buffered audio; a new header follows (after a seek).
+pub const FRAME_AUDIO_RESET: u64 = 0x13;
+
+/// Largest control message in either framing.
+pub const MAX_CONTROL_MESSAGE: usize = 64 * 1024;
+/// Largest audio frame payload; the stream header and Opus packets both
+/// fit in 16 bits in the legacy framing.
+pub const MAX_AUDIO_PAYLOAD: usize = u16::MAX as usize;
@@ -0,0 +1,46 @@
+//! QUIC variable-length integers.
+
+pub const MAX: u64 = (1 << 62) - 1;
+
+pub fn encode(value: u64, out: &mut Vec<u8>) {
+    debug_assert!(value <= MAX, "varint out of range");
+    if value < 1 << 6 {
+        out.push(value as u8);
+    } else if value < 1 << 14 {
+        out.extend_from_slice(&(value as u16 | 0x4000).to_be_bytes());
+    } else if value < 1 << 30 {
+        out.extend_from_slice(&(value as u32 | 0x8000_0000).to_be_bytes());
+    } else {
+        out.extend_from_slice(&(value | 0xc000_0000_0000_0000).to_be_bytes());
+    }
+}
+
+/// Returns the value and its encoded size, or `None` if `data` ends
+/// before the varint does.
+pub fn decode(data: &[u8]) -> Option<(u64, usize)> {
+    let first = *data.first()?;
+    let len = 1usize << (first >> 6);
+    let bytes = data.get(..len)?;
+    let mut value = (first & 0x3f) as u64;
+    for byte in &bytes[1..] {
+        value = (value << 8) | *byte as u64;
+    }
+    Some((value, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_every_size() {
+        for value in [0, 63, 64, 16_383, 16_384, (1 << 30) - 1, 1 << 30, MAX] {
+            let mut out = Vec::new();
+            encode(value, &mut out);
+            assert_eq!(decode(&out), Some((value, out.len())));
+            assert_eq!(decode(&out[..out.len() - 1]), None);
+        }
+        // RFC 9000 appendix A.1.
+        assert_eq!(decode(&[0x7b, 0xbd]), Some((15_293, 2)));
+    }
+}
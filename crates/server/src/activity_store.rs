@@ -5,6 +5,8 @@ use redb::{Database, ReadableTable, TableDefinition, TableError};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::events::{EventBus, EventTopic};
+
 const ACTIVITY_TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("activity");
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -18,11 +20,12 @@ pub struct ActivityEntry {
 #[derive(Clone)]
 pub struct ActivityStore {
     db: Arc<Database>,
+    events: EventBus,
 }
 
 impl ActivityStore {
-    pub fn new(db: Arc<Database>) -> Self {
-        Self { db }
+    pub fn new(db: Arc<Database>, events: EventBus) -> Self {
+        Self { db, events }
     }
 
     pub fn init_tables(&self) -> Result<(), String> {
@@ -54,6 +57,7 @@ impl ActivityStore {
                 .map_err(|e| e.to_string())?;
         }
         write_txn.commit().map_err(|e| e.to_string())?;
+        self.events.publish(EventTopic::Activity, "added", &entry);
         Ok(())
     }
 
@@ -92,6 +96,7 @@ impl ActivityStore {
             .open_table(ACTIVITY_TABLE)
             .map_err(|e| e.to_string())?;
         write_txn.commit().map_err(|e| e.to_string())?;
+        self.events.publish(EventTopic::Activity, "cleared", ());
         Ok(())
     }
 }
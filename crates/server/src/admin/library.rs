@@ -10,6 +10,7 @@ use axum::{
 use serde_json::json;
 use common::{Album, Artist, Track};
 pub use library::{Library, LibraryStats};
+use library::{AlbumSidecar, ArtistSidecar, LibraryError};
 use crate::state::LibraryStatus;
 use tracing::warn;
 
@@ -21,6 +22,7 @@ use crate::assets::{
 use crate::scan::{start_cover_sweep, start_enrichment_sweep, start_rescan};
 use crate::state::{
     AdminLibraryQuery, AppState, ArtistCoverQuery, HealthResponse, LibraryStatusResponse,
+    SidecarWriteQuery,
 };
 use crate::utils::{
     apply_template, escape_html, format_duration_ms, format_track_position, html_error,
@@ -52,52 +54,7 @@ pub async fn admin_library_status(State(state): State<AppState>, headers: Header
     }
 
     let guard = state.library_state.read();
-    let (status, message, artists, albums, tracks) = match &guard.status {
-        LibraryStatus::Unconfigured => (
-            "unconfigured".to_string(),
-            Some("music directory must be set".to_string()),
-            None,
-            None,
-            None,
-        ),
-        LibraryStatus::Missing(path) => (
-            "missing".to_string(),
-            Some(format!("music directory not found: {}", path.display())),
-            None,
-            None,
-            None,
-        ),
-        LibraryStatus::Scanning { started } => {
-            let since = started
-                .elapsed()
-                .map(|elapsed: std::time::Duration| format!("library scan in progress ({}s)", elapsed.as_secs()))
-                .unwrap_or_else(|_| "library scan in progress".to_string());
-            ("scanning".to_string(), Some(since), None, None, None)
-        }
-        LibraryStatus::Ready(stats) => (
-            "ready".to_string(),
-            None,
-            Some(stats.artists),
-            Some(stats.albums),
-            Some(stats.tracks),
-        ),
-        LibraryStatus::Error(message) => (
-            "error".to_string(),
-            Some(message.clone()),
-            None,
-            None,
-            None,
-        ),
-    };
-
-    Json(LibraryStatusResponse {
-        status,
-        message,
-        artists,
-        albums,
-        tracks,
-    })
-    .into_response()
+    Json(LibraryStatusResponse::from_status(&guard.status)).into_response()
 }
 
 pub async fn admin_reindex(State(state): State<AppState>, headers: HeaderMap) -> Response {
@@ -272,6 +229,122 @@ pub async fn admin_artist_cover(
     }
 }
 
+pub async fn admin_album_sidecar(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    AxumPath(album_id): AxumPath<String>,
+) -> Response {
+    let library = match sidecar_library(&state, &headers) {
+        Ok(library) => library,
+        Err(response) => return response,
+    };
+    match library.read_album_sidecar(&album_id) {
+        Ok(Some(sidecar)) => Json(sidecar).into_response(),
+        Ok(None) => json_error_response(StatusCode::NOT_FOUND, "album not found"),
+        Err(err) => json_error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("library error: {}", err),
+        ),
+    }
+}
+
+pub async fn admin_update_album_sidecar(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<SidecarWriteQuery>,
+    AxumPath(album_id): AxumPath<String>,
+    Json(sidecar): Json<AlbumSidecar>,
+) -> Response {
+    let library = match sidecar_library(&state, &headers) {
+        Ok(library) => library,
+        Err(response) => return response,
+    };
+    let result = library.write_album_sidecar(&album_id, sidecar);
+    sidecar_write_response(&state, library, query, result, "album not found")
+}
+
+pub async fn admin_artist_sidecar(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    AxumPath(artist_id): AxumPath<String>,
+) -> Response {
+    let library = match sidecar_library(&state, &headers) {
+        Ok(library) => library,
+        Err(response) => return response,
+    };
+    match library.read_artist_sidecar(&artist_id) {
+        Ok(Some(sidecar)) => Json(sidecar).into_response(),
+        Ok(None) => json_error_response(StatusCode::NOT_FOUND, "artist not found"),
+        Err(err) => json_error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("library error: {}", err),
+        ),
+    }
+}
+
+pub async fn admin_update_artist_sidecar(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<SidecarWriteQuery>,
+    AxumPath(artist_id): AxumPath<String>,
+    Json(sidecar): Json<ArtistSidecar>,
+) -> Response {
+    let library = match sidecar_library(&state, &headers) {
+        Ok(library) => library,
+        Err(response) => return response,
+    };
+    let result = library.write_artist_sidecar(&artist_id, sidecar);
+    sidecar_write_response(&state, library, query, result, "artist not found")
+}
+
+fn sidecar_library(state: &AppState, headers: &HeaderMap) -> Result<Library, Response> {
+    let user = match admin_user_from_headers(state, headers) {
+        Ok(Some(user)) => user,
+        Ok(None) => return Err(json_error_response(StatusCode::UNAUTHORIZED, "unauthorized")),
+        Err(err) => {
+            return Err(json_error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("auth error: {}", err),
+            ))
+        }
+    };
+    if !is_admin(&user) {
+        return Err(json_error_response(StatusCode::FORBIDDEN, "forbidden"));
+    }
+    library_or_response(state)
+}
+
+// Incremental scans skip albums that are already indexed, so a sidecar write
+// triggers a full rescan unless the caller is batching edits and asks to skip
+// it.
+fn sidecar_write_response(
+    state: &AppState,
+    library: Library,
+    query: SidecarWriteQuery,
+    result: Result<bool, LibraryError>,
+    not_found: &str,
+) -> Response {
+    match result {
+        Ok(true) => {}
+        Ok(false) => return json_error_response(StatusCode::NOT_FOUND, not_found),
+        Err(LibraryError::Sidecar(message)) => {
+            return json_error_response(StatusCode::BAD_REQUEST, message)
+        }
+        Err(err) => {
+            return json_error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("library error: {}", err),
+            )
+        }
+    }
+    if query.rescan.unwrap_or(true) {
+        start_rescan(state.clone(), library, false);
+        (StatusCode::ACCEPTED, Json(HealthResponse { status: "indexing" })).into_response()
+    } else {
+        Json(HealthResponse { status: "ok" }).into_response()
+    }
+}
+
 fn admin_library_page(
     state: &AppState,
     query: AdminLibraryQuery,
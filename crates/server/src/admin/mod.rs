@@ -76,6 +76,14 @@ pub fn admin_router(state: AppState) -> Router {
             "/covers/artists/:artist_id",
             get(library::admin_artist_cover),
         )
+        .route(
+            "/library/albums/:album_id/sidecar",
+            get(library::admin_album_sidecar).post(library::admin_update_album_sidecar),
+        )
+        .route(
+            "/library/artists/:artist_id/sidecar",
+            get(library::admin_artist_sidecar).post(library::admin_update_artist_sidecar),
+        )
         .route(
             "/users",
             get(users::admin_users).post(users::admin_create_user),
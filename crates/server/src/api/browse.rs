@@ -6,10 +6,16 @@ use axum::{
     Extension, Json,
 };
 use serde::Serialize;
-use common::Artist;
+use common::{Album, AlbumType, Artist};
+use library::{album_chronology_key, composer_id, group_by_work, ComposerInfo, GenreInfo};
 
-use crate::state::{AppState, ArtistQuery, AuthContext, JsonResult, ListResponse, Playlist};
+use crate::state::{
+    AlbumTracksQuery, AppState, ArtistAlbumsQuery, ArtistQuery, AuthContext, ComposerQuery,
+    GenreQuery, JsonResult, ListResponse, Playlist, WaveformQuery,
+};
+use crate::stream_limits::JobPriority;
 use crate::utils::json_error;
+use crate::waveform::{self, WaveformError};
 
 use super::library_or_json_error;
 
@@ -22,6 +28,7 @@ pub struct BrowseArtist {
     pub summary: Option<String>,
     pub logo_ref: Option<String>,
     pub banner_ref: Option<String>,
+    pub musicbrainz_id: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -31,9 +38,36 @@ pub struct BrowseAlbum {
     pub artist_name: String,
     pub title: String,
     pub year: Option<i32>,
+    pub release_date: Option<String>,
+    pub original_date: Option<String>,
+    pub album_type: Option<AlbumType>,
+    pub label: Option<String>,
+    pub catalog_number: Option<String>,
     pub genres: Vec<String>,
     pub track_count: usize,
     pub summary: Option<String>,
+    pub musicbrainz_release_id: Option<String>,
+    pub musicbrainz_release_group_id: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct ComposerRef {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Serialize)]
+pub struct WorkGroupView {
+    pub work: Option<String>,
+    pub composers: Vec<ComposerRef>,
+    pub tracks: Vec<TrackView>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum AlbumTracksResponse {
+    Tracks(Vec<TrackView>),
+    Works(Vec<WorkGroupView>),
 }
 
 #[derive(Serialize, Clone)]
@@ -49,6 +83,14 @@ pub struct TrackView {
     pub disc_no: Option<u16>,
     pub liked: bool,
     pub in_playlists: bool,
+    pub musicbrainz_recording_id: Option<String>,
+    pub composers: Vec<ComposerRef>,
+    pub conductor: Option<String>,
+    pub performers: Vec<String>,
+    pub work: Option<String>,
+    pub movement: Option<String>,
+    pub movement_no: Option<u16>,
+    pub movement_total: Option<u16>,
 }
 
 pub async fn list_artists(
@@ -71,30 +113,36 @@ pub async fn list_artists(
         }
     };
 
-    let mut items = Vec::with_capacity(artists.len());
-    for artist in artists {
-        let album_count = match library.list_artist_albums(&artist.id) {
-            Ok(albums) => albums.len(),
-            Err(_) => 0,
-        };
-        items.push(BrowseArtist {
-            id: artist.id,
-            name: artist.name,
-            genres: artist.genres,
-            album_count,
-            summary: artist.summary,
-            logo_ref: artist.logo_ref,
-            banner_ref: artist.banner_ref,
-        });
-    }
+    let items = artists
+        .into_iter()
+        .map(|artist| browse_artist(&library, artist))
+        .collect();
 
     Ok(Json(ListResponse { items, total }))
 }
 
+fn browse_artist(library: &library::Library, artist: Artist) -> BrowseArtist {
+    let album_count = match library.list_artist_albums(&artist.id) {
+        Ok(albums) => albums.len(),
+        Err(_) => 0,
+    };
+    BrowseArtist {
+        id: artist.id,
+        name: artist.name,
+        genres: artist.genres,
+        album_count,
+        summary: artist.summary,
+        logo_ref: artist.logo_ref,
+        banner_ref: artist.banner_ref,
+        musicbrainz_id: artist.musicbrainz_id,
+    }
+}
+
 pub async fn list_artist_albums(
     State(state): State<AppState>,
     Extension(_ctx): Extension<AuthContext>,
     AxumPath(artist_id): AxumPath<String>,
+    Query(params): Query<ArtistAlbumsQuery>,
 ) -> JsonResult<Vec<BrowseAlbum>> {
     let library = library_or_json_error(&state)?;
     let artist = match library.get_artist(&artist_id) {
@@ -107,7 +155,7 @@ pub async fn list_artist_albums(
             ))
         }
     };
-    let albums = match library.list_artist_albums(&artist_id) {
+    let mut albums = match library.list_artist_albums(&artist_id) {
         Ok(albums) => albums,
         Err(err) => {
             return Err(json_error(
@@ -116,27 +164,70 @@ pub async fn list_artist_albums(
             ))
         }
     };
-
-    let mut items = Vec::with_capacity(albums.len());
-    for album in albums {
-        let track_count = match library.get_album_tracks(&album.id) {
-            Ok(tracks) => tracks.len(),
-            Err(_) => 0,
-        };
-        items.push(BrowseAlbum {
-            id: album.id,
-            artist_id: album.artist_id,
-            artist_name: artist.name.clone(),
-            title: album.title,
-            year: album.year,
-            genres: album.genres,
-            track_count,
-            summary: album.summary,
-        });
+    if let Err(message) = order_artist_albums(&mut albums, &params) {
+        return Err(json_error(StatusCode::BAD_REQUEST, message));
     }
+
+    let items = albums
+        .into_iter()
+        .map(|album| browse_album(&library, album, artist.name.clone()))
+        .collect();
     Ok(Json(items))
 }
 
+fn browse_album(library: &library::Library, album: Album, artist_name: String) -> BrowseAlbum {
+    let track_count = match library.get_album_tracks(&album.id) {
+        Ok(tracks) => tracks.len(),
+        Err(_) => 0,
+    };
+    BrowseAlbum {
+        id: album.id,
+        artist_id: album.artist_id,
+        artist_name,
+        title: album.title,
+        year: album.year,
+        release_date: album.release_date,
+        original_date: album.original_date,
+        album_type: album.album_type,
+        label: album.label,
+        catalog_number: album.catalog_number,
+        genres: album.genres,
+        track_count,
+        summary: album.summary,
+        musicbrainz_release_id: album.musicbrainz_release_id,
+        musicbrainz_release_group_id: album.musicbrainz_release_group_id,
+    }
+}
+
+// The library already returns albums oldest first; `sort` re-orders and
+// `group=type` then clusters them by release type (untyped albums count as
+// albums).
+fn order_artist_albums(albums: &mut [Album], params: &ArtistAlbumsQuery) -> Result<(), String> {
+    match params.sort.as_deref().map(str::trim).unwrap_or("date") {
+        "" | "date" => {}
+        "-date" => albums.sort_by_key(|album| std::cmp::Reverse(album_chronology_key(album))),
+        "title" => albums.sort_by_key(|album| {
+            album
+                .sort_title
+                .as_deref()
+                .unwrap_or(&album.title)
+                .to_lowercase()
+        }),
+        other => return Err(format!("invalid sort: {}", other)),
+    }
+    match params.group.as_deref().map(str::trim).unwrap_or("") {
+        "" | "none" => {}
+        "type" => albums.sort_by_key(|album| {
+            album
+                .album_type
+                .unwrap_or(AlbumType::Album)
+                .group_order()
+        }),
+        other => return Err(format!("invalid group: {}", other)),
+    }
+    Ok(())
+}
+
 pub async fn get_artist(
     State(state): State<AppState>,
     Extension(_ctx): Extension<AuthContext>,
@@ -157,7 +248,18 @@ pub async fn list_album_tracks(
     State(state): State<AppState>,
     Extension(_ctx): Extension<AuthContext>,
     AxumPath(album_id): AxumPath<String>,
-) -> JsonResult<Vec<TrackView>> {
+    Query(params): Query<AlbumTracksQuery>,
+) -> JsonResult<AlbumTracksResponse> {
+    let group_by_works = match params.group.as_deref().map(str::trim).unwrap_or("") {
+        "" | "none" => false,
+        "work" => true,
+        other => {
+            return Err(json_error(
+                StatusCode::BAD_REQUEST,
+                format!("invalid group: {}", other),
+            ))
+        }
+    };
     let library = library_or_json_error(&state)?;
     let mut tracks = match library.get_album_tracks(&album_id) {
         Ok(tracks) => tracks,
@@ -182,13 +284,31 @@ pub async fn list_album_tracks(
     let liked_set = liked_set(&state)?;
     let playlist_set = playlist_set(&state)?;
 
+    if group_by_works {
+        let mut groups = Vec::new();
+        for group in group_by_work(tracks) {
+            let mut items = Vec::with_capacity(group.tracks.len());
+            for track in &group.tracks {
+                if let Ok(view) = build_track_view(&library, track, &liked_set, &playlist_set) {
+                    items.push(view);
+                }
+            }
+            groups.push(WorkGroupView {
+                work: group.work,
+                composers: composer_refs(&group.composers),
+                tracks: items,
+            });
+        }
+        return Ok(Json(AlbumTracksResponse::Works(groups)));
+    }
+
     let mut items = Vec::with_capacity(tracks.len());
     for track in tracks {
         if let Ok(view) = build_track_view(&library, &track, &liked_set, &playlist_set) {
             items.push(view);
         }
     }
-    Ok(Json(items))
+    Ok(Json(AlbumTracksResponse::Tracks(items)))
 }
 
 pub async fn get_track(
@@ -213,6 +333,62 @@ pub async fn get_track(
     Ok(Json(view))
 }
 
+#[derive(Serialize)]
+pub struct WaveformView {
+    pub track_id: String,
+    pub duration_ms: u32,
+    pub points: usize,
+    pub min: Vec<f32>,
+    pub max: Vec<f32>,
+}
+
+pub async fn get_track_waveform(
+    State(state): State<AppState>,
+    Extension(_ctx): Extension<AuthContext>,
+    AxumPath(track_id): AxumPath<String>,
+    Query(query): Query<WaveformQuery>,
+) -> JsonResult<WaveformView> {
+    let library = library_or_json_error(&state)?;
+    let track = match library.get_track(&track_id) {
+        Ok(Some(track)) => track,
+        Ok(None) => return Err(json_error(StatusCode::NOT_FOUND, "track not found".to_string())),
+        Err(err) => {
+            return Err(json_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("library error: {}", err),
+            ))
+        }
+    };
+    let points = query.points.unwrap_or(waveform::DEFAULT_POINTS);
+    if points == 0 {
+        return Err(json_error(StatusCode::BAD_REQUEST, "points must be positive".to_string()));
+    }
+    let stored = tokio::task::spawn_blocking(move || {
+        waveform::load_or_compute(&state, &library, &track, &JobPriority::new(true))
+    })
+    .await
+    .map_err(|err| json_error(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?
+    .map_err(|err| match err {
+        WaveformError::FileMissing => json_error(StatusCode::NOT_FOUND, err.to_string()),
+        WaveformError::Failed(message) => json_error(StatusCode::INTERNAL_SERVER_ERROR, message),
+    })?;
+    let (min, max) = waveform::downsample(&stored, points);
+    // Three decimals is finer than any scrubber can draw.
+    let round = |peaks: Vec<f32>| {
+        peaks
+            .into_iter()
+            .map(|peak| (peak * 1000.0).round() / 1000.0)
+            .collect()
+    };
+    Ok(Json(WaveformView {
+        track_id,
+        duration_ms: stored.duration_ms,
+        points: min.len(),
+        min: round(min),
+        max: round(max),
+    }))
+}
+
 pub async fn list_playlist_tracks(
     State(state): State<AppState>,
     Extension(_ctx): Extension<AuthContext>,
@@ -261,6 +437,245 @@ pub async fn list_liked_tracks(
     Ok(Json(items))
 }
 
+pub async fn list_genres(
+    State(state): State<AppState>,
+    Extension(_ctx): Extension<AuthContext>,
+    Query(params): Query<GenreQuery>,
+) -> JsonResult<ListResponse<GenreInfo>> {
+    let library = library_or_json_error(&state)?;
+    let limit = params.limit.unwrap_or(200).max(1);
+    let offset = params.offset.unwrap_or(0);
+    match library.list_genres(params.search.as_deref(), limit, offset) {
+        Ok((items, total)) => Ok(Json(ListResponse { items, total })),
+        Err(err) => Err(json_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("library error: {}", err),
+        )),
+    }
+}
+
+pub async fn get_genre(
+    State(state): State<AppState>,
+    Extension(_ctx): Extension<AuthContext>,
+    AxumPath(genre_id): AxumPath<String>,
+) -> JsonResult<GenreInfo> {
+    let library = library_or_json_error(&state)?;
+    genre_or_json_error(&library, &genre_id).map(Json)
+}
+
+pub async fn list_genre_albums(
+    State(state): State<AppState>,
+    Extension(_ctx): Extension<AuthContext>,
+    AxumPath(genre_id): AxumPath<String>,
+    Query(params): Query<GenreQuery>,
+) -> JsonResult<ListResponse<BrowseAlbum>> {
+    let library = library_or_json_error(&state)?;
+    genre_or_json_error(&library, &genre_id)?;
+    let limit = params.limit.unwrap_or(200).max(1);
+    let offset = params.offset.unwrap_or(0);
+    let (albums, total) = library
+        .list_genre_albums(&genre_id, limit, offset)
+        .map_err(|err| {
+            json_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("library error: {}", err),
+            )
+        })?;
+    let items = albums
+        .into_iter()
+        .map(|album| {
+            let artist_name = library
+                .get_artist(&album.artist_id)
+                .ok()
+                .flatten()
+                .map(|artist| artist.name)
+                .unwrap_or_else(|| "Unknown Artist".to_string());
+            browse_album(&library, album, artist_name)
+        })
+        .collect();
+    Ok(Json(ListResponse { items, total }))
+}
+
+pub async fn list_genre_artists(
+    State(state): State<AppState>,
+    Extension(_ctx): Extension<AuthContext>,
+    AxumPath(genre_id): AxumPath<String>,
+    Query(params): Query<GenreQuery>,
+) -> JsonResult<ListResponse<BrowseArtist>> {
+    let library = library_or_json_error(&state)?;
+    genre_or_json_error(&library, &genre_id)?;
+    let limit = params.limit.unwrap_or(200).max(1);
+    let offset = params.offset.unwrap_or(0);
+    let (artists, total) = library
+        .list_genre_artists(&genre_id, limit, offset)
+        .map_err(|err| {
+            json_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("library error: {}", err),
+            )
+        })?;
+    let items = artists
+        .into_iter()
+        .map(|artist| browse_artist(&library, artist))
+        .collect();
+    Ok(Json(ListResponse { items, total }))
+}
+
+pub async fn list_genre_tracks(
+    State(state): State<AppState>,
+    Extension(_ctx): Extension<AuthContext>,
+    AxumPath(genre_id): AxumPath<String>,
+    Query(params): Query<GenreQuery>,
+) -> JsonResult<ListResponse<TrackView>> {
+    let library = library_or_json_error(&state)?;
+    genre_or_json_error(&library, &genre_id)?;
+    let limit = params.limit.unwrap_or(200).max(1);
+    let offset = params.offset.unwrap_or(0);
+    let (tracks, total) = library
+        .list_genre_tracks(&genre_id, limit, offset)
+        .map_err(|err| {
+            json_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("library error: {}", err),
+            )
+        })?;
+    let liked_set = liked_set(&state)?;
+    let playlist_set = playlist_set(&state)?;
+    let mut items = Vec::with_capacity(tracks.len());
+    for track in tracks {
+        if let Ok(view) = build_track_view(&library, &track, &liked_set, &playlist_set) {
+            items.push(view);
+        }
+    }
+    Ok(Json(ListResponse { items, total }))
+}
+
+pub async fn list_composers(
+    State(state): State<AppState>,
+    Extension(_ctx): Extension<AuthContext>,
+    Query(params): Query<ComposerQuery>,
+) -> JsonResult<ListResponse<ComposerInfo>> {
+    let library = library_or_json_error(&state)?;
+    let limit = params.limit.unwrap_or(200).max(1);
+    let offset = params.offset.unwrap_or(0);
+    match library.list_composers(params.search.as_deref(), limit, offset) {
+        Ok((items, total)) => Ok(Json(ListResponse { items, total })),
+        Err(err) => Err(json_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("library error: {}", err),
+        )),
+    }
+}
+
+pub async fn get_composer(
+    State(state): State<AppState>,
+    Extension(_ctx): Extension<AuthContext>,
+    AxumPath(composer_id): AxumPath<String>,
+) -> JsonResult<ComposerInfo> {
+    let library = library_or_json_error(&state)?;
+    composer_or_json_error(&library, &composer_id).map(Json)
+}
+
+pub async fn list_composer_albums(
+    State(state): State<AppState>,
+    Extension(_ctx): Extension<AuthContext>,
+    AxumPath(composer_id): AxumPath<String>,
+    Query(params): Query<ComposerQuery>,
+) -> JsonResult<ListResponse<BrowseAlbum>> {
+    let library = library_or_json_error(&state)?;
+    composer_or_json_error(&library, &composer_id)?;
+    let limit = params.limit.unwrap_or(200).max(1);
+    let offset = params.offset.unwrap_or(0);
+    let (albums, total) = library
+        .list_composer_albums(&composer_id, limit, offset)
+        .map_err(|err| {
+            json_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("library error: {}", err),
+            )
+        })?;
+    let items = albums
+        .into_iter()
+        .map(|album| {
+            let artist_name = library
+                .get_artist(&album.artist_id)
+                .ok()
+                .flatten()
+                .map(|artist| artist.name)
+                .unwrap_or_else(|| "Unknown Artist".to_string());
+            browse_album(&library, album, artist_name)
+        })
+        .collect();
+    Ok(Json(ListResponse { items, total }))
+}
+
+pub async fn list_composer_tracks(
+    State(state): State<AppState>,
+    Extension(_ctx): Extension<AuthContext>,
+    AxumPath(composer_id): AxumPath<String>,
+    Query(params): Query<ComposerQuery>,
+) -> JsonResult<ListResponse<TrackView>> {
+    let library = library_or_json_error(&state)?;
+    composer_or_json_error(&library, &composer_id)?;
+    let limit = params.limit.unwrap_or(200).max(1);
+    let offset = params.offset.unwrap_or(0);
+    let (tracks, total) = library
+        .list_composer_tracks(&composer_id, limit, offset)
+        .map_err(|err| {
+            json_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("library error: {}", err),
+            )
+        })?;
+    let liked_set = liked_set(&state)?;
+    let playlist_set = playlist_set(&state)?;
+    let mut items = Vec::with_capacity(tracks.len());
+    for track in tracks {
+        if let Ok(view) = build_track_view(&library, &track, &liked_set, &playlist_set) {
+            items.push(view);
+        }
+    }
+    Ok(Json(ListResponse { items, total }))
+}
+
+fn composer_or_json_error(
+    library: &library::Library,
+    composer_id: &str,
+) -> Result<ComposerInfo, (StatusCode, Json<crate::state::ErrorResponse>)> {
+    match library.get_composer(composer_id) {
+        Ok(Some(composer)) => Ok(composer),
+        Ok(None) => Err(json_error(StatusCode::NOT_FOUND, "composer not found".to_string())),
+        Err(err) => Err(json_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("library error: {}", err),
+        )),
+    }
+}
+
+pub(super) fn composer_refs(names: &[String]) -> Vec<ComposerRef> {
+    names
+        .iter()
+        .map(|name| ComposerRef {
+            id: composer_id(name),
+            name: name.clone(),
+        })
+        .collect()
+}
+
+fn genre_or_json_error(
+    library: &library::Library,
+    genre_id: &str,
+) -> Result<GenreInfo, (StatusCode, Json<crate::state::ErrorResponse>)> {
+    match library.get_genre(genre_id) {
+        Ok(Some(genre)) => Ok(genre),
+        Ok(None) => Err(json_error(StatusCode::NOT_FOUND, "genre not found".to_string())),
+        Err(err) => Err(json_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("library error: {}", err),
+        )),
+    }
+}
+
 fn liked_set(state: &AppState) -> Result<HashSet<String>, (StatusCode, Json<crate::state::ErrorResponse>)> {
     let liked_ids = state
         .user_data
@@ -317,5 +732,13 @@ fn build_track_view(
         disc_no: track.disc_no,
         liked: liked_set.contains(&track.id),
         in_playlists: playlist_set.contains(&track.id),
+        musicbrainz_recording_id: track.musicbrainz_recording_id.clone(),
+        composers: composer_refs(&track.composers),
+        conductor: track.conductor.clone(),
+        performers: track.performers.clone(),
+        work: track.work.clone(),
+        movement: track.movement.clone(),
+        movement_no: track.movement_no,
+        movement_total: track.movement_total,
     })
 }
@@ -0,0 +1,118 @@
+use std::collections::HashSet;
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Query, State,
+    },
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::admin::is_admin;
+use crate::events::{EventMessage, EventTopic};
+use crate::state::{AppState, AuthContext, EventStreamQuery};
+use crate::utils::json_error_response;
+
+/// `GET /api/v1/events`: bus events as WebSocket text frames. Without
+/// `topics` the stream carries every topic the user may see.
+pub async fn event_stream(
+    State(state): State<AppState>,
+    Extension(ctx): Extension<AuthContext>,
+    Query(query): Query<EventStreamQuery>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    let admin = is_admin(&ctx.user);
+    let topics = match query.topics.as_deref() {
+        Some(names) => {
+            let mut topics = HashSet::new();
+            for name in names.split(',').filter(|name| !name.trim().is_empty()) {
+                let Some(topic) = EventTopic::parse(name) else {
+                    return json_error_response(
+                        StatusCode::BAD_REQUEST,
+                        format!("unknown event topic: {}", name.trim()),
+                    );
+                };
+                if topic.admin_only() && !admin {
+                    return json_error_response(
+                        StatusCode::FORBIDDEN,
+                        format!("{} events are for admins", topic.name()),
+                    );
+                }
+                topics.insert(topic);
+            }
+            topics
+        }
+        None => EventTopic::ALL
+            .into_iter()
+            .filter(|topic| admin || !topic.admin_only())
+            .collect(),
+    };
+    // Without the epoch the client's `since` can't be trusted.
+    let since = query.since.map(|since| (query.epoch.unwrap_or(0), since));
+    let user_id = ctx.user.id;
+    ws.on_upgrade(move |socket| forward_events(state, socket, user_id, topics, since))
+        .into_response()
+}
+
+async fn forward_events(
+    state: AppState,
+    mut socket: WebSocket,
+    user_id: String,
+    topics: HashSet<EventTopic>,
+    since: Option<(u64, u64)>,
+) {
+    let mut sub = state.events.subscribe(since);
+    let epoch = state.events.epoch();
+    let mut names: Vec<&'static str> = topics.iter().map(|topic| topic.name()).collect();
+    names.sort_unstable();
+    let mut opening = vec![encode(&EventMessage::Subscribed {
+        topics: names,
+        seq: sub.seq,
+        epoch,
+    })];
+    match sub.replay.take() {
+        Some(events) => opening.extend(
+            events
+                .iter()
+                .filter(|event| topics.contains(&event.topic) && event.visible_to(Some(&user_id)))
+                .map(|event| encode(&EventMessage::Event(event))),
+        ),
+        None if since.is_some() => opening.push(encode(&EventMessage::ResyncRequired { epoch })),
+        None => {}
+    }
+    for text in opening {
+        if socket.send(Message::Text(text)).await.is_err() {
+            return;
+        }
+    }
+    loop {
+        let text = tokio::select! {
+            received = sub.receiver.recv() => match received {
+                Ok(event) => {
+                    if !topics.contains(&event.topic) || !event.visible_to(Some(&user_id)) {
+                        continue;
+                    }
+                    encode(&EventMessage::Event(&event))
+                }
+                Err(RecvError::Lagged(_)) => encode(&EventMessage::ResyncRequired { epoch }),
+                Err(RecvError::Closed) => break,
+            },
+            incoming = socket.recv() => match incoming {
+                // Nothing is expected from the client; pings are answered
+                // by axum.
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+        };
+        if socket.send(Message::Text(text)).await.is_err() {
+            break;
+        }
+    }
+}
+
+fn encode(message: &EventMessage<'_>) -> String {
+    serde_json::to_string(message).unwrap_or_default()
+}
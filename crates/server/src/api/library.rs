@@ -20,6 +20,7 @@ use crate::state::{
 };
 use crate::utils::{json_error, json_error_response};
 
+use super::browse::{composer_refs, ComposerRef};
 use super::{library_or_json_error, library_or_response};
 
 const DEFAULT_SEARCH_LIMIT: usize = 40;
@@ -99,6 +100,28 @@ pub async fn search(
         }
     }
 
+    let (composers, _) = match library.list_composers(Some(query), limit, 0) {
+        Ok(value) => value,
+        Err(err) => {
+            return Err(json_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("library error: {}", err),
+            ))
+        }
+    };
+    for composer in composers {
+        let score = score_match(&normalized, &composer.name);
+        if score > 0 {
+            results.push(SearchResult {
+                kind: "composer".to_string(),
+                id: composer.id,
+                title: composer.name,
+                subtitle: None,
+                score,
+            });
+        }
+    }
+
     let albums = match fetch_albums_for_search(&library, query, limit) {
         Ok(items) => items,
         Err(err) => {
@@ -129,7 +152,19 @@ pub async fn search(
     }
 
     let tracks = match fetch_tracks_for_search(&library, query, limit) {
-        Ok(items) => items,
+        Ok(mut items) => {
+            let mut seen: HashSet<String> = items.iter().map(|track| track.id.clone()).collect();
+            let work_tracks = library.search_work_tracks(query, limit).unwrap_or_default();
+            let credit_tracks = library
+                .search_credit_tracks(query, limit)
+                .unwrap_or_default();
+            for track in work_tracks.into_iter().chain(credit_tracks) {
+                if seen.insert(track.id.clone()) {
+                    items.push(track);
+                }
+            }
+            items
+        }
         Err(err) => {
             return Err(json_error(
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -150,7 +185,16 @@ pub async fn search(
             .flatten()
             .map(|album| album.title)
             .unwrap_or_else(|| "Unknown Album".to_string());
-        let combined = format!("{} {} {}", track.title, artist_name, album_title);
+        let combined = format!(
+            "{} {} {} {} {} {} {}",
+            track.title,
+            artist_name,
+            album_title,
+            track.work.as_deref().unwrap_or(""),
+            track.composers.join(" "),
+            track.conductor.as_deref().unwrap_or(""),
+            track.performers.join(" ")
+        );
         let score = score_match(&normalized, &combined);
         if score > 0 {
             results.push(SearchResult {
@@ -182,6 +226,14 @@ pub struct TrackView {
     pub disc_no: Option<u16>,
     pub liked: bool,
     pub in_playlists: bool,
+    pub musicbrainz_recording_id: Option<String>,
+    pub composers: Vec<ComposerRef>,
+    pub conductor: Option<String>,
+    pub performers: Vec<String>,
+    pub work: Option<String>,
+    pub movement: Option<String>,
+    pub movement_no: Option<u16>,
+    pub movement_total: Option<u16>,
 }
 
 pub async fn shuffle_tracks(
@@ -488,6 +540,14 @@ fn build_track_view(
         disc_no: track.disc_no,
         liked: liked_set.contains(&track.id),
         in_playlists: playlist_set.contains(&track.id),
+        musicbrainz_recording_id: track.musicbrainz_recording_id.clone(),
+        composers: composer_refs(&track.composers),
+        conductor: track.conductor.clone(),
+        performers: track.performers.clone(),
+        work: track.work.clone(),
+        movement: track.movement.clone(),
+        movement_no: track.movement_no,
+        movement_total: track.movement_total,
     })
 }
 
@@ -1,5 +1,6 @@
 pub mod auth;
 pub mod browse;
+pub mod events;
 pub mod library;
 pub mod player;
 pub mod server;
@@ -45,6 +46,16 @@ pub fn api_router(state: AppState) -> Router {
         .route("/browse/artists/:artist_id/albums", get(browse::list_artist_albums))
         .route("/browse/albums/:album_id/tracks", get(browse::list_album_tracks))
         .route("/browse/tracks/:track_id", get(browse::get_track))
+        .route("/browse/tracks/:track_id/waveform", get(browse::get_track_waveform))
+        .route("/browse/composers", get(browse::list_composers))
+        .route("/browse/composers/:composer_id", get(browse::get_composer))
+        .route("/browse/composers/:composer_id/albums", get(browse::list_composer_albums))
+        .route("/browse/composers/:composer_id/tracks", get(browse::list_composer_tracks))
+        .route("/browse/genres", get(browse::list_genres))
+        .route("/browse/genres/:genre_id", get(browse::get_genre))
+        .route("/browse/genres/:genre_id/albums", get(browse::list_genre_albums))
+        .route("/browse/genres/:genre_id/artists", get(browse::list_genre_artists))
+        .route("/browse/genres/:genre_id/tracks", get(browse::list_genre_tracks))
         .route(
             "/browse/playlists/:playlist_id/tracks",
             get(browse::list_playlist_tracks),
@@ -55,6 +66,7 @@ pub fn api_router(state: AppState) -> Router {
         .route("/stats", get(stats::get_stats))
         .route("/player/settings", get(player::get_playback_settings))
         .route("/player/settings", post(player::update_playback_settings))
+        .route("/events", get(events::event_stream))
         .layer(middleware::from_fn_with_state(state.clone(), require_auth));
 
     Router::new()
@@ -3,6 +3,7 @@ use axum::{
     http::StatusCode,
     Extension, Json,
 };
+use library::genre_id;
 use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
 
@@ -117,10 +118,16 @@ pub async fn get_stats(
         })
         .collect();
 
-    let top_genres = top_n(&stats.genre_ms, 5)
+    // Listens are recorded with the raw labels of the day; fold them onto the
+    // current canonical names so aliases don't split the ranking.
+    let mut genre_ms: std::collections::HashMap<String, u64> = std::collections::HashMap::new();
+    for (genre, ms) in &stats.genre_ms {
+        *genre_ms.entry(library.canonical_genre(genre)).or_default() += ms;
+    }
+    let top_genres = top_n(&genre_ms, 5)
         .into_iter()
         .map(|(genre, ms)| StatsItem {
-            id: genre.clone(),
+            id: genre_id(&genre),
             name: genre,
             minutes: ms_to_minutes(ms),
         })
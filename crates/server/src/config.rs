@@ -37,6 +37,7 @@ pub struct ServerConfig {
     pub music_root: String,
     pub index_path: String,
     pub metadata_path: String,
+    pub genre_map_path: String,
     pub port: u16,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub bind_addr: Option<String>,
@@ -45,6 +46,13 @@ pub struct ServerConfig {
     pub quic_cert_path: String,
     pub quic_key_path: String,
     pub quic_self_signed: bool,
+    pub quic_workers: usize,
+    pub transcode_cache_enabled: bool,
+    pub transcode_cache_max_mb: u64,
+    pub transcode_workers: usize,
+    pub max_streams_per_user: u32,
+    pub abr_ladder_kbps: Vec<u32>,
+    pub waveform_sweep_enabled: bool,
     pub watch_music: bool,
     pub watch_debounce_secs: u64,
     pub session_ttl_secs: u64,
@@ -69,6 +77,7 @@ impl Default for ServerConfig {
             music_root: "".to_string(),
             index_path: "library.redb".to_string(),
             metadata_path: "metadata".to_string(),
+            genre_map_path: "genres.yaml".to_string(),
             port: 3000,
             bind_addr: None,
             quic_enabled: true,
@@ -76,6 +85,13 @@ impl Default for ServerConfig {
             quic_cert_path: "quic_cert.pem".to_string(),
             quic_key_path: "quic_key.pem".to_string(),
             quic_self_signed: true,
+            quic_workers: 0,
+            transcode_cache_enabled: false,
+            transcode_cache_max_mb: 2048,
+            transcode_workers: 0,
+            max_streams_per_user: 3,
+            abr_ladder_kbps: crate::stream_sessions::DEFAULT_LADDER_KBPS.to_vec(),
+            waveform_sweep_enabled: false,
             watch_music: true,
             watch_debounce_secs: 2,
             session_ttl_secs: 60 * 60 * 24 * 7,
@@ -163,6 +179,9 @@ pub fn load_or_create_config(path: &Path) -> Result<(ServerConfig, bool), Config
         if config.metadata_path.trim().is_empty() {
             config.metadata_path = "metadata".to_string();
         }
+        if config.genre_map_path.trim().is_empty() {
+            config.genre_map_path = "genres.yaml".to_string();
+        }
         if config.port == 0 {
             if let Some(bind_addr) = config.bind_addr.as_deref() {
                 if let Some(port) = parse_port(bind_addr) {
@@ -0,0 +1,227 @@
+//! Channel layouts and mixing matrices for the transcoder.
+//!
+//! Sources arrive in symphonia's (WAVE mask) channel order. Stereo and mono
+//! targets use ITU-R BS.775 fold-down coefficients; surround targets use the
+//! Vorbis order Opus mapping family 1 expects.
+
+use symphonia::core::audio::Channels;
+
+const MINUS_3DB: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Mono,
+    FrontLeft,
+    FrontRight,
+    FrontCentre,
+    Lfe,
+    RearLeft,
+    RearRight,
+    SideLeft,
+    SideRight,
+    RearCentre,
+    Other,
+}
+
+impl Role {
+    /// Rear and side surrounds stand in for each other when a layout only
+    /// has one pair.
+    fn matches(self, other: Role) -> bool {
+        use Role::*;
+        self == other
+            || matches!(
+                (self, other),
+                (RearLeft, SideLeft) | (SideLeft, RearLeft) | (RearRight, SideRight) | (SideRight, RearRight)
+            )
+    }
+
+    /// (left, right) gains when folding down to stereo.
+    fn stereo_fold(self) -> (f32, f32) {
+        match self {
+            Role::Mono => (1.0, 1.0),
+            Role::FrontLeft => (1.0, 0.0),
+            Role::FrontRight => (0.0, 1.0),
+            Role::FrontCentre => (MINUS_3DB, MINUS_3DB),
+            Role::Lfe => (0.0, 0.0),
+            Role::RearLeft | Role::SideLeft => (MINUS_3DB, 0.0),
+            Role::RearRight | Role::SideRight => (0.0, MINUS_3DB),
+            Role::RearCentre => (0.5, 0.5),
+            Role::Other => (0.5, 0.5),
+        }
+    }
+}
+
+pub fn source_roles(channels: Channels, count: usize) -> Vec<Role> {
+    match count {
+        1 => return vec![Role::Mono],
+        2 => return vec![Role::FrontLeft, Role::FrontRight],
+        _ => {}
+    }
+    let mut roles: Vec<Role> = channels
+        .iter()
+        .map(|channel| match channel {
+            Channels::FRONT_LEFT => Role::FrontLeft,
+            Channels::FRONT_RIGHT => Role::FrontRight,
+            Channels::FRONT_CENTRE => Role::FrontCentre,
+            Channels::LFE1 => Role::Lfe,
+            Channels::REAR_LEFT => Role::RearLeft,
+            Channels::REAR_RIGHT => Role::RearRight,
+            Channels::SIDE_LEFT => Role::SideLeft,
+            Channels::SIDE_RIGHT => Role::SideRight,
+            Channels::REAR_CENTRE => Role::RearCentre,
+            _ => Role::Other,
+        })
+        .collect();
+    roles.resize(count, Role::Other);
+    roles
+}
+
+/// Channel order for Opus mapping family 1 (same as Vorbis).
+pub fn vorbis_roles(count: usize) -> Option<Vec<Role>> {
+    use Role::*;
+    let roles = match count {
+        3 => vec![FrontLeft, FrontCentre, FrontRight],
+        4 => vec![FrontLeft, FrontRight, RearLeft, RearRight],
+        5 => vec![FrontLeft, FrontCentre, FrontRight, RearLeft, RearRight],
+        6 => vec![FrontLeft, FrontCentre, FrontRight, RearLeft, RearRight, Lfe],
+        7 => vec![FrontLeft, FrontCentre, FrontRight, SideLeft, SideRight, RearCentre, Lfe],
+        8 => vec![
+            FrontLeft,
+            FrontCentre,
+            FrontRight,
+            SideLeft,
+            SideRight,
+            RearLeft,
+            RearRight,
+            Lfe,
+        ],
+        _ => return None,
+    };
+    Some(roles)
+}
+
+/// Whether every source channel has its own slot in `target`, i.e. the
+/// source can be carried without folding anything down.
+pub fn fits_layout(source: &[Role], target: &[Role]) -> bool {
+    let mut used = vec![false; target.len()];
+    source.iter().all(|role| {
+        match target
+            .iter()
+            .enumerate()
+            .position(|(index, slot)| !used[index] && slot.matches(*role))
+        {
+            Some(index) => {
+                used[index] = true;
+                true
+            }
+            None => false,
+        }
+    })
+}
+
+/// Row-major `target x source` gain matrix. Channels with a slot in the
+/// target are copied; the rest fold into the front pair. Fold-downs are
+/// scaled so no output row can clip.
+pub fn mix_matrix(source: &[Role], target: &[Role]) -> Vec<f32> {
+    let mut matrix = vec![0.0f32; target.len() * source.len()];
+    let cols = source.len();
+    let mono_target = target == [Role::Mono];
+    let left = target.iter().position(|role| *role == Role::FrontLeft);
+    let right = target.iter().position(|role| *role == Role::FrontRight);
+    let mut used = vec![false; target.len()];
+
+    for (col, role) in source.iter().enumerate() {
+        if mono_target {
+            let (l, r) = role.stereo_fold();
+            matrix[col] = if *role == Role::Mono { 1.0 } else { (l + r) / 2.0 };
+            continue;
+        }
+        let slot = target
+            .iter()
+            .enumerate()
+            .position(|(index, slot)| !used[index] && slot.matches(*role));
+        if let Some(row) = slot {
+            used[row] = true;
+            matrix[row * cols + col] = 1.0;
+            continue;
+        }
+        let (l, r) = role.stereo_fold();
+        if let Some(row) = left {
+            matrix[row * cols + col] += l;
+        }
+        if let Some(row) = right {
+            matrix[row * cols + col] += r;
+        }
+    }
+
+    if source.len() > 2 {
+        let peak = matrix
+            .chunks(cols)
+            .map(|row| row.iter().sum::<f32>())
+            .fold(1.0f32, f32::max);
+        if peak > 1.0 {
+            for gain in matrix.iter_mut() {
+                *gain /= peak;
+            }
+        }
+    }
+    matrix
+}
+
+pub fn apply_matrix(samples: &[f32], matrix: &[f32], source: usize, target: usize) -> Vec<f32> {
+    let frames = samples.len() / source;
+    let mut out = Vec::with_capacity(frames * target);
+    for frame in samples.chunks_exact(source) {
+        for row in matrix.chunks_exact(source) {
+            out.push(row.iter().zip(frame).map(|(gain, sample)| gain * sample).sum());
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folds_five_one_to_stereo() {
+        let source = source_roles(
+            Channels::FRONT_LEFT
+                | Channels::FRONT_RIGHT
+                | Channels::FRONT_CENTRE
+                | Channels::LFE1
+                | Channels::REAR_LEFT
+                | Channels::REAR_RIGHT,
+            6,
+        );
+        let matrix = mix_matrix(&source, &[Role::FrontLeft, Role::FrontRight]);
+        let scale = 1.0 / (1.0 + 2.0 * MINUS_3DB);
+        let expected_left = [1.0, 0.0, MINUS_3DB, 0.0, MINUS_3DB, 0.0].map(|gain| gain * scale);
+        for (got, want) in matrix[..6].iter().zip(expected_left) {
+            assert!((got - want).abs() < 1e-6, "{} != {}", got, want);
+        }
+
+        // Full-scale centre plus both left channels must not clip.
+        let frame = [1.0, 0.0, 1.0, 1.0, 1.0, 0.0];
+        let out = apply_matrix(&frame, &matrix, 6, 2);
+        assert!(out[0] <= 1.0 + 1e-6);
+    }
+
+    #[test]
+    fn reorders_side_surrounds_into_vorbis_order() {
+        let source = source_roles(
+            Channels::FRONT_LEFT
+                | Channels::FRONT_RIGHT
+                | Channels::FRONT_CENTRE
+                | Channels::LFE1
+                | Channels::SIDE_LEFT
+                | Channels::SIDE_RIGHT,
+            6,
+        );
+        let target = vorbis_roles(6).unwrap();
+        assert!(fits_layout(&source, &target));
+        let frame = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let matrix = mix_matrix(&source, &target);
+        assert_eq!(apply_matrix(&frame, &matrix, 6, 6), vec![1.0, 3.0, 2.0, 5.0, 6.0, 4.0]);
+    }
+}
@@ -0,0 +1,273 @@
+//! Change notifications for connected clients. Every event gets the next
+//! sequence number, and a bounded history lets a client that reconnects
+//! replay what it missed, or learn that it has to refetch instead.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use parking_lot::Mutex;
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tracing::warn;
+
+const HISTORY: usize = 512;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventTopic {
+    /// Scan state changes (`LibraryStatus`) and scan progress.
+    Library,
+    /// Artists and albums updated from external metadata.
+    Enrichment,
+    /// Playlists, likes and playback settings.
+    UserData,
+    /// What a user's sessions are playing; only sent to that user.
+    Playback,
+    /// The admin activity log.
+    Activity,
+}
+
+impl EventTopic {
+    pub const ALL: [Self; 5] = [
+        Self::Library,
+        Self::Enrichment,
+        Self::UserData,
+        Self::Playback,
+        Self::Activity,
+    ];
+
+    pub fn admin_only(self) -> bool {
+        self == Self::Activity
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "library" => Some(Self::Library),
+            "enrichment" => Some(Self::Enrichment),
+            "user_data" => Some(Self::UserData),
+            "playback" => Some(Self::Playback),
+            "activity" => Some(Self::Activity),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Library => "library",
+            Self::Enrichment => "enrichment",
+            Self::UserData => "user_data",
+            Self::Playback => "playback",
+            Self::Activity => "activity",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ServerEvent {
+    pub seq: u64,
+    pub topic: EventTopic,
+    pub kind: &'static str,
+    /// Unix milliseconds.
+    pub at: u64,
+    pub data: serde_json::Value,
+    /// Set for events only their user may see.
+    #[serde(skip)]
+    pub user_id: Option<String>,
+}
+
+impl ServerEvent {
+    pub fn visible_to(&self, user_id: Option<&str>) -> bool {
+        self.user_id.is_none() || self.user_id.as_deref() == user_id
+    }
+}
+
+pub type EventReceiver = broadcast::Receiver<Arc<ServerEvent>>;
+
+/// What event streams send, as JSON with a `type` tag.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type")]
+pub enum EventMessage<'a> {
+    /// `seq` is the newest event published before the subscription took
+    /// effect.
+    #[serde(rename = "subscribed")]
+    Subscribed {
+        topics: Vec<&'static str>,
+        seq: u64,
+        epoch: u64,
+    },
+    #[serde(rename = "event")]
+    Event(&'a ServerEvent),
+    /// Events were missed; refetch over HTTP. Later events keep coming.
+    #[serde(rename = "resync_required")]
+    ResyncRequired { epoch: u64 },
+}
+
+pub struct Subscription {
+    pub receiver: EventReceiver,
+    /// Sequence number of the newest event when the subscription started.
+    pub seq: u64,
+    /// Events after the requested `since`; `None` when the history no
+    /// longer reaches back that far or `since` came from another epoch.
+    pub replay: Option<Vec<Arc<ServerEvent>>>,
+}
+
+/// Sequence numbers restart with the process; `epoch` tells clients which
+/// run theirs belong to.
+#[derive(Clone)]
+pub struct EventBus {
+    epoch: u64,
+    log: Arc<Mutex<EventLog>>,
+    sender: broadcast::Sender<Arc<ServerEvent>>,
+}
+
+struct EventLog {
+    last_seq: u64,
+    history: VecDeque<Arc<ServerEvent>>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(HISTORY);
+        Self {
+            epoch: now_millis(),
+            log: Arc::new(Mutex::new(EventLog {
+                last_seq: 0,
+                history: VecDeque::with_capacity(HISTORY),
+            })),
+            sender,
+        }
+    }
+}
+
+impl EventBus {
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    pub fn publish(&self, topic: EventTopic, kind: &'static str, data: impl Serialize) {
+        self.publish_event(None, topic, kind, data);
+    }
+
+    pub fn publish_for_user(
+        &self,
+        user_id: &str,
+        topic: EventTopic,
+        kind: &'static str,
+        data: impl Serialize,
+    ) {
+        self.publish_event(Some(user_id.to_string()), topic, kind, data);
+    }
+
+    fn publish_event(
+        &self,
+        user_id: Option<String>,
+        topic: EventTopic,
+        kind: &'static str,
+        data: impl Serialize,
+    ) {
+        let data = match serde_json::to_value(data) {
+            Ok(value) => value,
+            Err(err) => {
+                warn!("Failed to encode {} event {}: {}", topic.name(), kind, err);
+                return;
+            }
+        };
+        let mut log = self.log.lock();
+        log.last_seq += 1;
+        let event = Arc::new(ServerEvent {
+            seq: log.last_seq,
+            topic,
+            kind,
+            at: now_millis(),
+            data,
+            user_id,
+        });
+        if log.history.len() == HISTORY {
+            log.history.pop_front();
+        }
+        log.history.push_back(Arc::clone(&event));
+        // Sent under the lock so receivers see sequence order.
+        let _ = self.sender.send(event);
+    }
+
+    /// Starts receiving new events. With `since` (and the epoch it was
+    /// issued in), also returns the events published after it.
+    pub fn subscribe(&self, since: Option<(u64, u64)>) -> Subscription {
+        let log = self.log.lock();
+        let receiver = self.sender.subscribe();
+        let replay = since.and_then(|(epoch, since)| {
+            if epoch != self.epoch || since > log.last_seq {
+                return None;
+            }
+            let oldest = log.history.front().map_or(log.last_seq + 1, |event| event.seq);
+            if oldest > since + 1 {
+                return None;
+            }
+            Some(
+                log.history
+                    .iter()
+                    .filter(|event| event.seq > since)
+                    .cloned()
+                    .collect(),
+            )
+        });
+        Subscription {
+            receiver,
+            seq: log.last_seq,
+            replay,
+        }
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|value| value.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replays_from_history_until_it_runs_out() {
+        let bus = EventBus::default();
+        for index in 0..HISTORY + 10 {
+            bus.publish(EventTopic::Library, "status", index);
+        }
+        let latest = (HISTORY + 10) as u64;
+        let sub = bus.subscribe(Some((bus.epoch(), latest - 3)));
+        assert_eq!(sub.seq, latest);
+        let seqs: Vec<u64> = sub.replay.unwrap().iter().map(|event| event.seq).collect();
+        assert_eq!(seqs, vec![latest - 2, latest - 1, latest]);
+        assert!(bus.subscribe(Some((bus.epoch(), 5))).replay.is_none());
+        assert!(bus.subscribe(Some((bus.epoch() + 1, latest))).replay.is_none());
+        assert!(bus.subscribe(Some((bus.epoch(), latest + 1))).replay.is_none());
+    }
+
+    #[test]
+    fn subscribers_see_later_events_in_order() {
+        let bus = EventBus::default();
+        bus.publish(EventTopic::UserData, "like_added", "a");
+        let mut sub = bus.subscribe(None);
+        assert!(sub.replay.is_none());
+        bus.publish(EventTopic::UserData, "like_removed", "a");
+        bus.publish(EventTopic::Activity, "index", "b");
+        assert_eq!(sub.receiver.try_recv().unwrap().seq, 2);
+        assert_eq!(sub.receiver.try_recv().unwrap().kind, "index");
+        assert!(sub.receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn user_events_are_private() {
+        let bus = EventBus::default();
+        bus.publish_for_user("alice", EventTopic::Playback, "now_playing", "t1");
+        let sub = bus.subscribe(Some((bus.epoch(), 0)));
+        let event = &sub.replay.unwrap()[0];
+        assert!(event.visible_to(Some("alice")));
+        assert!(!event.visible_to(Some("bob")));
+        assert!(!event.visible_to(None));
+    }
+}
@@ -1,5 +1,6 @@
 use std::time::Duration;
 
+use common::AlbumType;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 
@@ -29,6 +30,7 @@ pub struct ExternalMetadata {
     pub genres: Vec<String>,
     pub logo_url: Option<String>,
     pub banner_url: Option<String>,
+    pub album_type: Option<AlbumType>,
 }
 
 pub fn provider_from_str(value: &str) -> Option<Provider> {
@@ -39,17 +41,30 @@ pub fn provider_from_str(value: &str) -> Option<Provider> {
     }
 }
 
+/// Identifiers for an exact lookup; when present they are used instead of a
+/// name search.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AlbumIds<'a> {
+    pub release_id: Option<&'a str>,
+    pub release_group_id: Option<&'a str>,
+}
+
 pub async fn fetch_artist(
     client: &Client,
     config: &ExternalConfig,
     artist_name: &str,
+    musicbrainz_id: Option<&str>,
 ) -> Result<Option<ExternalMetadata>, String> {
     let mut combined = ExternalMetadata::default();
     let mut found = false;
     for source in &config.sources {
         let result = match source.provider {
-            Provider::TheAudioDb => fetch_theaudiodb_artist(client, source, artist_name).await,
-            Provider::MusicBrainz => fetch_musicbrainz_artist(client, source, artist_name).await,
+            Provider::TheAudioDb => {
+                fetch_theaudiodb_artist(client, source, artist_name, musicbrainz_id).await
+            }
+            Provider::MusicBrainz => {
+                fetch_musicbrainz_artist(client, source, artist_name, musicbrainz_id).await
+            }
         }?;
         if let Some(metadata) = result {
             merge_metadata(&mut combined, metadata, source.provider);
@@ -68,16 +83,17 @@ pub async fn fetch_album(
     config: &ExternalConfig,
     artist_name: &str,
     album_title: &str,
+    ids: AlbumIds<'_>,
 ) -> Result<Option<ExternalMetadata>, String> {
     let mut combined = ExternalMetadata::default();
     let mut found = false;
     for source in &config.sources {
         let result = match source.provider {
             Provider::TheAudioDb => {
-                fetch_theaudiodb_album(client, source, artist_name, album_title).await
+                fetch_theaudiodb_album(client, source, artist_name, album_title, ids).await
             }
             Provider::MusicBrainz => {
-                fetch_musicbrainz_album(client, source, artist_name, album_title).await
+                fetch_musicbrainz_album(client, source, artist_name, album_title, ids).await
             }
         }?;
         if let Some(metadata) = result {
@@ -185,38 +201,49 @@ struct TheAudioDbAlbum {
     genre: Option<String>,
     #[serde(rename = "strStyle")]
     style: Option<String>,
+    #[serde(rename = "strReleaseFormat")]
+    release_format: Option<String>,
 }
 
 async fn fetch_theaudiodb_artist(
     client: &Client,
     source: &ExternalSource,
     artist_name: &str,
+    musicbrainz_id: Option<&str>,
 ) -> Result<Option<ExternalMetadata>, String> {
     let api_key = source.api_key.as_deref().unwrap_or("").trim();
     if api_key.is_empty() {
         return Ok(None);
     }
-    let url = format!(
-        "https://www.theaudiodb.com/api/v1/json/{}/search.php?s={}",
-        api_key,
-        url_escape(artist_name)
-    );
-    let response = client
-        .get(&url)
-        .timeout(source.timeout)
-        .send()
-        .await
-        .map_err(|err| err.to_string())?;
-    if !response.status().is_success() {
-        return Err(format!("http {}", response.status()));
-    }
-    let payload = response
-        .json::<TheAudioDbArtistResponse>()
-        .await
-        .map_err(|err| err.to_string())?;
-    let artist = match payload.artists.and_then(|mut items| items.pop()) {
+    let by_id = match musicbrainz_id {
+        Some(mbid) => {
+            let url = format!(
+                "https://www.theaudiodb.com/api/v1/json/{}/artist-mb.php?i={}",
+                api_key,
+                url_escape(mbid)
+            );
+            theaudiodb_get::<TheAudioDbArtistResponse>(client, source, &url)
+                .await?
+                .artists
+                .and_then(|mut items| items.pop())
+        }
+        None => None,
+    };
+    // An id TheAudioDB doesn't know still leaves the name to search by.
+    let artist = match by_id {
         Some(artist) => artist,
-        None => return Ok(None),
+        None => {
+            let url = format!(
+                "https://www.theaudiodb.com/api/v1/json/{}/search.php?s={}",
+                api_key,
+                url_escape(artist_name)
+            );
+            let payload = theaudiodb_get::<TheAudioDbArtistResponse>(client, source, &url).await?;
+            match payload.artists.and_then(|mut items| items.pop()) {
+                Some(artist) => artist,
+                None => return Ok(None),
+            }
+        }
     };
 
     let summary = clean_text(artist.bio);
@@ -238,6 +265,7 @@ async fn fetch_theaudiodb_artist(
         genres,
         logo_url,
         banner_url,
+        album_type: None,
     }))
 }
 
@@ -246,42 +274,56 @@ async fn fetch_theaudiodb_album(
     source: &ExternalSource,
     artist_name: &str,
     album_title: &str,
+    ids: AlbumIds<'_>,
 ) -> Result<Option<ExternalMetadata>, String> {
     let api_key = source.api_key.as_deref().unwrap_or("").trim();
     if api_key.is_empty() {
         return Ok(None);
     }
-    let url = format!(
-        "https://www.theaudiodb.com/api/v1/json/{}/searchalbum.php?s={}&a={}",
-        api_key,
-        url_escape(artist_name),
-        url_escape(album_title)
-    );
-    let response = client
-        .get(&url)
-        .timeout(source.timeout)
-        .send()
-        .await
-        .map_err(|err| err.to_string())?;
-    if !response.status().is_success() {
-        return Err(format!("http {}", response.status()));
-    }
-    let payload = response
-        .json::<TheAudioDbAlbumResponse>()
-        .await
-        .map_err(|err| err.to_string())?;
-    let album = match payload.album.and_then(|mut items| items.pop()) {
+    // TheAudioDB keys albums by release group, not by release.
+    let by_id = match ids.release_group_id {
+        Some(mbid) => {
+            let url = format!(
+                "https://www.theaudiodb.com/api/v1/json/{}/album-mb.php?i={}",
+                api_key,
+                url_escape(mbid)
+            );
+            theaudiodb_get::<TheAudioDbAlbumResponse>(client, source, &url)
+                .await?
+                .album
+                .and_then(|mut items| items.pop())
+        }
+        None => None,
+    };
+    let album = match by_id {
         Some(album) => album,
-        None => return Ok(None),
+        None => {
+            let url = format!(
+                "https://www.theaudiodb.com/api/v1/json/{}/searchalbum.php?s={}&a={}",
+                api_key,
+                url_escape(artist_name),
+                url_escape(album_title)
+            );
+            let payload = theaudiodb_get::<TheAudioDbAlbumResponse>(client, source, &url).await?;
+            match payload.album.and_then(|mut items| items.pop()) {
+                Some(album) => album,
+                None => return Ok(None),
+            }
+        }
     };
 
     let summary = clean_text(album.description);
     let genres = collect_genres(&[album.genre, album.style]);
+    let album_type = album
+        .release_format
+        .as_deref()
+        .and_then(AlbumType::from_release_types);
     Ok(Some(ExternalMetadata {
         summary,
         genres,
         logo_url: None,
         banner_url: None,
+        album_type,
     }))
 }
 
@@ -305,6 +347,25 @@ struct MusicBrainzReleaseGroupResponse {
 #[derive(Deserialize)]
 struct MusicBrainzReleaseGroup {
     tags: Option<Vec<MusicBrainzTag>>,
+    #[serde(rename = "primary-type")]
+    primary_type: Option<String>,
+    #[serde(rename = "secondary-types", default)]
+    secondary_types: Vec<String>,
+}
+
+impl MusicBrainzReleaseGroup {
+    fn album_type(&self) -> Option<AlbumType> {
+        let mut types = self.secondary_types.clone();
+        types.extend(self.primary_type.clone());
+        AlbumType::from_release_types(&types.join(";"))
+    }
+}
+
+#[derive(Deserialize)]
+struct MusicBrainzRelease {
+    tags: Option<Vec<MusicBrainzTag>>,
+    #[serde(rename = "release-group")]
+    release_group: Option<MusicBrainzReleaseGroup>,
 }
 
 #[derive(Deserialize)]
@@ -316,32 +377,39 @@ async fn fetch_musicbrainz_artist(
     client: &Client,
     source: &ExternalSource,
     artist_name: &str,
+    musicbrainz_id: Option<&str>,
 ) -> Result<Option<ExternalMetadata>, String> {
     let user_agent = source.user_agent.as_deref().unwrap_or("").trim();
     if user_agent.is_empty() {
         return Ok(None);
     }
-    let url = format!(
-        "https://musicbrainz.org/ws/2/artist/?query=artist:{}&fmt=json&limit=1&inc=tags",
-        url_escape(artist_name)
-    );
-    let response = client
-        .get(&url)
-        .timeout(source.timeout)
-        .header("User-Agent", user_agent)
-        .send()
-        .await
-        .map_err(|err| err.to_string())?;
-    if !response.status().is_success() {
-        return Err(format!("http {}", response.status()));
-    }
-    let payload = response
-        .json::<MusicBrainzArtistResponse>()
-        .await
-        .map_err(|err| err.to_string())?;
-    let artist = match payload.artists.and_then(|mut items| items.pop()) {
+    let by_id = match musicbrainz_id {
+        Some(mbid) => {
+            let url = format!(
+                "https://musicbrainz.org/ws/2/artist/{}?fmt=json&inc=tags",
+                url_escape(mbid)
+            );
+            musicbrainz_get::<MusicBrainzArtist>(client, source, user_agent, &url).await?
+        }
+        None => None,
+    };
+    // An id MusicBrainz doesn't know still leaves the name to search by.
+    let artist = match by_id {
         Some(artist) => artist,
-        None => return Ok(None),
+        None => {
+            let url = format!(
+                "https://musicbrainz.org/ws/2/artist/?query=artist:{}&fmt=json&limit=1&inc=tags",
+                url_escape(artist_name)
+            );
+            let payload = musicbrainz_get::<MusicBrainzArtistResponse>(
+                client, source, user_agent, &url,
+            )
+            .await?;
+            match payload.and_then(|payload| payload.artists).and_then(|mut items| items.pop()) {
+                Some(artist) => artist,
+                None => return Ok(None),
+            }
+        }
     };
     let summary = clean_text(artist.disambiguation);
     let genres = collect_tag_genres(artist.tags);
@@ -350,6 +418,7 @@ async fn fetch_musicbrainz_artist(
         genres,
         logo_url: None,
         banner_url: None,
+        album_type: None,
     }))
 }
 
@@ -358,45 +427,124 @@ async fn fetch_musicbrainz_album(
     source: &ExternalSource,
     artist_name: &str,
     album_title: &str,
+    ids: AlbumIds<'_>,
 ) -> Result<Option<ExternalMetadata>, String> {
     let user_agent = source.user_agent.as_deref().unwrap_or("").trim();
     if user_agent.is_empty() {
         return Ok(None);
     }
-    let query = format!(
-        "artist:{} releasegroup:{}",
-        artist_name,
-        album_title
-    );
-    let url = format!(
-        "https://musicbrainz.org/ws/2/release-group/?query={}&fmt=json&limit=1&inc=tags",
-        url_escape(&query)
-    );
+    let mut found = None;
+    if let Some(mbid) = ids.release_group_id {
+        let url = format!(
+            "https://musicbrainz.org/ws/2/release-group/{}?fmt=json&inc=tags",
+            url_escape(mbid)
+        );
+        found = musicbrainz_get::<MusicBrainzReleaseGroup>(client, source, user_agent, &url)
+            .await?
+            .map(|album| {
+                let album_type = album.album_type();
+                (album.tags, album_type)
+            });
+    }
+    if let (None, Some(mbid)) = (&found, ids.release_id) {
+        let url = format!(
+            "https://musicbrainz.org/ws/2/release/{}?fmt=json&inc=tags+release-groups",
+            url_escape(mbid)
+        );
+        found = musicbrainz_get::<MusicBrainzRelease>(client, source, user_agent, &url)
+            .await?
+            .map(|release| {
+                let album_type = release
+                    .release_group
+                    .as_ref()
+                    .and_then(MusicBrainzReleaseGroup::album_type);
+                let tags = release
+                    .tags
+                    .filter(|tags| !tags.is_empty())
+                    .or_else(|| release.release_group.and_then(|group| group.tags));
+                (tags, album_type)
+            });
+    }
+    // Ids MusicBrainz doesn't know still leave the names to search by.
+    let (tags, album_type) = match found {
+        Some(found) => found,
+        None => {
+            let query = format!(
+                "artist:{} releasegroup:{}",
+                artist_name,
+                album_title
+            );
+            let url = format!(
+                "https://musicbrainz.org/ws/2/release-group/?query={}&fmt=json&limit=1&inc=tags",
+                url_escape(&query)
+            );
+            let payload = musicbrainz_get::<MusicBrainzReleaseGroupResponse>(
+                client, source, user_agent, &url,
+            )
+            .await?;
+            match payload
+                .and_then(|payload| payload.release_groups)
+                .and_then(|mut items| items.pop())
+            {
+                Some(album) => {
+                    let album_type = album.album_type();
+                    (album.tags, album_type)
+                }
+                None => return Ok(None),
+            }
+        }
+    };
+    let genres = collect_tag_genres(tags);
+    Ok(Some(ExternalMetadata {
+        summary: None,
+        genres,
+        logo_url: None,
+        banner_url: None,
+        album_type,
+    }))
+}
+
+/// Unknown ids and empty searches both come back as `null` lists.
+async fn theaudiodb_get<T: for<'de> Deserialize<'de>>(
+    client: &Client,
+    source: &ExternalSource,
+    url: &str,
+) -> Result<T, String> {
     let response = client
-        .get(&url)
+        .get(url)
         .timeout(source.timeout)
-        .header("User-Agent", user_agent)
         .send()
         .await
         .map_err(|err| err.to_string())?;
     if !response.status().is_success() {
         return Err(format!("http {}", response.status()));
     }
-    let payload = response
-        .json::<MusicBrainzReleaseGroupResponse>()
+    response.json::<T>().await.map_err(|err| err.to_string())
+}
+
+/// Lookups by id answer 404 for unknown or merged ids; that is a miss, not
+/// a provider failure.
+async fn musicbrainz_get<T: for<'de> Deserialize<'de>>(
+    client: &Client,
+    source: &ExternalSource,
+    user_agent: &str,
+    url: &str,
+) -> Result<Option<T>, String> {
+    let response = client
+        .get(url)
+        .timeout(source.timeout)
+        .header("User-Agent", user_agent)
+        .send()
         .await
         .map_err(|err| err.to_string())?;
-    let album = match payload.release_groups.and_then(|mut items| items.pop()) {
-        Some(album) => album,
-        None => return Ok(None),
-    };
-    let genres = collect_tag_genres(album.tags);
-    Ok(Some(ExternalMetadata {
-        summary: None,
-        genres,
-        logo_url: None,
-        banner_url: None,
-    }))
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    if !response.status().is_success() {
+        return Err(format!("http {}", response.status()));
+    }
+    let payload = response.json::<T>().await.map_err(|err| err.to_string())?;
+    Ok(Some(payload))
 }
 
 fn collect_genres(values: &[Option<String>]) -> Vec<String> {
@@ -463,6 +611,11 @@ fn merge_metadata(base: &mut ExternalMetadata, incoming: ExternalMetadata, provi
     if base.banner_url.is_none() {
         base.banner_url = incoming.banner_url;
     }
+    if let Some(album_type) = incoming.album_type {
+        if prefer || base.album_type.is_none() {
+            base.album_type = Some(album_type);
+        }
+    }
 }
 
 fn clean_text(value: Option<String>) -> Option<String> {
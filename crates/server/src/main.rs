@@ -4,19 +4,26 @@ mod api;
 mod assets;
 mod auth;
 mod config;
+mod downmix;
+mod events;
 mod external;
 mod quic;
 mod range;
+mod resample;
 mod scan;
 mod shuffle;
+mod stream_limits;
 mod streaming;
 mod stats_store;
 mod state;
 mod stream_sessions;
+mod time_stretch;
 mod transcode;
+mod transcode_cache;
 mod user_data;
 mod utils;
 mod watch;
+mod waveform;
 
 use std::sync::Arc;
 use std::time::Duration;
@@ -29,7 +36,7 @@ use api::api_router;
 use activity_store::ActivityStore;
 use auth::AuthStore;
 use config::{
-    config_path_from_env, load_or_create_config, resolve_music_root, resolve_path,
+    config_path_from_env, load_or_create_config, resolve_music_root, resolve_path, ServerConfig,
 };
 use library::Library;
 use parking_lot::RwLock;
@@ -38,6 +45,7 @@ use scan::{set_library_missing, start_index};
 use stats_store::StatsStore;
 use state::{AppState, LibraryState, LibraryStatus};
 use user_data::{open_or_create_db as open_user_db, UserDataStore};
+use transcode_cache::TranscodeCache;
 use tower_http::request_id::{MakeRequestUuid, SetRequestIdLayer};
 use tower_http::trace::TraceLayer;
 use tracing::{info, warn};
@@ -87,7 +95,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     if let Err(err) = auth.ensure_superadmin() {
         warn!("Failed to ensure superadmin: {}", err);
     }
-    let activity = ActivityStore::new(Arc::clone(&db));
+    let events = events::EventBus::default();
+    let activity = ActivityStore::new(Arc::clone(&db), events.clone());
     if let Err(err) = activity.init_tables() {
         warn!("Failed to create activity table: {}", err);
     }
@@ -99,7 +108,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
     let user_db = Arc::new(open_user_db(&user_db_path)?);
-    let user_data = UserDataStore::new(Arc::clone(&user_db));
+    let user_data = UserDataStore::new(Arc::clone(&user_db), events.clone());
     if let Err(err) = user_data.init_tables() {
         warn!("Failed to create user data tables: {:?}", err);
     }
@@ -123,6 +132,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         status: LibraryStatus::Unconfigured,
     }));
     let watcher = Arc::new(RwLock::new(None));
+    let transcode_cache = open_transcode_cache(&config_path, &config);
     let state = AppState {
         library_state,
         auth,
@@ -132,9 +142,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         user_data,
         stats,
         activity,
+        events,
         watcher,
         external_client,
-        stream_sessions: stream_sessions::StreamSessions::new(),
+        stream_sessions: stream_sessions::StreamSessions::new(
+            stream_sessions::BitrateLadder::from_kbps(&config.abr_ladder_kbps),
+        ),
+        transcode_cache,
+        transcode_scheduler: stream_limits::TranscodeScheduler::new(config.transcode_workers),
+        stream_quotas: stream_limits::StreamQuotas::new(config.max_streams_per_user),
+        resume_sessions: quic::ResumeStore::default(),
     };
     if let Some(music_root) = resolve_music_root(&state.config_path, &config.music_root) {
         if music_root.exists() {
@@ -178,6 +195,21 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+fn open_transcode_cache(config_path: &std::path::Path, config: &ServerConfig) -> TranscodeCache {
+    if !config.transcode_cache_enabled {
+        return TranscodeCache::disabled();
+    }
+    let root = resolve_path(config_path, &config.metadata_path).join("transcode_cache");
+    let max_bytes = config.transcode_cache_max_mb.saturating_mul(1024 * 1024);
+    match TranscodeCache::open(root, max_bytes) {
+        Ok(cache) => cache,
+        Err(err) => {
+            warn!("Failed to open transcode cache: {}", err);
+            TranscodeCache::disabled()
+        }
+    }
+}
+
 async fn shutdown_signal() {
     #[cfg(unix)]
     {
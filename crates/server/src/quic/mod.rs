@@ -1,35 +1,84 @@
+use std::cell::RefCell;
 use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::IoSliceMut;
 use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::sync::atomic::AtomicU32;
+use std::sync::{Arc, Weak};
 use std::time::{Duration, Instant};
 
 use bytes::Bytes;
+use library::Library;
+use parking_lot::Mutex;
+use quinn_udp::RecvMeta;
 use rand::RngCore;
 use serde::{Deserialize, Serialize};
-use tokio::net::UdpSocket;
+use tokio::sync::broadcast::error::TryRecvError;
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+mod protocol;
+mod resume;
+mod udp;
+
+use resume::ResumeSnapshot;
+pub use resume::ResumeStore;
+use udp::QuicSocket;
 
 use crate::config::{resolve_path, ServerConfig};
+use crate::events::{EventMessage, EventReceiver, EventTopic, ServerEvent};
 use crate::state::AppState;
 use crate::streaming::{
-    build_raw_opus_meta, parse_frame_ms, parse_transcode_mode, parse_transcode_quality,
-    transcode_mode_label, transcode_quality_label,
+    build_raw_opus_meta, parse_crossfade, parse_frame_ms, parse_speed, parse_transcode_mode,
+    parse_transcode_quality, transcode_mode_label, transcode_quality_label,
 };
-use crate::transcode::{BitrateSelector, TranscodeMode, TranscodeQuality};
-use common::join_relpath;
+use crate::transcode::{
+    BitrateSelector, Crossfade, GaplessOptions, GaplessTrim, RawOptions, StreamEvent,
+    StreamFormat, TranscodeMode, TranscodeQuality, TranscodeSource,
+};
+use crate::stream_limits::{JobPriority, PauseSignal, StreamLease};
+use crate::stream_sessions::{AbrSample, PacketLossEstimate, PathSample, StreamSessionHandle};
+use crate::transcode_cache::{CacheProfile, SourceFingerprint};
+use common::{join_relpath, Track};
+use protocol::{Capabilities, ProtocolRange};
+use quic_wire::{encode_control, AudioFraming, ControlFraming, ControlReader};
 
 const ALPN_QUIC: &[&[u8]] = &[b"phonolite-quic"];
 const SERVER_CONN_ID_LEN: usize = 16;
 const MAX_UDP_SIZE: usize = 65535;
 const MAX_QUIC_DATAGRAM: usize = 1350;
-const CONTROL_STREAM_MAX_LINE: usize = 64 * 1024;
+/// Datagrams waiting for a worker before newer ones are dropped.
+const SHARD_QUEUE_DATAGRAMS: usize = 4096;
 const MAX_STREAM_BUFFER_BYTES: usize = 6 * 1024 * 1024;
-const SEEK_RESET_MARKER: u16 = 0xFFFF;
+/// Bus events stay on the receiver while the control outbox holds this
+/// much, so a client that stops reading lags and gets a resync instead.
+const MAX_CONTROL_OUTBOX_BYTES: usize = 256 * 1024;
+const ABR_REPORT_INTERVAL: Duration = Duration::from_secs(1);
+/// A reported position this close to the end counts as finishing the track.
+const TRACK_END_SLACK_MS: u32 = 500;
+const RESUME_SAVE_INTERVAL: Duration = Duration::from_secs(1);
+/// Spare connection IDs kept with the client so it can move to a new path.
+const ACTIVE_CONN_ID_LIMIT: u64 = 4;
+/// Application close code for a session taken over by `resume`.
+const CLOSE_RESUMED_ELSEWHERE: u64 = 0x101;
 
 #[derive(Debug, Deserialize)]
 #[serde(tag = "type")]
 enum ControlMessage {
+    #[serde(rename = "hello")]
+    Hello {
+        protocol: u32,
+        min_protocol: Option<u32>,
+        client: Option<String>,
+        capabilities: Option<Vec<String>>,
+    },
     #[serde(rename = "auth")]
     Auth { token: String },
+    /// `position_ms` overrides the last position the server heard.
+    #[serde(rename = "resume_session")]
+    ResumeSession {
+        token: String,
+        position_ms: Option<u32>,
+    },
     #[serde(rename = "open")]
     Open {
         track_id: String,
@@ -37,6 +86,11 @@ enum ControlMessage {
         quality: Option<String>,
         frame_ms: Option<u32>,
         queue: Option<Vec<String>>,
+        gapless: Option<bool>,
+        crossfade_ms: Option<u32>,
+        crossfade_curve: Option<String>,
+        surround: Option<bool>,
+        speed: Option<f32>,
     },
     #[serde(rename = "queue")]
     Queue { track_ids: Vec<String> },
@@ -45,7 +99,28 @@ enum ControlMessage {
     #[serde(rename = "buffer")]
     Buffer { buffer_ms: u32, target_ms: Option<u32> },
     #[serde(rename = "seek")]
-    Seek { track_id: String, position_ms: u32 },
+    Seek {
+        track_id: String,
+        position_ms: u32,
+        speed: Option<f32>,
+    },
+    #[serde(rename = "pause")]
+    Pause,
+    #[serde(rename = "resume")]
+    Resume,
+    #[serde(rename = "stop")]
+    Stop,
+    #[serde(rename = "position")]
+    Position { track_id: String, position_ms: u32 },
+    /// `since` and `epoch` come from an earlier subscription's events.
+    #[serde(rename = "subscribe")]
+    Subscribe {
+        topics: Vec<String>,
+        since: Option<u64>,
+        epoch: Option<u64>,
+    },
+    #[serde(rename = "unsubscribe")]
+    Unsubscribe { topics: Vec<String> },
     #[serde(rename = "ping")]
     Ping { ts: Option<i64> },
 }
@@ -53,10 +128,33 @@ enum ControlMessage {
 #[derive(Debug, Serialize)]
 #[serde(tag = "type")]
 enum ControlResponse<'a> {
+    #[serde(rename = "hello_ok")]
+    HelloOk {
+        protocol: u32,
+        server: &'a str,
+        capabilities: Vec<&'static str>,
+    },
+    /// `resume_token` lets a later connection take over this session.
     #[serde(rename = "auth_ok")]
-    AuthOk,
+    AuthOk { resume_token: &'a str },
+    #[serde(rename = "session_resumed")]
+    SessionResumed {
+        track_id: Option<&'a str>,
+        position_ms: u32,
+        queue: Vec<String>,
+        paused: bool,
+    },
     #[serde(rename = "error")]
     Error { message: &'a str },
+    /// An `error` with a machine-readable `code`; `supported` is set when
+    /// the protocol version was the problem.
+    #[serde(rename = "error")]
+    CodedError {
+        code: &'a str,
+        message: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        supported: Option<ProtocolRange>,
+    },
     #[serde(rename = "pong")]
     Pong { ts: Option<i64> },
     #[serde(rename = "stream")]
@@ -65,9 +163,44 @@ enum ControlResponse<'a> {
         stream_id: u64,
         role: &'a str,
         frame_ms: u32,
+        gapless: bool,
+        /// Stream sample counts cover `speed` times as much source time.
+        speed: f32,
     },
     #[serde(rename = "open_ok")]
     OpenOk { track_id: &'a str },
+    /// A stitched stream reached the next queue entry. `sample_offset` counts
+    /// 48 kHz samples per channel after pre-skip from the start of the stream.
+    #[serde(rename = "track_boundary")]
+    TrackBoundary {
+        stream_id: u64,
+        track_id: &'a str,
+        sample_offset: u64,
+        crossfade_samples: u64,
+    },
+    /// Everything past `pre_skip + valid_samples` in the stream is padding.
+    #[serde(rename = "stream_end")]
+    StreamEnd {
+        stream_id: u64,
+        track_id: &'a str,
+        pre_skip: u16,
+        valid_samples: u64,
+    },
+    #[serde(rename = "track_started")]
+    TrackStarted {
+        track_id: &'a str,
+        position_ms: u32,
+        duration_ms: u32,
+    },
+    /// `reason` is `finished`, `skipped` or `stopped`.
+    #[serde(rename = "track_ended")]
+    TrackEnded {
+        track_id: &'a str,
+        position_ms: u32,
+        reason: &'a str,
+    },
+    #[serde(rename = "queue_exhausted")]
+    QueueExhausted,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -76,49 +209,10 @@ enum StreamRole {
     Prefetch,
 }
 
-struct ControlParser {
-    buffer: Vec<u8>,
-}
-
-impl ControlParser {
-    fn new() -> Self {
-        Self { buffer: Vec::new() }
-    }
-
-    fn push(&mut self, data: &[u8]) -> Vec<ControlMessage> {
-        self.buffer.extend_from_slice(data);
-        if self.buffer.len() > CONTROL_STREAM_MAX_LINE {
-            self.buffer.clear();
-            return Vec::new();
-        }
-        let mut out = Vec::new();
-        loop {
-            let newline = self.buffer.iter().position(|b| *b == b'\n');
-            let Some(pos) = newline else { break };
-            let mut line = self.buffer.drain(..=pos).collect::<Vec<u8>>();
-            if let Some(b'\n') = line.last() {
-                line.pop();
-            }
-            if line.is_empty() {
-                continue;
-            }
-            if line.len() > CONTROL_STREAM_MAX_LINE {
-                self.buffer.clear();
-                break;
-            }
-            if let Ok(text) = std::str::from_utf8(&line) {
-                if let Ok(msg) = serde_json::from_str::<ControlMessage>(text) {
-                    out.push(msg);
-                }
-            }
-        }
-        out
-    }
-}
-
 struct ControlOutbox {
     pending: VecDeque<Bytes>,
     offset: usize,
+    buffered_bytes: usize,
 }
 
 impl ControlOutbox {
@@ -126,12 +220,58 @@ impl ControlOutbox {
         Self {
             pending: VecDeque::new(),
             offset: 0,
+            buffered_bytes: 0,
         }
     }
 
     fn enqueue(&mut self, payload: Bytes) {
+        self.buffered_bytes = self.buffered_bytes.saturating_add(payload.len());
         self.pending.push_back(payload);
     }
+
+    fn pop(&mut self) {
+        if let Some(front) = self.pending.pop_front() {
+            self.buffered_bytes = self.buffered_bytes.saturating_sub(front.len());
+        }
+        self.offset = 0;
+    }
+}
+
+/// Shared by a stitched stream and its transcode worker. The worker pops the
+/// next track from `upcoming` and records it in `encoding` under the same
+/// lock, so queue edits always apply after the track being encoded.
+#[derive(Default)]
+struct StitchedQueue {
+    encoding: String,
+    upcoming: VecDeque<String>,
+}
+
+type SharedStitchedQueue = Arc<Mutex<StitchedQueue>>;
+
+struct StitchedSpec {
+    queue: SharedStitchedQueue,
+    crossfade: Option<Crossfade>,
+}
+
+struct TranscodeChannels {
+    rx: tokio::sync::mpsc::Receiver<Result<Bytes, std::io::Error>>,
+    events: tokio::sync::mpsc::UnboundedReceiver<StreamEvent>,
+    abr: Option<StreamSessionHandle>,
+}
+
+/// Everything a transcode worker needs besides the app state.
+struct TranscodeJob<'a> {
+    track_id: &'a str,
+    format: StreamFormat,
+    mode: TranscodeMode,
+    quality: TranscodeQuality,
+    start_ms: u32,
+    stitched: Option<StitchedSpec>,
+    priority: JobPriority,
+    /// Adaptive session to keep using, so a seek doesn't reset the bitrate.
+    abr: Option<StreamSessionHandle>,
+    packet_loss_perc: Arc<AtomicU32>,
+    pause: PauseSignal,
 }
 
 struct OutgoingStream {
@@ -142,6 +282,15 @@ struct OutgoingStream {
     mode: TranscodeMode,
     quality: TranscodeQuality,
     rx: tokio::sync::mpsc::Receiver<Result<Bytes, std::io::Error>>,
+    events: tokio::sync::mpsc::UnboundedReceiver<StreamEvent>,
+    abr: Option<StreamSessionHandle>,
+    stitched: Option<SharedStitchedQueue>,
+    surround: bool,
+    speed: f32,
+    framing: AudioFraming,
+    priority: JobPriority,
+    packet_loss_perc: Arc<AtomicU32>,
+    pause: PauseSignal,
     pending: VecDeque<Bytes>,
     offset: usize,
     finished: bool,
@@ -161,7 +310,7 @@ impl OutgoingStream {
         frame_ms: u32,
         mode: TranscodeMode,
         quality: TranscodeQuality,
-        rx: tokio::sync::mpsc::Receiver<Result<Bytes, std::io::Error>>,
+        channels: TranscodeChannels,
     ) -> Self {
         let now = Instant::now();
         Self {
@@ -171,7 +320,16 @@ impl OutgoingStream {
             frame_ms,
             mode,
             quality,
-            rx,
+            rx: channels.rx,
+            events: channels.events,
+            abr: channels.abr,
+            stitched: None,
+            surround: false,
+            speed: 1.0,
+            framing: AudioFraming::Legacy,
+            priority: JobPriority::new(role == StreamRole::Active),
+            packet_loss_perc: Arc::new(AtomicU32::new(0)),
+            pause: PauseSignal::default(),
             pending: VecDeque::new(),
             offset: 0,
             finished: false,
@@ -210,36 +368,81 @@ impl OutgoingStream {
 }
 
 struct SessionState {
+    protocol: u32,
+    client_name: Option<String>,
+    capabilities: Capabilities,
     authed: bool,
     user_id: Option<String>,
+    admin: bool,
+    events: Option<EventSubscription>,
     control_stream: Option<u64>,
     control_outbox: ControlOutbox,
-    control_parser: ControlParser,
+    control_reader: ControlReader,
+    /// Binary once `hello` agrees on `binary_framing`.
+    control_framing: ControlFraming,
     next_uni_stream_id: u64,
     active_track: Option<String>,
     queue: VecDeque<String>,
+    gapless: bool,
+    crossfade: Option<Crossfade>,
+    surround: bool,
+    speed: f32,
+    /// Held from the first `open` until the connection closes.
+    stream_lease: Option<Arc<StreamLease>>,
     outgoing: HashMap<u64, OutgoingStream>,
     track_streams: HashMap<String, u64>,
     buffer_target_ms: u32,
     client_buffer_ms: u32,
+    /// Whether the client has sent `buffer` at all; until then its level
+    /// is unknown rather than empty.
+    buffer_reported: bool,
+    packet_loss: PacketLossEstimate,
+    /// Set by `pause`; transcoders yield their worker slot while it holds.
+    pause: PauseSignal,
+    playing: Option<PlayingTrack>,
+    resume_token: Option<String>,
+    last_resume_save: Instant,
+    /// Messages that arrived as 0-RTT data and may be replays; handled
+    /// once the handshake completes.
+    deferred: Vec<ControlMessage>,
+    last_abr_report: Instant,
     last_debug: Instant,
 }
 
 impl SessionState {
     fn new() -> Self {
         Self {
+            protocol: protocol::LEGACY_PROTOCOL,
+            client_name: None,
+            capabilities: Capabilities::default(),
             authed: false,
             user_id: None,
+            admin: false,
+            events: None,
             control_stream: None,
             control_outbox: ControlOutbox::new(),
-            control_parser: ControlParser::new(),
+            control_reader: ControlReader::default(),
+            control_framing: ControlFraming::Json,
             next_uni_stream_id: 3,
             active_track: None,
             queue: VecDeque::new(),
+            gapless: false,
+            crossfade: None,
+            surround: false,
+            speed: 1.0,
+            stream_lease: None,
             outgoing: HashMap::new(),
             track_streams: HashMap::new(),
             buffer_target_ms: 8000,
             client_buffer_ms: 0,
+            buffer_reported: false,
+            packet_loss: PacketLossEstimate::default(),
+            pause: PauseSignal::default(),
+            playing: None,
+            resume_token: None,
+            last_resume_save: Instant::now(),
+            deferred: Vec::new(),
+            last_abr_report: Instant::now(),
             last_debug: Instant::now(),
         }
     }
@@ -251,6 +454,24 @@ impl SessionState {
     }
 }
 
+struct EventSubscription {
+    topics: HashSet<EventTopic>,
+    receiver: EventReceiver,
+    epoch: u64,
+    /// Newest event seen, whether or not its topic was delivered.
+    seq: u64,
+}
+
+/// The track lifecycle notices are about, with the last position the
+/// client reported for it. Kept after it ends so late `position` reports
+/// for it are ignored.
+struct PlayingTrack {
+    track_id: String,
+    duration_ms: u32,
+    position_ms: u32,
+    ended: bool,
+}
+
 struct ClientConn {
     conn: quiche::Connection,
     session: SessionState,
@@ -267,157 +488,302 @@ pub async fn run(state: AppState) -> Result<(), String> {
     let (cert_path, key_path) = ensure_quic_certs(&state, &config)?;
     let bind_addr = resolve_quic_bind_addr(&config)?;
 
-    let mut quic_config = build_quic_config(&cert_path, &key_path)?;
-    let socket = UdpSocket::bind(bind_addr)
+    // Shared by every worker so a ticket works whichever one the client
+    // lands on. Tickets only need to outlive the process; reconnects after
+    // a restart fall back to a full handshake.
+    let mut ticket_key = [0u8; 48];
+    rand::rng().fill_bytes(&mut ticket_key);
+    let socket = QuicSocket::bind(bind_addr)
         .await
         .map_err(|err| format!("quic bind error: {}", err))?;
+    let socket = Arc::new(socket);
     let local_addr = socket
         .local_addr()
         .map_err(|err| format!("quic local addr error: {}", err))?;
+    let workers = quic_worker_count(config.quic_workers);
+
+    let mut shards = Vec::with_capacity(workers);
+    for index in 0..workers {
+        let (sender, inbox) = mpsc::channel(SHARD_QUEUE_DATAGRAMS);
+        let shard = Shard {
+            index: index as u8,
+            state: state.clone(),
+            socket: Arc::clone(&socket),
+            local_addr,
+            quic_config: build_quic_config(&cert_path, &key_path, &ticket_key)?,
+            connections: HashMap::new(),
+            conn_id_map: HashMap::new(),
+            send_buf: vec![0u8; MAX_UDP_SIZE],
+        };
+        tokio::spawn(shard.run(inbox));
+        shards.push(sender);
+    }
 
-    tracing::info!("QUIC listening on {}", local_addr);
-
-    let mut connections: HashMap<Vec<u8>, ClientConn> = HashMap::new();
-    let mut conn_id_map: HashMap<Vec<u8>, Vec<u8>> = HashMap::new();
-    let mut recv_buf = vec![0u8; MAX_UDP_SIZE];
-    let mut send_buf = vec![0u8; MAX_UDP_SIZE];
-
-    let mut tick = tokio::time::interval(Duration::from_millis(25));
+    tracing::info!(
+        "QUIC listening on {} workers={} gso_segments={} gro_segments={}",
+        local_addr,
+        workers,
+        socket.max_gso_segments(),
+        socket.gro_segments(),
+    );
 
+    let mut bufs = vec![vec![0u8; MAX_UDP_SIZE]; udp::BATCH_SIZE];
+    let mut meta = vec![RecvMeta::default(); udp::BATCH_SIZE];
     loop {
-        tokio::select! {
-            result = socket.recv_from(&mut recv_buf) => {
-                let (len, from) = match result {
-                    Ok(value) => value,
-                    Err(err) => {
-                        tracing::error!("quic recv error: {}", err);
-                        continue;
-                    }
+        let received = {
+            let mut slices: Vec<IoSliceMut> =
+                bufs.iter_mut().map(|buf| IoSliceMut::new(buf)).collect();
+            socket.recv(&mut slices, &mut meta).await
+        };
+        let count = match received {
+            Ok(count) => count,
+            Err(err) => {
+                tracing::error!("quic recv error: {}", err);
+                continue;
+            }
+        };
+        for (buf, meta) in bufs.iter().zip(&meta).take(count) {
+            for datagram in buf[..meta.len].chunks(meta.stride.max(1)) {
+                let shard = route_datagram(datagram, workers);
+                let datagram = Datagram {
+                    data: datagram.to_vec(),
+                    from: meta.addr,
                 };
-                let packet = &mut recv_buf[..len];
-                let hdr = match quiche::Header::from_slice(packet, SERVER_CONN_ID_LEN) {
-                    Ok(hdr) => hdr,
-                    Err(err) => {
-                        tracing::debug!("quic header parse failed: {:?}", err);
-                        continue;
+                match shards[shard].try_send(datagram) {
+                    Ok(()) => {}
+                    Err(TrySendError::Full(_)) => {
+                        tracing::debug!("QUIC worker {} backlogged; dropping datagram", shard);
                     }
-                };
-                let conn_id = hdr.dcid.to_vec();
-                let mut lookup_id = conn_id_map
-                    .get(&conn_id)
-                    .cloned()
-                    .unwrap_or_else(|| conn_id.clone());
-                if !connections.contains_key(&lookup_id) {
-                    if !quiche::version_is_supported(hdr.version) {
-                        match quiche::negotiate_version(&hdr.scid, &hdr.dcid, &mut send_buf) {
-                            Ok(len) => {
-                                let _ = socket.send_to(&send_buf[..len], from).await;
-                            }
-                            Err(err) => {
-                                tracing::debug!("quic version negotiation failed: {:?}", err);
-                            }
-                        }
-                        continue;
+                    Err(TrySendError::Closed(_)) => {
+                        return Err(format!("QUIC worker {} stopped", shard));
                     }
-                    let scid = generate_cid();
-                    let scid_conn_id = quiche::ConnectionId::from_ref(&scid);
-                    let conn = quiche::accept(
-                        &scid_conn_id,
-                        Some(&hdr.dcid),
-                        local_addr,
-                        from,
-                        &mut quic_config,
-                    )
-                        .map_err(|err| format!("quic accept error: {:?}", err))?;
-                    let timeout_at = conn.timeout().map(|t| Instant::now() + t);
-                    let primary_id = scid.to_vec();
-                    connections.insert(
-                        primary_id.clone(),
-                        ClientConn {
-                            conn,
-                            session: SessionState::new(),
-                            timeout_at,
-                        },
-                    );
-                    conn_id_map.insert(primary_id.clone(), primary_id.clone());
-                    conn_id_map.insert(conn_id.clone(), primary_id.clone());
-                    lookup_id = primary_id;
                 }
+            }
+        }
+    }
+}
 
-                let client = match connections.get_mut(&lookup_id) {
-                    Some(client) => client,
-                    None => continue,
-                };
+/// `configured` of 0 uses one worker per available core. Worker indexes
+/// have to fit the connection ID byte that routes to them.
+fn quic_worker_count(configured: usize) -> usize {
+    let count = if configured == 0 {
+        std::thread::available_parallelism()
+            .map(|cores| cores.get())
+            .unwrap_or(2)
+    } else {
+        configured
+    };
+    count.clamp(1, 64)
+}
 
-                let recv_info = quiche::RecvInfo {
-                    from,
-                    to: local_addr,
-                };
-                if let Err(err) = client.conn.recv(packet, recv_info) {
-                    if err != quiche::Error::Done {
-                        tracing::debug!("quic recv failed: {:?}", err);
+/// Picks the worker for a datagram from the first byte of its destination
+/// connection ID. IDs this server issues start with their worker's index,
+/// and a client's first packets, which carry an ID the client chose, all
+/// land on the same worker too.
+fn route_datagram(datagram: &[u8], workers: usize) -> usize {
+    let Some(first) = datagram.first() else {
+        return 0;
+    };
+    // Long headers have 4 version bytes and a length byte before the ID.
+    let dcid_start = if first & 0x80 != 0 { 6 } else { 1 };
+    if first & 0x80 != 0 && datagram.get(5).copied().unwrap_or(0) == 0 {
+        return 0;
+    }
+    datagram
+        .get(dcid_start)
+        .map_or(0, |byte| *byte as usize % workers)
+}
+
+struct Datagram {
+    data: Vec<u8>,
+    from: SocketAddr,
+}
+
+/// One worker task's connections. Each shard runs its connections' timers
+/// and stream pumping independently, so a busy connection only delays the
+/// others on its shard.
+struct Shard {
+    index: u8,
+    state: AppState,
+    socket: Arc<QuicSocket>,
+    local_addr: SocketAddr,
+    quic_config: quiche::Config,
+    connections: HashMap<Vec<u8>, ClientConn>,
+    conn_id_map: HashMap<Vec<u8>, Vec<u8>>,
+    send_buf: Vec<u8>,
+}
+
+impl Shard {
+    async fn run(mut self, mut inbox: mpsc::Receiver<Datagram>) {
+        let mut tick = tokio::time::interval(Duration::from_millis(25));
+        loop {
+            let next_timeout = self
+                .connections
+                .values()
+                .filter_map(|client| client.timeout_at)
+                .min()
+                .unwrap_or_else(|| Instant::now() + Duration::from_secs(60));
+            tokio::select! {
+                datagram = inbox.recv() => {
+                    let Some(mut datagram) = datagram else { return };
+                    self.handle_datagram(&mut datagram);
+                }
+                _ = tokio::time::sleep_until(next_timeout.into()) => {
+                    self.handle_timeouts();
+                }
+                _ = tick.tick() => {
+                    self.pump();
+                }
+            }
+        }
+    }
+
+    fn handle_datagram(&mut self, datagram: &mut Datagram) {
+        let from = datagram.from;
+        let packet = &mut datagram.data[..];
+        let hdr = match quiche::Header::from_slice(packet, SERVER_CONN_ID_LEN) {
+            Ok(hdr) => hdr,
+            Err(err) => {
+                tracing::debug!("quic header parse failed: {:?}", err);
+                return;
+            }
+        };
+        let conn_id = hdr.dcid.to_vec();
+        let mut lookup_id = self
+            .conn_id_map
+            .get(&conn_id)
+            .cloned()
+            .unwrap_or_else(|| conn_id.clone());
+        if !self.connections.contains_key(&lookup_id) {
+            if !quiche::version_is_supported(hdr.version) {
+                match quiche::negotiate_version(&hdr.scid, &hdr.dcid, &mut self.send_buf) {
+                    Ok(len) => self.socket.send(from, &self.send_buf[..len], len),
+                    Err(err) => {
+                        tracing::debug!("quic version negotiation failed: {:?}", err);
                     }
-                    continue;
                 }
-                refresh_conn_ids(&mut conn_id_map, &mut client.conn, &lookup_id);
-                handle_readable(&state, client);
-                flush_control(&mut client.session, &mut client.conn);
-                flush_streams(&mut client.session, &mut client.conn);
+                return;
+            }
+            let scid = generate_cid(self.index);
+            let scid_conn_id = quiche::ConnectionId::from_ref(&scid);
+            let conn = match quiche::accept(
+                &scid_conn_id,
+                Some(&hdr.dcid),
+                self.local_addr,
+                from,
+                &mut self.quic_config,
+            ) {
+                Ok(conn) => conn,
+                Err(err) => {
+                    tracing::warn!("quic accept error: {:?}", err);
+                    return;
+                }
+            };
+            let timeout_at = conn.timeout().map(|t| Instant::now() + t);
+            let primary_id = scid.to_vec();
+            self.connections.insert(
+                primary_id.clone(),
+                ClientConn {
+                    conn,
+                    session: SessionState::new(),
+                    timeout_at,
+                },
+            );
+            self.conn_id_map
+                .insert(primary_id.clone(), primary_id.clone());
+            self.conn_id_map.insert(conn_id, primary_id.clone());
+            lookup_id = primary_id;
+        }
+
+        let Some(client) = self.connections.get_mut(&lookup_id) else {
+            return;
+        };
+        let recv_info = quiche::RecvInfo {
+            from,
+            to: self.local_addr,
+        };
+        if let Err(err) = client.conn.recv(packet, recv_info) {
+            if err != quiche::Error::Done {
+                tracing::debug!("quic recv failed: {:?}", err);
+            }
+            return;
+        }
+        refresh_conn_ids(
+            &mut self.conn_id_map,
+            &mut client.conn,
+            &lookup_id,
+            self.index,
+        );
+        log_path_events(&mut client.conn);
+        handle_readable(&self.state, client);
+        drain_stream_events(&mut client.session);
+        drain_server_events(&mut client.session);
+        flush_control(&mut client.session, &mut client.conn);
+        flush_streams(&mut client.session, &mut client.conn);
+
+        flush_conn(&mut client.conn, &self.socket, &mut self.send_buf);
+        client.timeout_at = client.conn.timeout().map(|t| Instant::now() + t);
+    }
 
-                flush_conn(&mut client.conn, &socket, &mut send_buf);
+    fn handle_timeouts(&mut self) {
+        let now = Instant::now();
+        for client in self.connections.values_mut() {
+            if client.timeout_at.is_some_and(|deadline| deadline <= now) {
+                client.conn.on_timeout();
+                flush_conn(&mut client.conn, &self.socket, &mut self.send_buf);
                 client.timeout_at = client.conn.timeout().map(|t| Instant::now() + t);
             }
-            _ = tick.tick() => {
-                let now = Instant::now();
-                let mut closed = Vec::new();
-                for (id, client) in connections.iter_mut() {
-                    if let Some(deadline) = client.timeout_at {
-                        if now >= deadline {
-                            client.conn.on_timeout();
-                        }
-                    }
-                    client.timeout_at = client.conn.timeout().map(|t| Instant::now() + t);
-                    if client.conn.is_closed() {
-                        if let Some(err) = client.conn.peer_error() {
-                            tracing::warn!(
-                                "QUIC closed by peer: code={} app={} reason={}",
-                                err.error_code,
-                                err.is_app,
-                                String::from_utf8_lossy(&err.reason),
-                            );
-                        }
-                        if let Some(err) = client.conn.local_error() {
-                            tracing::warn!(
-                                "QUIC closed locally: code={} app={} reason={}",
-                                err.error_code,
-                                err.is_app,
-                                String::from_utf8_lossy(&err.reason),
-                            );
-                        }
-                        if client.conn.is_timed_out() {
-                            tracing::warn!("QUIC closed: idle timeout");
-                        }
-                        closed.push(id.clone());
-                        continue;
-                    }
-                    refresh_conn_ids(&mut conn_id_map, &mut client.conn, id);
-                    handle_readable(&state, client);
-                    flush_control(&mut client.session, &mut client.conn);
-                    flush_streams(&mut client.session, &mut client.conn);
-                    flush_conn(&mut client.conn, &socket, &mut send_buf);
-                    client.timeout_at = client.conn.timeout().map(|t| Instant::now() + t);
-                    maybe_log_streams(&mut client.session, &client.conn);
+        }
+    }
+
+    /// Moves transcoded audio, events and control replies onto the wire,
+    /// and drops closed connections.
+    fn pump(&mut self) {
+        let mut closed = Vec::new();
+        for (id, client) in self.connections.iter_mut() {
+            if client.conn.is_closed() {
+                if let Some(err) = client.conn.peer_error() {
+                    tracing::warn!(
+                        "QUIC closed by peer: code={} app={} reason={}",
+                        err.error_code,
+                        err.is_app,
+                        String::from_utf8_lossy(&err.reason),
+                    );
                 }
-                for id in closed {
-                    if let Some(client) = connections.get(&id) {
-                        for scid in client.conn.source_ids() {
-                            conn_id_map.remove(scid.as_ref());
-                        }
-                    }
-                    connections.remove(&id);
-                    conn_id_map.remove(&id);
+                if let Some(err) = client.conn.local_error() {
+                    tracing::warn!(
+                        "QUIC closed locally: code={} app={} reason={}",
+                        err.error_code,
+                        err.is_app,
+                        String::from_utf8_lossy(&err.reason),
+                    );
+                }
+                if client.conn.is_timed_out() {
+                    tracing::warn!("QUIC closed: idle timeout");
+                }
+                closed.push(id.clone());
+                continue;
+            }
+            refresh_conn_ids(&mut self.conn_id_map, &mut client.conn, id, self.index);
+            handle_readable(&self.state, client);
+            drain_stream_events(&mut client.session);
+            drain_server_events(&mut client.session);
+            flush_control(&mut client.session, &mut client.conn);
+            flush_streams(&mut client.session, &mut client.conn);
+            flush_conn(&mut client.conn, &self.socket, &mut self.send_buf);
+            client.timeout_at = client.conn.timeout().map(|t| Instant::now() + t);
+            report_abr(&self.state, &mut client.session, &client.conn);
+            save_resume_state(&self.state, client);
+            maybe_log_streams(&mut client.session, &client.conn);
+        }
+        for id in closed {
+            if let Some(client) = self.connections.get(&id) {
+                for scid in client.conn.source_ids() {
+                    self.conn_id_map.remove(scid.as_ref());
                 }
             }
+            self.connections.remove(&id);
+            self.conn_id_map.remove(&id);
         }
     }
 }
@@ -426,6 +792,7 @@ fn refresh_conn_ids(
     conn_id_map: &mut HashMap<Vec<u8>, Vec<u8>>,
     conn: &mut quiche::Connection,
     primary_id: &Vec<u8>,
+    shard: u8,
 ) {
     for scid in conn.source_ids() {
         conn_id_map
@@ -435,6 +802,36 @@ fn refresh_conn_ids(
     while let Some(retired) = conn.retired_scid_next() {
         conn_id_map.remove(retired.as_ref());
     }
+    // Spare IDs let the client switch networks without being linkable
+    // across paths; they are mapped on the next call.
+    if conn.is_established() {
+        while conn.scids_left() > 0 {
+            let scid = generate_cid(shard);
+            let mut reset_token = [0u8; 16];
+            rand::rng().fill_bytes(&mut reset_token);
+            let reset_token = u128::from_be_bytes(reset_token);
+            if conn
+                .new_scid(&quiche::ConnectionId::from_ref(&scid), reset_token, false)
+                .is_err()
+            {
+                break;
+            }
+        }
+    }
+}
+
+fn log_path_events(conn: &mut quiche::Connection) {
+    while let Some(event) = conn.path_event_next() {
+        match event {
+            quiche::PathEvent::PeerMigrated(local, peer) => {
+                tracing::info!("QUIC client migrated local={} peer={}", local, peer);
+            }
+            quiche::PathEvent::FailedValidation(local, peer) => {
+                tracing::debug!("QUIC path validation failed local={} peer={}", local, peer);
+            }
+            other => tracing::debug!("QUIC path event: {:?}", other),
+        }
+    }
 }
 
 fn resolve_quic_bind_addr(config: &ServerConfig) -> Result<SocketAddr, String> {
@@ -491,7 +888,11 @@ fn ensure_quic_certs(state: &AppState, config: &ServerConfig) -> Result<(PathBuf
     Ok((cert_path, key_path))
 }
 
-fn build_quic_config(cert_path: &PathBuf, key_path: &PathBuf) -> Result<quiche::Config, String> {
+fn build_quic_config(
+    cert_path: &PathBuf,
+    key_path: &PathBuf,
+    ticket_key: &[u8],
+) -> Result<quiche::Config, String> {
     let mut config =
         quiche::Config::new(quiche::PROTOCOL_VERSION).map_err(|e| format!("{:?}", e))?;
     config
@@ -521,17 +922,29 @@ fn build_quic_config(cert_path: &PathBuf, key_path: &PathBuf) -> Result<quiche::
     config.set_initial_max_stream_data_uni(10_000_000);
     config.set_initial_max_streams_bidi(16);
     config.set_initial_max_streams_uni(32);
-    config.set_disable_active_migration(true);
+    config.set_active_connection_id_limit(ACTIVE_CONN_ID_LIMIT);
+    config
+        .set_ticket_key(ticket_key)
+        .map_err(|err| format!("ticket key error: {:?}", err))?;
+    config.enable_early_data();
     Ok(config)
 }
 
-fn generate_cid() -> Vec<u8> {
-    let mut scid = [0u8; 16];
+/// A connection ID for worker `shard`, which `route_datagram` reads back
+/// from its first byte.
+fn generate_cid(shard: u8) -> Vec<u8> {
+    let mut scid = [0u8; SERVER_CONN_ID_LEN];
     rand::rng().fill_bytes(&mut scid);
+    scid[0] = shard;
     scid.to_vec()
 }
 
 fn handle_readable(state: &AppState, client: &mut ClientConn) {
+    if client.conn.is_established() && !client.session.deferred.is_empty() {
+        for msg in std::mem::take(&mut client.session.deferred) {
+            handle_control_message(state, client, msg);
+        }
+    }
     let mut buf = [0u8; 65535];
     let readable: Vec<u64> = client.conn.readable().collect();
     for stream_id in readable {
@@ -542,8 +955,13 @@ fn handle_readable(state: &AppState, client: &mut ClientConn) {
                         client.session.control_stream = Some(stream_id);
                     }
                     if client.session.control_stream == Some(stream_id) {
-                        let messages = client.session.control_parser.push(&buf[..len]);
+                        let messages: Vec<ControlMessage> =
+                            client.session.control_reader.push(&buf[..len]);
                         for msg in messages {
+                            if client.conn.is_in_early_data() && !safe_in_early_data(&msg) {
+                                client.session.deferred.push(msg);
+                                continue;
+                            }
                             handle_control_message(state, client, msg);
                         }
                     }
@@ -558,8 +976,74 @@ fn handle_readable(state: &AppState, client: &mut ClientConn) {
     }
 }
 
+/// 0-RTT data can be replayed by an attacker, so only messages that
+/// change nothing run before the handshake completes. `auth` waits too,
+/// since it issues a resume token.
+fn safe_in_early_data(msg: &ControlMessage) -> bool {
+    matches!(msg, ControlMessage::Hello { .. } | ControlMessage::Ping { .. })
+}
+
 fn handle_control_message(state: &AppState, client: &mut ClientConn, msg: ControlMessage) {
     match msg {
+        ControlMessage::Hello {
+            protocol,
+            min_protocol,
+            client: client_name,
+            capabilities,
+        } => {
+            if client.session.authed || client.session.protocol != protocol::LEGACY_PROTOCOL {
+                send_control(
+                    client,
+                    ControlResponse::CodedError {
+                        code: "unexpected_hello",
+                        message: "hello must be sent once, before auth",
+                        supported: None,
+                    },
+                );
+                return;
+            }
+            let version = match protocol::negotiate(protocol, min_protocol) {
+                Ok(version) => version,
+                Err(err) => {
+                    tracing::warn!("QUIC hello rejected client={:?}: {}", client_name, err);
+                    send_control(
+                        client,
+                        ControlResponse::CodedError {
+                            code: "unsupported_protocol",
+                            message: &err,
+                            supported: Some(protocol::SERVER_RANGE),
+                        },
+                    );
+                    return;
+                }
+            };
+            let requested = Capabilities::parse(&capabilities.unwrap_or_default());
+            let agreed = requested.intersect(Capabilities::SERVER);
+            tracing::info!(
+                "QUIC hello client={:?} protocol={} capabilities={:?}",
+                client_name,
+                version,
+                agreed.names()
+            );
+            client.session.protocol = version;
+            client.session.client_name = client_name;
+            client.session.capabilities = agreed;
+            send_control(
+                client,
+                ControlResponse::HelloOk {
+                    protocol: version,
+                    server: concat!("phonolite/", env!("CARGO_PKG_VERSION")),
+                    capabilities: agreed.names(),
+                },
+            );
+            // `hello_ok` goes out in the old framing; everything after it
+            // in the new one.
+            client.session.control_framing = if agreed.contains(Capabilities::BINARY_FRAMING) {
+                ControlFraming::Binary
+            } else {
+                ControlFraming::Json
+            };
+        }
         ControlMessage::Auth { token } => {
             tracing::info!("QUIC auth attempt");
             if !state.auth.has_any_user().unwrap_or(false) {
@@ -575,8 +1059,13 @@ fn handle_control_message(state: &AppState, client: &mut ClientConn, msg: Contro
             match state.auth.user_from_token(&token) {
                 Ok(Some(user)) => {
                     client.session.authed = true;
+                    client.session.admin = crate::admin::is_admin(&user);
                     client.session.user_id = Some(user.id);
-                    tracing::info!("QUIC auth ok");
+                    tracing::info!(
+                        "QUIC auth ok client={:?} protocol={}",
+                        client.session.client_name,
+                        client.session.protocol
+                    );
                     if let Some(tp) = client.conn.peer_transport_params() {
                         tracing::info!(
                             "QUIC peer transport params: max_idle_timeout={} max_udp_payload_size={} initial_max_data={} initial_max_stream_data_uni={} initial_max_stream_data_bidi_local={} initial_max_stream_data_bidi_remote={} initial_max_streams_uni={} initial_max_streams_bidi={}",
@@ -592,7 +1081,14 @@ fn handle_control_message(state: &AppState, client: &mut ClientConn, msg: Contro
                     } else {
                         tracing::info!("QUIC peer transport params not available yet");
                     }
-                    send_control(client, ControlResponse::AuthOk);
+                    let resume_token = state.resume_sessions.issue();
+                    send_control(
+                        client,
+                        ControlResponse::AuthOk {
+                            resume_token: &resume_token,
+                        },
+                    );
+                    client.session.resume_token = Some(resume_token);
                 }
                 Ok(None) => {
                     tracing::warn!("QUIC auth failed: unauthorized");
@@ -614,19 +1110,65 @@ fn handle_control_message(state: &AppState, client: &mut ClientConn, msg: Contro
                 }
             }
         }
+        ControlMessage::ResumeSession { token, position_ms } => {
+            if !client.session.authed {
+                send_control(
+                    client,
+                    ControlResponse::Error {
+                        message: "unauthorized",
+                    },
+                );
+                return;
+            }
+            let user_id = client.session.user_id.clone().unwrap_or_default();
+            let snapshot = if client.session.resume_token.as_deref() == Some(token.as_str()) {
+                None
+            } else {
+                state.resume_sessions.take(&token, &user_id)
+            };
+            let Some(snapshot) = snapshot else {
+                tracing::info!("QUIC resume rejected user={}", user_id);
+                send_control(
+                    client,
+                    ControlResponse::CodedError {
+                        code: "resume_failed",
+                        message: "unknown or expired resume token",
+                        supported: None,
+                    },
+                );
+                return;
+            };
+            tracing::info!(
+                "QUIC resume user={} track={:?} position_ms={}",
+                user_id,
+                snapshot.active_track,
+                position_ms.unwrap_or(snapshot.position_ms)
+            );
+            close_streams(&mut client.session, &mut client.conn, |_| true);
+            resume_session(state, client, snapshot, position_ms);
+        }
         ControlMessage::Open {
             track_id,
             mode,
             quality,
             frame_ms,
             queue,
+            gapless,
+            crossfade_ms,
+            crossfade_curve,
+            surround,
+            speed,
         } => {
             tracing::info!(
-                "QUIC open track={} mode={:?} quality={:?} frame_ms={:?}",
+                "QUIC open track={} mode={:?} quality={:?} frame_ms={:?} gapless={:?} crossfade_ms={:?} surround={:?} speed={:?}",
                 track_id,
                 mode,
                 quality,
-                frame_ms
+                frame_ms,
+                gapless,
+                crossfade_ms,
+                surround,
+                speed
             );
             if !client.session.authed {
                 tracing::warn!("QUIC open rejected: unauthorized");
@@ -638,6 +1180,31 @@ fn handle_control_message(state: &AppState, client: &mut ClientConn, msg: Contro
                 );
                 return;
             }
+            let requested = [
+                (gapless == Some(true), Capabilities::GAPLESS),
+                (crossfade_ms.unwrap_or(0) > 0, Capabilities::CROSSFADE),
+                (surround == Some(true), Capabilities::SURROUND),
+                (speed.is_some_and(|speed| speed != 1.0), Capabilities::SPEED),
+            ];
+            for (wanted, capability) in requested {
+                if wanted && !require_capability(client, capability) {
+                    return;
+                }
+            }
+            let crossfade = match parse_crossfade(crossfade_ms, crossfade_curve.as_deref()) {
+                Ok(value) => value,
+                Err(err) => {
+                    send_control(client, ControlResponse::Error { message: &err });
+                    return;
+                }
+            };
+            let speed = match parse_speed(speed) {
+                Ok(value) => value,
+                Err(err) => {
+                    send_control(client, ControlResponse::Error { message: &err });
+                    return;
+                }
+            };
             client.session.active_track = Some(track_id.clone());
             if let Some(queue) = queue {
                 client.session.queue = queue.into();
@@ -645,10 +1212,23 @@ fn handle_control_message(state: &AppState, client: &mut ClientConn, msg: Contro
                 client.session.queue.push_back(track_id.clone());
             }
             ensure_active_in_queue(&mut client.session);
+            // Crossfading needs both tracks in one stream, so it implies gapless.
+            client.session.crossfade = crossfade;
+            client.session.gapless = gapless.unwrap_or(false) || crossfade.is_some();
+            client.session.surround = surround.unwrap_or(false);
+            set_speed(&mut client.session, &mut client.conn, speed);
+            if client.session.gapless {
+                close_stitched_streams(&mut client.session, &mut client.conn);
+            }
             promote_existing_stream(&mut client.session, &track_id, StreamRole::Active);
             prune_streams(&mut client.session, &mut client.conn);
             let frame_ms = frame_ms.unwrap_or(20);
             if !client.session.track_streams.contains_key(&track_id) {
+                // Only an open that gets this far counts against the user's
+                // stream limit.
+                if !ensure_stream_lease(state, client) {
+                    return;
+                }
                 if let Err(err) = start_track_stream(
                     state,
                     client,
@@ -658,6 +1238,7 @@ fn handle_control_message(state: &AppState, client: &mut ClientConn, msg: Contro
                     0,
                     mode.as_deref(),
                     quality.as_deref(),
+                    None,
                 ) {
                     tracing::warn!("QUIC open failed: {}", err);
                     send_control(
@@ -675,11 +1256,14 @@ fn handle_control_message(state: &AppState, client: &mut ClientConn, msg: Contro
                     track_id: &track_id,
                 },
             );
+            client.session.pause.set(false);
+            start_playing(state, &mut client.session, &track_id, 0);
             prebuffer_next_two(state, client, mode.as_deref(), quality.as_deref(), frame_ms);
         }
         ControlMessage::Queue { track_ids } => {
             client.session.queue = track_ids.into();
             ensure_active_in_queue(&mut client.session);
+            refresh_stitched_queues(&mut client.session);
             prune_streams(&mut client.session, &mut client.conn);
             let frame_ms = active_frame_ms(&client.session);
             prebuffer_next_two(state, client, None, None, frame_ms);
@@ -688,6 +1272,12 @@ fn handle_control_message(state: &AppState, client: &mut ClientConn, msg: Contro
             if let Some(next) = next_track_in_queue(&client.session) {
                 let frame_ms = active_frame_ms(&client.session);
                 client.session.active_track = Some(next.clone());
+                start_playing(state, &mut client.session, &next, 0);
+                if stitched_stream_carries(&client.session, &next) {
+                    return;
+                }
+                // The stitched stream is on another track; it can't catch up.
+                close_stitched_streams(&mut client.session, &mut client.conn);
                 let _ = start_track_stream(
                     state,
                     client,
@@ -697,18 +1287,29 @@ fn handle_control_message(state: &AppState, client: &mut ClientConn, msg: Contro
                     0,
                     None,
                     None,
+                    None,
                 );
                 prebuffer_next_two(state, client, None, None, frame_ms);
             }
         }
         ControlMessage::Buffer { buffer_ms, target_ms } => {
             client.session.client_buffer_ms = buffer_ms;
+            client.session.buffer_reported = true;
             if let Some(target) = target_ms {
                 client.session.buffer_target_ms = target;
             }
         }
-        ControlMessage::Seek { track_id, position_ms } => {
-            tracing::info!("QUIC seek track={} position_ms={}", track_id, position_ms);
+        ControlMessage::Seek {
+            track_id,
+            position_ms,
+            speed,
+        } => {
+            tracing::info!(
+                "QUIC seek track={} position_ms={} speed={:?}",
+                track_id,
+                position_ms,
+                speed
+            );
             if !client.session.authed {
                 tracing::warn!("QUIC seek rejected: unauthorized");
                 send_control(
@@ -719,16 +1320,35 @@ fn handle_control_message(state: &AppState, client: &mut ClientConn, msg: Contro
                 );
                 return;
             }
+            if speed.is_some_and(|speed| speed != 1.0)
+                && !require_capability(client, Capabilities::SPEED)
+            {
+                return;
+            }
+            // Absent keeps the session's current speed.
+            let speed = match speed.map(|speed| parse_speed(Some(speed))).transpose() {
+                Ok(value) => value,
+                Err(err) => {
+                    send_control(client, ControlResponse::Error { message: &err });
+                    return;
+                }
+            };
             client.session.active_track = Some(track_id.clone());
             ensure_active_in_queue(&mut client.session);
             let mut frame_ms = active_frame_ms(&client.session);
             let mut mode_label: Option<&str> = None;
             let mut quality_label: Option<&str> = None;
+            // The new stream carries on with the old one's ABR session.
+            let mut abr = None;
+            if client.session.gapless {
+                close_stitched_streams(&mut client.session, &mut client.conn);
+            }
             if let Some(stream_id) = client.session.track_streams.get(&track_id).cloned() {
-                if let Some(outgoing) = client.session.outgoing.get(&stream_id) {
+                if let Some(outgoing) = client.session.outgoing.remove(&stream_id) {
                     frame_ms = outgoing.frame_ms;
                     mode_label = Some(transcode_mode_label(outgoing.mode));
                     quality_label = Some(transcode_quality_label(outgoing.quality));
+                    abr = outgoing.abr;
                 }
                 tracing::info!(
                     "QUIC seek switching streams track={} stream_id={}",
@@ -736,31 +1356,459 @@ fn handle_control_message(state: &AppState, client: &mut ClientConn, msg: Contro
                     stream_id
                 );
                 client.session.track_streams.remove(&track_id);
-                client.session.outgoing.remove(&stream_id);
                 let _ = client
                     .conn
                     .stream_shutdown(stream_id, quiche::Shutdown::Write, 0);
             }
+            if let Some(speed) = speed {
+                set_speed(&mut client.session, &mut client.conn, speed);
+            }
             if let Err(err) = start_track_stream(
                 state,
                 client,
-                track_id,
+                track_id.clone(),
                 StreamRole::Active,
                 frame_ms,
                 position_ms,
                 mode_label,
                 quality_label,
+                abr,
             ) {
                 tracing::warn!("QUIC seek failed: {}", err);
                 send_control(client, ControlResponse::Error { message: &err });
+                return;
             }
+            start_playing(state, &mut client.session, &track_id, position_ms);
         }
-        ControlMessage::Ping { ts } => {
-            send_control(client, ControlResponse::Pong { ts });
-        }
-    }
-}
-
+        ControlMessage::Pause => {
+            if !client.session.authed {
+                send_control(
+                    client,
+                    ControlResponse::Error {
+                        message: "unauthorized",
+                    },
+                );
+                return;
+            }
+            client.session.pause.set(true);
+            publish_pause(state, &client.session, "paused");
+        }
+        ControlMessage::Resume => {
+            if !client.session.authed {
+                send_control(
+                    client,
+                    ControlResponse::Error {
+                        message: "unauthorized",
+                    },
+                );
+                return;
+            }
+            client.session.pause.set(false);
+            publish_pause(state, &client.session, "resumed");
+        }
+        ControlMessage::Stop => {
+            if !client.session.authed {
+                send_control(
+                    client,
+                    ControlResponse::Error {
+                        message: "unauthorized",
+                    },
+                );
+                return;
+            }
+            tracing::info!("QUIC stop track={:?}", client.session.active_track);
+            close_streams(&mut client.session, &mut client.conn, |_| true);
+            client.session.active_track = None;
+            client.session.pause.set(false);
+            end_playing(state, &mut client.session, "stopped");
+        }
+        ControlMessage::Position {
+            track_id,
+            position_ms,
+        } => {
+            if !client.session.authed {
+                send_control(
+                    client,
+                    ControlResponse::Error {
+                        message: "unauthorized",
+                    },
+                );
+                return;
+            }
+            track_position(state, &mut client.session, &track_id, position_ms);
+        }
+        ControlMessage::Subscribe {
+            topics,
+            since,
+            epoch,
+        } => {
+            if !client.session.authed {
+                send_control(
+                    client,
+                    ControlResponse::Error {
+                        message: "unauthorized",
+                    },
+                );
+                return;
+            }
+            let mut parsed = Vec::new();
+            for name in &topics {
+                let Some(topic) = EventTopic::parse(name) else {
+                    let message = format!("unknown event topic: {}", name);
+                    send_control(
+                        client,
+                        ControlResponse::CodedError {
+                            code: "unknown_topic",
+                            message: &message,
+                            supported: None,
+                        },
+                    );
+                    return;
+                };
+                parsed.push(topic);
+            }
+            if parsed.iter().any(|topic| topic.admin_only()) && !client.session.admin {
+                send_control(
+                    client,
+                    ControlResponse::CodedError {
+                        code: "forbidden",
+                        message: "activity events are for admins",
+                        supported: None,
+                    },
+                );
+                return;
+            }
+            // Without the epoch the client's `since` can't be trusted.
+            let since = since.map(|since| (epoch.unwrap_or(0), since));
+            subscribe_events(state, &mut client.session, parsed, since);
+        }
+        ControlMessage::Unsubscribe { topics } => {
+            drain_server_events(&mut client.session);
+            let Some(sub) = client.session.events.as_mut() else { return };
+            for topic in topics.iter().filter_map(|name| EventTopic::parse(name)) {
+                sub.topics.remove(&topic);
+            }
+            let (names, seq, epoch) = (topic_names(&sub.topics), sub.seq, sub.epoch);
+            if sub.topics.is_empty() {
+                client.session.events = None;
+            }
+            enqueue_control(
+                &mut client.session,
+                EventMessage::Subscribed {
+                    topics: names,
+                    seq,
+                    epoch,
+                },
+            );
+        }
+        ControlMessage::Ping { ts } => {
+            send_control(client, ControlResponse::Pong { ts });
+        }
+    }
+}
+
+/// Counts the connection against its user's stream limit from the first
+/// `open` (or `resume`); sends the error and returns false over the limit.
+fn ensure_stream_lease(state: &AppState, client: &mut ClientConn) -> bool {
+    if client.session.stream_lease.is_some() {
+        return true;
+    }
+    let user_id = client.session.user_id.clone().unwrap_or_default();
+    match state.stream_quotas.try_acquire(&user_id) {
+        Ok(lease) => {
+            client.session.stream_lease = Some(Arc::new(lease));
+            true
+        }
+        Err(err) => {
+            tracing::warn!("QUIC open rejected user={}: {}", user_id, err);
+            send_control(
+                client,
+                ControlResponse::Error {
+                    message: &err.to_string(),
+                },
+            );
+            false
+        }
+    }
+}
+
+/// Restores a saved session into this one. Settings the new `hello` didn't
+/// negotiate are dropped.
+fn resume_session(
+    state: &AppState,
+    client: &mut ClientConn,
+    snapshot: ResumeSnapshot,
+    position_ms: Option<u32>,
+) {
+    if client.session.stream_lease.is_none() {
+        client.session.stream_lease = snapshot.lease.upgrade();
+    }
+    let caps = client.session.capabilities;
+    client.session.queue = snapshot.queue;
+    client.session.crossfade = snapshot
+        .crossfade
+        .filter(|_| caps.contains(Capabilities::CROSSFADE));
+    client.session.gapless = (snapshot.gapless && caps.contains(Capabilities::GAPLESS))
+        || client.session.crossfade.is_some();
+    client.session.surround = snapshot.surround && caps.contains(Capabilities::SURROUND);
+    let speed = if caps.contains(Capabilities::SPEED) {
+        snapshot.speed
+    } else {
+        1.0
+    };
+    set_speed(&mut client.session, &mut client.conn, speed);
+    client.session.pause.set(snapshot.paused);
+    let position_ms = position_ms.unwrap_or(snapshot.position_ms);
+    let queue: Vec<String> = client.session.queue.iter().cloned().collect();
+    let Some(track_id) = snapshot.active_track else {
+        send_control(
+            client,
+            ControlResponse::SessionResumed {
+                track_id: None,
+                position_ms: 0,
+                queue,
+                paused: snapshot.paused,
+            },
+        );
+        return;
+    };
+    if !ensure_stream_lease(state, client) {
+        return;
+    }
+    client.session.active_track = Some(track_id.clone());
+    ensure_active_in_queue(&mut client.session);
+    if let Err(err) = start_track_stream(
+        state,
+        client,
+        track_id.clone(),
+        StreamRole::Active,
+        snapshot.frame_ms,
+        position_ms,
+        snapshot.mode,
+        snapshot.quality,
+        None,
+    ) {
+        tracing::warn!("QUIC resume failed: {}", err);
+        send_control(client, ControlResponse::Error { message: &err });
+        return;
+    }
+    send_control(
+        client,
+        ControlResponse::SessionResumed {
+            track_id: Some(&track_id),
+            position_ms,
+            queue,
+            paused: snapshot.paused,
+        },
+    );
+    start_playing(state, &mut client.session, &track_id, position_ms);
+    prebuffer_next_two(
+        state,
+        client,
+        snapshot.mode,
+        snapshot.quality,
+        snapshot.frame_ms,
+    );
+}
+
+/// Saves the session under its resume token once a second. A token that
+/// was already used means another connection resumed this session, so
+/// this one closes.
+fn save_resume_state(state: &AppState, client: &mut ClientConn) {
+    let session = &mut client.session;
+    let (Some(token), Some(user_id)) = (session.resume_token.as_ref(), session.user_id.as_ref())
+    else {
+        return;
+    };
+    let now = Instant::now();
+    if now.duration_since(session.last_resume_save) < RESUME_SAVE_INTERVAL {
+        return;
+    }
+    session.last_resume_save = now;
+    let active = session
+        .active_track
+        .as_ref()
+        .and_then(|track_id| session.track_streams.get(track_id))
+        .and_then(|stream_id| session.outgoing.get(stream_id));
+    let position_ms = session
+        .playing
+        .as_ref()
+        .filter(|playing| {
+            !playing.ended && session.active_track.as_ref() == Some(&playing.track_id)
+        })
+        .map_or(0, |playing| playing.position_ms);
+    let snapshot = ResumeSnapshot {
+        user_id: user_id.clone(),
+        queue: session.queue.clone(),
+        active_track: session.active_track.clone(),
+        position_ms,
+        paused: session.pause.is_paused(),
+        frame_ms: active.map_or(20, |outgoing| outgoing.frame_ms),
+        mode: active.map(|outgoing| transcode_mode_label(outgoing.mode)),
+        quality: active.map(|outgoing| transcode_quality_label(outgoing.quality)),
+        gapless: session.gapless,
+        crossfade: session.crossfade,
+        surround: session.surround,
+        speed: session.speed,
+        lease: session
+            .stream_lease
+            .as_ref()
+            .map_or_else(Weak::new, Arc::downgrade),
+    };
+    if !state.resume_sessions.save(token, snapshot) {
+        tracing::info!("QUIC session resumed elsewhere; closing");
+        session.resume_token = None;
+        let _ = client
+            .conn
+            .close(true, CLOSE_RESUMED_ELSEWHERE, b"session resumed elsewhere");
+    }
+}
+
+/// Sends `capability_required` unless `hello` negotiated `capability`.
+fn require_capability(client: &mut ClientConn, capability: Capabilities) -> bool {
+    if client.session.capabilities.contains(capability) {
+        return true;
+    }
+    let message = format!("{} was not negotiated in hello", capability.label());
+    send_control(
+        client,
+        ControlResponse::CodedError {
+            code: "capability_required",
+            message: &message,
+            supported: None,
+        },
+    );
+    false
+}
+
+/// Lifecycle notices go only to clients that negotiated them.
+fn enqueue_lifecycle(session: &mut SessionState, message: ControlResponse<'_>) {
+    if session.capabilities.contains(Capabilities::LIFECYCLE) {
+        enqueue_control(session, message);
+    }
+}
+
+/// Moves lifecycle tracking to `track_id`, ending the previous track as
+/// skipped. Seeking within the playing track only updates its position.
+fn start_playing(state: &AppState, session: &mut SessionState, track_id: &str, position_ms: u32) {
+    if let Some(playing) = session.playing.as_mut() {
+        if playing.track_id == track_id && !playing.ended {
+            playing.position_ms = position_ms;
+            return;
+        }
+    }
+    end_playing(state, session, "skipped");
+    let duration_ms = state
+        .library_state
+        .read()
+        .library
+        .as_ref()
+        .and_then(|library| library.get_track(track_id).ok().flatten())
+        .map(|track| track.duration_ms)
+        .unwrap_or(0);
+    enqueue_lifecycle(
+        session,
+        ControlResponse::TrackStarted {
+            track_id,
+            position_ms,
+            duration_ms,
+        },
+    );
+    publish_playback(
+        state,
+        session,
+        "track_started",
+        serde_json::json!({
+            "track_id": track_id,
+            "position_ms": position_ms,
+            "duration_ms": duration_ms,
+        }),
+    );
+    session.playing = Some(PlayingTrack {
+        track_id: track_id.to_string(),
+        duration_ms,
+        position_ms,
+        ended: false,
+    });
+}
+
+fn end_playing(state: &AppState, session: &mut SessionState, reason: &str) {
+    let Some(playing) = session.playing.as_mut() else { return };
+    if playing.ended {
+        return;
+    }
+    playing.ended = true;
+    let (track_id, position_ms) = (playing.track_id.clone(), playing.position_ms);
+    enqueue_lifecycle(
+        session,
+        ControlResponse::TrackEnded {
+            track_id: &track_id,
+            position_ms,
+            reason,
+        },
+    );
+    publish_playback(
+        state,
+        session,
+        "track_ended",
+        serde_json::json!({
+            "track_id": track_id,
+            "position_ms": position_ms,
+            "reason": reason,
+        }),
+    );
+}
+
+/// Tells the user's other clients what this session is doing. `data` gains
+/// the client name.
+fn publish_playback(
+    state: &AppState,
+    session: &SessionState,
+    kind: &'static str,
+    mut data: serde_json::Value,
+) {
+    let Some(user_id) = session.user_id.as_deref() else { return };
+    data["client"] = serde_json::json!(session.client_name);
+    state.events.publish_for_user(user_id, EventTopic::Playback, kind, data);
+}
+
+fn publish_pause(state: &AppState, session: &SessionState, kind: &'static str) {
+    let track_id = session
+        .playing
+        .as_ref()
+        .filter(|playing| !playing.ended)
+        .map(|playing| playing.track_id.as_str());
+    publish_playback(state, session, kind, serde_json::json!({ "track_id": track_id }));
+}
+
+/// Handles a `position` report. Reaching the end of the track finishes it;
+/// a report for another track means a stitched stream moved on without an
+/// `advance`.
+fn track_position(state: &AppState, session: &mut SessionState, track_id: &str, position_ms: u32) {
+    let same = session
+        .playing
+        .as_ref()
+        .is_some_and(|playing| playing.track_id == track_id);
+    if !same {
+        end_playing(state, session, "finished");
+        start_playing(state, session, track_id, position_ms);
+    }
+    let Some(playing) = session.playing.as_mut() else { return };
+    if playing.ended {
+        return;
+    }
+    playing.position_ms = position_ms;
+    if playing.duration_ms == 0 || position_ms + TRACK_END_SLACK_MS < playing.duration_ms {
+        return;
+    }
+    end_playing(state, session, "finished");
+    let last = session.active_track.as_deref() == Some(track_id)
+        && next_track_in_queue(session).is_none();
+    if last {
+        enqueue_lifecycle(session, ControlResponse::QueueExhausted);
+    }
+}
+
 fn next_track_in_queue(session: &SessionState) -> Option<String> {
     let mut iter = session.queue.iter();
     let active = session.active_track.as_ref()?;
@@ -786,6 +1834,63 @@ fn active_frame_ms(session: &SessionState) -> u32 {
         .unwrap_or(20)
 }
 
+/// Whether a stitched stream is encoding `track_id` or will reach it.
+fn stitched_stream_carries(session: &SessionState, track_id: &str) -> bool {
+    session.outgoing.values().any(|outgoing| {
+        outgoing.stitched.as_ref().is_some_and(|stitched| {
+            let stitched = stitched.lock();
+            stitched.encoding == track_id || stitched.upcoming.iter().any(|id| id == track_id)
+        })
+    })
+}
+
+fn tracks_after(queue: &VecDeque<String>, track_id: &str) -> VecDeque<String> {
+    queue
+        .iter()
+        .skip_while(|id| id.as_str() != track_id)
+        .skip(1)
+        .cloned()
+        .collect()
+}
+
+fn refresh_stitched_queues(session: &mut SessionState) {
+    for outgoing in session.outgoing.values() {
+        let Some(stitched) = outgoing.stitched.as_ref() else { continue };
+        let mut stitched = stitched.lock();
+        stitched.upcoming = tracks_after(&session.queue, &stitched.encoding);
+    }
+}
+
+fn close_stitched_streams(session: &mut SessionState, conn: &mut quiche::Connection) {
+    close_streams(session, conn, |outgoing| outgoing.stitched.is_some());
+}
+
+/// Streams already encoded at another speed are useless once it changes.
+fn set_speed(session: &mut SessionState, conn: &mut quiche::Connection, speed: f32) {
+    session.speed = speed;
+    close_streams(session, conn, |outgoing| outgoing.speed != speed);
+}
+
+fn close_streams(
+    session: &mut SessionState,
+    conn: &mut quiche::Connection,
+    matches: impl Fn(&OutgoingStream) -> bool,
+) {
+    let closing: Vec<(u64, String)> = session
+        .outgoing
+        .iter()
+        .filter(|(_, outgoing)| matches(outgoing))
+        .map(|(stream_id, outgoing)| (*stream_id, outgoing.track_id.clone()))
+        .collect();
+    for (stream_id, track_id) in closing {
+        let _ = conn.stream_shutdown(stream_id, quiche::Shutdown::Write, 0);
+        session.outgoing.remove(&stream_id);
+        if session.track_streams.get(&track_id) == Some(&stream_id) {
+            session.track_streams.remove(&track_id);
+        }
+    }
+}
+
 fn ensure_active_in_queue(session: &mut SessionState) {
     let Some(active) = session.active_track.as_ref() else { return };
     if session.queue.iter().any(|id| id == active) {
@@ -798,6 +1903,9 @@ fn promote_existing_stream(session: &mut SessionState, track_id: &str, role: Str
     if let Some(stream_id) = session.track_streams.get(track_id).cloned() {
         if let Some(outgoing) = session.outgoing.get_mut(&stream_id) {
             outgoing.role = role;
+            if role == StreamRole::Active {
+                outgoing.priority.promote();
+            }
         }
     }
 }
@@ -830,6 +1938,9 @@ fn prebuffer_next_two(
     quality: Option<&str>,
     frame_ms: u32,
 ) {
+    if client.session.gapless {
+        return;
+    }
     let active = match client.session.active_track.as_ref() {
         Some(value) => value.clone(),
         None => return,
@@ -858,37 +1969,52 @@ fn prebuffer_next_two(
             0,
             mode,
             quality,
+            None,
         );
     }
 }
 
-fn spawn_track_transcode(
-    state: &AppState,
-    track_id: &str,
-    frame_ms: u32,
-    mode: TranscodeMode,
-    quality: TranscodeQuality,
-    start_ms: u32,
-) -> Result<tokio::sync::mpsc::Receiver<Result<Bytes, std::io::Error>>, String> {
-    let library_guard = state.library_state.read();
-    let library = library_guard
-        .library
-        .clone()
-        .ok_or_else(|| "library not ready".to_string())?;
+fn resolve_transcode_source(library: &Library, track_id: &str) -> Result<(Track, TranscodeSource), String> {
     let track = library
         .get_track(track_id)
         .map_err(|err| format!("library error: {}", err))?
         .ok_or_else(|| "track not found".to_string())?;
-    let root = library.root().to_path_buf();
-    let path = join_relpath(&root, &track.file_relpath);
+    let path = join_relpath(library.root(), &track.file_relpath);
     if !path.exists() {
         tracing::warn!("QUIC track file missing: {}", path.display());
         return Err("file not found".to_string());
     }
+    let source = TranscodeSource {
+        track_id: track.id.clone(),
+        path,
+        trim: GaplessTrim::from_track(&track),
+    };
+    Ok((track, source))
+}
+
+fn spawn_track_transcode(state: &AppState, job: TranscodeJob<'_>) -> Result<TranscodeChannels, String> {
+    let TranscodeJob {
+        track_id,
+        format,
+        mode,
+        quality,
+        start_ms,
+        stitched,
+        priority,
+        abr,
+        packet_loss_perc,
+        pause,
+    } = job;
+    let library_guard = state.library_state.read();
+    let library = library_guard
+        .library
+        .clone()
+        .ok_or_else(|| "library not ready".to_string())?;
+    let (track, source) = resolve_transcode_source(&library, track_id)?;
 
     let fixed_bitrate_bps = None;
     let session = if mode == TranscodeMode::Auto {
-        Some(state.stream_sessions.create_session(quality))
+        Some(state.stream_sessions.keep_or_create(abr, quality))
     } else {
         None
     };
@@ -897,30 +2023,112 @@ fn spawn_track_transcode(
         quality,
         fixed_bitrate_bps,
         adaptive_bitrate_bps: session.as_ref().map(|s| std::sync::Arc::clone(&s.target_bitrate_bps)),
+        packet_loss_perc: Some(packet_loss_perc),
     };
 
     let meta = build_raw_opus_meta(&library, &track);
     let start_ms = start_ms.min(meta.duration_ms);
     let (tx, rx) =
         tokio::sync::mpsc::channel::<Result<Bytes, std::io::Error>>(256);
-    let path_clone = path.clone();
+    let (events_tx, events) = tokio::sync::mpsc::unbounded_channel::<StreamEvent>();
     let track_id_clone = track_id.to_string();
+    let cache = state.transcode_cache.clone();
+    let profile = CacheProfile {
+        mode,
+        quality,
+        frame_ms: format.frame_ms,
+        surround: format.surround,
+    };
+    let scheduler = state.transcode_scheduler.clone();
     tokio::task::spawn_blocking(move || {
-        let result = crate::transcode::transcode_to_raw_opus(
-            &path_clone,
-            selector,
-            frame_ms,
-            meta,
-            start_ms,
-            &tx,
-        );
+        // Waits for a worker slot; gives up if the stream is dropped first.
+        let acquire = || scheduler.acquire(&priority, || tx.is_closed());
+        let permit = RefCell::new(None);
+        // A paused listener's slot goes to other sessions until it resumes.
+        let throttle = || {
+            scheduler.yield_while_paused(&mut permit.borrow_mut(), &pause, &priority, || {
+                tx.is_closed()
+            })
+        };
+        // Stretched and adaptive encodes are per-listener: the cache only
+        // holds fixed-bitrate encodes at 1x.
+        let cacheable = format.speed == 1.0 && mode == TranscodeMode::Fixed;
+        let result = match stitched {
+            Some(StitchedSpec { queue: stitched, crossfade }) => {
+                let Some(acquired) = acquire() else { return };
+                permit.replace(Some(acquired));
+                let upcoming = std::iter::from_fn(move || loop {
+                    let next = {
+                        let mut queue = stitched.lock();
+                        let next = queue.upcoming.pop_front()?;
+                        queue.encoding = next.clone();
+                        next
+                    };
+                    match resolve_transcode_source(&library, &next) {
+                        Ok((_, source)) => return Some(source),
+                        Err(err) => {
+                            tracing::warn!("QUIC gapless skipping track={} err={}", next, err);
+                        }
+                    }
+                });
+                crate::transcode::transcode_gapless_raw_opus(
+                    std::iter::once(source).chain(upcoming),
+                    selector,
+                    format,
+                    meta,
+                    start_ms,
+                    &tx,
+                    GaplessOptions {
+                        events: &events_tx,
+                        crossfade,
+                        throttle: Some(&throttle),
+                    },
+                )
+            }
+            None => {
+                let fingerprint = (cacheable && cache.enabled())
+                    .then(|| SourceFingerprint::of(&source.path))
+                    .flatten();
+                let cached = fingerprint
+                    .and_then(|fingerprint| cache.lookup(&source.track_id, &profile, fingerprint));
+                match cached {
+                    Some(cached) => cached.replay(start_ms, format.framing, &tx, Some(&events_tx)),
+                    None => {
+                        let Some(acquired) = acquire() else { return };
+                        permit.replace(Some(acquired));
+                        // Only whole-track encodes are recorded; seeks on a
+                        // miss are one-off.
+                        let writer = fingerprint.filter(|_| start_ms == 0).and_then(|fingerprint| {
+                            cache.writer(&source.track_id, &profile, fingerprint)
+                        });
+                        crate::transcode::transcode_to_raw_opus(
+                            source,
+                            selector,
+                            format,
+                            meta,
+                            start_ms,
+                            &tx,
+                            RawOptions {
+                                events: Some(&events_tx),
+                                cache: writer,
+                                throttle: Some(&throttle),
+                            },
+                        )
+                    }
+                }
+            }
+        };
         if let Err(err) = result {
             tracing::warn!("QUIC transcode failed track={} err={}", track_id_clone, err);
             let _ = tx.blocking_send(Err(std::io::Error::new(std::io::ErrorKind::Other, err)));
         }
     });
 
-    Ok(rx)
+    Ok(TranscodeChannels {
+        rx,
+        events,
+        abr: session,
+    })
 }
 
 fn start_track_stream(
@@ -932,14 +2140,18 @@ fn start_track_stream(
     start_ms: u32,
     mode: Option<&str>,
     quality: Option<&str>,
+    abr: Option<StreamSessionHandle>,
 ) -> Result<(), String> {
+    let (running, waiting) = state.transcode_scheduler.load();
     tracing::info!(
-        "QUIC start stream track={} role={:?} frame_ms={} mode={:?} quality={:?}",
+        "QUIC start stream track={} role={:?} frame_ms={} mode={:?} quality={:?} transcodes={} queued={}",
         track_id,
         role,
         frame_ms,
         mode,
-        quality
+        quality,
+        running,
+        waiting
     );
     if client.session.track_streams.contains_key(&track_id) {
         return Ok(());
@@ -948,17 +2160,56 @@ fn start_track_stream(
     let mode = parse_transcode_mode(mode).unwrap_or(TranscodeMode::Auto);
     let quality = parse_transcode_quality(quality).unwrap_or(TranscodeQuality::High);
     let frame_ms = parse_frame_ms(Some(frame_ms)).unwrap_or(20);
-    let rx = spawn_track_transcode(state, &track_id, frame_ms, mode, quality, start_ms)?;
+    let gapless = client.session.gapless && role == StreamRole::Active;
+    let stitched = gapless.then(|| {
+        Arc::new(Mutex::new(StitchedQueue {
+            encoding: track_id.clone(),
+            upcoming: tracks_after(&client.session.queue, &track_id),
+        }))
+    });
+    let surround = client.session.surround;
+    let speed = client.session.speed;
+    let framing = audio_framing(&client.session);
+    let priority = JobPriority::new(role == StreamRole::Active);
+    let channels = spawn_track_transcode(
+        state,
+        TranscodeJob {
+            track_id: &track_id,
+            format: StreamFormat {
+                frame_ms,
+                surround,
+                speed,
+                framing,
+            },
+            mode,
+            quality,
+            start_ms,
+            stitched: stitched.clone().map(|queue| StitchedSpec {
+                queue,
+                crossfade: client.session.crossfade,
+            }),
+            priority: priority.clone(),
+            abr,
+            packet_loss_perc: client.session.packet_loss.shared(),
+            pause: client.session.pause.clone(),
+        },
+    )?;
 
     let stream_id = client.session.next_server_uni_stream();
     client
         .session
         .track_streams
         .insert(track_id.clone(), stream_id);
-    client.session.outgoing.insert(
-        stream_id,
-        OutgoingStream::new(stream_id, track_id.clone(), role, frame_ms, mode, quality, rx),
-    );
+    let mut outgoing =
+        OutgoingStream::new(stream_id, track_id.clone(), role, frame_ms, mode, quality, channels);
+    outgoing.stitched = stitched;
+    outgoing.surround = surround;
+    outgoing.speed = speed;
+    outgoing.framing = framing;
+    outgoing.priority = priority;
+    outgoing.packet_loss_perc = client.session.packet_loss.shared();
+    outgoing.pause = client.session.pause.clone();
+    client.session.outgoing.insert(stream_id, outgoing);
 
     let role_label = match role {
         StreamRole::Active => "active",
@@ -971,50 +2222,202 @@ fn start_track_stream(
             stream_id,
             role: role_label,
             frame_ms,
+            gapless,
+            speed,
         },
     );
 
     Ok(())
 }
 
-fn seek_track_stream(
-    state: &AppState,
-    outgoing: &mut OutgoingStream,
-    track_id: &str,
-    position_ms: u32,
-) -> Result<(), String> {
-    let frame_ms = outgoing.frame_ms;
-    let mode = outgoing.mode;
-    let quality = outgoing.quality;
-    let rx = spawn_track_transcode(state, track_id, frame_ms, mode, quality, position_ms)?;
-
-    outgoing.rx = rx;
-    outgoing.pending.clear();
-    outgoing.offset = 0;
-    outgoing.finished = false;
-    outgoing.buffered_bytes = 0;
-    outgoing.sent_bytes = 0;
-    let now = Instant::now();
-    outgoing.last_send = now;
-    outgoing.last_drain = now;
-    outgoing.last_send_log = now;
-    outgoing.last_send_err = None;
+fn send_control(client: &mut ClientConn, message: ControlResponse<'_>) {
+    enqueue_control(&mut client.session, message);
+}
+
+/// Also takes `EventMessage`s, which share the control stream's framing.
+fn enqueue_control(session: &mut SessionState, message: impl Serialize) {
+    match encode_control(session.control_framing, &message) {
+        Ok(payload) => session.control_outbox.enqueue(Bytes::from(payload)),
+        Err(err) => tracing::warn!("QUIC control encode failed: {}", err),
+    }
+}
 
-    let marker = SEEK_RESET_MARKER.to_le_bytes().to_vec();
-    outgoing.pending.push_back(Bytes::from(marker));
-    outgoing.buffered_bytes = outgoing.buffered_bytes.saturating_add(2);
+/// Audio streams use binary framing along with the control stream.
+fn audio_framing(session: &SessionState) -> AudioFraming {
+    if session.capabilities.contains(Capabilities::BINARY_FRAMING) {
+        AudioFraming::Binary
+    } else {
+        AudioFraming::Legacy
+    }
+}
 
-    Ok(())
+/// Forwards transcoder events to the control channel. A boundary also moves
+/// the stitched stream's track mapping on to the track now being encoded.
+fn drain_stream_events(session: &mut SessionState) {
+    // Clients that didn't negotiate gapless don't know these notices.
+    let notify = session.capabilities.contains(Capabilities::GAPLESS);
+    let mut notices = Vec::new();
+    for (stream_id, outgoing) in session.outgoing.iter_mut() {
+        while let Ok(event) = outgoing.events.try_recv() {
+            if let StreamEvent::TrackBoundary { track_id, .. } = &event {
+                notices.push((*stream_id, Some(outgoing.track_id.clone()), event.clone()));
+                outgoing.track_id = track_id.clone();
+            } else {
+                notices.push((*stream_id, None, event));
+            }
+        }
+    }
+    for (stream_id, previous, event) in notices {
+        match event {
+            StreamEvent::TrackBoundary {
+                track_id,
+                sample_offset,
+                crossfade_samples,
+            } => {
+                if let Some(previous) = previous {
+                    if session.track_streams.get(&previous) == Some(&stream_id) {
+                        session.track_streams.remove(&previous);
+                    }
+                }
+                session.track_streams.insert(track_id.clone(), stream_id);
+                if !notify {
+                    continue;
+                }
+                enqueue_control(
+                    session,
+                    ControlResponse::TrackBoundary {
+                        stream_id,
+                        track_id: &track_id,
+                        sample_offset,
+                        crossfade_samples,
+                    },
+                );
+            }
+            StreamEvent::End {
+                pre_skip,
+                valid_samples,
+            } => {
+                let track_id = session
+                    .outgoing
+                    .get(&stream_id)
+                    .map(|outgoing| outgoing.track_id.clone())
+                    .unwrap_or_default();
+                if !notify {
+                    continue;
+                }
+                enqueue_control(
+                    session,
+                    ControlResponse::StreamEnd {
+                        stream_id,
+                        track_id: &track_id,
+                        pre_skip,
+                        valid_samples,
+                    },
+                );
+            }
+        }
+    }
 }
 
-fn send_control(client: &mut ClientConn, message: ControlResponse<'_>) {
-    let payload = match serde_json::to_string(&message) {
-        Ok(value) => value,
-        Err(_) => return,
-    };
-    let mut line = payload;
-    line.push('\n');
-    client.session.control_outbox.enqueue(Bytes::from(line));
+/// Adds `topics` to the session's subscription. With `since`, replays what
+/// the client missed, or asks it to resync when that is no longer known.
+fn subscribe_events(
+    state: &AppState,
+    session: &mut SessionState,
+    topics: Vec<EventTopic>,
+    since: Option<(u64, u64)>,
+) {
+    if since.is_none() {
+        if let Some(sub) = session.events.as_mut() {
+            // Keep the receiver so nothing published meanwhile is lost.
+            sub.topics.extend(topics);
+            let (names, seq, epoch) = (topic_names(&sub.topics), sub.seq, sub.epoch);
+            enqueue_control(
+                session,
+                EventMessage::Subscribed {
+                    topics: names,
+                    seq,
+                    epoch,
+                },
+            );
+            return;
+        }
+    }
+    // Anything the old receiver still holds predates the new one.
+    drain_server_events(session);
+    let mut all = session
+        .events
+        .take()
+        .map(|sub| sub.topics)
+        .unwrap_or_default();
+    all.extend(topics);
+    let sub = state.events.subscribe(since);
+    let epoch = state.events.epoch();
+    enqueue_control(
+        session,
+        EventMessage::Subscribed {
+            topics: topic_names(&all),
+            seq: sub.seq,
+            epoch,
+        },
+    );
+    match sub.replay {
+        Some(events) => {
+            let user_id = session.user_id.clone();
+            let wanted = |event: &&Arc<ServerEvent>| {
+                all.contains(&event.topic) && event.visible_to(user_id.as_deref())
+            };
+            for event in events.iter().filter(wanted) {
+                enqueue_control(session, EventMessage::Event(event));
+            }
+        }
+        None if since.is_some() => {
+            enqueue_control(session, EventMessage::ResyncRequired { epoch });
+        }
+        None => {}
+    }
+    session.events = Some(EventSubscription {
+        topics: all,
+        receiver: sub.receiver,
+        epoch,
+        seq: sub.seq,
+    });
+}
+
+fn topic_names(topics: &HashSet<EventTopic>) -> Vec<&'static str> {
+    let mut names: Vec<&'static str> = topics.iter().map(|topic| topic.name()).collect();
+    names.sort_unstable();
+    names
+}
+
+/// Forwards bus events for the subscribed topics, pausing while the
+/// outbox is over `MAX_CONTROL_OUTBOX_BYTES`. A receiver that fell behind
+/// the bus lost events, so the client is told to resync.
+fn drain_server_events(session: &mut SessionState) {
+    let Some(sub) = session.events.as_mut() else { return };
+    let outbox = &mut session.control_outbox;
+    while outbox.buffered_bytes < MAX_CONTROL_OUTBOX_BYTES {
+        let payload = match sub.receiver.try_recv() {
+            Ok(event) => {
+                sub.seq = event.seq;
+                if !sub.topics.contains(&event.topic)
+                    || !event.visible_to(session.user_id.as_deref())
+                {
+                    continue;
+                }
+                encode_control(session.control_framing, &EventMessage::Event(&event))
+            }
+            Err(TryRecvError::Lagged(_)) => encode_control(
+                session.control_framing,
+                &EventMessage::ResyncRequired { epoch: sub.epoch },
+            ),
+            Err(TryRecvError::Empty | TryRecvError::Closed) => break,
+        };
+        match payload {
+            Ok(payload) => outbox.enqueue(Bytes::from(payload)),
+            Err(err) => tracing::warn!("QUIC control encode failed: {}", err),
+        }
+    }
 }
 
 fn flush_control(session: &mut SessionState, conn: &mut quiche::Connection) {
@@ -1028,18 +2431,14 @@ fn flush_control(session: &mut SessionState, conn: &mut quiche::Connection) {
         match conn.stream_send(stream_id, data, false) {
             Ok(sent) => {
                 if sent == data.len() {
-                    session.control_outbox.pending.pop_front();
-                    session.control_outbox.offset = 0;
+                    session.control_outbox.pop();
                 } else {
                     session.control_outbox.offset = session.control_outbox.offset.saturating_add(sent);
                     break;
                 }
             }
             Err(quiche::Error::Done) => break,
-            Err(_) => {
-                session.control_outbox.pending.pop_front();
-                session.control_outbox.offset = 0;
-            }
+            Err(_) => session.control_outbox.pop(),
         }
     }
 }
@@ -1057,13 +2456,7 @@ fn flush_streams(session: &mut SessionState, conn: &mut quiche::Connection) {
     }
     for stream_id in active_ids.into_iter().chain(prefetch_ids.into_iter()) {
         let Some(outgoing) = session.outgoing.get_mut(&stream_id) else { continue };
-        let force_send = outgoing
-            .pending
-            .front()
-            .map(|front| front.len() == 2 && front[0] == 0xFF && front[1] == 0xFF)
-            .unwrap_or(false);
-        if !force_send
-            && outgoing.role == StreamRole::Active
+        if outgoing.role == StreamRole::Active
             && session.buffer_target_ms > 0
             && session.client_buffer_ms >= session.buffer_target_ms
         {
@@ -1122,14 +2515,16 @@ fn flush_streams(session: &mut SessionState, conn: &mut quiche::Connection) {
             }
         }
         if outgoing.finished && outgoing.pending.is_empty() {
-            let keep_open = outgoing.role == StreamRole::Active
-                && session
-                    .active_track
-                    .as_deref()
-                    .map(|id| id == outgoing.track_id)
-                    .unwrap_or(false);
+            let keep_open = outgoing.stitched.is_some()
+                || (outgoing.role == StreamRole::Active
+                    && session
+                        .active_track
+                        .as_deref()
+                        .map(|id| id == outgoing.track_id)
+                        .unwrap_or(false));
             if keep_open {
-                // Keep the current active stream open so seeks reuse it.
+                // Keep the current active stream open until it's replaced; a
+                // stitched stream stays open so `advance` never re-sends it.
                 continue;
             }
             let _ = conn.stream_shutdown(stream_id, quiche::Shutdown::Write, 0);
@@ -1142,6 +2537,39 @@ fn flush_streams(session: &mut SessionState, conn: &mut quiche::Connection) {
     }
 }
 
+/// Feeds the connection's path stats and the client's buffer level to the
+/// adaptive bitrate controller of every `auto` stream, and updates the loss
+/// estimate every encoder on the connection tunes FEC from.
+fn report_abr(state: &AppState, session: &mut SessionState, conn: &quiche::Connection) {
+    let now = Instant::now();
+    if now.duration_since(session.last_abr_report) < ABR_REPORT_INTERVAL {
+        return;
+    }
+    session.last_abr_report = now;
+    let path = conn.path_stats().find(|path| path.active).map(|path| PathSample {
+        rtt: path.rtt,
+        min_rtt: path.min_rtt,
+        cwnd: path.cwnd,
+        delivery_rate: path.delivery_rate,
+        sent: path.sent,
+        lost: path.lost,
+    });
+    if let Some(path) = path.as_ref() {
+        session.packet_loss.observe(path);
+    }
+    // A paused client's buffer level says nothing about the network.
+    let sample = AbrSample {
+        buffer_ms: (session.buffer_reported && !session.pause.is_paused())
+            .then_some(session.client_buffer_ms as u64),
+        path,
+    };
+    for outgoing in session.outgoing.values() {
+        if let Some(abr) = outgoing.abr.as_ref() {
+            state.stream_sessions.report(abr.id, sample);
+        }
+    }
+}
+
 fn maybe_log_streams(session: &mut SessionState, conn: &quiche::Connection) {
     let now = Instant::now();
     if now.duration_since(session.last_debug) < Duration::from_secs(5) {
@@ -1176,25 +2604,107 @@ fn maybe_log_streams(session: &mut SessionState, conn: &quiche::Connection) {
     }
 }
 
-fn flush_conn(
-    conn: &mut quiche::Connection,
-    socket: &UdpSocket,
-    out: &mut [u8],
-) {
+/// Sends what the connection has queued. Consecutive datagrams to one
+/// address go out as a single GSO batch where the platform supports it.
+fn flush_conn(conn: &mut quiche::Connection, socket: &QuicSocket, out: &mut [u8]) {
+    let max_segments = socket
+        .max_gso_segments()
+        .clamp(1, out.len() / MAX_QUIC_DATAGRAM);
+    let mut filled = 0;
+    let mut segments = 0;
+    let mut segment_size = 0;
+    let mut to = None;
     loop {
-        match conn.send(out) {
-            Ok((len, send_info)) => {
-                let _ = socket.try_send_to(&out[..len], send_info.to);
-            }
+        let (len, send_info) = match conn.send(&mut out[filled..filled + MAX_QUIC_DATAGRAM]) {
+            Ok(value) => value,
             Err(quiche::Error::Done) => break,
             Err(err) => {
                 tracing::debug!("quic send error: {:?}", err);
                 break;
             }
+        };
+        // Every datagram in a batch but the last has the batch's size.
+        if let Some(batch_to) =
+            to.filter(|batch_to| *batch_to != send_info.to || len > segment_size)
+        {
+            socket.send(batch_to, &out[..filled], segment_size);
+            out.copy_within(filled..filled + len, 0);
+            filled = 0;
+            segments = 0;
+        }
+        if segments == 0 {
+            segment_size = len;
+            to = Some(send_info.to);
+        }
+        filled += len;
+        segments += 1;
+        if len < segment_size || segments == max_segments {
+            socket.send(send_info.to, &out[..filled], segment_size);
+            filled = 0;
+            segments = 0;
+            to = None;
         }
     }
+    if let Some(to) = to.filter(|_| filled > 0) {
+        socket.send(to, &out[..filled], segment_size);
+    }
 }
 
 fn is_bidi_stream(stream_id: u64) -> bool {
     stream_id % 4 == 0 || stream_id % 4 == 1
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn issued_connection_ids_route_to_their_worker() {
+        for shard in 0..8u8 {
+            let cid = generate_cid(shard);
+            let mut short = vec![0x40];
+            short.extend_from_slice(&cid);
+            assert_eq!(route_datagram(&short, 8), shard as usize);
+            let mut long = vec![0xc0, 0, 0, 0, 1, cid.len() as u8];
+            long.extend_from_slice(&cid);
+            assert_eq!(route_datagram(&long, 8), shard as usize);
+        }
+        assert_eq!(route_datagram(&[], 8), 0);
+        assert_eq!(route_datagram(&[0xc0, 0, 0, 0, 1, 0], 8), 0);
+    }
+
+    #[test]
+    fn stalled_event_subscribers_get_a_resync() {
+        let bus = crate::events::EventBus::default();
+        let mut session = SessionState::new();
+        let sub = bus.subscribe(None);
+        session.events = Some(EventSubscription {
+            topics: HashSet::from([EventTopic::Library]),
+            receiver: sub.receiver,
+            epoch: bus.epoch(),
+            seq: sub.seq,
+        });
+        let padding = "x".repeat(4096);
+        for _ in 0..100 {
+            bus.publish(EventTopic::Library, "scan_progress", &padding);
+        }
+        drain_server_events(&mut session);
+        let held = session.control_outbox.buffered_bytes;
+        assert!(held >= MAX_CONTROL_OUTBOX_BYTES);
+        assert!(held < MAX_CONTROL_OUTBOX_BYTES + 8192);
+
+        // Nothing is read meanwhile, so the receiver falls off the bus.
+        for _ in 0..1024 {
+            bus.publish(EventTopic::Library, "scan_progress", &padding);
+        }
+        drain_server_events(&mut session);
+        assert_eq!(session.control_outbox.buffered_bytes, held);
+
+        while !session.control_outbox.pending.is_empty() {
+            session.control_outbox.pop();
+        }
+        drain_server_events(&mut session);
+        let first = session.control_outbox.pending.front().unwrap();
+        assert!(std::str::from_utf8(first).unwrap().contains("resync_required"));
+    }
+}
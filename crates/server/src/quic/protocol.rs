@@ -0,0 +1,134 @@
+//! Control protocol versioning. Clients open with `hello` before `auth`;
+//! connections that skip it are treated as protocol 1 with no optional
+//! capabilities, which is what clients predating `hello` understand.
+
+use serde::Serialize;
+
+/// Protocol spoken by clients that never send `hello`.
+pub const LEGACY_PROTOCOL: u32 = 1;
+/// Oldest protocol a `hello` may settle on.
+pub const MIN_PROTOCOL: u32 = 2;
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Optional behaviours a client has to opt into, as a bit set.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+    /// Stitched streams plus `track_boundary` and `stream_end` notices.
+    pub const GAPLESS: Self = Self(1);
+    pub const CROSSFADE: Self = Self(1 << 1);
+    pub const SPEED: Self = Self(1 << 2);
+    pub const SURROUND: Self = Self(1 << 3);
+    pub const LYRICS_PUSH: Self = Self(1 << 4);
+    /// `track_started`, `track_ended` and `queue_exhausted` notices.
+    pub const LIFECYCLE: Self = Self(1 << 5);
+    /// Length-prefixed binary frames on the control and audio streams.
+    pub const BINARY_FRAMING: Self = Self(1 << 6);
+
+    const NAMES: [(&'static str, Self); 7] = [
+        ("gapless", Self::GAPLESS),
+        ("crossfade", Self::CROSSFADE),
+        ("speed", Self::SPEED),
+        ("surround", Self::SURROUND),
+        ("lyrics_push", Self::LYRICS_PUSH),
+        ("lifecycle", Self::LIFECYCLE),
+        ("binary_framing", Self::BINARY_FRAMING),
+    ];
+
+    /// What this server can do. Lyrics push is understood but not offered
+    /// yet.
+    pub const SERVER: Self = Self(
+        Self::GAPLESS.0
+            | Self::CROSSFADE.0
+            | Self::SPEED.0
+            | Self::SURROUND.0
+            | Self::LIFECYCLE.0
+            | Self::BINARY_FRAMING.0,
+    );
+
+    /// Unknown names are ignored so newer clients can list more.
+    pub fn parse(names: &[String]) -> Self {
+        let mut caps = Self::default();
+        for name in names {
+            let name = name.trim().to_ascii_lowercase();
+            if let Some((_, cap)) = Self::NAMES.iter().find(|(known, _)| *known == name) {
+                caps.0 |= cap.0;
+            }
+        }
+        caps
+    }
+
+    pub fn names(self) -> Vec<&'static str> {
+        Self::NAMES
+            .iter()
+            .filter(|(_, cap)| self.contains(*cap))
+            .map(|(name, _)| *name)
+            .collect()
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn intersect(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+
+    pub fn label(self) -> &'static str {
+        Self::NAMES
+            .iter()
+            .find(|(_, cap)| *cap == self)
+            .map(|(name, _)| *name)
+            .unwrap_or("unknown")
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct ProtocolRange {
+    pub min: u32,
+    pub max: u32,
+}
+
+pub const SERVER_RANGE: ProtocolRange = ProtocolRange {
+    min: MIN_PROTOCOL,
+    max: PROTOCOL_VERSION,
+};
+
+/// The highest version both sides speak. `protocol` is the client's
+/// newest, `min_protocol` its oldest (defaults to `protocol`).
+pub fn negotiate(protocol: u32, min_protocol: Option<u32>) -> Result<u32, String> {
+    let client_min = min_protocol.unwrap_or(protocol).min(protocol);
+    let version = protocol.min(PROTOCOL_VERSION);
+    if version < MIN_PROTOCOL.max(client_min) {
+        return Err(format!(
+            "unsupported protocol: client speaks {}-{}, server speaks {}-{}",
+            client_min, protocol, MIN_PROTOCOL, PROTOCOL_VERSION
+        ));
+    }
+    Ok(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiates_the_highest_shared_version() {
+        assert_eq!(negotiate(2, None), Ok(2));
+        assert_eq!(negotiate(7, Some(1)), Ok(PROTOCOL_VERSION));
+        assert!(negotiate(1, None).is_err());
+        assert!(negotiate(9, Some(8)).is_err());
+    }
+
+    #[test]
+    fn capabilities_round_trip_and_drop_unknown_names() {
+        let names = ["Gapless", "speed", "lyrics_push", "telepathy"].map(String::from);
+        let caps = Capabilities::parse(&names);
+        assert_eq!(caps.names(), vec!["gapless", "speed", "lyrics_push"]);
+        let agreed = caps.intersect(Capabilities::SERVER);
+        assert!(agreed.contains(Capabilities::SPEED));
+        assert!(!agreed.contains(Capabilities::LYRICS_PUSH));
+        assert_eq!(Capabilities::CROSSFADE.label(), "crossfade");
+    }
+}
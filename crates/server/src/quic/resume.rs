@@ -0,0 +1,151 @@
+//! Resume tokens. `auth_ok` hands each session a token; while the
+//! connection lives, its playback state is saved under it. A client that
+//! reconnects (say after moving from Wi-Fi to LTE) sends `resume` with the
+//! token to get the queue, track and position back without re-opening.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use rand::RngCore;
+
+use crate::stream_limits::StreamLease;
+use crate::transcode::Crossfade;
+
+/// How long a saved session outlives its last update.
+const RESUME_TTL: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Clone, Debug)]
+pub struct ResumeSnapshot {
+    pub user_id: String,
+    pub queue: VecDeque<String>,
+    pub active_track: Option<String>,
+    pub position_ms: u32,
+    pub paused: bool,
+    pub frame_ms: u32,
+    pub mode: Option<&'static str>,
+    pub quality: Option<&'static str>,
+    pub gapless: bool,
+    pub crossfade: Option<Crossfade>,
+    pub surround: bool,
+    pub speed: f32,
+    /// The saved connection's stream lease. The connection resuming the
+    /// session takes it over, so it doesn't count twice against the user's
+    /// limit while the old connection winds down.
+    pub lease: Weak<StreamLease>,
+}
+
+struct Entry {
+    snapshot: Option<ResumeSnapshot>,
+    expires: Instant,
+}
+
+#[derive(Clone, Default)]
+pub struct ResumeStore {
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+impl ResumeStore {
+    /// A new token with nothing saved under it yet.
+    pub fn issue(&self) -> String {
+        let mut bytes = [0u8; 24];
+        rand::rng().fill_bytes(&mut bytes);
+        let token: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let now = Instant::now();
+        let mut entries = self.entries.lock();
+        entries.retain(|_, entry| entry.expires > now);
+        entries.insert(
+            token.clone(),
+            Entry {
+                snapshot: None,
+                expires: now + RESUME_TTL,
+            },
+        );
+        token
+    }
+
+    /// Saves the session's state. Returns false once the token has been
+    /// used by another connection, which means this one was superseded.
+    pub fn save(&self, token: &str, snapshot: ResumeSnapshot) -> bool {
+        let mut entries = self.entries.lock();
+        let Some(entry) = entries.get_mut(token) else {
+            return false;
+        };
+        entry.snapshot = Some(snapshot);
+        entry.expires = Instant::now() + RESUME_TTL;
+        true
+    }
+
+    /// Uses up `token`. Only its own user can resume a session.
+    pub fn take(&self, token: &str, user_id: &str) -> Option<ResumeSnapshot> {
+        let mut entries = self.entries.lock();
+        let entry = entries.get(token)?;
+        let owned = entry
+            .snapshot
+            .as_ref()
+            .is_some_and(|snapshot| snapshot.user_id == user_id);
+        if !owned || entry.expires <= Instant::now() {
+            return None;
+        }
+        entries.remove(token).and_then(|entry| entry.snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream_limits::StreamQuotas;
+
+    fn snapshot(user_id: &str) -> ResumeSnapshot {
+        ResumeSnapshot {
+            user_id: user_id.to_string(),
+            queue: VecDeque::from(vec!["a".to_string(), "b".to_string()]),
+            active_track: Some("b".to_string()),
+            position_ms: 61_000,
+            paused: false,
+            frame_ms: 20,
+            mode: None,
+            quality: None,
+            gapless: false,
+            crossfade: None,
+            surround: false,
+            speed: 1.0,
+            lease: Weak::new(),
+        }
+    }
+
+    #[test]
+    fn tokens_resume_once_for_their_user() {
+        let store = ResumeStore::default();
+        let token = store.issue();
+        assert!(store.take(&token, "alice").is_none());
+        assert!(store.save(&token, snapshot("alice")));
+        assert!(store.take(&token, "bob").is_none());
+        let resumed = store.take(&token, "alice").unwrap();
+        assert_eq!(resumed.active_track.as_deref(), Some("b"));
+        assert!(store.take(&token, "alice").is_none());
+        // The old connection learns it was superseded.
+        assert!(!store.save(&token, snapshot("alice")));
+    }
+
+    #[test]
+    fn resumed_sessions_take_over_the_stream_lease() {
+        let quotas = StreamQuotas::new(1);
+        let lease = Arc::new(quotas.try_acquire("alice").unwrap());
+        let store = ResumeStore::default();
+        let token = store.issue();
+        let mut saved = snapshot("alice");
+        saved.lease = Arc::downgrade(&lease);
+        assert!(store.save(&token, saved));
+
+        // At the limit a new lease is refused, but the saved one carries over.
+        let resumed = store.take(&token, "alice").unwrap();
+        assert!(quotas.try_acquire("alice").is_err());
+        let adopted = resumed.lease.upgrade().unwrap();
+        drop(lease);
+        assert!(quotas.try_acquire("alice").is_err());
+        drop(adopted);
+        assert!(quotas.try_acquire("alice").is_ok());
+    }
+}
@@ -0,0 +1,73 @@
+//! The QUIC UDP socket. On Linux, sends batch datagrams of one connection
+//! with GSO and receives coalesced datagrams with GRO; elsewhere each
+//! datagram is its own syscall.
+
+use std::io::{self, IoSliceMut};
+use std::net::SocketAddr;
+
+use quinn_udp::{RecvMeta, Transmit, UdpSocketState};
+use tokio::io::Interest;
+use tokio::net::UdpSocket;
+
+pub use quinn_udp::BATCH_SIZE;
+
+pub struct QuicSocket {
+    socket: UdpSocket,
+    state: UdpSocketState,
+}
+
+impl QuicSocket {
+    pub async fn bind(addr: SocketAddr) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr).await?;
+        let state = UdpSocketState::new((&socket).into())?;
+        Ok(Self { socket, state })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Datagrams one `send` may carry; 1 without GSO.
+    pub fn max_gso_segments(&self) -> usize {
+        self.state.max_gso_segments()
+    }
+
+    /// Datagrams the kernel may coalesce into one received buffer.
+    pub fn gro_segments(&self) -> usize {
+        self.state.gro_segments()
+    }
+
+    /// Waits for datagrams. Each `meta` entry covers `len` bytes of its
+    /// buffer, split into datagrams of `stride` bytes.
+    pub async fn recv(
+        &self,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> io::Result<usize> {
+        self.socket
+            .async_io(Interest::READABLE, || {
+                self.state.recv((&self.socket).into(), bufs, meta)
+            })
+            .await
+    }
+
+    /// Sends `contents` to `to` as datagrams of `segment_size` bytes (the
+    /// last may be shorter). Like plain UDP, gives up if the socket is full.
+    pub fn send(&self, to: SocketAddr, contents: &[u8], segment_size: usize) {
+        let transmit = Transmit {
+            destination: to,
+            ecn: None,
+            contents,
+            segment_size: (contents.len() > segment_size).then_some(segment_size),
+            src_ip: None,
+        };
+        let result = self.socket.try_io(Interest::WRITABLE, || {
+            self.state.send((&self.socket).into(), &transmit)
+        });
+        if let Err(err) = result {
+            if err.kind() != io::ErrorKind::WouldBlock {
+                tracing::debug!("quic send to {} failed: {}", to, err);
+            }
+        }
+    }
+}
@@ -0,0 +1,310 @@
+//! Band-limited sample rate conversion for the transcoder.
+//!
+//! A polyphase windowed-sinc filter: the rate ratio is reduced to `up/down`
+//! and one Kaiser-windowed sinc phase is precomputed per output position
+//! between two input samples. Ratios with too many phases (odd rates such as
+//! 44056 Hz) interpolate between the nearest two table phases instead.
+
+const MAX_PHASES: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResampleQuality {
+    Fast,
+    Balanced,
+    Best,
+}
+
+impl ResampleQuality {
+    /// (zero crossings per side, passband edge as a fraction of Nyquist,
+    /// Kaiser beta).
+    fn params(self) -> (usize, f64, f64) {
+        match self {
+            ResampleQuality::Fast => (8, 0.90, 6.0),
+            ResampleQuality::Balanced => (16, 0.94, 8.6),
+            ResampleQuality::Best => (32, 0.97, 10.0),
+        }
+    }
+}
+
+pub struct Resampler {
+    channels: usize,
+    up: u64,
+    down: u64,
+    phases: usize,
+    taps: usize,
+    table: Vec<f32>,
+    /// Interleaved input; frame `b` is input frame `b - (taps / 2 - 1)`
+    /// relative to the read position, so the first output needs no lookback.
+    buffer: Vec<f32>,
+    /// Next output position in the buffer, in units of `1 / up` frames.
+    pos: u64,
+    /// Real input frames still in the buffer, so `flush` knows where the
+    /// signal ends.
+    pending_frames: u64,
+}
+
+impl Resampler {
+    pub fn new(input_rate: u32, output_rate: u32, channels: u8, quality: ResampleQuality) -> Self {
+        let divisor = gcd(input_rate as u64, output_rate as u64).max(1);
+        let up = (output_rate as u64 / divisor).max(1);
+        let down = (input_rate as u64 / divisor).max(1);
+        let phases = (up as usize).min(MAX_PHASES);
+
+        let (zero_crossings, rolloff, beta) = quality.params();
+        // Cutoff in cycles per input sample (1.0 = input Nyquist); when
+        // downsampling it drops to the output Nyquist.
+        let cutoff = rolloff * (up as f64 / down as f64).min(1.0);
+        let half = (zero_crossings as f64 / cutoff).ceil() as usize;
+        let taps = half * 2;
+
+        let norm = bessel_i0(beta);
+        let mut table = Vec::with_capacity((phases + 1) * taps);
+        for phase in 0..=phases {
+            let frac = phase as f64 / phases as f64;
+            let mut sum = 0.0f64;
+            let mut row = Vec::with_capacity(taps);
+            for m in 0..taps {
+                let x = frac + half as f64 - 1.0 - m as f64;
+                let ratio = x / half as f64;
+                let window = if ratio.abs() >= 1.0 {
+                    0.0
+                } else {
+                    bessel_i0(beta * (1.0 - ratio * ratio).sqrt()) / norm
+                };
+                let value = cutoff * sinc(cutoff * x) * window;
+                sum += value;
+                row.push(value);
+            }
+            // Normalising every phase keeps DC gain exactly 1 so phases don't
+            // add a ripple at the phase rate.
+            let scale = if sum.abs() > f64::EPSILON { 1.0 / sum } else { 1.0 };
+            table.extend(row.into_iter().map(|value| (value * scale) as f32));
+        }
+
+        Self {
+            channels: channels.max(1) as usize,
+            up,
+            down,
+            phases,
+            taps,
+            table,
+            buffer: vec![0.0; (half - 1) * channels.max(1) as usize],
+            pos: 0,
+            pending_frames: 0,
+        }
+    }
+
+    pub fn process(&mut self, input: &[f32]) -> Vec<f32> {
+        self.buffer.extend_from_slice(input);
+        self.pending_frames += (input.len() / self.channels) as u64;
+        self.drain(None)
+    }
+
+    /// Output frames still owed for input already pushed.
+    pub fn pending_output(&self) -> u64 {
+        let span = (self.pending_frames * self.up).saturating_sub(self.pos);
+        span.div_ceil(self.down)
+    }
+
+    /// Pads with silence so the last input samples come out, producing no
+    /// more output than the input covers.
+    pub fn flush(&mut self) -> Vec<f32> {
+        let limit = self.pending_frames;
+        let padding = (self.taps / 2) * self.channels;
+        self.buffer.extend(std::iter::repeat_n(0.0, padding));
+        let out = self.drain(Some(limit));
+        self.buffer.clear();
+        self.buffer.resize((self.taps / 2 - 1) * self.channels, 0.0);
+        self.pos = 0;
+        self.pending_frames = 0;
+        out
+    }
+
+    fn drain(&mut self, limit: Option<u64>) -> Vec<f32> {
+        let channels = self.channels;
+        let frames = (self.buffer.len() / channels) as u64;
+        let mut out = Vec::new();
+        loop {
+            let index = self.pos / self.up;
+            if index + self.taps as u64 > frames {
+                break;
+            }
+            if limit.is_some_and(|limit| index >= limit) {
+                break;
+            }
+            let phase_pos = (self.pos % self.up) as f64 * self.phases as f64 / self.up as f64;
+            let phase = phase_pos.floor() as usize;
+            let blend = (phase_pos - phase as f64) as f32;
+            let row_a = &self.table[phase * self.taps..(phase + 1) * self.taps];
+            let row_b = &self.table[(phase + 1) * self.taps..(phase + 2) * self.taps];
+            let base = index as usize * channels;
+            for ch in 0..channels {
+                let mut acc = 0.0f32;
+                for m in 0..self.taps {
+                    let coeff = if blend > 0.0 {
+                        row_a[m] + (row_b[m] - row_a[m]) * blend
+                    } else {
+                        row_a[m]
+                    };
+                    acc += self.buffer[base + m * channels + ch] * coeff;
+                }
+                out.push(acc);
+            }
+            self.pos += self.down;
+        }
+
+        let consumed = (self.pos / self.up) as usize;
+        if consumed > 0 {
+            self.buffer.drain(..consumed * channels);
+            self.pos -= consumed as u64 * self.up;
+            self.pending_frames = self.pending_frames.saturating_sub(consumed as u64);
+        }
+        out
+    }
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        let rem = a % b;
+        a = b;
+        b = rem;
+    }
+    a
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-12 {
+        1.0
+    } else {
+        let px = std::f64::consts::PI * x;
+        px.sin() / px
+    }
+}
+
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let half = x / 2.0;
+    for k in 1..64 {
+        term *= (half / k as f64) * (half / k as f64);
+        sum += term;
+        if term < sum * 1e-12 {
+            break;
+        }
+    }
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ResampleQuality, Resampler};
+
+    const AMPLITUDE: f64 = 0.5;
+
+    fn tone(rate: u32, freq: f64, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .map(|n| {
+                let t = n as f64 / rate as f64;
+                (AMPLITUDE * (2.0 * std::f64::consts::PI * freq * t).sin()) as f32
+            })
+            .collect()
+    }
+
+    fn resample(input: &[f32], from: u32, to: u32, quality: ResampleQuality) -> Vec<f32> {
+        let mut resampler = Resampler::new(from, to, 1, quality);
+        let mut out = Vec::new();
+        for chunk in input.chunks(1000) {
+            out.extend(resampler.process(chunk));
+        }
+        out.extend(resampler.flush());
+        out
+    }
+
+    /// Least-squares fit of a sine at `freq`, skipping the edges. Returns the
+    /// fitted amplitude and the RMS of everything else.
+    fn fit(samples: &[f32], rate: u32, freq: f64) -> (f64, f64) {
+        let skip = samples.len() / 10;
+        let body = &samples[skip..samples.len() - skip];
+        let (mut ss, mut cc, mut sc, mut ys, mut yc) = (0.0, 0.0, 0.0, 0.0, 0.0);
+        for (i, sample) in body.iter().enumerate() {
+            let t = (i + skip) as f64 / rate as f64;
+            let w = 2.0 * std::f64::consts::PI * freq * t;
+            let (s, c) = (w.sin(), w.cos());
+            let y = *sample as f64;
+            ss += s * s;
+            cc += c * c;
+            sc += s * c;
+            ys += y * s;
+            yc += y * c;
+        }
+        let det = ss * cc - sc * sc;
+        let a = (ys * cc - yc * sc) / det;
+        let b = (yc * ss - ys * sc) / det;
+        let mut residual = 0.0;
+        for (i, sample) in body.iter().enumerate() {
+            let t = (i + skip) as f64 / rate as f64;
+            let w = 2.0 * std::f64::consts::PI * freq * t;
+            let err = *sample as f64 - (a * w.sin() + b * w.cos());
+            residual += err * err;
+        }
+        ((a * a + b * b).sqrt(), (residual / body.len() as f64).sqrt())
+    }
+
+    fn db(ratio: f64) -> f64 {
+        20.0 * ratio.log10()
+    }
+
+    #[test]
+    fn passband_ripple_is_small() {
+        for freq in [100.0, 1_000.0, 10_000.0, 18_000.0] {
+            let input = tone(44_100, freq, 44_100);
+            let output = resample(&input, 44_100, 48_000, ResampleQuality::Best);
+            let (amplitude, _) = fit(&output, 48_000, freq);
+            let ripple = db(amplitude / AMPLITUDE).abs();
+            assert!(ripple < 0.05, "{} Hz ripple {:.3} dB", freq, ripple);
+        }
+    }
+
+    #[test]
+    fn upsampling_bright_tones_stays_clean() {
+        // Linear interpolation leaves images around 40 dB down here.
+        let input = tone(44_100, 17_000.0, 44_100);
+        let output = resample(&input, 44_100, 48_000, ResampleQuality::Best);
+        let (amplitude, residual) = fit(&output, 48_000, 17_000.0);
+        assert!(db(residual / amplitude) < -80.0, "{:.1} dB", db(residual / amplitude));
+    }
+
+    #[test]
+    fn rejects_tones_above_output_nyquist() {
+        for (rate, freq) in [(96_000, 30_000.0), (88_200, 30_000.0), (176_400, 60_000.0)] {
+            let input = tone(rate, freq, rate as usize);
+            for quality in [ResampleQuality::Balanced, ResampleQuality::Best] {
+                let output = resample(&input, rate, 48_000, quality);
+                // Skip the edges, where the tone switching on is broadband.
+                let body = &output[output.len() / 10..output.len() * 9 / 10];
+                let rms = (body.iter().map(|s| (*s as f64).powi(2)).sum::<f64>()
+                    / body.len() as f64)
+                    .sqrt();
+                let level = db(rms / (AMPLITUDE / 2f64.sqrt()));
+                assert!(level < -70.0, "{} Hz from {} Hz at {:?}: {:.1} dB", freq, rate, quality, level);
+            }
+        }
+    }
+
+    #[test]
+    fn odd_ratios_keep_length_and_level() {
+        for rate in [22_050, 88_200, 176_400, 44_056] {
+            let input = tone(rate, 1_000.0, rate as usize);
+            let output = resample(&input, rate, 48_000, ResampleQuality::Balanced);
+            let expected = 48_000i64;
+            assert!(
+                (output.len() as i64 - expected).abs() <= 1,
+                "{} Hz gave {} frames",
+                rate,
+                output.len()
+            );
+            let (amplitude, _) = fit(&output, 48_000, 1_000.0);
+            assert!(db(amplitude / AMPLITUDE).abs() < 0.1, "{} Hz level", rate);
+        }
+    }
+}
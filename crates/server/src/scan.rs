@@ -1,6 +1,6 @@
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 
 use bytes::Bytes;
 use reqwest::Client;
@@ -13,18 +13,27 @@ use crate::assets::{
 use crate::config::{resolve_path, ServerConfig};
 use crate::external::{self, ExternalConfig, ExternalSource, Provider};
 use crate::activity_store::ActivityStore;
-use crate::state::{AppState, LibraryStatus};
+use crate::events::{EventBus, EventTopic};
+use crate::state::{AppState, LibraryState, LibraryStatus, LibraryStatusResponse};
 use crate::watch::configure_watcher;
+use crate::waveform;
 use common::{Album, Artist};
-use library::{Library, LibraryStats};
+use library::{GenreMap, GenreMapFile, Library, LibraryStats, ScanProgress};
+use parking_lot::Mutex;
+
+const SCAN_PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
 
 pub fn start_index(state: AppState, root: PathBuf, force_rescan: bool) {
     {
         let mut guard = state.library_state.write();
         guard.library = None;
-        guard.status = LibraryStatus::Scanning {
-            started: SystemTime::now(),
-        };
+        set_status(
+            &state,
+            &mut guard,
+            LibraryStatus::Scanning {
+                started: SystemTime::now(),
+            },
+        );
     }
     *state.watcher.write() = None;
 
@@ -40,11 +49,14 @@ pub fn start_index(state: AppState, root: PathBuf, force_rescan: bool) {
 
         let db = Arc::clone(&state.db);
         let root_clone = root.clone();
+        let genre_map = load_genre_map(&state);
+        let progress = scan_progress_publisher(state.events.clone());
         let result = tokio::task::spawn_blocking(move || {
-            let (library, mut scanned) = Library::load_or_scan_with_db(root_clone, db)?;
+            let (library, mut scanned) =
+                Library::load_or_scan_with_db(root_clone, db, genre_map, &progress)?;
             let stats = if force_rescan {
                 scanned = true;
-                library.rescan()?
+                library.rescan_with_progress(&progress)?
             } else {
                 library.stats()?
             };
@@ -57,7 +69,7 @@ pub fn start_index(state: AppState, root: PathBuf, force_rescan: bool) {
                 {
                     let mut guard = state.library_state.write();
                     guard.library = Some(library.clone());
-                    guard.status = LibraryStatus::Ready(stats.clone());
+                    set_status(&state, &mut guard, LibraryStatus::Ready(stats.clone()));
                 }
                 info!(
                     "Library ready: {} artists, {} albums, {} tracks",
@@ -76,6 +88,7 @@ pub fn start_index(state: AppState, root: PathBuf, force_rescan: bool) {
                 } else {
                     info!("External metadata sweep skipped (no new scan)");
                 }
+                start_waveform_sweep(state.clone(), library.clone());
                 start_cover_sweep(state.clone(), library);
             }
             Ok(Err(err)) => {
@@ -83,7 +96,7 @@ pub fn start_index(state: AppState, root: PathBuf, force_rescan: bool) {
                 {
                     let mut guard = state.library_state.write();
                     guard.library = None;
-                    guard.status = LibraryStatus::Error(message.clone());
+                    set_status(&state, &mut guard, LibraryStatus::Error(message.clone()));
                 }
                 warn!("Library scan failed: {}", message);
                 let _ = state.activity.add_event(
@@ -96,7 +109,7 @@ pub fn start_index(state: AppState, root: PathBuf, force_rescan: bool) {
                 {
                     let mut guard = state.library_state.write();
                     guard.library = None;
-                    guard.status = LibraryStatus::Error(message.clone());
+                    set_status(&state, &mut guard, LibraryStatus::Error(message.clone()));
                 }
                 warn!("Library scan join error: {}", message);
                 let _ = state.activity.add_event(
@@ -108,12 +121,57 @@ pub fn start_index(state: AppState, root: PathBuf, force_rescan: bool) {
     });
 }
 
+/// Publishes full-scan progress, at most once a second.
+fn scan_progress_publisher(events: EventBus) -> impl Fn(ScanProgress) {
+    let last = Mutex::new(None::<Instant>);
+    move |progress| {
+        let mut last = last.lock();
+        if last.is_some_and(|at| at.elapsed() < SCAN_PROGRESS_INTERVAL) {
+            return;
+        }
+        *last = Some(Instant::now());
+        events.publish(EventTopic::Library, "scan_progress", progress);
+    }
+}
+
+/// A missing mapping file is normal; only the built-in aliases apply then.
+fn load_genre_map(state: &AppState) -> GenreMap {
+    let path = {
+        let config = state.config.read();
+        resolve_path(&state.config_path, &config.genre_map_path)
+    };
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return GenreMap::builtin(),
+        Err(err) => {
+            warn!("Failed to read genre map {:?}: {}", path, err);
+            return GenreMap::builtin();
+        }
+    };
+    match serde_yaml::from_str::<GenreMapFile>(&contents) {
+        Ok(file) => {
+            info!("Loaded genre map from {:?}", path);
+            GenreMap::from_file(&file)
+        }
+        Err(err) => {
+            warn!("Invalid genre map {:?}: {}", path, err);
+            GenreMap::builtin()
+        }
+    }
+}
+
+/// Reloads the genre map first, so edits to it apply without a restart.
 pub fn start_rescan(state: AppState, library: Library, replace_complete: bool) {
+    let library = library.with_genre_map(load_genre_map(&state));
     {
         let mut guard = state.library_state.write();
-        guard.status = LibraryStatus::Scanning {
-            started: SystemTime::now(),
-        };
+        set_status(
+            &state,
+            &mut guard,
+            LibraryStatus::Scanning {
+                started: SystemTime::now(),
+            },
+        );
     }
     let library_clone = library.clone();
     tokio::spawn(async move {
@@ -125,11 +183,16 @@ pub fn start_rescan(state: AppState, library: Library, replace_complete: bool) {
                 warn!("Failed to clear metadata: {}", e);
             }
         }
-        let result = tokio::task::spawn_blocking(move || library.rescan()).await;
+        let progress = scan_progress_publisher(state.events.clone());
+        let result =
+            tokio::task::spawn_blocking(move || library.rescan_with_progress(&progress)).await;
         match result {
             Ok(Ok(stats)) => {
-                let mut guard = state.library_state.write();
-                guard.status = LibraryStatus::Ready(stats.clone());
+                {
+                    let mut guard = state.library_state.write();
+                    guard.library = Some(library_clone.clone());
+                    set_status(&state, &mut guard, LibraryStatus::Ready(stats.clone()));
+                }
                 info!(
                     "Library rescan complete: {} artists, {} albums, {} tracks",
                     stats.artists, stats.albums, stats.tracks
@@ -141,13 +204,15 @@ pub fn start_rescan(state: AppState, library: Library, replace_complete: bool) {
                         stats.artists, stats.albums, stats.tracks
                     ),
                 );
+                configure_watcher(&state, &library_clone, library_clone.root().to_path_buf());
                 start_enrichment_sweep(state.clone(), library_clone.clone(), replace_complete);
+                start_waveform_sweep(state.clone(), library_clone.clone());
                 start_cover_sweep(state.clone(), library_clone);
             }
             Ok(Err(err)) => {
                 let message = err.to_string();
                 let mut guard = state.library_state.write();
-                guard.status = LibraryStatus::Error(message.clone());
+                set_status(&state, &mut guard, LibraryStatus::Error(message.clone()));
                 warn!("Library rescan failed: {}", message);
                 let _ = state.activity.add_event(
                     "index",
@@ -157,7 +222,7 @@ pub fn start_rescan(state: AppState, library: Library, replace_complete: bool) {
             Err(err) => {
                 let message = err.to_string();
                 let mut guard = state.library_state.write();
-                guard.status = LibraryStatus::Error(message.clone());
+                set_status(&state, &mut guard, LibraryStatus::Error(message.clone()));
                 warn!("Library rescan join error: {}", message);
                 let _ = state.activity.add_event(
                     "index",
@@ -171,7 +236,17 @@ pub fn start_rescan(state: AppState, library: Library, replace_complete: bool) {
 pub fn set_library_missing(state: &AppState, path: PathBuf) {
     let mut guard = state.library_state.write();
     guard.library = None;
-    guard.status = LibraryStatus::Missing(path);
+    set_status(state, &mut guard, LibraryStatus::Missing(path));
+}
+
+/// Updates the library status and tells event subscribers.
+fn set_status(state: &AppState, guard: &mut LibraryState, status: LibraryStatus) {
+    state.events.publish(
+        EventTopic::Library,
+        "status",
+        LibraryStatusResponse::from_status(&status),
+    );
+    guard.status = status;
 }
 
 pub fn apply_music_root_update(state: AppState, new_root: &str, force: bool) -> String {
@@ -184,6 +259,15 @@ pub fn apply_music_root_update(state: AppState, new_root: &str, force: bool) ->
     "Scanning started.".to_string()
 }
 
+/// Only runs when `waveform_sweep_enabled` is set; otherwise peaks are
+/// computed the first time a client asks for them.
+pub fn start_waveform_sweep(state: AppState, library: Library) {
+    if !state.config.read().waveform_sweep_enabled {
+        return;
+    }
+    tokio::task::spawn_blocking(move || waveform::sweep(&state, &library));
+}
+
 pub fn start_cover_sweep(state: AppState, library: Library) {
     tokio::spawn(async move {
         run_cover_sweep(state, library).await;
@@ -249,10 +333,11 @@ pub fn start_enrichment_sweep(state: AppState, library: Library, replace_complet
     let tag_error_first = config.external_metadata_on_tag_error;
     let metadata_root = resolve_path(&state.config_path, &config.metadata_path);
     let activity = state.activity.clone();
+    let events = state.events.clone();
     let _ = activity.add_event("scan", "Metadata scan started.");
     tokio::spawn(async move {
         run_enrichment_sweep(
-            library,
+            library.clone(),
             client,
             fetch_config,
             min_interval,
@@ -261,8 +346,14 @@ pub fn start_enrichment_sweep(state: AppState, library: Library, replace_complet
             metadata_root,
             replace_complete,
             activity,
+            events,
         )
         .await;
+        // Enrichment may have added genres; refresh counts and membership.
+        let result = tokio::task::spawn_blocking(move || library.rebuild_genre_index()).await;
+        if let Ok(Err(err)) = result {
+            warn!("Genre index rebuild failed: {}", err);
+        }
     });
 }
 
@@ -276,6 +367,7 @@ async fn run_enrichment_sweep(
     metadata_root: PathBuf,
     replace_complete: bool,
     activity: ActivityStore,
+    events: EventBus,
 ) {
     let mut remaining = max_items;
     let mut artist_updates = 0usize;
@@ -293,6 +385,7 @@ async fn run_enrichment_sweep(
             remaining,
             replace_complete,
             &activity,
+            &events,
             &mut artist_updates,
             &mut album_updates,
         )
@@ -324,6 +417,7 @@ async fn run_enrichment_sweep(
                 &artist,
                 replace_complete,
                 Some(&activity),
+                &events,
             )
             .await;
             if result.attempted {
@@ -369,6 +463,7 @@ async fn run_enrichment_sweep(
                 &artist_name,
                 replace_complete,
                 Some(&activity),
+                &events,
             )
             .await;
             if result.attempted {
@@ -389,6 +484,11 @@ async fn run_enrichment_sweep(
         artist_updates, album_updates
     );
     let _ = activity.add_event("scan", summary);
+    events.publish(
+        EventTopic::Enrichment,
+        "sweep_finished",
+        serde_json::json!({ "artists": artist_updates, "albums": album_updates }),
+    );
 }
 
 async fn run_tag_error_enrichment(
@@ -400,6 +500,7 @@ async fn run_tag_error_enrichment(
     mut remaining: usize,
     replace_complete: bool,
     activity: &ActivityStore,
+    events: &EventBus,
     artist_updates: &mut usize,
     album_updates: &mut usize,
 ) -> usize {
@@ -427,6 +528,7 @@ async fn run_tag_error_enrichment(
                     &artist,
                     replace_complete,
                     Some(activity),
+                    events,
                 )
                 .await;
                 if result.attempted {
@@ -458,6 +560,7 @@ async fn run_tag_error_enrichment(
                     &artist_name,
                     replace_complete,
                     Some(activity),
+                    events,
                 )
                 .await;
                 if result.attempted {
@@ -507,6 +610,7 @@ pub async fn fetch_artist_enrichment(
     artist: &Artist,
     replace: bool,
     activity: Option<&ActivityStore>,
+    events: &EventBus,
 ) -> FetchResult {
     if !replace && !needs_artist_enrichment(artist) {
         return FetchResult::skipped();
@@ -524,7 +628,13 @@ pub async fn fetch_artist_enrichment(
     }
     let _ = library.record_external_attempt(&key, false);
     info!("Fetching external artist metadata for '{}'", artist.name);
-    match external::fetch_artist(client, config, &artist.name).await {
+    match external::fetch_artist(
+        client,
+        config,
+        &artist.name,
+        artist.musicbrainz_id.as_deref(),
+    )
+    .await {
         Ok(Some(metadata)) => {
             let (logo_ref, banner_ref) =
                 store_artist_assets(metadata_root, client, config, &artist.id, &metadata).await;
@@ -536,6 +646,11 @@ pub async fn fetch_artist_enrichment(
                 banner_ref,
                 replace,
             );
+            events.publish(
+                EventTopic::Enrichment,
+                "artist_updated",
+                serde_json::json!({ "id": artist.id, "name": artist.name }),
+            );
             if let Some(activity) = activity {
                 let _ = activity.add_event(
                     "metadata",
@@ -565,6 +680,7 @@ pub async fn fetch_album_enrichment(
     artist_name: &str,
     replace: bool,
     activity: Option<&ActivityStore>,
+    events: &EventBus,
 ) -> FetchResult {
     if !replace && !needs_album_enrichment(album) {
         return FetchResult::skipped();
@@ -585,9 +701,33 @@ pub async fn fetch_album_enrichment(
         "Fetching external album metadata for '{}' - '{}'",
         artist_name, album.title
     );
-    match external::fetch_album(client, config, artist_name, &album.title).await {
+    match external::fetch_album(
+        client,
+        config,
+        artist_name,
+        &album.title,
+        external::AlbumIds {
+            release_id: album.musicbrainz_release_id.as_deref(),
+            release_group_id: album.musicbrainz_release_group_id.as_deref(),
+        },
+    )
+    .await {
         Ok(Some(metadata)) => {
-            let _ = library.update_album_enrichment(&album.id, metadata.summary, &metadata.genres);
+            let _ = library.update_album_enrichment(
+                &album.id,
+                metadata.summary,
+                &metadata.genres,
+                metadata.album_type,
+            );
+            events.publish(
+                EventTopic::Enrichment,
+                "album_updated",
+                serde_json::json!({
+                    "id": album.id,
+                    "title": album.title,
+                    "artist": artist_name,
+                }),
+            );
             if let Some(activity) = activity {
                 let _ = activity.add_event(
                     "metadata",
@@ -629,6 +769,7 @@ pub fn schedule_artist_enrichment(state: &AppState, library: &Library, artist: &
     let client = state.external_client.clone();
     let metadata_root = metadata_root_path(state);
     let activity = state.activity.clone();
+    let events = state.events.clone();
     tokio::spawn(async move {
         let _ = fetch_artist_enrichment(
             &library,
@@ -639,6 +780,7 @@ pub fn schedule_artist_enrichment(state: &AppState, library: &Library, artist: &
             &artist,
             false,
             Some(&activity),
+            &events,
         )
         .await;
     });
@@ -663,6 +805,7 @@ pub fn schedule_album_enrichment(
     let library = library.clone();
     let client = state.external_client.clone();
     let activity = state.activity.clone();
+    let events = state.events.clone();
     tokio::spawn(async move {
         let _ = fetch_album_enrichment(
             &library,
@@ -673,6 +816,7 @@ pub fn schedule_album_enrichment(
             &artist_name,
             false,
             Some(&activity),
+            &events,
         )
         .await;
     });
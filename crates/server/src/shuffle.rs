@@ -1,7 +1,7 @@
 use std::collections::HashSet;
 
 use common::Track;
-use library::{Library, LibraryError};
+use library::{genre_id, Library, LibraryError};
 use rand::seq::SliceRandom;
 
 #[derive(Clone, Copy, Debug)]
@@ -68,14 +68,10 @@ pub fn build_shuffle_queue(
             let (tracks, _) = library.list_tracks(None, usize::MAX, 0)?;
             let artist_filter: HashSet<&str> =
                 custom_artist_ids.iter().map(|id| id.as_str()).collect();
-            let genre_filter: HashSet<String> = custom_genres
-                .iter()
-                .map(|genre| genre.trim().to_ascii_lowercase())
-                .filter(|genre| !genre.is_empty())
-                .collect();
+            let genre_track_ids = genre_track_ids(library, custom_genres)?;
 
             let filter_artists = !artist_filter.is_empty();
-            let filter_genres = !genre_filter.is_empty();
+            let filter_genres = !custom_genres.iter().all(|genre| genre.trim().is_empty());
 
             if !filter_artists && !filter_genres {
                 tracks
@@ -85,10 +81,8 @@ pub fn build_shuffle_queue(
                     .filter(|track| {
                         let matches_artist = filter_artists
                             && artist_filter.contains(track.artist_id.as_str());
-                        let matches_genre = filter_genres
-                            && track.genres.iter().any(|genre| {
-                                genre_filter.contains(&genre.trim().to_ascii_lowercase())
-                            });
+                        let matches_genre =
+                            filter_genres && genre_track_ids.contains(track.id.as_str());
 
                         match (filter_artists, filter_genres) {
                             (true, true) => matches_artist || matches_genre,
@@ -106,3 +100,25 @@ pub fn build_shuffle_queue(
     tracks.shuffle(&mut rng);
     Ok(tracks)
 }
+
+/// Genre filters accept ids from `/browse/genres` or plain names; either way
+/// the genre index supplies the tracks, so sub-genres and album genres count.
+fn genre_track_ids(
+    library: &Library,
+    genres: &[String],
+) -> Result<HashSet<String>, ShuffleError> {
+    let mut ids = HashSet::new();
+    for genre in genres {
+        let genre = genre.trim();
+        if genre.is_empty() {
+            continue;
+        }
+        let genre_id = match library.get_genre(genre)? {
+            Some(info) => info.id,
+            None => genre_id(&library.canonical_genre(genre)),
+        };
+        let (tracks, _) = library.list_genre_tracks(&genre_id, usize::MAX, 0)?;
+        ids.extend(tracks.into_iter().map(|track| track.id));
+    }
+    Ok(ids)
+}
@@ -13,9 +13,13 @@ use serde::{Deserialize, Serialize};
 use crate::auth::{AuthStore, AuthUser};
 use crate::activity_store::ActivityStore;
 use crate::config::ServerConfig;
+use crate::events::EventBus;
 use crate::stats_store::StatsStore;
 use crate::user_data::UserDataStore;
+use crate::quic::ResumeStore;
+use crate::stream_limits::{StreamQuotas, TranscodeScheduler};
 use crate::stream_sessions::StreamSessions;
+use crate::transcode_cache::TranscodeCache;
 use library::{Library, LibraryStats};
 
 #[derive(Clone)]
@@ -28,9 +32,14 @@ pub struct AppState {
     pub user_data: UserDataStore,
     pub stats: StatsStore,
     pub activity: ActivityStore,
+    pub events: EventBus,
     pub watcher: Arc<RwLock<Option<RecommendedWatcher>>>,
     pub external_client: Client,
     pub stream_sessions: StreamSessions,
+    pub transcode_cache: TranscodeCache,
+    pub transcode_scheduler: TranscodeScheduler,
+    pub stream_quotas: StreamQuotas,
+    pub resume_sessions: ResumeStore,
 }
 
 #[derive(Clone)]
@@ -80,6 +89,55 @@ pub struct LibraryStatusResponse {
     pub tracks: Option<usize>,
 }
 
+impl LibraryStatusResponse {
+    pub fn from_status(status: &LibraryStatus) -> Self {
+        let (status, message, artists, albums, tracks) = match status {
+            LibraryStatus::Unconfigured => (
+                "unconfigured".to_string(),
+                Some("music directory must be set".to_string()),
+                None,
+                None,
+                None,
+            ),
+            LibraryStatus::Missing(path) => (
+                "missing".to_string(),
+                Some(format!("music directory not found: {}", path.display())),
+                None,
+                None,
+                None,
+            ),
+            LibraryStatus::Scanning { started } => {
+                let since = started
+                    .elapsed()
+                    .map(|elapsed: std::time::Duration| format!("library scan in progress ({}s)", elapsed.as_secs()))
+                    .unwrap_or_else(|_| "library scan in progress".to_string());
+                ("scanning".to_string(), Some(since), None, None, None)
+            }
+            LibraryStatus::Ready(stats) => (
+                "ready".to_string(),
+                None,
+                Some(stats.artists),
+                Some(stats.albums),
+                Some(stats.tracks),
+            ),
+            LibraryStatus::Error(message) => (
+                "error".to_string(),
+                Some(message.clone()),
+                None,
+                None,
+                None,
+            ),
+        };
+        Self {
+            status,
+            message,
+            artists,
+            albums,
+            tracks,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct ArtistQuery {
     pub search: Option<String>,
@@ -87,6 +145,43 @@ pub struct ArtistQuery {
     pub offset: Option<usize>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct ComposerQuery {
+    pub search: Option<String>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GenreQuery {
+    pub search: Option<String>,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WaveformQuery {
+    pub points: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EventStreamQuery {
+    pub topics: Option<String>,
+    pub since: Option<u64>,
+    pub epoch: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AlbumTracksQuery {
+    pub group: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ArtistAlbumsQuery {
+    pub group: Option<String>,
+    pub sort: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct ShuffleQuery {
     pub mode: String,
@@ -227,6 +322,11 @@ pub struct ArtistCoverQuery {
     pub kind: Option<String>,
 }
 
+#[derive(Deserialize)]
+pub struct SidecarWriteQuery {
+    pub rescan: Option<bool>,
+}
+
 #[derive(Deserialize)]
 pub struct MetadataSourceForm {
     pub provider: String,
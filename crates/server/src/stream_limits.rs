@@ -0,0 +1,307 @@
+//! Server-wide limits on streaming work: a bounded pool of transcode
+//! workers shared by every session, and a cap on how many sessions one user
+//! can stream from at once.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::{Condvar, Mutex};
+
+/// How often queued jobs re-check whether their stream is still wanted.
+const CANCEL_POLL: Duration = Duration::from_millis(200);
+
+/// Whether a job feeds the track being played. Shared with the stream so a
+/// prefetch that gets promoted jumps the queue.
+#[derive(Clone, Debug)]
+pub struct JobPriority(Arc<AtomicBool>);
+
+impl JobPriority {
+    pub fn new(active: bool) -> Self {
+        Self(Arc::new(AtomicBool::new(active)))
+    }
+
+    pub fn promote(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    fn is_active(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Set while a session is paused; its transcodes hand back their worker
+/// slot and wait for playback to resume.
+#[derive(Clone, Debug, Default)]
+pub struct PauseSignal(Arc<AtomicBool>);
+
+impl PauseSignal {
+    pub fn set(&self, paused: bool) {
+        self.0.store(paused, Ordering::Relaxed);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Clone)]
+pub struct TranscodeScheduler {
+    inner: Arc<SchedulerInner>,
+}
+
+struct SchedulerInner {
+    state: Mutex<SchedulerState>,
+    changed: Condvar,
+}
+
+struct SchedulerState {
+    limit: usize,
+    running: usize,
+    next_ticket: u64,
+    waiting: Vec<(u64, JobPriority)>,
+}
+
+impl SchedulerState {
+    /// Active jobs first, then first come first served.
+    fn next_in_line(&self) -> Option<u64> {
+        self.waiting
+            .iter()
+            .min_by_key(|(ticket, priority)| (!priority.is_active(), *ticket))
+            .map(|(ticket, _)| *ticket)
+    }
+
+    fn leave(&mut self, ticket: u64) {
+        self.waiting.retain(|(waiting, _)| *waiting != ticket);
+    }
+}
+
+impl TranscodeScheduler {
+    /// `limit` of 0 uses one worker per available core.
+    pub fn new(limit: usize) -> Self {
+        let limit = if limit == 0 {
+            std::thread::available_parallelism()
+                .map(|cores| cores.get())
+                .unwrap_or(2)
+        } else {
+            limit
+        };
+        Self {
+            inner: Arc::new(SchedulerInner {
+                state: Mutex::new(SchedulerState {
+                    limit,
+                    running: 0,
+                    next_ticket: 0,
+                    waiting: Vec::new(),
+                }),
+                changed: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Blocks until a worker slot is free and this job is next in line.
+    /// Returns `None` if `cancelled` reports the stream went away first.
+    pub fn acquire(
+        &self,
+        priority: &JobPriority,
+        cancelled: impl Fn() -> bool,
+    ) -> Option<TranscodePermit> {
+        let mut state = self.inner.state.lock();
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        state.waiting.push((ticket, priority.clone()));
+        loop {
+            if cancelled() {
+                state.leave(ticket);
+                // Someone behind us may be runnable now.
+                self.inner.changed.notify_all();
+                return None;
+            }
+            if state.running < state.limit && state.next_in_line() == Some(ticket) {
+                state.leave(ticket);
+                state.running += 1;
+                return Some(TranscodePermit {
+                    inner: Arc::clone(&self.inner),
+                });
+            }
+            self.inner.changed.wait_for(&mut state, CANCEL_POLL);
+        }
+    }
+
+    /// Parks a running job while `pause` is set, releasing `permit` so
+    /// other sessions can use the slot, then queues for a slot again.
+    /// Leaves `permit` empty if `cancelled` fires first.
+    pub fn yield_while_paused(
+        &self,
+        permit: &mut Option<TranscodePermit>,
+        pause: &PauseSignal,
+        priority: &JobPriority,
+        cancelled: impl Fn() -> bool,
+    ) {
+        if !pause.is_paused() {
+            return;
+        }
+        permit.take();
+        while pause.is_paused() && !cancelled() {
+            std::thread::sleep(CANCEL_POLL);
+        }
+        if !cancelled() {
+            *permit = self.acquire(priority, &cancelled);
+        }
+    }
+
+    /// (running, waiting) jobs.
+    pub fn load(&self) -> (usize, usize) {
+        let state = self.inner.state.lock();
+        (state.running, state.waiting.len())
+    }
+}
+
+/// A held worker slot, released on drop.
+pub struct TranscodePermit {
+    inner: Arc<SchedulerInner>,
+}
+
+impl Drop for TranscodePermit {
+    fn drop(&mut self) {
+        self.inner.state.lock().running -= 1;
+        self.inner.changed.notify_all();
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct QuotaExceeded {
+    pub limit: u32,
+}
+
+impl std::fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "stream limit reached: {} concurrent stream(s) per user; stop playback on another device first",
+            self.limit
+        )
+    }
+}
+
+/// Counts streaming sessions per user.
+#[derive(Clone)]
+pub struct StreamQuotas {
+    limit: u32,
+    active: Arc<Mutex<HashMap<String, u32>>>,
+}
+
+impl StreamQuotas {
+    /// `limit` of 0 means unlimited.
+    pub fn new(limit: u32) -> Self {
+        Self {
+            limit,
+            active: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn try_acquire(&self, user_id: &str) -> Result<StreamLease, QuotaExceeded> {
+        let mut active = self.active.lock();
+        let count = active.entry(user_id.to_string()).or_insert(0);
+        if self.limit > 0 && *count >= self.limit {
+            return Err(QuotaExceeded { limit: self.limit });
+        }
+        *count += 1;
+        Ok(StreamLease {
+            user_id: user_id.to_string(),
+            active: Arc::clone(&self.active),
+        })
+    }
+}
+
+/// One session's claim on its user's quota, returned on drop.
+pub struct StreamLease {
+    user_id: String,
+    active: Arc<Mutex<HashMap<String, u32>>>,
+}
+
+impl Drop for StreamLease {
+    fn drop(&mut self) {
+        let mut active = self.active.lock();
+        if let Some(count) = active.get_mut(&self.user_id) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                active.remove(&self.user_id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn active_jobs_jump_the_queue() {
+        let scheduler = TranscodeScheduler::new(1);
+        let held = scheduler.acquire(&JobPriority::new(false), || false).unwrap();
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut workers = Vec::new();
+        for (label, active) in [("prefetch", false), ("active", true)] {
+            let worker = scheduler.clone();
+            let tx = tx.clone();
+            workers.push(std::thread::spawn(move || {
+                let _permit = worker.acquire(&JobPriority::new(active), || false).unwrap();
+                tx.send(label).unwrap();
+            }));
+            while scheduler.load().1 < workers.len() {
+                std::thread::yield_now();
+            }
+        }
+        drop(held);
+        assert_eq!(rx.recv().unwrap(), "active");
+        assert_eq!(rx.recv().unwrap(), "prefetch");
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        assert_eq!(scheduler.load(), (0, 0));
+    }
+
+    #[test]
+    fn cancelled_jobs_leave_the_queue() {
+        let scheduler = TranscodeScheduler::new(1);
+        let _held = scheduler.acquire(&JobPriority::new(true), || false).unwrap();
+        assert!(scheduler.acquire(&JobPriority::new(true), || true).is_none());
+        assert_eq!(scheduler.load(), (1, 0));
+    }
+
+    #[test]
+    fn paused_jobs_give_up_their_slot() {
+        let scheduler = TranscodeScheduler::new(1);
+        let priority = JobPriority::new(true);
+        let pause = PauseSignal::default();
+        let mut permit = scheduler.acquire(&priority, || false);
+
+        pause.set(true);
+        let worker = scheduler.clone();
+        let resumer = pause.clone();
+        let other = std::thread::spawn(move || {
+            let _permit = worker.acquire(&JobPriority::new(false), || false).unwrap();
+            resumer.set(false);
+        });
+        scheduler.yield_while_paused(&mut permit, &pause, &priority, || false);
+        other.join().unwrap();
+        assert!(permit.is_some());
+        assert_eq!(scheduler.load(), (1, 0));
+    }
+
+    #[test]
+    fn quotas_are_per_user_and_released_on_drop() {
+        let quotas = StreamQuotas::new(1);
+        let lease = quotas.try_acquire("alice").unwrap();
+        assert_eq!(
+            quotas.try_acquire("alice").err(),
+            Some(QuotaExceeded { limit: 1 })
+        );
+        let _other = quotas.try_acquire("bob").unwrap();
+        drop(lease);
+        assert!(quotas.try_acquire("alice").is_ok());
+    }
+}
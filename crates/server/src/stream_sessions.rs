@@ -15,53 +15,192 @@ const DOWN_SHIFT_MS: u64 = 2000;
 const UP_SHIFT_MS: u64 = 8000;
 const UP_STABLE_SECS: u64 = 8;
 const CHANGE_COOLDOWN_SECS: u64 = 4;
+/// Estimated capacity must exceed a rung by this factor to pick it.
+const CAPACITY_HEADROOM: f64 = 1.5;
+/// Loss over one report interval that forces a step down.
+const LOSS_DOWN: f64 = 0.05;
+/// Loss below which the path counts as clean for stepping up.
+const LOSS_CLEAN: f64 = 0.01;
+/// RTT this many times the path minimum means a queue is building.
+const RTT_INFLATION: f64 = 3.0;
+const RTT_INFLATION_FLOOR: Duration = Duration::from_millis(150);
+/// Smoothing for the capacity estimate (weight of the newest sample).
+const CAPACITY_ALPHA: f64 = 0.3;
+/// Smoothing for the connection loss estimate fed to the encoder.
+const LOSS_ALPHA: f64 = 0.25;
+
+pub const DEFAULT_LADDER_KBPS: [u32; 5] = [48, 64, 96, 128, 160];
 
 #[derive(Clone)]
 pub struct StreamSessions {
     inner: Arc<RwLock<HashMap<Uuid, StreamSession>>>,
+    ladder: Arc<BitrateLadder>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct StreamSessionHandle {
     pub id: Uuid,
     pub target_bitrate_bps: Arc<AtomicU32>,
 }
 
+/// Bitrates the controller moves between, lowest first.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BitrateLadder {
+    rungs: Vec<u32>,
+}
+
+impl BitrateLadder {
+    /// Keeps rates Opus can produce (6-510 kbps); falls back to the default
+    /// ladder if nothing usable is left.
+    pub fn from_kbps(kbps: &[u32]) -> Self {
+        let mut rungs: Vec<u32> = kbps
+            .iter()
+            .filter(|rate| (6..=510).contains(*rate))
+            .map(|rate| rate * 1000)
+            .collect();
+        rungs.sort_unstable();
+        rungs.dedup();
+        if rungs.is_empty() {
+            return Self::from_kbps(&DEFAULT_LADDER_KBPS);
+        }
+        Self { rungs }
+    }
+
+    /// Highest rung at or below the quality's nominal bitrate.
+    fn start_rung(&self, quality: TranscodeQuality) -> usize {
+        let nominal = match quality {
+            TranscodeQuality::High => 160_000,
+            TranscodeQuality::Medium => 96_000,
+            TranscodeQuality::Low => 48_000,
+        };
+        self.rungs
+            .iter()
+            .rposition(|rate| *rate <= nominal)
+            .unwrap_or(0)
+    }
+
+    /// Highest rung the capacity estimate supports with headroom.
+    fn fitting_rung(&self, capacity_bps: f64) -> usize {
+        self.rungs
+            .iter()
+            .rposition(|rate| *rate as f64 * CAPACITY_HEADROOM <= capacity_bps)
+            .unwrap_or(0)
+    }
+}
+
+/// One report's worth of signals. Either half may be missing: clients
+/// don't have to send buffer levels, and a connection may have no path
+/// stats yet.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AbrSample {
+    pub buffer_ms: Option<u64>,
+    pub path: Option<PathSample>,
+}
+
+/// Cumulative quiche path counters plus the current estimates.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PathSample {
+    pub rtt: Duration,
+    pub min_rtt: Option<Duration>,
+    pub cwnd: usize,
+    /// Bytes per second.
+    pub delivery_rate: u64,
+    pub sent: usize,
+    pub lost: usize,
+}
+
+impl PathSample {
+    /// Bits per second the path could carry: the congestion window per RTT,
+    /// or the measured delivery rate if that is higher. Audio is
+    /// application-limited, so delivery rate alone understates capacity.
+    fn capacity_bps(&self) -> Option<f64> {
+        let window = (self.rtt > Duration::ZERO)
+            .then(|| self.cwnd as f64 * 8.0 / self.rtt.as_secs_f64());
+        let delivered = (self.delivery_rate > 0).then_some(self.delivery_rate as f64 * 8.0);
+        match (window, delivered) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn rtt_inflated(&self) -> bool {
+        match self.min_rtt {
+            Some(min_rtt) if min_rtt > Duration::ZERO => {
+                self.rtt > RTT_INFLATION_FLOOR
+                    && self.rtt.as_secs_f64() > min_rtt.as_secs_f64() * RTT_INFLATION
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Connection-wide packet loss, shared with every encoder on the
+/// connection as a percentage for the Opus loss hint and in-band FEC.
+#[derive(Default)]
+pub struct PacketLossEstimate {
+    percent: Arc<AtomicU32>,
+    smoothed: f64,
+    last_counters: Option<(usize, usize)>,
+}
+
+impl PacketLossEstimate {
+    pub fn shared(&self) -> Arc<AtomicU32> {
+        Arc::clone(&self.percent)
+    }
+
+    pub fn observe(&mut self, path: &PathSample) {
+        if let Some((sent, lost)) = self.last_counters {
+            let sent_delta = path.sent.saturating_sub(sent);
+            if sent_delta > 0 {
+                let loss = path.lost.saturating_sub(lost) as f64 / sent_delta as f64;
+                self.smoothed += (loss.min(1.0) - self.smoothed) * LOSS_ALPHA;
+            }
+        }
+        self.last_counters = Some((path.sent, path.lost));
+        let percent = (self.smoothed * 100.0).round() as u32;
+        self.percent.store(percent.min(100), Ordering::Relaxed);
+    }
+}
+
 struct StreamSession {
     target_bitrate_bps: Arc<AtomicU32>,
-    quality: StreamQuality,
+    rung: usize,
     last_seen: Instant,
     last_change: Instant,
     high_since: Option<Instant>,
+    capacity_bps: Option<f64>,
+    last_counters: Option<(usize, usize)>,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-enum StreamQuality {
-    Low,
-    Medium,
-    High,
+#[derive(Debug, PartialEq)]
+struct Decision {
+    from: usize,
+    to: usize,
+    reason: &'static str,
+}
+
+struct Signals {
+    buffer_ms: Option<u64>,
+    loss: Option<f64>,
+    capacity_bps: Option<f64>,
+    rtt_inflated: bool,
 }
 
 impl StreamSessions {
-    pub fn new() -> Self {
+    pub fn new(ladder: BitrateLadder) -> Self {
         Self {
             inner: Arc::new(RwLock::new(HashMap::new())),
+            ladder: Arc::new(ladder),
         }
     }
 
     pub fn create_session(&self, initial: TranscodeQuality) -> StreamSessionHandle {
         let id = Uuid::new_v4();
-        let quality = StreamQuality::from_transcode(initial);
-        let bitrate_bps = quality.bitrate_bps();
+        let rung = self.ladder.start_rung(initial);
+        let bitrate_bps = self.ladder.rungs[rung];
         let target_bitrate_bps = Arc::new(AtomicU32::new(bitrate_bps));
         let now = Instant::now();
-        let session = StreamSession {
-            target_bitrate_bps: Arc::clone(&target_bitrate_bps),
-            quality,
-            last_seen: now,
-            last_change: now,
-            high_since: None,
-        };
+        let session = StreamSession::new(Arc::clone(&target_bitrate_bps), rung, now);
         self.inner.write().insert(id, session);
         StreamSessionHandle {
             id,
@@ -69,92 +208,248 @@ impl StreamSessions {
         }
     }
 
-    pub fn report_buffer(&self, id: Uuid, buffer_ms: u64) {
+    /// Carries on with `existing` while the controller still tracks it, so a
+    /// stream restarted by a seek keeps its rung; otherwise starts afresh.
+    pub fn keep_or_create(
+        &self,
+        existing: Option<StreamSessionHandle>,
+        initial: TranscodeQuality,
+    ) -> StreamSessionHandle {
+        match existing {
+            Some(handle) if self.inner.read().contains_key(&handle.id) => handle,
+            _ => self.create_session(initial),
+        }
+    }
+
+        pub fn report(&self, id: Uuid, sample: AbrSample) {
         let now = Instant::now();
         let mut guard = self.inner.write();
         guard.retain(|_, session| now.duration_since(session.last_seen) <= SESSION_TTL);
         let Some(session) = guard.get_mut(&id) else {
             return;
         };
-        session.last_seen = now;
+        let signals = session.observe(now, &sample);
+        let Some(decision) = session.decide(now, &signals, &self.ladder) else {
+            return;
+        };
+        let bitrate_bps = self.ladder.rungs[decision.to];
+        session.target_bitrate_bps.store(bitrate_bps, Ordering::Relaxed);
+        tracing::info!(
+            "ABR session={} bitrate {} -> {} bps reason={} buffer_ms={:?} loss={:?} capacity_bps={:?} rtt_ms={:?} cwnd={:?}",
+            id,
+            self.ladder.rungs[decision.from],
+            bitrate_bps,
+            decision.reason,
+            signals.buffer_ms,
+            signals.loss.map(|loss| (loss * 1000.0).round() / 1000.0),
+            signals.capacity_bps.map(|capacity| capacity.round() as u64),
+            sample.path.map(|path| path.rtt.as_millis()),
+            sample.path.map(|path| path.cwnd),
+        );
+    }
+}
+
+impl StreamSession {
+    fn new(target_bitrate_bps: Arc<AtomicU32>, rung: usize, now: Instant) -> Self {
+        Self {
+            target_bitrate_bps,
+            rung,
+            last_seen: now,
+            last_change: now,
+            high_since: None,
+            capacity_bps: None,
+            last_counters: None,
+        }
+    }
 
-        if buffer_ms >= UP_SHIFT_MS {
-            if session.high_since.is_none() {
-                session.high_since = Some(now);
+    /// Folds a sample into the session's running estimates.
+    fn observe(&mut self, now: Instant, sample: &AbrSample) -> Signals {
+        self.last_seen = now;
+        let mut loss = None;
+        let mut rtt_inflated = false;
+        if let Some(path) = sample.path {
+            if let Some((sent, lost)) = self.last_counters {
+                let sent_delta = path.sent.saturating_sub(sent);
+                if sent_delta > 0 {
+                    loss = Some(path.lost.saturating_sub(lost) as f64 / sent_delta as f64);
+                }
+            }
+            self.last_counters = Some((path.sent, path.lost));
+            if let Some(capacity) = path.capacity_bps() {
+                self.capacity_bps = Some(match self.capacity_bps {
+                    Some(previous) => previous + (capacity - previous) * CAPACITY_ALPHA,
+                    None => capacity,
+                });
             }
+            rtt_inflated = path.rtt_inflated();
+        }
+        Signals {
+            buffer_ms: sample.buffer_ms,
+            loss,
+            capacity_bps: self.capacity_bps,
+            rtt_inflated,
+        }
+    }
+
+    fn decide(&mut self, now: Instant, signals: &Signals, ladder: &BitrateLadder) -> Option<Decision> {
+        let clean = signals.loss.map(|loss| loss < LOSS_CLEAN).unwrap_or(true)
+            && !signals.rtt_inflated
+            && signals.buffer_ms.map(|buffer| buffer >= UP_SHIFT_MS).unwrap_or(true);
+        if clean {
+            self.high_since.get_or_insert(now);
         } else {
-            session.high_since = None;
+            self.high_since = None;
         }
 
-        let since_change = now.duration_since(session.last_change);
-        if buffer_ms < DOWN_SHIFT_MS && since_change >= Duration::from_secs(CHANGE_COOLDOWN_SECS) {
-            if session.quality.downshift() {
-                session.last_change = now;
-                session.high_since = None;
-                session
-                    .target_bitrate_bps
-                    .store(session.quality.bitrate_bps(), Ordering::Relaxed);
-            }
-            return;
+        let cooled = now.duration_since(self.last_change) >= Duration::from_secs(CHANGE_COOLDOWN_SECS);
+        if !cooled {
+            return None;
         }
 
-        if let Some(since_high) = session.high_since {
-            if since_change >= Duration::from_secs(CHANGE_COOLDOWN_SECS)
-                && now.duration_since(since_high) >= Duration::from_secs(UP_STABLE_SECS)
-            {
-                if session.quality.upshift() {
-                    session.last_change = now;
-                    session
-                        .target_bitrate_bps
-                        .store(session.quality.bitrate_bps(), Ordering::Relaxed);
-                }
+        let fitting = signals.capacity_bps.map(|capacity| ladder.fitting_rung(capacity));
+        let down_reason = if signals.buffer_ms.is_some_and(|buffer| buffer < DOWN_SHIFT_MS) {
+            Some("buffer_low")
+        } else if signals.loss.is_some_and(|loss| loss > LOSS_DOWN) {
+            Some("loss")
+        } else if signals.rtt_inflated {
+            Some("rtt_inflated")
+        } else if fitting.is_some_and(|rung| rung < self.rung) {
+            Some("capacity")
+        } else {
+            None
+        };
+        if let Some(reason) = down_reason {
+            if self.rung == 0 {
+                return None;
             }
+            // Drop straight to what the path supports, and at least one rung.
+            let to = fitting.unwrap_or(self.rung).min(self.rung - 1);
+            return Some(self.shift(now, to, reason));
         }
+
+        let stable = self
+            .high_since
+            .is_some_and(|since| now.duration_since(since) >= Duration::from_secs(UP_STABLE_SECS));
+        let room = fitting.map(|rung| rung > self.rung).unwrap_or(true);
+        if stable && room && self.rung + 1 < ladder.rungs.len() {
+            return Some(self.shift(now, self.rung + 1, "stable"));
+        }
+        None
+    }
+
+    fn shift(&mut self, now: Instant, to: usize, reason: &'static str) -> Decision {
+        let decision = Decision {
+            from: self.rung,
+            to,
+            reason,
+        };
+        self.rung = to;
+        self.last_change = now;
+        self.high_since = None;
+        decision
     }
 }
 
-impl StreamQuality {
-    fn from_transcode(value: TranscodeQuality) -> Self {
-        match value {
-            TranscodeQuality::High => StreamQuality::High,
-            TranscodeQuality::Medium => StreamQuality::Medium,
-            TranscodeQuality::Low => StreamQuality::Low,
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(rtt_ms: u64, cwnd: usize, sent: usize, lost: usize) -> AbrSample {
+        AbrSample {
+            buffer_ms: None,
+            path: Some(PathSample {
+                rtt: Duration::from_millis(rtt_ms),
+                min_rtt: Some(Duration::from_millis(20)),
+                cwnd,
+                delivery_rate: 0,
+                sent,
+                lost,
+            }),
         }
     }
 
-    fn bitrate_bps(self) -> u32 {
-        match self {
-            StreamQuality::High => 160_000,
-            StreamQuality::Medium => 96_000,
-            StreamQuality::Low => 48_000,
-        }
+    #[test]
+    fn ladder_is_sorted_and_picks_start_rungs() {
+        let ladder = BitrateLadder::from_kbps(&[160, 48, 96, 96, 1000]);
+        assert_eq!(ladder.rungs, vec![48_000, 96_000, 160_000]);
+        assert_eq!(ladder.start_rung(TranscodeQuality::Medium), 1);
+        assert_eq!(BitrateLadder::from_kbps(&[]), BitrateLadder::from_kbps(&DEFAULT_LADDER_KBPS));
     }
 
-    fn downshift(&mut self) -> bool {
-        match self {
-            StreamQuality::High => {
-                *self = StreamQuality::Medium;
-                true
-            }
-            StreamQuality::Medium => {
-                *self = StreamQuality::Low;
-                true
-            }
-            StreamQuality::Low => false,
+    #[test]
+    fn loss_and_thin_paths_step_down() {
+        let ladder = BitrateLadder::from_kbps(&DEFAULT_LADDER_KBPS);
+        let start = Instant::now();
+        let mut session = StreamSession::new(Arc::new(AtomicU32::new(0)), 4, start);
+        let later = start + Duration::from_secs(CHANGE_COOLDOWN_SECS);
+
+        // 12 kB window over 40 ms is 2.4 Mbps: plenty.
+        let signals = session.observe(later, &path(40, 12_000, 1_000, 0));
+        assert_eq!(session.decide(later, &signals, &ladder), None);
+
+        // 10% loss since the last report.
+        let signals = session.observe(later, &path(40, 12_000, 1_100, 10));
+        let decision = session.decide(later, &signals, &ladder).unwrap();
+        assert_eq!((decision.to, decision.reason), (3, "loss"));
+
+        // A path carrying ~100 kbps can't hold 128 kbps with headroom.
+        let mut session = StreamSession::new(Arc::new(AtomicU32::new(0)), 4, start);
+        let signals = session.observe(later, &path(40, 500, 100, 0));
+        let decision = session.decide(later, &signals, &ladder).unwrap();
+        assert_eq!((decision.to, decision.reason), (1, "capacity"));
+    }
+
+    #[test]
+    fn steps_up_after_a_stable_period() {
+        let ladder = BitrateLadder::from_kbps(&DEFAULT_LADDER_KBPS);
+        let start = Instant::now();
+        let mut session = StreamSession::new(Arc::new(AtomicU32::new(0)), 0, start);
+        let mut sent = 0;
+        let mut decisions = Vec::new();
+        for second in 0..=UP_STABLE_SECS {
+            sent += 100;
+            let now = start + Duration::from_secs(second);
+            let signals = session.observe(now, &path(30, 20_000, sent, 0));
+            decisions.extend(session.decide(now, &signals, &ladder));
         }
+        assert_eq!(decisions.len(), 1);
+        assert_eq!((decisions[0].to, decisions[0].reason), (1, "stable"));
     }
 
-    fn upshift(&mut self) -> bool {
-        match self {
-            StreamQuality::Low => {
-                *self = StreamQuality::Medium;
-                true
-            }
-            StreamQuality::Medium => {
-                *self = StreamQuality::High;
-                true
-            }
-            StreamQuality::High => false,
+    #[test]
+    fn loss_estimate_rises_and_decays() {
+        let mut estimate = PacketLossEstimate::default();
+        let percent = estimate.shared();
+        let sample = |sent, lost| path(30, 20_000, sent, lost).path.unwrap();
+
+        estimate.observe(&sample(1_000, 0));
+        estimate.observe(&sample(1_100, 20));
+        assert_eq!(percent.load(Ordering::Relaxed), 5);
+
+        let mut sent = 1_100;
+        for _ in 0..20 {
+            sent += 100;
+            estimate.observe(&sample(sent, 20));
         }
+        assert_eq!(percent.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn restarted_streams_keep_their_rung() {
+        let sessions = StreamSessions::new(BitrateLadder::from_kbps(&DEFAULT_LADDER_KBPS));
+        let handle = sessions.create_session(TranscodeQuality::High);
+        // The controller stepped down while the old stream played.
+        sessions.inner.write().get_mut(&handle.id).unwrap().rung = 1;
+        handle.target_bitrate_bps.store(64_000, Ordering::Relaxed);
+
+        let kept = sessions.keep_or_create(Some(handle.clone()), TranscodeQuality::High);
+        assert_eq!(kept.id, handle.id);
+        assert_eq!(kept.target_bitrate_bps.load(Ordering::Relaxed), 64_000);
+
+        // An expired session can't be continued.
+        sessions.inner.write().remove(&handle.id);
+        let fresh = sessions.keep_or_create(Some(handle.clone()), TranscodeQuality::High);
+        assert_ne!(fresh.id, handle.id);
+        assert_eq!(fresh.target_bitrate_bps.load(Ordering::Relaxed), 160_000);
     }
 }
@@ -1,7 +1,8 @@
 use library::Library;
 use common::Track;
 
-use crate::transcode::{RawOpusMeta, TranscodeMode, TranscodeQuality};
+use crate::time_stretch::{MAX_SPEED, MIN_SPEED};
+use crate::transcode::{Crossfade, CrossfadeCurve, RawOpusMeta, TranscodeMode, TranscodeQuality};
 
 pub fn parse_transcode_mode(value: Option<&str>) -> Result<TranscodeMode, String> {
     let value = value.unwrap_or("auto").trim().to_ascii_lowercase();
@@ -30,6 +31,39 @@ pub fn parse_frame_ms(value: Option<u32>) -> Result<u32, String> {
     }
 }
 
+const MAX_CROSSFADE_MS: u32 = 12_000;
+
+/// `crossfade_ms` of 0 (or absent) turns crossfading off.
+pub fn parse_crossfade(
+    duration_ms: Option<u32>,
+    curve: Option<&str>,
+) -> Result<Option<Crossfade>, String> {
+    let duration_ms = duration_ms.unwrap_or(0);
+    if duration_ms == 0 {
+        return Ok(None);
+    }
+    if duration_ms > MAX_CROSSFADE_MS {
+        return Err(format!("crossfade_ms must be at most {}", MAX_CROSSFADE_MS));
+    }
+    let value = curve.unwrap_or("equal_power").trim().to_ascii_lowercase();
+    let curve = match value.as_str() {
+        "linear" => CrossfadeCurve::Linear,
+        "equal_power" | "equal-power" | "constant_power" => CrossfadeCurve::EqualPower,
+        "s_curve" | "s-curve" | "smooth" => CrossfadeCurve::SCurve,
+        other => return Err(format!("invalid crossfade_curve: {}", other)),
+    };
+    Ok(Some(Crossfade { duration_ms, curve }))
+}
+
+/// Absent means normal speed.
+pub fn parse_speed(value: Option<f32>) -> Result<f32, String> {
+    let speed = value.unwrap_or(1.0);
+    if !(MIN_SPEED..=MAX_SPEED).contains(&speed) {
+        return Err(format!("speed must be between {} and {}", MIN_SPEED, MAX_SPEED));
+    }
+    Ok(speed)
+}
+
 pub fn transcode_mode_label(mode: TranscodeMode) -> &'static str {
     match mode {
         TranscodeMode::Auto => "auto",
@@ -0,0 +1,263 @@
+//! Pitch-preserving tempo change for the transcoder (WSOLA).
+//!
+//! Hann-windowed segments are overlap-added at a fixed synthesis hop while
+//! the read position advances by `hop * speed`. Each segment start is moved
+//! within a small tolerance to where it best lines up with the natural
+//! continuation of the previous segment, so waveforms join in phase instead
+//! of smearing.
+
+const WINDOW_MS: u32 = 30;
+const TOLERANCE_MS: u32 = 8;
+/// Correlation reads every n-th frame; plenty for picking an alignment.
+const CORRELATION_STRIDE: usize = 4;
+/// Candidate spacing for the coarse search, refined around the best hit.
+const COARSE_STEP: usize = 4;
+
+pub const MIN_SPEED: f32 = 0.5;
+pub const MAX_SPEED: f32 = 3.0;
+
+pub struct TimeStretch {
+    channels: usize,
+    speed: f64,
+    window: usize,
+    hop: usize,
+    tolerance: usize,
+    hann: Vec<f32>,
+    /// Interleaved input; frame 0 is absolute input frame `base`.
+    input: Vec<f32>,
+    base: u64,
+    /// Ideal (unaligned) start of the next segment.
+    next_pos: f64,
+    /// Where the previous segment would carry on if left alone; `None`
+    /// before the first segment.
+    natural: Option<u64>,
+    /// Faded-out second half of the previous segment.
+    overlap: Vec<f32>,
+}
+
+impl TimeStretch {
+    pub fn new(rate: u32, channels: u8, speed: f32) -> Self {
+        let channels = channels.max(1) as usize;
+        let window = ((rate * WINDOW_MS / 1000) as usize / 2 * 2).max(2);
+        let hop = window / 2;
+        let hann = (0..window)
+            .map(|n| {
+                let phase = 2.0 * std::f64::consts::PI * n as f64 / window as f64;
+                (0.5 - 0.5 * phase.cos()) as f32
+            })
+            .collect();
+        Self {
+            channels,
+            speed: speed.clamp(MIN_SPEED, MAX_SPEED) as f64,
+            window,
+            hop,
+            tolerance: (rate * TOLERANCE_MS / 1000) as usize,
+            hann,
+            input: Vec::new(),
+            base: 0,
+            next_pos: 0.0,
+            natural: None,
+            overlap: Vec::new(),
+        }
+    }
+
+    pub fn process(&mut self, input: &[f32]) -> Vec<f32> {
+        self.input.extend_from_slice(input);
+        let mut output = Vec::new();
+        loop {
+            let ideal = self.next_pos.round() as u64;
+            let reach = match self.natural {
+                Some(_) => ideal + self.tolerance as u64 + self.window as u64,
+                None => ideal + self.window as u64,
+            };
+            if reach > self.end() {
+                break;
+            }
+            let start = match self.natural {
+                Some(natural) => self.align(ideal, natural),
+                None => ideal,
+            };
+            self.emit(start, &mut output);
+            self.natural = Some(start + self.hop as u64);
+            self.next_pos += self.hop as f64 * self.speed;
+            self.trim();
+        }
+        output
+    }
+
+    /// Output still owed for buffered input plus `extra` more input frames:
+    /// whatever keeps the total at `input / speed`, and at least the
+    /// pending fade-out.
+    pub fn pending_output(&self, extra: u64) -> u64 {
+        let remaining = ((self.end() + extra) as f64 - self.next_pos).max(0.0);
+        let overlap = (self.overlap.len() / self.channels) as u64;
+        ((remaining / self.speed).round() as u64).max(overlap)
+    }
+
+    /// Ends the stretch: the previous segment's fade-out plus the unread
+    /// continuation under it is just that input, so the rest goes out
+    /// unstretched, cut to the length still owed. Leaves the stretcher
+    /// ready for a new, unrelated input.
+    pub fn flush(&mut self) -> Vec<f32> {
+        let owed = self.pending_output(0) as usize;
+        let from = self.natural.unwrap_or(self.base);
+        let offset = (from.saturating_sub(self.base) as usize * self.channels).min(self.input.len());
+        let mut output = self.input.split_off(offset);
+        output.truncate(owed * self.channels);
+        self.input.clear();
+        self.base = 0;
+        self.next_pos = 0.0;
+        self.natural = None;
+        self.overlap.clear();
+        output
+    }
+
+    fn end(&self) -> u64 {
+        self.base + (self.input.len() / self.channels) as u64
+    }
+
+    /// Picks the segment start near `ideal` whose opening best matches the
+    /// input following `natural`.
+    fn align(&self, ideal: u64, natural: u64) -> u64 {
+        let lo = ideal.saturating_sub(self.tolerance as u64).max(self.base);
+        let hi = ideal + self.tolerance as u64;
+        let mut best = (f64::MIN, ideal.max(lo));
+        let mut candidate = lo;
+        while candidate <= hi {
+            best = self.better(best, candidate, natural);
+            candidate += COARSE_STEP as u64;
+        }
+        let coarse = best.1;
+        let fine_lo = coarse.saturating_sub(COARSE_STEP as u64 - 1).max(lo);
+        let fine_hi = (coarse + COARSE_STEP as u64 - 1).min(hi);
+        for candidate in fine_lo..=fine_hi {
+            best = self.better(best, candidate, natural);
+        }
+        best.1
+    }
+
+    fn better(&self, best: (f64, u64), candidate: u64, natural: u64) -> (f64, u64) {
+        let score = self.similarity(candidate, natural);
+        if score > best.0 {
+            (score, candidate)
+        } else {
+            best
+        }
+    }
+
+    /// Normalised cross-correlation of the channel sums over one hop.
+    fn similarity(&self, candidate: u64, natural: u64) -> f64 {
+        let candidate = (candidate - self.base) as usize;
+        let natural = (natural - self.base) as usize;
+        let mut dot = 0.0f64;
+        let mut energy = 0.0f64;
+        for frame in (0..self.hop).step_by(CORRELATION_STRIDE) {
+            let a = self.frame_sum(natural + frame);
+            let b = self.frame_sum(candidate + frame);
+            dot += a * b;
+            energy += b * b;
+        }
+        if energy <= f64::EPSILON {
+            return 0.0;
+        }
+        dot / energy.sqrt()
+    }
+
+    fn frame_sum(&self, frame: usize) -> f64 {
+        let start = frame * self.channels;
+        self.input[start..start + self.channels]
+            .iter()
+            .map(|sample| *sample as f64)
+            .sum()
+    }
+
+    fn emit(&mut self, start: u64, output: &mut Vec<f32>) {
+        let channels = self.channels;
+        let offset = (start - self.base) as usize * channels;
+        let segment = &self.input[offset..offset + self.window * channels];
+        let half = self.hop * channels;
+        if self.natural.is_none() {
+            // Nothing to fade in from; start at full level.
+            output.extend_from_slice(&segment[..half]);
+        } else {
+            for (index, sample) in segment[..half].iter().enumerate() {
+                let faded = sample * self.hann[index / channels];
+                output.push(self.overlap[index] + faded);
+            }
+        }
+        self.overlap.clear();
+        for (index, sample) in segment[half..].iter().enumerate() {
+            self.overlap.push(sample * self.hann[self.hop + index / channels]);
+        }
+    }
+
+    /// Drops input no later segment or alignment can reach.
+    fn trim(&mut self) {
+        let next = (self.next_pos.round() as u64).saturating_sub(self.tolerance as u64);
+        let keep = self.natural.map_or(next, |natural| natural.min(next));
+        if keep > self.base {
+            let frames = ((keep - self.base) as usize).min(self.input.len() / self.channels);
+            self.input.drain(..frames * self.channels);
+            self.base += frames as u64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(rate: u32, freq: f64, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .flat_map(|n| {
+                let value = (2.0 * std::f64::consts::PI * freq * n as f64 / rate as f64).sin() as f32;
+                [value * 0.5, value * 0.5]
+            })
+            .collect()
+    }
+
+    fn stretch(input: &[f32], speed: f32) -> Vec<f32> {
+        let mut stretcher = TimeStretch::new(48_000, 2, speed);
+        let mut output = Vec::new();
+        for chunk in input.chunks(2 * 1000) {
+            output.extend(stretcher.process(chunk));
+        }
+        output.extend(stretcher.flush());
+        output
+    }
+
+    /// Rising zero crossings per second on the left channel.
+    fn pitch(samples: &[f32], rate: u32) -> f64 {
+        let left: Vec<f32> = samples.iter().step_by(2).copied().collect();
+        let crossings = left.windows(2).filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0).count();
+        crossings as f64 * rate as f64 / left.len() as f64
+    }
+
+    #[test]
+    fn changes_length_but_not_pitch() {
+        let input = tone(48_000, 440.0, 48_000 * 2);
+        for speed in [0.5f32, 1.5, 3.0] {
+            let output = stretch(&input, speed);
+            // Slowing down, the flush passes up to a window's worth of
+            // input through unstretched.
+            let expected = input.len() as f64 / 2.0 / speed as f64;
+            let frames = output.len() as f64 / 2.0;
+            let slack = (48_000 * (WINDOW_MS + TOLERANCE_MS) / 1000) as f64 / speed as f64;
+            assert!((frames - expected).abs() < slack, "speed {} frames {}", speed, frames);
+            let freq = pitch(&output, 48_000);
+            assert!((freq - 440.0).abs() < 10.0, "speed {} pitch {}", speed, freq);
+        }
+    }
+
+    #[test]
+    fn steady_tones_keep_their_level() {
+        let input = tone(48_000, 440.0, 48_000);
+        let output = stretch(&input, 2.0);
+        // Skip the raw tail flush adds at the end.
+        let body = &output[..output.len() / 2];
+        let peak = body.iter().fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        let rms = (body.iter().map(|s| (*s as f64).powi(2)).sum::<f64>() / body.len() as f64).sqrt();
+        assert!(peak < 0.55, "peak {}", peak);
+        assert!((rms - 0.5 / 2f64.sqrt()).abs() < 0.02, "rms {}", rms);
+    }
+}
@@ -1,30 +1,44 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::{
     atomic::{AtomicU32, Ordering},
     Arc,
 };
 
 use bytes::Bytes;
-use codecs_ffi::OpusEncoderWrapper;
-use symphonia::core::audio::SampleBuffer;
-use symphonia::core::codecs::DecoderOptions;
+use codecs_ffi::{
+    OpusEncodeError, OpusEncoderWrapper, OpusMultistreamEncoderWrapper, OpusSignal, OpusVbrMode,
+};
+use common::Track;
+use quic_wire::AudioFraming;
+use symphonia::core::audio::{Channels, SampleBuffer};
+use symphonia::core::codecs::{Decoder, DecoderOptions};
 use symphonia::core::errors::Error as SymphoniaError;
-use symphonia::core::formats::{FormatOptions, SeekMode, SeekTo};
+use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo};
 use symphonia::core::io::MediaSourceStream;
 use symphonia::core::meta::MetadataOptions;
 use symphonia::core::probe::Hint;
-use symphonia::core::units::Time;
+use symphonia::core::units::{Time, TimeBase};
+
+use crate::downmix::{apply_matrix, fits_layout, mix_matrix, source_roles, vorbis_roles, Role};
+use crate::resample::{ResampleQuality, Resampler};
+use crate::time_stretch::TimeStretch;
+use crate::transcode_cache::CacheWriter;
 
 
 const TARGET_SAMPLE_RATE: u32 = 48_000;
 const DEFAULT_FRAME_MS: u32 = 20;
 const MAX_SEEK_SKIP_MS: u32 = 250;
+/// Byte offset of the pre-skip field in the raw stream header.
+const RAW_HEADER_PRE_SKIP_OFFSET: usize = 26;
 
 pub struct BitrateSelector {
     pub mode: TranscodeMode,
     pub quality: TranscodeQuality,
     pub fixed_bitrate_bps: Option<u32>,
     pub adaptive_bitrate_bps: Option<Arc<AtomicU32>>,
+    /// Packet loss the listener is seeing, in percent. Drives the encoder's
+    /// loss hint and in-band FEC.
+    pub packet_loss_perc: Option<Arc<AtomicU32>>,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -40,23 +54,186 @@ pub enum TranscodeQuality {
     Low,
 }
 
+/// Encoder delay/padding recorded at scan time, in decoded samples at the
+/// source rate. Symphonia is run without its own gapless handling so these
+/// are the only trimming applied.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GaplessTrim {
+    pub encoder_delay: u32,
+    pub encoder_padding: u32,
+    pub total_samples: Option<u64>,
+}
+
+impl GaplessTrim {
+    pub fn from_track(track: &Track) -> Self {
+        Self {
+            encoder_delay: track.encoder_delay.unwrap_or(0),
+            encoder_padding: track.encoder_padding.unwrap_or(0),
+            total_samples: track.total_samples,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TranscodeSource {
+    pub track_id: String,
+    pub path: PathBuf,
+    pub trim: GaplessTrim,
+}
+
+/// Out-of-band notices for the control channel. Sample positions count
+/// 48 kHz samples per channel after the Opus pre-skip.
+#[derive(Clone, Debug)]
+pub enum StreamEvent {
+    /// `crossfade_samples` is 0 for a plain gapless join; otherwise the
+    /// next track fades in over that many samples from `sample_offset`.
+    TrackBoundary {
+        track_id: String,
+        sample_offset: u64,
+        crossfade_samples: u64,
+    },
+    End { pre_skip: u16, valid_samples: u64 },
+}
+
+pub type StreamEventSender = tokio::sync::mpsc::UnboundedSender<StreamEvent>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CrossfadeCurve {
+    Linear,
+    EqualPower,
+    SCurve,
+}
+
+impl CrossfadeCurve {
+    /// (fade-out, fade-in) gains at `t` in 0..=1.
+    fn gains(self, t: f32) -> (f32, f32) {
+        match self {
+            CrossfadeCurve::Linear => (1.0 - t, t),
+            CrossfadeCurve::EqualPower => {
+                let angle = t * std::f32::consts::FRAC_PI_2;
+                (angle.cos(), angle.sin())
+            }
+            CrossfadeCurve::SCurve => {
+                let s = t * t * (3.0 - 2.0 * t);
+                (1.0 - s, s)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Crossfade {
+    pub duration_ms: u32,
+    pub curve: CrossfadeCurve,
+}
+
+impl Crossfade {
+    fn samples(&self) -> usize {
+        (TARGET_SAMPLE_RATE as u64 * self.duration_ms as u64 / 1000) as usize
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct StreamFormat {
+    pub frame_ms: u32,
+    /// Keep surround sources as Opus mapping family 1 instead of folding
+    /// them down to stereo.
+    pub surround: bool,
+    /// Tempo multiplier, pitch preserved. Positions and seeks stay in
+    /// source time; only the stream's own sample counts are scaled.
+    pub speed: f32,
+    /// How raw streams delimit the header and packets.
+    pub framing: AudioFraming,
+}
+
+#[derive(Default)]
+pub struct RawOptions<'a> {
+    pub events: Option<&'a StreamEventSender>,
+    /// Records the encoded stream for later replay.
+    pub cache: Option<CacheWriter>,
+    /// Called between decoded chunks; may block to hold the encode back.
+    pub throttle: Option<&'a dyn Fn()>,
+}
+
+pub struct GaplessOptions<'a> {
+    pub events: &'a StreamEventSender,
+    /// Overlap consecutive tracks instead of butting them together.
+    pub crossfade: Option<Crossfade>,
+    pub throttle: Option<&'a dyn Fn()>,
+}
+
 pub fn transcode_to_ogg_opus(
-    path: &Path,
+    source: TranscodeSource,
     selector: BitrateSelector,
     tx: &tokio::sync::mpsc::Sender<Result<Bytes, std::io::Error>>,
 ) -> Result<(), String> {
-    transcode_to_opus(path, selector, DEFAULT_FRAME_MS, OpusOutput::Ogg(tx), 0)
+    let format = StreamFormat {
+        frame_ms: DEFAULT_FRAME_MS,
+        surround: false,
+        speed: 1.0,
+        framing: AudioFraming::Legacy,
+    };
+    transcode_to_opus(
+        std::iter::once(source),
+        selector,
+        format,
+        OpusOutput::Ogg(tx),
+        0,
+        OpusOptions::default(),
+    )
 }
 
 pub fn transcode_to_raw_opus(
-    path: &Path,
+    source: TranscodeSource,
     selector: BitrateSelector,
-    frame_ms: u32,
+    format: StreamFormat,
+    meta: RawOpusMeta,
+    start_ms: u32,
+    tx: &tokio::sync::mpsc::Sender<Result<Bytes, std::io::Error>>,
+    options: RawOptions<'_>,
+) -> Result<(), String> {
+    transcode_to_opus(
+        std::iter::once(source),
+        selector,
+        format,
+        OpusOutput::Raw(tx, meta),
+        start_ms,
+        OpusOptions {
+            events: options.events,
+            crossfade: None,
+            cache: options.cache,
+            throttle: options.throttle,
+        },
+    )
+}
+
+/// Encodes consecutive sources into one continuous Opus stream. The encoder
+/// is never reset between tracks, so there is no priming gap at the joins;
+/// each join is reported as a `TrackBoundary` event instead. With a
+/// crossfade the tail of each track is mixed with the head of the next
+/// before encoding.
+pub fn transcode_gapless_raw_opus(
+    sources: impl Iterator<Item = TranscodeSource>,
+    selector: BitrateSelector,
+    format: StreamFormat,
     meta: RawOpusMeta,
     start_ms: u32,
     tx: &tokio::sync::mpsc::Sender<Result<Bytes, std::io::Error>>,
+    options: GaplessOptions<'_>,
 ) -> Result<(), String> {
-    transcode_to_opus(path, selector, frame_ms, OpusOutput::Raw(tx, meta), start_ms)
+    transcode_to_opus(
+        sources,
+        selector,
+        format,
+        OpusOutput::Raw(tx, meta),
+        start_ms,
+        OpusOptions {
+            events: Some(options.events),
+            crossfade: options.crossfade,
+            cache: None,
+            throttle: options.throttle,
+        },
+    )
 }
 
 #[derive(Clone, Debug)]
@@ -75,294 +252,901 @@ enum OpusOutput<'a> {
     Raw(&'a tokio::sync::mpsc::Sender<Result<Bytes, std::io::Error>>, RawOpusMeta),
 }
 
+#[derive(Default)]
+struct OpusOptions<'a> {
+    events: Option<&'a StreamEventSender>,
+    crossfade: Option<Crossfade>,
+    cache: Option<CacheWriter>,
+    throttle: Option<&'a dyn Fn()>,
+}
+
 fn transcode_to_opus(
-    path: &Path,
+    mut sources: impl Iterator<Item = TranscodeSource>,
     selector: BitrateSelector,
-    frame_ms: u32,
+    format: StreamFormat,
     output: OpusOutput<'_>,
     start_ms: u32,
+    options: OpusOptions<'_>,
 ) -> Result<(), String> {
-    let frame_ms = validate_frame_ms(frame_ms)?;
-    let file = std::fs::File::open(path).map_err(|err| err.to_string())?;
-    let mss = MediaSourceStream::new(Box::new(file), Default::default());
-    let mut hint = Hint::new();
-    if let Some(ext) = path.extension().and_then(|s| s.to_str()) {
-        hint.with_extension(ext);
-    }
-    let probed = symphonia::default::get_probe()
-        .format(&hint, mss, &FormatOptions::default(), &MetadataOptions::default())
-        .map_err(|err| err.to_string())?;
-    let mut format = probed.format;
-    let track = format
-        .default_track()
-        .ok_or_else(|| "no default audio track".to_string())?;
-    let track_id = track.id;
-    let track_codec_params = track.codec_params.clone();
-    let track_time_base = track.codec_params.time_base;
-    let mut decoder = symphonia::default::get_codecs()
-        .make(&track_codec_params, &DecoderOptions::default())
-        .map_err(|err| err.to_string())?;
-
-    let mut channels: Option<u8> = None;
-    let mut resampler: Option<LinearResampler> = None;
-    let mut pcm_buffer: Vec<i16> = Vec::new();
-    let mut total_samples = 0u64;
-    let mut ogg = OggWriter::new(serial_from_path(path));
-    let mut encoder: Option<OpusEncoderWrapper> = None;
-    let mut current_bitrate = 0u32;
-    let mut frame_size = 0usize;
-    let mut packet_counter = 0u32;
-    let mut skip_samples: u64 = 0;
-    let mut skip_initialized = false;
-    let mut seek_used = false;
-    let mut seek_skip_per_channel: Option<u64> = None;
-
-    if start_ms > 0 {
-        let seconds = (start_ms / 1000) as u64;
-        let frac = (start_ms % 1000) as f64 / 1000.0;
-        let time = Time::new(seconds, frac);
-        let seek_mode = SeekMode::Coarse;
-        match format.seek(seek_mode, SeekTo::Time { time, track_id: Some(track_id) }) {
-            Ok(seeked) => {
-                seek_used = true;
-                if let Some(time_base) = track_time_base {
-                    let required = time_base.calc_time(seeked.required_ts);
-                    let actual = time_base.calc_time(seeked.actual_ts);
-                    let required_secs = required.seconds as f64 + required.frac;
-                    let actual_secs = actual.seconds as f64 + actual.frac;
-                    let delta_secs = required_secs - actual_secs;
-                    if delta_secs > 0.0 {
-                        let skip = (delta_secs * TARGET_SAMPLE_RATE as f64).round() as u64;
-                        let max_skip = (TARGET_SAMPLE_RATE as u64)
-                            .saturating_mul(MAX_SEEK_SKIP_MS as u64)
-                            / 1000;
-                        if skip > max_skip {
-                            tracing::info!(
-                                "QUIC transcode seek skip capped: requested_skip={} max_skip={} (ms={})",
-                                skip,
-                                max_skip,
-                                MAX_SEEK_SKIP_MS
-                            );
-                            seek_skip_per_channel = None;
-                        } else {
-                            seek_skip_per_channel = Some(skip);
-                        }
-                    }
-                }
-                decoder = symphonia::default::get_codecs()
-                    .make(&track_codec_params, &DecoderOptions::default())
-                    .map_err(|err| err.to_string())?;
-                resampler = None;
-                pcm_buffer.clear();
-                tracing::info!(
-                    "QUIC transcode seek start_ms={} actual_ts={} required_ts={} skip_samples_per_channel={}",
-                    start_ms,
-                    seeked.actual_ts,
-                    seeked.required_ts,
-                    seek_skip_per_channel.unwrap_or(0)
+    let events = options.events;
+    let throttle = options.throttle;
+    let frame_ms = validate_frame_ms(format.frame_ms)?;
+    let first = sources
+        .next()
+        .ok_or_else(|| "no source to transcode".to_string())?;
+    let mut sink = OpusSink::new(selector, frame_ms, output, serial_from_path(&first.path));
+    sink.surround = format.surround;
+    sink.speed = format.speed;
+    sink.framing = format.framing;
+    sink.crossfade = options.crossfade.filter(|crossfade| crossfade.samples() > 0);
+    sink.cache = options.cache;
+    let mut next = Some((first, start_ms));
+    let mut index = 0usize;
+
+    while let Some((source, offset_ms)) = next.take() {
+        let mut decoder = match SourceDecoder::open(&source, offset_ms) {
+            Ok(decoder) => decoder,
+            Err(err) if index > 0 => {
+                tracing::warn!(
+                    "QUIC gapless skipping track={} err={}",
+                    source.track_id,
+                    err
                 );
+                next = sources.next().map(|source| (source, 0));
+                continue;
             }
-            Err(err) => {
-                tracing::warn!("QUIC transcode seek failed (fallback to skip): {}", err);
+            Err(err) => return Err(err),
+        };
+        if index > 0 {
+            let crossfade_samples = sink.begin_crossfade()? as u64;
+            if let Some(events) = events {
+                let _ = events.send(StreamEvent::TrackBoundary {
+                    track_id: source.track_id.clone(),
+                    sample_offset: sink.boundary_offset() - crossfade_samples,
+                    crossfade_samples,
+                });
+            }
+        }
+        while let Some(chunk) = decoder.next_chunk()? {
+            if let Some(throttle) = throttle {
+                throttle();
             }
+            sink.push(&chunk)?;
         }
+        index += 1;
+        next = sources.next().map(|source| (source, 0));
     }
 
-    loop {
-        let packet = match format.next_packet() {
-            Ok(packet) => packet,
-            Err(SymphoniaError::ResetRequired) => {
-                return Err("decoder reset required".to_string());
-            }
-            Err(SymphoniaError::IoError(_)) => break,
-            Err(err) => return Err(err.to_string()),
-        };
-        let decoded = decoder.decode(&packet).map_err(|err| err.to_string())?;
+    let packets = sink.packets;
+    let (pre_skip, valid_samples) = sink.finish()?;
+    if let Some(events) = events {
+        let _ = events.send(StreamEvent::End {
+            pre_skip,
+            valid_samples,
+        });
+    }
+
+    tracing::info!(
+        "QUIC transcode done start_ms={} tracks={} frames={} pre_skip={} valid_samples={}",
+        start_ms,
+        index,
+        packets,
+        pre_skip,
+        valid_samples
+    );
 
-        let spec = *decoded.spec();
-        let decoded_channels = spec.channels.count() as u8;
-        if decoded_channels == 0 || decoded_channels > 2 {
-            return Err("unsupported channel count".to_string());
+    Ok(())
+}
+
+pub(crate) struct DecodedChunk {
+    pub(crate) samples: Vec<f32>,
+    pub(crate) rate: u32,
+    pub(crate) channels: usize,
+    layout: Channels,
+    /// Source bit depth; `None` for lossy codecs, which decode to float.
+    bits_per_sample: Option<u32>,
+}
+
+/// Decodes one file, dropping the encoder delay, the seek lead-in and the
+/// encoder padding so only the track's own samples come out.
+pub(crate) struct SourceDecoder {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    time_base: Option<TimeBase>,
+    bits_per_sample: Option<u32>,
+    trim: GaplessTrim,
+    /// Position in the track (excluding delay) to start from; `None` when a
+    /// seek landed somewhere we can't locate.
+    start_secs: Option<f64>,
+    bounds: Option<(u64, Option<u64>)>,
+    next_frame: u64,
+    held: Vec<f32>,
+    done: bool,
+}
+
+impl SourceDecoder {
+    pub(crate) fn open(source: &TranscodeSource, start_ms: u32) -> Result<Self, String> {
+        let path = source.path.as_path();
+        let file = std::fs::File::open(path).map_err(|err| err.to_string())?;
+        let mss = MediaSourceStream::new(Box::new(file), Default::default());
+        let mut hint = Hint::new();
+        if let Some(ext) = path.extension().and_then(|s| s.to_str()) {
+            hint.with_extension(ext);
         }
-        if channels.is_none() {
-            channels = Some(decoded_channels);
+        let probed = symphonia::default::get_probe()
+            .format(&hint, mss, &FormatOptions::default(), &MetadataOptions::default())
+            .map_err(|err| err.to_string())?;
+        let mut format = probed.format;
+        let track = format
+            .default_track()
+            .ok_or_else(|| "no default audio track".to_string())?;
+        let track_id = track.id;
+        let track_codec_params = track.codec_params.clone();
+        let time_base = track.codec_params.time_base;
+        let bits_per_sample = track.codec_params.bits_per_sample;
+        let mut decoder = symphonia::default::get_codecs()
+            .make(&track_codec_params, &DecoderOptions::default())
+            .map_err(|err| err.to_string())?;
+
+        let mut start_secs = Some(start_ms as f64 / 1000.0);
+        if start_ms > 0 {
+            let seconds = (start_ms / 1000) as u64;
+            let frac = (start_ms % 1000) as f64 / 1000.0;
+            let time = Time::new(seconds, frac);
+            match format.seek(SeekMode::Coarse, SeekTo::Time { time, track_id: Some(track_id) }) {
+                Ok(seeked) => {
+                    match time_base {
+                        Some(time_base) => {
+                            let required = time_base.calc_time(seeked.required_ts);
+                            let actual = time_base.calc_time(seeked.actual_ts);
+                            let required_secs = required.seconds as f64 + required.frac;
+                            let actual_secs = actual.seconds as f64 + actual.frac;
+                            let delta_ms = (required_secs - actual_secs) * 1000.0;
+                            if delta_ms > MAX_SEEK_SKIP_MS as f64 {
+                                tracing::info!(
+                                    "QUIC transcode seek skip capped: requested_skip_ms={:.0} max_skip_ms={}",
+                                    delta_ms,
+                                    MAX_SEEK_SKIP_MS
+                                );
+                                start_secs = Some(actual_secs);
+                            }
+                        }
+                        None => start_secs = None,
+                    }
+                    decoder = symphonia::default::get_codecs()
+                        .make(&track_codec_params, &DecoderOptions::default())
+                        .map_err(|err| err.to_string())?;
+                    tracing::info!(
+                        "QUIC transcode seek start_ms={} actual_ts={} required_ts={}",
+                        start_ms,
+                        seeked.actual_ts,
+                        seeked.required_ts
+                    );
+                }
+                Err(err) => {
+                    tracing::warn!("QUIC transcode seek failed (fallback to skip): {}", err);
+                }
+            }
         }
-        let channels = channels.unwrap();
-        if !skip_initialized && start_ms > 0 {
-            let per_channel = if seek_used {
-                seek_skip_per_channel.unwrap_or(0)
-            } else {
-                (TARGET_SAMPLE_RATE as u64).saturating_mul(start_ms as u64) / 1000
+
+        Ok(Self {
+            format,
+            decoder,
+            track_id,
+            time_base,
+            bits_per_sample,
+            trim: source.trim,
+            start_secs,
+            bounds: None,
+            next_frame: 0,
+            held: Vec::new(),
+            done: false,
+        })
+    }
+
+    pub(crate) fn next_chunk(&mut self) -> Result<Option<DecodedChunk>, String> {
+        while !self.done {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::ResetRequired) => {
+                    return Err("decoder reset required".to_string());
+                }
+                Err(SymphoniaError::IoError(_)) => break,
+                Err(err) => return Err(err.to_string()),
             };
-            if per_channel > 0 {
-                skip_samples = per_channel.saturating_mul(channels as u64);
+            if packet.track_id() != self.track_id {
+                continue;
             }
-            skip_initialized = true;
-        }
-
-        if encoder.is_none() {
-            let bitrate = match selector.mode {
-                TranscodeMode::Fixed => selector
-                    .fixed_bitrate_bps
-                    .unwrap_or_else(|| quality_bitrate(selector.quality)),
-                TranscodeMode::Auto => selector
-                    .adaptive_bitrate_bps
-                    .as_ref()
-                    .map(|value| value.load(Ordering::Relaxed))
-                    .unwrap_or_else(|| quality_bitrate(selector.quality)),
-            };
-            let mut created = OpusEncoderWrapper::new(TARGET_SAMPLE_RATE, channels, bitrate)
-                .map_err(|err| err.to_string())?;
-            match &output {
-                OpusOutput::Ogg(tx) => {
-                    send_headers(&mut ogg, created.channels(), created.sample_rate(), tx)?;
+            let decoded = self.decoder.decode(&packet).map_err(|err| err.to_string())?;
+            let spec = *decoded.spec();
+            let channels = spec.channels.count();
+            if channels == 0 || channels > 8 {
+                return Err("unsupported channel count".to_string());
+            }
+            let rate = spec.rate;
+            let frames = decoded.frames() as u64;
+            if frames == 0 {
+                continue;
+            }
+
+            let first_frame = match self.time_base {
+                Some(time_base) => {
+                    let time = time_base.calc_time(packet.ts());
+                    ((time.seconds as f64 + time.frac) * rate as f64).round() as u64
                 }
-                OpusOutput::Raw(tx, meta) => {
-                    send_raw_header(
-                        meta,
-                        created.sample_rate(),
-                        created.channels(),
-                        frame_ms,
-                        bitrate,
-                        tx,
-                    )?;
+                None => self.next_frame,
+            };
+            self.next_frame = first_frame + frames;
+
+            let delay = self.trim.encoder_delay as u64;
+            let (start, end) = *self.bounds.get_or_insert_with(|| {
+                let start = self
+                    .start_secs
+                    .map(|secs| delay + (secs * rate as f64).round() as u64)
+                    .unwrap_or(0);
+                let end = self.trim.total_samples.map(|total| delay + total);
+                (start, end)
+            });
+            let lo = start.saturating_sub(first_frame).min(frames) as usize;
+            let hi = end
+                .map(|end| end.saturating_sub(first_frame).min(frames))
+                .unwrap_or(frames) as usize;
+            if end.is_some_and(|end| first_frame + frames >= end) {
+                self.done = true;
+            }
+            if hi <= lo {
+                continue;
+            }
+
+            let mut sample_buf = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+            sample_buf.copy_interleaved_ref(decoded);
+            let samples = &sample_buf.samples()[lo * channels..hi * channels];
+
+            // Without an exact length the padding can only be found at EOF,
+            // so hold that many samples back until more audio arrives.
+            let hold = if end.is_none() {
+                self.trim.encoder_padding as usize * channels
+            } else {
+                0
+            };
+            let out = if hold > 0 {
+                self.held.extend_from_slice(samples);
+                if self.held.len() <= hold {
+                    continue;
                 }
+                let ready = self.held.len() - hold;
+                self.held.drain(..ready).collect()
+            } else {
+                samples.to_vec()
+            };
+            return Ok(Some(DecodedChunk {
+                samples: out,
+                rate,
+                channels,
+                layout: spec.channels,
+                bits_per_sample: self.bits_per_sample,
+            }));
+        }
+        Ok(None)
+    }
+}
+
+/// Either a plain (mono/stereo) or a mapping family 1 surround encoder.
+enum Encoder {
+    Plain(OpusEncoderWrapper),
+    Surround(OpusMultistreamEncoderWrapper),
+}
+
+impl Encoder {
+    fn channels(&self) -> u8 {
+        match self {
+            Encoder::Plain(encoder) => encoder.channels(),
+            Encoder::Surround(encoder) => encoder.channels(),
+        }
+    }
+
+    fn sample_rate(&self) -> u32 {
+        match self {
+            Encoder::Plain(encoder) => encoder.sample_rate(),
+            Encoder::Surround(encoder) => encoder.sample_rate(),
+        }
+    }
+
+    fn set_bitrate(&mut self, bitrate_bps: u32) -> Result<(), OpusEncodeError> {
+        match self {
+            Encoder::Plain(encoder) => encoder.set_bitrate(bitrate_bps),
+            Encoder::Surround(encoder) => encoder.set_bitrate(bitrate_bps),
+        }
+    }
+
+    fn lookahead(&mut self) -> Result<u32, OpusEncodeError> {
+        match self {
+            Encoder::Plain(encoder) => encoder.lookahead(),
+            Encoder::Surround(encoder) => encoder.lookahead(),
+        }
+    }
+
+    fn encode(&mut self, pcm: &[i16], frame_size: usize) -> Result<Vec<u8>, OpusEncodeError> {
+        match self {
+            Encoder::Plain(encoder) => encoder.encode(pcm, frame_size),
+            Encoder::Surround(encoder) => encoder.encode(pcm, frame_size),
+        }
+    }
+
+    fn configure(&mut self, vbr: OpusVbrMode) -> Result<(), OpusEncodeError> {
+        match self {
+            Encoder::Plain(encoder) => {
+                encoder.set_signal(OpusSignal::Music)?;
+                encoder.set_vbr_mode(vbr)
             }
-            frame_size = (created.sample_rate() / 1000 * frame_ms) as usize;
-            current_bitrate = bitrate;
-            encoder = Some(created);
+            Encoder::Surround(encoder) => {
+                encoder.set_signal(OpusSignal::Music)?;
+                encoder.set_vbr_mode(vbr)
+            }
+        }
+    }
+
+    /// FEC costs bits, so it is only switched on once loss is seen.
+    fn set_packet_loss(&mut self, percent: u8) -> Result<(), OpusEncodeError> {
+        match self {
+            Encoder::Plain(encoder) => {
+                encoder.set_packet_loss_perc(percent)?;
+                encoder.set_inband_fec(percent > 0)
+            }
+            Encoder::Surround(encoder) => {
+                encoder.set_packet_loss_perc(percent)?;
+                encoder.set_inband_fec(percent > 0)
+            }
+        }
+    }
+
+    fn mapping(&self) -> Option<ChannelMapping> {
+        match self {
+            Encoder::Plain(_) => None,
+            Encoder::Surround(encoder) => Some(ChannelMapping {
+                streams: encoder.streams(),
+                coupled_streams: encoder.coupled_streams(),
+                mapping: encoder.mapping().to_vec(),
+            }),
+        }
+    }
+}
+
+/// Whether `chunk` reaches the encoder with its samples untouched: a 16-bit
+/// source at the output rate and layout, at normal speed and not being mixed
+/// into a crossfade.
+fn bit_transparent(chunk: &DecodedChunk, roles: &[Role], speed: f32, fading: bool) -> bool {
+    chunk.bits_per_sample.is_some_and(|bits| bits <= 16)
+        && chunk.rate == TARGET_SAMPLE_RATE
+        && speed == 1.0
+        && !fading
+        && source_roles(chunk.layout, chunk.channels) == roles
+}
+
+/// Opus mapping family 1 stream layout, as written into both headers.
+struct ChannelMapping {
+    streams: u8,
+    coupled_streams: u8,
+    mapping: Vec<u8>,
+}
+
+/// Triangular (TPDF) dither for the float to 16-bit conversion. It's off
+/// only while a 16-bit source passes through unchanged, so its samples are
+/// rounded back exactly.
+struct Dither {
+    state: u32,
+    enabled: bool,
+}
+
+impl Dither {
+    fn new(seed: u32) -> Self {
+        Self {
+            state: seed | 1,
+            enabled: true,
         }
+    }
 
-        let mut sample_buf =
-            SampleBuffer::<i16>::new(decoded.capacity() as u64, spec);
-        sample_buf.copy_interleaved_ref(decoded);
-        let samples = sample_buf.samples();
+    fn next_unit(&mut self) -> f32 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        (x >> 8) as f32 / (1u32 << 24) as f32
+    }
 
-        let input_rate = spec.rate;
-        let output_samples = if input_rate == TARGET_SAMPLE_RATE {
-            samples.to_vec()
+    fn quantize(&mut self, sample: f32) -> i16 {
+        // Sum of two uniform values gives a triangular spread of +/-1 LSB.
+        let noise = if self.enabled {
+            self.next_unit() + self.next_unit() - 1.0
         } else {
-            if resampler.is_none() {
-                resampler = Some(LinearResampler::new(input_rate, TARGET_SAMPLE_RATE, channels));
-            }
-            resampler
-                .as_mut()
-                .ok_or_else(|| "resampler missing".to_string())?
-                .process(samples)
+            0.0
         };
+        // Symphonia scales 16-bit samples by 1/32768, so they come back exact.
+        let scaled = sample * 32768.0 + noise;
+        scaled.round().clamp(i16::MIN as f32, i16::MAX as f32) as i16
+    }
+}
 
-        pcm_buffer.extend_from_slice(&output_samples);
-        if skip_samples > 0 {
-            let drop = std::cmp::min(skip_samples as usize, pcm_buffer.len());
-            if drop > 0 {
-                pcm_buffer.drain(..drop);
-                skip_samples = skip_samples.saturating_sub(drop as u64);
+/// Owns the Opus encoder for a whole stream; every source is pushed through
+/// the same encoder so stitched tracks join without a gap. PCM stays in
+/// float until the encoder, so downmixing, resampling and crossfades don't
+/// each round to 16 bits.
+struct OpusSink<'a> {
+    selector: BitrateSelector,
+    frame_ms: u32,
+    surround: bool,
+    output: OpusOutput<'a>,
+    ogg: OggWriter,
+    encoder: Option<Encoder>,
+    /// Channel layout of the encoded stream.
+    roles: Vec<Role>,
+    /// Mix matrix for the current source layout, rebuilt when it changes.
+    mix: Option<(Vec<Role>, Vec<f32>)>,
+    dither: Dither,
+    current_bitrate: u32,
+    current_loss_perc: u8,
+    frame_size: usize,
+    pre_skip: u16,
+    resampler: Option<(u32, Resampler)>,
+    speed: f32,
+    framing: AudioFraming,
+    stretch: Option<TimeStretch>,
+    pcm_buffer: Vec<f32>,
+    encoded_samples: u64,
+    valid_samples: u64,
+    packets: u32,
+    crossfade: Option<Crossfade>,
+    /// Tail of the previous track still to be mixed into the current one,
+    /// and how far into it the mix has got.
+    fade_tail: Vec<f32>,
+    fade_pos: usize,
+    cache: Option<CacheWriter>,
+}
+
+impl<'a> OpusSink<'a> {
+    fn new(selector: BitrateSelector, frame_ms: u32, output: OpusOutput<'a>, serial: u32) -> Self {
+        Self {
+            selector,
+            frame_ms,
+            surround: false,
+            output,
+            ogg: OggWriter::new(serial),
+            encoder: None,
+            roles: Vec::new(),
+            mix: None,
+            dither: Dither::new(serial),
+            current_bitrate: 0,
+            current_loss_perc: 0,
+            frame_size: 0,
+            pre_skip: 0,
+            resampler: None,
+            speed: 1.0,
+            framing: AudioFraming::Legacy,
+            stretch: None,
+            pcm_buffer: Vec::new(),
+            encoded_samples: 0,
+            valid_samples: 0,
+            packets: 0,
+            crossfade: None,
+            fade_tail: Vec::new(),
+            fade_pos: 0,
+            cache: None,
+        }
+    }
+
+    fn desired_bitrate(&self) -> u32 {
+        let base = match self.selector.mode {
+            TranscodeMode::Fixed => self
+                .selector
+                .fixed_bitrate_bps
+                .unwrap_or_else(|| quality_bitrate(self.selector.quality)),
+            TranscodeMode::Auto => self
+                .selector
+                .adaptive_bitrate_bps
+                .as_ref()
+                .map(|value| value.load(Ordering::Relaxed))
+                .unwrap_or_else(|| quality_bitrate(self.selector.quality)),
+        };
+        surround_bitrate(base, self.roles.len())
+    }
+
+    /// Picks the output layout from the first source: surround sources keep
+    /// their channels when the client asked for it and the layout maps onto
+    /// family 1, everything else becomes stereo (or stays mono).
+    fn output_roles(&self, chunk: &DecodedChunk) -> Vec<Role> {
+        let source = source_roles(chunk.layout, chunk.channels);
+        if self.surround && source.len() > 2 {
+            if let Some(target) = vorbis_roles(source.len()) {
+                if fits_layout(&source, &target) {
+                    return target;
+                }
             }
-            if skip_samples > 0 {
-                continue;
+        }
+        if source == [Role::Mono] {
+            vec![Role::Mono]
+        } else {
+            vec![Role::FrontLeft, Role::FrontRight]
+        }
+    }
+
+    fn init_encoder(&mut self, chunk: &DecodedChunk) -> Result<(), String> {
+        self.roles = self.output_roles(chunk);
+        let channels = self.roles.len() as u8;
+        let bitrate = self.desired_bitrate();
+        let mut created = if channels > 2 {
+            Encoder::Surround(
+                OpusMultistreamEncoderWrapper::new(TARGET_SAMPLE_RATE, channels, bitrate)
+                    .map_err(|err| err.to_string())?,
+            )
+        } else {
+            Encoder::Plain(
+                OpusEncoderWrapper::new(TARGET_SAMPLE_RATE, channels, bitrate)
+                    .map_err(|err| err.to_string())?,
+            )
+        };
+        // Constrained VBR keeps raw QUIC frames close to the pacing budget.
+        let vbr = match self.output {
+            OpusOutput::Ogg(_) => OpusVbrMode::Vbr,
+            OpusOutput::Raw(..) => OpusVbrMode::ConstrainedVbr,
+        };
+        created.configure(vbr).map_err(|err| err.to_string())?;
+        let lookahead = created.lookahead().map_err(|err| err.to_string())?;
+        self.pre_skip = lookahead.min(u16::MAX as u32) as u16;
+        let mapping = created.mapping();
+        let mut raw_header_bytes = None;
+        match &self.output {
+            OpusOutput::Ogg(tx) => {
+                send_headers(
+                    &mut self.ogg,
+                    created.channels(),
+                    created.sample_rate(),
+                    self.pre_skip,
+                    mapping.as_ref(),
+                    tx,
+                )?;
             }
+            OpusOutput::Raw(tx, meta) => {
+                let format = RawHeaderFormat {
+                    sample_rate: created.sample_rate(),
+                    channels: created.channels(),
+                    frame_ms: self.frame_ms,
+                    bitrate_bps: bitrate,
+                    pre_skip: self.pre_skip,
+                    mapping: mapping.as_ref(),
+                };
+                let header = raw_header(meta, &format)?;
+                tx.blocking_send(Ok(Bytes::from(self.framing.header(&header))))
+                    .map_err(|_| "stream closed".to_string())?;
+                raw_header_bytes = Some(header);
+            }
+        }
+        if let Some(header) = raw_header_bytes {
+            self.record(|cache| cache.header(&header));
+        }
+        self.frame_size = (created.sample_rate() / 1000 * self.frame_ms) as usize;
+        if self.speed != 1.0 {
+            self.stretch = Some(TimeStretch::new(TARGET_SAMPLE_RATE, channels, self.speed));
         }
+        self.current_bitrate = bitrate;
+        self.encoder = Some(created);
+        Ok(())
+    }
 
-        while pcm_buffer.len() >= frame_size * channels as usize {
-            if selector.mode == TranscodeMode::Auto {
-                let desired = selector
-                    .adaptive_bitrate_bps
-                    .as_ref()
-                    .map(|value| value.load(Ordering::Relaxed))
-                    .unwrap_or_else(|| quality_bitrate(selector.quality));
-                if desired != current_bitrate {
-                    if let Some(enc) = encoder.as_mut() {
-                        enc.set_bitrate(desired)
-                            .map_err(|err| err.to_string())?;
-                    }
-                    current_bitrate = desired;
-                }
+    fn channels(&self) -> usize {
+        self.roles.len().max(1)
+    }
+
+    /// Maps a source onto the output layout, reusing the matrix while the
+    /// source layout stays the same.
+    fn remix(&mut self, chunk: &DecodedChunk) -> Vec<f32> {
+        let source = source_roles(chunk.layout, chunk.channels);
+        if source == self.roles {
+            return chunk.samples.clone();
+        }
+        let stale = !matches!(&self.mix, Some((roles, _)) if *roles == source);
+        if stale {
+            let matrix = mix_matrix(&source, &self.roles);
+            self.mix = Some((source, matrix));
+        }
+        let (_, matrix) = self.mix.as_ref().expect("mix matrix");
+        apply_matrix(&chunk.samples, matrix, chunk.channels, self.roles.len())
+    }
+
+    fn push(&mut self, chunk: &DecodedChunk) -> Result<(), String> {
+        if self.encoder.is_none() {
+            self.init_encoder(chunk)?;
+        }
+        let channels = self.channels();
+        let fading = self.fade_pos < self.fade_tail.len();
+        self.dither.enabled = !bit_transparent(chunk, &self.roles, self.speed, fading);
+        let samples = self.remix(chunk);
+
+        let reuse = matches!(&self.resampler, Some((rate, _)) if *rate == chunk.rate);
+        if !reuse {
+            self.flush_resampler()?;
+            if chunk.rate != TARGET_SAMPLE_RATE {
+                let quality = resample_quality(self.selector.quality);
+                self.resampler = Some((
+                    chunk.rate,
+                    Resampler::new(chunk.rate, TARGET_SAMPLE_RATE, channels as u8, quality),
+                ));
             }
+        }
+        let output_samples = match self.resampler.as_mut() {
+            Some((_, resampler)) => resampler.process(&samples),
+            None => samples,
+        };
+        let output_samples = self.stretch(output_samples);
+        self.append(&output_samples)
+    }
 
-            let frame = &pcm_buffer[..frame_size * channels as usize];
-            let encoded = encoder
-                .as_mut()
-                .ok_or_else(|| "encoder not initialized".to_string())?
-                .encode(frame, frame_size)
-                .map_err(|err| err.to_string())?;
-            total_samples += frame_size as u64;
-            match &output {
-                OpusOutput::Ogg(tx) => {
-                    let pages = ogg.write_packet(&encoded, total_samples, false, false);
-                    send_pages(&pages, tx)?;
-                }
-                OpusOutput::Raw(tx, _) => {
-                    send_raw_frame(&encoded, tx)?;
-                }
+    fn stretch(&mut self, samples: Vec<f32>) -> Vec<f32> {
+        match self.stretch.as_mut() {
+            Some(stretch) => stretch.process(&samples),
+            None => samples,
+        }
+    }
+
+    fn append(&mut self, samples: &[f32]) -> Result<(), String> {
+        let channels = self.channels();
+        let mixed = self.mix_fade(samples);
+        let fresh = &samples[mixed..];
+        self.valid_samples += (fresh.len() / channels) as u64;
+        self.pcm_buffer.extend_from_slice(fresh);
+        self.encode_ready()
+    }
+
+    /// Drains the resampler's filter delay when the source rate changes or
+    /// the stream ends.
+    fn flush_resampler(&mut self) -> Result<(), String> {
+        match self.resampler.take() {
+            Some((_, mut resampler)) => {
+                let tail = resampler.flush();
+                let tail = self.stretch(tail);
+                self.append(&tail)
             }
-            pcm_buffer.drain(..frame_size * channels as usize);
-            packet_counter = packet_counter.wrapping_add(1);
+            None => Ok(()),
         }
     }
 
-    if encoder.is_none() {
-        return Err("encoder not initialized".to_string());
+    /// Drains the time stretcher at the end of a track it can't carry on
+    /// into (a crossfade or the end of the stream).
+    fn flush_stretch(&mut self) -> Result<(), String> {
+        match self.stretch.as_mut() {
+            Some(stretch) => {
+                let tail = stretch.flush();
+                self.append(&tail)
+            }
+            None => Ok(()),
+        }
     }
 
-    if skip_samples > 0 && !pcm_buffer.is_empty() {
-        let drop = std::cmp::min(skip_samples as usize, pcm_buffer.len());
-        if drop > 0 {
-            pcm_buffer.drain(..drop);
-            skip_samples = skip_samples.saturating_sub(drop as u64);
+    /// Encodes whole frames, keeping back enough samples to crossfade into
+    /// the next track.
+    fn encode_ready(&mut self) -> Result<(), String> {
+        let channels = self.channels();
+        let hold = self
+            .crossfade
+            .map(|crossfade| crossfade.samples() * channels)
+            .unwrap_or(0);
+        while self.pcm_buffer.len() >= self.frame_size * channels + hold {
+            self.encode_frame(false)?;
         }
+        Ok(())
+    }
+
+    /// Moves the held-back tail aside so the next track can be mixed over
+    /// it. Returns the overlap length in samples per channel.
+    fn begin_crossfade(&mut self) -> Result<usize, String> {
+        self.flush_fade();
+        let Some(crossfade) = self.crossfade else { return Ok(0) };
+        // The fade starts wherever the previous track ends, so its filter
+        // tail has to be out before the overlap is cut.
+        self.flush_resampler()?;
+        self.flush_stretch()?;
+        let channels = self.channels();
+        let tail_len = (crossfade.samples() * channels).min(self.pcm_buffer.len());
+        let tail_len = tail_len - tail_len % channels;
+        let split = self.pcm_buffer.len() - tail_len;
+        self.fade_tail = self.pcm_buffer.split_off(split);
+        self.fade_pos = 0;
+        Ok(tail_len / channels)
     }
 
-    if !pcm_buffer.is_empty() {
-        let channels = encoder.as_ref().unwrap().channels() as usize;
-        let needed = frame_size * channels;
-        if pcm_buffer.len() < needed {
-            pcm_buffer.resize(needed, 0);
+    /// Where the next pushed source starts, counting output the resampler
+    /// and time stretcher still owe for the previous one.
+    fn boundary_offset(&self) -> u64 {
+        let owed = self
+            .resampler
+            .as_ref()
+            .map(|(_, resampler)| resampler.pending_output())
+            .unwrap_or(0);
+        let owed = match self.stretch.as_ref() {
+            Some(stretch) => stretch.pending_output(owed),
+            None => owed,
+        };
+        self.valid_samples + owed
+    }
+
+    /// Mixes `incoming` over the pending fade tail and appends the result.
+    /// Returns how many of `incoming`'s samples were consumed.
+    fn mix_fade(&mut self, incoming: &[f32]) -> usize {
+        let Some(crossfade) = self.crossfade else { return 0 };
+        let remaining = self.fade_tail.len() - self.fade_pos;
+        if remaining == 0 {
+            return 0;
+        }
+        let channels = self.channels();
+        let total_frames = (self.fade_tail.len() / channels).max(1) as f32;
+        let take = remaining.min(incoming.len());
+        for (offset, sample) in incoming[..take].iter().enumerate() {
+            let index = self.fade_pos + offset;
+            let t = (index / channels) as f32 / total_frames;
+            let (out_gain, in_gain) = crossfade.curve.gains(t);
+            self.pcm_buffer
+                .push(self.fade_tail[index] * out_gain + *sample * in_gain);
         }
-        let encoded = encoder
+        self.fade_pos += take;
+        take
+    }
+
+    /// Fades out whatever tail the next track was too short to cover.
+    fn flush_fade(&mut self) {
+        let remaining = self.fade_tail.len() - self.fade_pos;
+        if remaining > 0 {
+            let silence = vec![0.0f32; remaining];
+            self.mix_fade(&silence);
+        }
+        self.fade_tail.clear();
+        self.fade_pos = 0;
+    }
+
+    fn encode_frame(&mut self, eos: bool) -> Result<(), String> {
+        if self.selector.mode == TranscodeMode::Auto {
+            let desired = self.desired_bitrate();
+            if desired != self.current_bitrate {
+                if let Some(encoder) = self.encoder.as_mut() {
+                    encoder.set_bitrate(desired).map_err(|err| err.to_string())?;
+                }
+                self.current_bitrate = desired;
+                // Only constant-rate encodes are worth replaying.
+                self.cache = None;
+            }
+        }
+        if let Some(loss) = self.selector.packet_loss_perc.as_ref() {
+            let percent = loss.load(Ordering::Relaxed).min(100) as u8;
+            if percent != self.current_loss_perc {
+                if let Some(encoder) = self.encoder.as_mut() {
+                    encoder.set_packet_loss(percent).map_err(|err| err.to_string())?;
+                }
+                self.current_loss_perc = percent;
+                // FEC packets are tuned for this listener's link.
+                self.cache = None;
+            }
+        }
+
+        let frame_len = self.frame_size * self.channels();
+        let dither = &mut self.dither;
+        let pcm: Vec<i16> = self.pcm_buffer[..frame_len]
+            .iter()
+            .map(|sample| dither.quantize(*sample))
+            .collect();
+        let encoded = self
+            .encoder
             .as_mut()
-            .unwrap()
-            .encode(&pcm_buffer, frame_size)
+            .ok_or_else(|| "encoder not initialized".to_string())?
+            .encode(&pcm, self.frame_size)
             .map_err(|err| err.to_string())?;
-        total_samples += frame_size as u64;
-        match &output {
+        let sample_pos = self.encoded_samples;
+        self.encoded_samples += self.frame_size as u64;
+        match &self.output {
             OpusOutput::Ogg(tx) => {
-                let pages = ogg.write_packet(&encoded, total_samples, false, true);
+                let granule = if eos {
+                    self.pre_skip as u64 + self.valid_samples
+                } else {
+                    self.encoded_samples
+                };
+                let pages = self.ogg.write_packet(&encoded, granule, false, eos);
                 send_pages(&pages, tx)?;
             }
             OpusOutput::Raw(tx, _) => {
-                send_raw_frame(&encoded, tx)?;
-                send_raw_eos(tx)?;
+                send_raw_frame(self.framing, &encoded, tx)?;
             }
         }
-    } else {
-        match &output {
-            OpusOutput::Ogg(tx) => {
-                let pages = ogg.write_packet(&[], total_samples, false, true);
-                send_pages(&pages, tx)?;
+        self.record(|cache| cache.packet(sample_pos, &encoded));
+        self.pcm_buffer.drain(..frame_len);
+        self.packets = self.packets.wrapping_add(1);
+        Ok(())
+    }
+
+    /// Pads the tail so the last valid sample survives the pre-skip shift,
+    /// then closes the stream. Returns `(pre_skip, valid_samples)`.
+    fn finish(mut self) -> Result<(u16, u64), String> {
+        if self.encoder.is_none() {
+            return Err("encoder not initialized".to_string());
+        }
+        self.flush_resampler()?;
+        self.flush_stretch()?;
+        self.flush_fade();
+        let channels = self.channels();
+        let needed = self.pre_skip as u64 + self.valid_samples;
+        let covered = self.encoded_samples + (self.pcm_buffer.len() / channels) as u64;
+        if covered < needed {
+            let extra = (needed - covered) as usize * channels;
+            self.pcm_buffer.resize(self.pcm_buffer.len() + extra, 0.0);
+        }
+        let frame_len = self.frame_size * channels;
+        if !self.pcm_buffer.is_empty() {
+            let padded = self.pcm_buffer.len().div_ceil(frame_len) * frame_len;
+            self.pcm_buffer.resize(padded, 0.0);
+            while self.pcm_buffer.len() > frame_len {
+                self.encode_frame(false)?;
             }
-            OpusOutput::Raw(tx, _) => {
-                send_raw_eos(tx)?;
+            self.encode_frame(true)?;
+            if let OpusOutput::Raw(tx, _) = &self.output {
+                send_raw_eos(self.framing, tx)?;
+            }
+        } else {
+            match &self.output {
+                OpusOutput::Ogg(tx) => {
+                    let granule = self.pre_skip as u64 + self.valid_samples;
+                    let pages = self.ogg.write_packet(&[], granule, false, true);
+                    send_pages(&pages, tx)?;
+                }
+                OpusOutput::Raw(tx, _) => {
+                    send_raw_eos(self.framing, tx)?;
+                }
             }
         }
+        if let Some(cache) = self.cache.take() {
+            if let Err(err) = cache.finish(self.pre_skip, self.valid_samples) {
+                tracing::warn!("transcode cache write failed: {}", err);
+            }
+        }
+        Ok((self.pre_skip, self.valid_samples))
     }
 
-    tracing::info!(
-        "QUIC transcode done start_ms={} frames={} total_samples={}",
-        start_ms,
-        packet_counter,
-        total_samples
-    );
+    /// Writes to the cache entry, abandoning it on the first error.
+    fn record(&mut self, write: impl FnOnce(&mut CacheWriter) -> std::io::Result<()>) {
+        if let Some(cache) = self.cache.as_mut() {
+            if let Err(err) = write(cache) {
+                tracing::warn!("transcode cache write failed: {}", err);
+                self.cache = None;
+            }
+        }
+    }
+}
 
-    Ok(())
+/// Higher bitrates make resampling artefacts audible, so they get the
+/// longer filter.
+fn resample_quality(quality: TranscodeQuality) -> ResampleQuality {
+    match quality {
+        TranscodeQuality::High => ResampleQuality::Best,
+        TranscodeQuality::Medium => ResampleQuality::Balanced,
+        TranscodeQuality::Low => ResampleQuality::Fast,
+    }
+}
+
+/// Surround streams get a per-channel share on top of the stereo rate so
+/// the extra channels don't starve the front pair.
+fn surround_bitrate(stereo_bps: u32, channels: usize) -> u32 {
+    if channels <= 2 {
+        return stereo_bps;
+    }
+    stereo_bps / 2 * channels as u32
 }
 
 fn send_headers(
     ogg: &mut OggWriter,
     channels: u8,
     sample_rate: u32,
+    pre_skip: u16,
+    mapping: Option<&ChannelMapping>,
     tx: &tokio::sync::mpsc::Sender<Result<Bytes, std::io::Error>>,
 ) -> Result<(), String> {
-    let head = opus_head_packet(channels, sample_rate);
+    let head = opus_head_packet(channels, sample_rate, pre_skip, mapping);
     let tags = opus_tags_packet("phonolite");
     let head_pages = ogg.write_packet(&head, 0, true, false);
     send_pages(&head_pages, tx)?;
@@ -382,14 +1166,19 @@ fn send_pages(
     Ok(())
 }
 
-fn send_raw_header(
-    meta: &RawOpusMeta,
+struct RawHeaderFormat<'a> {
     sample_rate: u32,
     channels: u8,
     frame_ms: u32,
     bitrate_bps: u32,
-    tx: &tokio::sync::mpsc::Sender<Result<Bytes, std::io::Error>>,
-) -> Result<(), String> {
+    pre_skip: u16,
+    mapping: Option<&'a ChannelMapping>,
+}
+
+/// Version 1 for mono/stereo. Version 2 appends the family 1 mapping
+/// (family, streams, coupled streams, one index per channel) after the
+/// strings.
+fn raw_header(meta: &RawOpusMeta, format: &RawHeaderFormat<'_>) -> Result<Vec<u8>, String> {
     let track_id = meta.track_id.as_bytes();
     let title = meta.title.as_bytes();
     let artist = meta.artist.as_bytes();
@@ -403,22 +1192,26 @@ fn send_raw_header(
         + artist.len()
         + album.len()
         + codec.len()
-        + container.len();
+        + container.len()
+        + format
+            .mapping
+            .map(|mapping| 3 + mapping.mapping.len())
+            .unwrap_or(0);
     if header_len > u16::MAX as usize {
         return Err("raw opus header too large".to_string());
     }
 
     let mut buf = Vec::with_capacity(header_len);
     buf.extend_from_slice(b"OPUSR01\0");
-    buf.push(1);
+    buf.push(if format.mapping.is_some() { 2 } else { 1 });
     buf.push(0);
     buf.extend_from_slice(&(header_len as u16).to_le_bytes());
-    buf.extend_from_slice(&sample_rate.to_le_bytes());
-    buf.push(channels);
-    buf.push(frame_ms as u8);
-    buf.extend_from_slice(&bitrate_bps.to_le_bytes());
+    buf.extend_from_slice(&format.sample_rate.to_le_bytes());
+    buf.push(format.channels);
+    buf.push(format.frame_ms as u8);
+    buf.extend_from_slice(&format.bitrate_bps.to_le_bytes());
     buf.extend_from_slice(&meta.duration_ms.to_le_bytes());
-    buf.extend_from_slice(&0u16.to_le_bytes()); // pre_skip
+    buf.extend_from_slice(&format.pre_skip.to_le_bytes());
     buf.extend_from_slice(&(track_id.len() as u16).to_le_bytes());
     buf.extend_from_slice(&(title.len() as u16).to_le_bytes());
     buf.extend_from_slice(&(artist.len() as u16).to_le_bytes());
@@ -431,33 +1224,39 @@ fn send_raw_header(
     buf.extend_from_slice(album);
     buf.extend_from_slice(codec);
     buf.extend_from_slice(container);
+    if let Some(mapping) = format.mapping {
+        buf.push(1);
+        buf.push(mapping.streams);
+        buf.push(mapping.coupled_streams);
+        buf.extend_from_slice(&mapping.mapping);
+    }
+    Ok(buf)
+}
 
-    tx.blocking_send(Ok(Bytes::from(buf)))
-        .map_err(|_| "stream closed".to_string())?;
-    Ok(())
+/// Rewrites the pre-skip of a raw stream header, for replays that start
+/// on a later packet.
+pub(crate) fn patch_raw_pre_skip(header: &mut [u8], pre_skip: u16) {
+    if let Some(field) = header.get_mut(RAW_HEADER_PRE_SKIP_OFFSET..RAW_HEADER_PRE_SKIP_OFFSET + 2) {
+        field.copy_from_slice(&pre_skip.to_le_bytes());
+    }
 }
 
-fn send_raw_frame(
+pub(crate) fn send_raw_frame(
+    framing: AudioFraming,
     data: &[u8],
     tx: &tokio::sync::mpsc::Sender<Result<Bytes, std::io::Error>>,
 ) -> Result<(), String> {
-    let len = data.len();
-    if len > u16::MAX as usize {
-        return Err("opus frame too large".to_string());
-    }
-    let mut buf = Vec::with_capacity(2 + len);
-    buf.extend_from_slice(&(len as u16).to_le_bytes());
-    buf.extend_from_slice(data);
+    let buf = framing.packet(data)?;
     tx.blocking_send(Ok(Bytes::from(buf)))
         .map_err(|_| "stream closed".to_string())?;
     Ok(())
 }
 
-fn send_raw_eos(
+pub(crate) fn send_raw_eos(
+    framing: AudioFraming,
     tx: &tokio::sync::mpsc::Sender<Result<Bytes, std::io::Error>>,
 ) -> Result<(), String> {
-    let buf = (0u16).to_le_bytes().to_vec();
-    tx.blocking_send(Ok(Bytes::from(buf)))
+    tx.blocking_send(Ok(Bytes::from(framing.end())))
         .map_err(|_| "stream closed".to_string())?;
     Ok(())
 }
@@ -483,15 +1282,28 @@ fn serial_from_path(path: &Path) -> u32 {
     u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
 }
 
-fn opus_head_packet(channels: u8, sample_rate: u32) -> Vec<u8> {
-    let mut packet = Vec::with_capacity(19);
+fn opus_head_packet(
+    channels: u8,
+    sample_rate: u32,
+    pre_skip: u16,
+    mapping: Option<&ChannelMapping>,
+) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(21 + channels as usize);
     packet.extend_from_slice(b"OpusHead");
     packet.push(1);
     packet.push(channels);
-    packet.extend_from_slice(&0u16.to_le_bytes());
+    packet.extend_from_slice(&pre_skip.to_le_bytes());
     packet.extend_from_slice(&sample_rate.to_le_bytes());
     packet.extend_from_slice(&0u16.to_le_bytes());
-    packet.push(0);
+    match mapping {
+        Some(mapping) => {
+            packet.push(1);
+            packet.push(mapping.streams);
+            packet.push(mapping.coupled_streams);
+            packet.extend_from_slice(&mapping.mapping);
+        }
+        None => packet.push(0),
+    }
     packet
 }
 
@@ -616,55 +1428,32 @@ fn ogg_crc32(data: &[u8]) -> u32 {
     crc
 }
 
-struct LinearResampler {
-    input_rate: u32,
-    output_rate: u32,
-    channels: u8,
-    buffer: Vec<i16>,
-    position: f64,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-impl LinearResampler {
-    fn new(input_rate: u32, output_rate: u32, channels: u8) -> Self {
-        Self {
-            input_rate,
-            output_rate,
-            channels,
-            buffer: Vec::new(),
-            position: 0.0,
+    fn stereo_chunk(rate: u32, bits: Option<u32>) -> DecodedChunk {
+        DecodedChunk {
+            samples: vec![0.25; 960 * 2],
+            rate,
+            channels: 2,
+            layout: Channels::FRONT_LEFT | Channels::FRONT_RIGHT,
+            bits_per_sample: bits,
         }
     }
 
-    fn process(&mut self, input: &[i16]) -> Vec<i16> {
-        self.buffer.extend_from_slice(input);
-        let channels = self.channels as usize;
-        let in_frames = self.buffer.len() / channels;
-        if in_frames < 2 {
-            return Vec::new();
-        }
-
-        let step = self.input_rate as f64 / self.output_rate as f64;
-        let mut out = Vec::new();
-
-        while self.position + 1.0 < in_frames as f64 {
-            let idx = self.position.floor() as usize;
-            let frac = self.position - idx as f64;
-            for ch in 0..channels {
-                let s0 = self.buffer[idx * channels + ch] as f64;
-                let s1 = self.buffer[(idx + 1) * channels + ch] as f64;
-                let sample = s0 + (s1 - s0) * frac;
-                out.push(sample.round().clamp(i16::MIN as f64, i16::MAX as f64) as i16);
-            }
-            self.position += step;
-        }
-
-        let drop_frames = (self.position.floor() - 1.0).max(0.0) as usize;
-        if drop_frames > 0 {
-            let drop_samples = drop_frames * channels;
-            self.buffer.drain(0..drop_samples);
-            self.position -= drop_frames as f64;
-        }
+    #[test]
+    fn only_untouched_16_bit_sources_skip_dither() {
+        let stereo = [Role::FrontLeft, Role::FrontRight];
+        assert!(bit_transparent(&stereo_chunk(48_000, Some(16)), &stereo, 1.0, false));
+        // CD audio is resampled to 48 kHz, so it needs dither like 24-bit.
+        assert!(!bit_transparent(&stereo_chunk(44_100, Some(16)), &stereo, 1.0, false));
+        assert!(!bit_transparent(&stereo_chunk(48_000, Some(24)), &stereo, 1.0, false));
+        assert!(!bit_transparent(&stereo_chunk(48_000, None), &stereo, 1.0, false));
 
-        out
+        let chunk = stereo_chunk(48_000, Some(16));
+        assert!(!bit_transparent(&chunk, &[Role::Mono], 1.0, false));
+        assert!(!bit_transparent(&chunk, &stereo, 1.5, false));
+        assert!(!bit_transparent(&chunk, &stereo, 1.0, true));
     }
 }
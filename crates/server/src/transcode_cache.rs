@@ -0,0 +1,581 @@
+//! Size-bounded LRU cache of encoded raw Opus streams.
+//!
+//! Each entry holds the raw stream header and every packet with the encoder
+//! sample position it starts at, so a seek can start replaying mid-file.
+//! Entries record the source file's size and modification time and are
+//! dropped when either changes. Only fixed-bitrate encodes are stored;
+//! adaptive ones follow a single listener's link. Replays read packets from
+//! the entry as they are sent rather than loading it whole.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use parking_lot::Mutex;
+use quic_wire::AudioFraming;
+
+use crate::transcode::{
+    patch_raw_pre_skip, send_raw_eos, send_raw_frame, StreamEvent, StreamEventSender,
+    TranscodeMode, TranscodeQuality,
+};
+
+const ENTRY_MAGIC: &[u8; 8] = b"PHTC0001";
+const TRAILER_MAGIC: &[u8; 8] = b"PHTCEND\0";
+const ENTRY_EXT: &str = "opc";
+/// Packets decoded ahead of a seek point so the decoder has converged by
+/// the time audio is heard (RFC 7845 recommends 80 ms).
+const SEEK_PREROLL_SAMPLES: u64 = 3840;
+const SAMPLES_PER_MS: u64 = 48;
+const TRAILER_LEN: usize = 4 + 2 + 8 + 8;
+
+#[derive(Clone, Copy, Debug)]
+pub struct CacheProfile {
+    pub mode: TranscodeMode,
+    pub quality: TranscodeQuality,
+    pub frame_ms: u32,
+    pub surround: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SourceFingerprint {
+    pub len: u64,
+    pub modified_ns: u64,
+}
+
+impl SourceFingerprint {
+    pub fn of(path: &Path) -> Option<Self> {
+        let meta = fs::metadata(path).ok()?;
+        let modified = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(Self {
+            len: meta.len(),
+            modified_ns: modified.as_nanos() as u64,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct TranscodeCache {
+    inner: Option<Arc<CacheInner>>,
+}
+
+struct CacheInner {
+    root: PathBuf,
+    max_bytes: u64,
+    entries: Mutex<CacheIndex>,
+    next_tmp: AtomicU64,
+}
+
+#[derive(Default)]
+struct CacheIndex {
+    entries: HashMap<String, CacheEntry>,
+    total_bytes: u64,
+    clock: u64,
+}
+
+struct CacheEntry {
+    size: u64,
+    last_used: u64,
+}
+
+impl TranscodeCache {
+    pub fn disabled() -> Self {
+        Self { inner: None }
+    }
+
+    /// Opens the cache directory, indexing entries left by earlier runs in
+    /// modification-time order and removing unfinished writes.
+    pub fn open(root: PathBuf, max_bytes: u64) -> std::io::Result<Self> {
+        fs::create_dir_all(&root)?;
+        let mut found = Vec::new();
+        for entry in fs::read_dir(&root)? {
+            let entry = entry?;
+            let path = entry.path();
+            let meta = entry.metadata()?;
+            if !meta.is_file() {
+                continue;
+            }
+            if path.extension().and_then(|ext| ext.to_str()) != Some(ENTRY_EXT) {
+                let _ = fs::remove_file(&path);
+                continue;
+            }
+            let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            let modified = meta.modified().unwrap_or(UNIX_EPOCH);
+            found.push((modified, name.to_string(), meta.len()));
+        }
+        found.sort();
+
+        let mut index = CacheIndex::default();
+        for (_, name, size) in found {
+            index.clock += 1;
+            index.total_bytes += size;
+            index.entries.insert(
+                name,
+                CacheEntry {
+                    size,
+                    last_used: index.clock,
+                },
+            );
+        }
+        let inner = CacheInner {
+            root,
+            max_bytes,
+            entries: Mutex::new(index),
+            next_tmp: AtomicU64::new(0),
+        };
+        inner.evict(0);
+        Ok(Self {
+            inner: Some(Arc::new(inner)),
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.inner.is_some()
+    }
+
+    pub fn lookup(
+        &self,
+        track_id: &str,
+        profile: &CacheProfile,
+        fingerprint: SourceFingerprint,
+    ) -> Option<CachedStream> {
+        let inner = self.inner.as_ref()?;
+        let name = entry_name(track_id, profile);
+        if !inner.entries.lock().entries.contains_key(&name) {
+            return None;
+        }
+        let path = inner.entry_path(&name);
+        match CachedStream::open(&path) {
+            Some((stored, stream)) if stored == fingerprint => {
+                inner.touch(&name, &path);
+                Some(stream)
+            }
+            _ => {
+                tracing::info!("transcode cache invalidated track={}", track_id);
+                inner.remove(&name);
+                None
+            }
+        }
+    }
+
+    pub fn writer(
+        &self,
+        track_id: &str,
+        profile: &CacheProfile,
+        fingerprint: SourceFingerprint,
+    ) -> Option<CacheWriter> {
+        let inner = self.inner.as_ref()?;
+        let name = entry_name(track_id, profile);
+        let tmp_id = inner.next_tmp.fetch_add(1, Ordering::Relaxed);
+        let tmp_path = inner.root.join(format!("{}.{}.tmp", name, tmp_id));
+        let file = match File::create(&tmp_path) {
+            Ok(file) => file,
+            Err(err) => {
+                tracing::warn!("transcode cache write failed: {}", err);
+                return None;
+            }
+        };
+        let mut out = BufWriter::new(file);
+        let mut prefix = Vec::with_capacity(24);
+        prefix.extend_from_slice(ENTRY_MAGIC);
+        prefix.extend_from_slice(&fingerprint.len.to_le_bytes());
+        prefix.extend_from_slice(&fingerprint.modified_ns.to_le_bytes());
+        if out.write_all(&prefix).is_err() {
+            let _ = fs::remove_file(&tmp_path);
+            return None;
+        }
+        Some(CacheWriter {
+            inner: Arc::clone(inner),
+            name,
+            tmp_path,
+            out: Some(out),
+            packets: 0,
+        })
+    }
+}
+
+impl CacheInner {
+    fn entry_path(&self, name: &str) -> PathBuf {
+        self.root.join(format!("{}.{}", name, ENTRY_EXT))
+    }
+
+    fn touch(&self, name: &str, path: &Path) {
+        {
+            let mut index = self.entries.lock();
+            index.clock += 1;
+            let clock = index.clock;
+            if let Some(entry) = index.entries.get_mut(name) {
+                entry.last_used = clock;
+            }
+        }
+        // Keeps the LRU order across restarts.
+        if let Ok(file) = File::options().write(true).open(path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+    }
+
+    fn remove(&self, name: &str) {
+        let mut index = self.entries.lock();
+        if let Some(entry) = index.entries.remove(name) {
+            index.total_bytes -= entry.size;
+        }
+        let _ = fs::remove_file(self.entry_path(name));
+    }
+
+    fn insert(&self, name: String, size: u64) {
+        {
+            let mut index = self.entries.lock();
+            index.clock += 1;
+            let last_used = index.clock;
+            if let Some(old) = index.entries.insert(name, CacheEntry { size, last_used }) {
+                index.total_bytes -= old.size;
+            }
+            index.total_bytes += size;
+        }
+        self.evict(0);
+    }
+
+    /// Drops least recently used entries until `reserve` more bytes fit.
+    fn evict(&self, reserve: u64) {
+        let mut index = self.entries.lock();
+        while index.total_bytes + reserve > self.max_bytes {
+            let oldest = index
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(name, _)| name.clone());
+            let Some(name) = oldest else { break };
+            if let Some(entry) = index.entries.remove(&name) {
+                index.total_bytes -= entry.size;
+            }
+            let _ = fs::remove_file(self.entry_path(&name));
+        }
+    }
+}
+
+fn entry_name(track_id: &str, profile: &CacheProfile) -> String {
+    let key = format!(
+        "{}|{:?}|{:?}|{}|{}",
+        track_id, profile.mode, profile.quality, profile.frame_ms, profile.surround
+    );
+    blake3::hash(key.as_bytes()).to_hex().to_string()
+}
+
+/// Records one encode as it happens. The entry only becomes visible once
+/// `finish` succeeds; dropping the writer early discards it.
+pub struct CacheWriter {
+    inner: Arc<CacheInner>,
+    name: String,
+    tmp_path: PathBuf,
+    out: Option<BufWriter<File>>,
+    packets: u32,
+}
+
+impl CacheWriter {
+    pub fn header(&mut self, header: &[u8]) -> std::io::Result<()> {
+        let out = self.out.as_mut().ok_or_else(closed)?;
+        out.write_all(&(header.len() as u16).to_le_bytes())?;
+        out.write_all(header)
+    }
+
+    /// `sample_pos` is the encoder position (pre-skip included) of the
+    /// packet's first sample.
+    pub fn packet(&mut self, sample_pos: u64, data: &[u8]) -> std::io::Result<()> {
+        let out = self.out.as_mut().ok_or_else(closed)?;
+        out.write_all(&sample_pos.to_le_bytes())?;
+        out.write_all(&(data.len() as u16).to_le_bytes())?;
+        out.write_all(data)?;
+        self.packets += 1;
+        Ok(())
+    }
+
+    pub fn finish(mut self, pre_skip: u16, valid_samples: u64) -> std::io::Result<()> {
+        let mut out = self.out.take().ok_or_else(closed)?;
+        out.write_all(&self.packets.to_le_bytes())?;
+        out.write_all(&pre_skip.to_le_bytes())?;
+        out.write_all(&valid_samples.to_le_bytes())?;
+        out.write_all(TRAILER_MAGIC)?;
+        let file = out.into_inner().map_err(|err| err.into_error())?;
+        file.sync_all()?;
+        let size = file.metadata()?.len();
+        drop(file);
+        if size > self.inner.max_bytes {
+            return Ok(());
+        }
+        self.inner.evict(size);
+        fs::rename(&self.tmp_path, self.inner.entry_path(&self.name))?;
+        self.inner.insert(self.name.clone(), size);
+        Ok(())
+    }
+}
+
+impl Drop for CacheWriter {
+    fn drop(&mut self) {
+        self.out = None;
+        let _ = fs::remove_file(&self.tmp_path);
+    }
+}
+
+fn closed() -> std::io::Error {
+    std::io::Error::other("cache writer closed")
+}
+
+pub struct CachedStream {
+    file: BufReader<File>,
+    header: Vec<u8>,
+    /// Offsets of the first packet and of the trailer.
+    packets_start: u64,
+    packets_end: u64,
+    packet_count: u32,
+    pre_skip: u16,
+    valid_samples: u64,
+}
+
+/// Where a replay starting at some position begins in the entry.
+#[derive(Debug, PartialEq, Eq)]
+struct SeekPoint {
+    index: u32,
+    offset: u64,
+    pre_skip: u16,
+    valid_samples: u64,
+}
+
+impl CachedStream {
+    /// Reads an entry's header and trailer; packets stay on disk.
+    fn open(path: &Path) -> Option<(SourceFingerprint, CachedStream)> {
+        let mut file = BufReader::new(File::open(path).ok()?);
+        let len = file.get_ref().metadata().ok()?.len();
+        if len < (26 + TRAILER_LEN) as u64 {
+            return None;
+        }
+        let mut prefix = [0u8; 26];
+        file.read_exact(&mut prefix).ok()?;
+        if !prefix.starts_with(ENTRY_MAGIC) {
+            return None;
+        }
+        let fingerprint = SourceFingerprint {
+            len: u64::from_le_bytes(prefix[8..16].try_into().ok()?),
+            modified_ns: u64::from_le_bytes(prefix[16..24].try_into().ok()?),
+        };
+        let header_len = u16::from_le_bytes(prefix[24..26].try_into().ok()?) as u64;
+        let packets_start = 26 + header_len;
+        let packets_end = len - TRAILER_LEN as u64;
+        if packets_start > packets_end {
+            return None;
+        }
+        let mut header = vec![0u8; header_len as usize];
+        file.read_exact(&mut header).ok()?;
+
+        let mut trailer = [0u8; TRAILER_LEN];
+        file.seek(SeekFrom::Start(packets_end)).ok()?;
+        file.read_exact(&mut trailer).ok()?;
+        if &trailer[14..] != TRAILER_MAGIC {
+            return None;
+        }
+        let stream = CachedStream {
+            file,
+            header,
+            packets_start,
+            packets_end,
+            packet_count: u32::from_le_bytes(trailer[0..4].try_into().ok()?),
+            pre_skip: u16::from_le_bytes(trailer[4..6].try_into().ok()?),
+            valid_samples: u64::from_le_bytes(trailer[6..14].try_into().ok()?),
+        };
+        Some((fingerprint, stream))
+    }
+
+    /// Reads the next packet's sample position and length.
+    fn packet_header(&mut self) -> std::io::Result<(u64, u16)> {
+        let mut header = [0u8; 10];
+        self.file.read_exact(&mut header)?;
+        let sample_pos = u64::from_le_bytes(header[0..8].try_into().unwrap());
+        let len = u16::from_le_bytes(header[8..10].try_into().unwrap());
+        Ok((sample_pos, len))
+    }
+
+    /// The first packet to send for a start at `start_ms`, and the
+    /// pre-skip that lands playback exactly on it. Walks the packet
+    /// headers without reading the audio.
+    fn seek_point(&mut self, start_ms: u32) -> std::io::Result<SeekPoint> {
+        let skipped = (start_ms as u64 * SAMPLES_PER_MS).min(self.valid_samples);
+        let target = self.pre_skip as u64 + skipped;
+        let wanted = target.saturating_sub(SEEK_PREROLL_SAMPLES);
+        let mut point = (0, self.packets_start, 0);
+        let mut offset = self.file.seek(SeekFrom::Start(self.packets_start))?;
+        for index in 0..self.packet_count {
+            let (sample_pos, len) = self.packet_header()?;
+            if sample_pos > wanted && index > 0 {
+                break;
+            }
+            point = (index, offset, sample_pos);
+            self.file.seek_relative(len as i64)?;
+            offset += 10 + len as u64;
+        }
+        let (index, offset, first_pos) = point;
+        Ok(SeekPoint {
+            index,
+            offset,
+            pre_skip: (target - first_pos).min(u16::MAX as u64) as u16,
+            valid_samples: self.valid_samples - skipped,
+        })
+    }
+
+    /// Sends the stream exactly as a fresh encode starting at `start_ms`
+    /// would, ending with the usual `End` event.
+    pub fn replay(
+        mut self,
+        start_ms: u32,
+        framing: AudioFraming,
+        tx: &tokio::sync::mpsc::Sender<Result<Bytes, std::io::Error>>,
+        events: Option<&StreamEventSender>,
+    ) -> Result<(), String> {
+        let corrupt = |err: std::io::Error| format!("transcode cache entry unreadable: {}", err);
+        let point = self.seek_point(start_ms).map_err(corrupt)?;
+        let mut header = self.header.clone();
+        patch_raw_pre_skip(&mut header, point.pre_skip);
+        tx.blocking_send(Ok(Bytes::from(framing.header(&header))))
+            .map_err(|_| "stream closed".to_string())?;
+        self.file
+            .seek(SeekFrom::Start(point.offset))
+            .map_err(corrupt)?;
+        let mut offset = point.offset;
+        while offset < self.packets_end {
+            let (_, len) = self.packet_header().map_err(corrupt)?;
+            let mut packet = vec![0u8; len as usize];
+            self.file.read_exact(&mut packet).map_err(corrupt)?;
+            send_raw_frame(framing, &packet, tx)?;
+            offset += 10 + len as u64;
+        }
+        send_raw_eos(framing, tx)?;
+        if let Some(events) = events {
+            let _ = events.send(StreamEvent::End {
+                pre_skip: point.pre_skip,
+                valid_samples: point.valid_samples,
+            });
+        }
+        tracing::info!(
+            "QUIC transcode cache hit start_ms={} frames={} pre_skip={} valid_samples={}",
+            start_ms,
+            self.packet_count - point.index,
+            point.pre_skip,
+            point.valid_samples
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: u64 = 960;
+
+    fn temp_cache(max_bytes: u64) -> (PathBuf, TranscodeCache) {
+        let root = std::env::temp_dir().join(format!("phonolite-cache-{}", uuid::Uuid::new_v4()));
+        let cache = TranscodeCache::open(root.clone(), max_bytes).unwrap();
+        (root, cache)
+    }
+
+    fn profile() -> CacheProfile {
+        CacheProfile {
+            mode: TranscodeMode::Fixed,
+            quality: TranscodeQuality::High,
+            frame_ms: 20,
+            surround: false,
+        }
+    }
+
+    fn fingerprint(len: u64) -> SourceFingerprint {
+        SourceFingerprint {
+            len,
+            modified_ns: 1,
+        }
+    }
+
+    fn write_entry(cache: &TranscodeCache, track_id: &str, packets: u64) {
+        let mut writer = cache.writer(track_id, &profile(), fingerprint(10)).unwrap();
+        writer.header(&[0u8; 40]).unwrap();
+        for index in 0..packets {
+            writer.packet(index * FRAME, &[index as u8; 100]).unwrap();
+        }
+        writer.finish(312, packets * FRAME - 312).unwrap();
+    }
+
+    #[test]
+    fn seeks_start_on_a_preroll_packet() {
+        let (root, cache) = temp_cache(1 << 20);
+        write_entry(&cache, "a", 500);
+        let mut stream = cache.lookup("a", &profile(), fingerprint(10)).unwrap();
+
+        let start = stream.seek_point(0).unwrap();
+        assert_eq!(
+            (start.index, start.pre_skip, start.valid_samples),
+            (0, 312, 500 * FRAME - 312)
+        );
+
+        let SeekPoint {
+            index,
+            offset,
+            pre_skip,
+            valid_samples: valid,
+        } = stream.seek_point(1_000).unwrap();
+        assert_eq!(offset, stream.packets_start + index as u64 * 110);
+        let first = index as u64 * FRAME;
+        assert_eq!(first + pre_skip as u64, 312 + 48_000);
+        assert!(pre_skip as u64 >= SEEK_PREROLL_SAMPLES);
+        assert!((pre_skip as u64) < SEEK_PREROLL_SAMPLES + FRAME);
+        assert_eq!(valid, 500 * FRAME - 312 - 48_000);
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn replays_packets_from_the_seek_point() {
+        let (root, cache) = temp_cache(1 << 20);
+        write_entry(&cache, "a", 50);
+        let stream = cache.lookup("a", &profile(), fingerprint(10)).unwrap();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(64);
+        stream.replay(500, AudioFraming::Binary, &tx, None).unwrap();
+        drop(tx);
+
+        let mut sent = Vec::new();
+        while let Ok(Ok(data)) = rx.try_recv() {
+            sent.push(data);
+        }
+        // Header, packets 21..50 (the first within the preroll), end.
+        assert_eq!(sent.len(), 1 + 29 + 1);
+        for (index, data) in (21u8..50).zip(&sent[1..30]) {
+            assert_eq!(
+                data[..],
+                AudioFraming::Binary.packet(&[index; 100]).unwrap()[..]
+            );
+        }
+        assert_eq!(sent[30][..], AudioFraming::Binary.end()[..]);
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn changed_sources_and_old_entries_are_dropped() {
+        let (root, cache) = temp_cache(1 << 20);
+        write_entry(&cache, "a", 10);
+        assert!(cache.lookup("a", &profile(), fingerprint(11)).is_none());
+        assert!(cache.lookup("a", &profile(), fingerprint(10)).is_none());
+
+        // Each entry is a little over 5 KB, so only two fit.
+        let (root_small, small) = temp_cache(12_000);
+        write_entry(&small, "a", 50);
+        write_entry(&small, "b", 50);
+        assert!(small.lookup("a", &profile(), fingerprint(10)).is_some());
+        write_entry(&small, "c", 50);
+        assert!(small.lookup("b", &profile(), fingerprint(10)).is_none());
+        assert!(small.lookup("a", &profile(), fingerprint(10)).is_some());
+        assert!(small.lookup("c", &profile(), fingerprint(10)).is_some());
+        let _ = fs::remove_dir_all(root);
+        let _ = fs::remove_dir_all(root_small);
+    }
+}
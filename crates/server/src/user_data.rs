@@ -9,6 +9,7 @@ use redb::{
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::events::{EventBus, EventTopic};
 use crate::state::Playlist;
 
 const PLAYLISTS_TABLE: TableDefinition<&str, &[u8]> = TableDefinition::new("playlists");
@@ -26,11 +27,12 @@ pub struct PlaybackSettings {
 #[derive(Clone)]
 pub struct UserDataStore {
     db: Arc<Database>,
+    events: EventBus,
 }
 
 impl UserDataStore {
-    pub fn new(db: Arc<Database>) -> Self {
-        Self { db }
+    pub fn new(db: Arc<Database>, events: EventBus) -> Self {
+        Self { db, events }
     }
 
     pub fn init_tables(&self) -> Result<(), UserDataError> {
@@ -91,6 +93,7 @@ impl UserDataStore {
             table.insert(playlist.id.as_str(), bytes.as_slice())?;
         }
         write_txn.commit()?;
+        self.events.publish(EventTopic::UserData, "playlist_created", &playlist);
         Ok(playlist)
     }
 
@@ -122,6 +125,7 @@ impl UserDataStore {
             playlist
         };
         write_txn.commit()?;
+        self.events.publish(EventTopic::UserData, "playlist_updated", &updated);
         Ok(Some(updated))
     }
 
@@ -137,6 +141,13 @@ impl UserDataStore {
             removed
         };
         write_txn.commit()?;
+        if deleted {
+            self.events.publish(
+                EventTopic::UserData,
+                "playlist_deleted",
+                serde_json::json!({ "id": playlist_id }),
+            );
+        }
         Ok(deleted)
     }
 
@@ -163,6 +174,11 @@ impl UserDataStore {
             table.insert(track_id, value.as_slice())?;
         }
         write_txn.commit()?;
+        self.events.publish(
+            EventTopic::UserData,
+            "like_added",
+            serde_json::json!({ "track_id": track_id }),
+        );
         Ok(())
     }
 
@@ -177,6 +193,11 @@ impl UserDataStore {
             let _ = table.remove(track_id)?;
         }
         write_txn.commit()?;
+        self.events.publish(
+            EventTopic::UserData,
+            "like_removed",
+            serde_json::json!({ "track_id": track_id }),
+        );
         Ok(())
     }
 
@@ -205,6 +226,7 @@ impl UserDataStore {
             table.insert(PLAYBACK_SETTINGS_KEY, bytes.as_slice())?;
         }
         write_txn.commit()?;
+        self.events.publish(EventTopic::UserData, "playback_settings", &settings);
         Ok(())
     }
 }
@@ -6,7 +6,7 @@ use notify::{Config as NotifyConfig, Event, EventKind, RecommendedWatcher, Recur
 use tokio::sync::mpsc::UnboundedReceiver;
 use tracing::{info, warn};
 
-use crate::scan::{start_cover_sweep, start_enrichment_sweep};
+use crate::scan::{start_cover_sweep, start_enrichment_sweep, start_waveform_sweep};
 use crate::state::AppState;
 
 pub fn configure_watcher(state: &AppState, library: &Library, root: PathBuf) {
@@ -102,6 +102,7 @@ async fn watch_loop(
                                 ),
                             );
                             start_enrichment_sweep(state.clone(), library.clone(), false);
+                            start_waveform_sweep(state.clone(), library.clone());
                             start_cover_sweep(state.clone(), library.clone());
                         }
                         Ok(Err(err)) => warn!("Auto-rescan failed: {}", err),
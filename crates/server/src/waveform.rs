@@ -0,0 +1,257 @@
+//! Min/max peaks for drawing scrubbers. Each track is decoded once at
+//! `STORED_POINTS` buckets and kept in the library DB; requests for fewer
+//! points are folded down from that.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use common::{join_relpath, Track, Waveform};
+use library::Library;
+use tracing::{info, warn};
+
+use crate::state::AppState;
+use crate::stream_limits::JobPriority;
+use crate::transcode::{GaplessTrim, SourceDecoder, TranscodeSource};
+use crate::transcode_cache::SourceFingerprint;
+
+pub const STORED_POINTS: usize = 2048;
+pub const DEFAULT_POINTS: usize = 800;
+
+static SWEEP_RUNNING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug)]
+pub enum WaveformError {
+    /// The track's file is no longer on disk.
+    FileMissing,
+    Failed(String),
+}
+
+impl From<String> for WaveformError {
+    fn from(message: String) -> Self {
+        WaveformError::Failed(message)
+    }
+}
+
+impl std::fmt::Display for WaveformError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WaveformError::FileMissing => write!(f, "file not found"),
+            WaveformError::Failed(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// The stored peaks for `track`, decoding the file first if they are
+/// missing or the file changed since. Blocks, and waits for a transcode
+/// worker slot before decoding.
+pub fn load_or_compute(
+    state: &AppState,
+    library: &Library,
+    track: &Track,
+    priority: &JobPriority,
+) -> Result<Waveform, WaveformError> {
+    let path = join_relpath(library.root(), &track.file_relpath);
+    let source = SourceFingerprint::of(&path).ok_or(WaveformError::FileMissing)?;
+    if let Some(waveform) = stored(library, track, source)? {
+        return Ok(waveform);
+    }
+    let _permit = state
+        .transcode_scheduler
+        .acquire(priority, || false)
+        .ok_or_else(|| "waveform job cancelled".to_string())?;
+    // Another request may have filled it in while this one queued.
+    if let Some(waveform) = stored(library, track, source)? {
+        return Ok(waveform);
+    }
+    let waveform = compute(track, path, source)?;
+    library
+        .put_waveform(&track.id, &waveform)
+        .map_err(|err| format!("library error: {}", err))?;
+    Ok(waveform)
+}
+
+fn stored(
+    library: &Library,
+    track: &Track,
+    source: SourceFingerprint,
+) -> Result<Option<Waveform>, String> {
+    let waveform = library
+        .get_waveform(&track.id)
+        .map_err(|err| format!("library error: {}", err))?;
+    Ok(waveform.filter(|waveform| {
+        waveform.source_size == source.len
+            && waveform.source_modified_ns == source.modified_ns
+            && waveform.min.len() == STORED_POINTS
+    }))
+}
+
+fn compute(
+    track: &Track,
+    path: PathBuf,
+    fingerprint: SourceFingerprint,
+) -> Result<Waveform, String> {
+    let source = TranscodeSource {
+        track_id: track.id.clone(),
+        path,
+        trim: GaplessTrim::from_track(track),
+    };
+    let mut decoder = SourceDecoder::open(&source, 0)?;
+    let mut buckets: Option<PeakBuckets> = None;
+    while let Some(chunk) = decoder.next_chunk()? {
+        let buckets = buckets.get_or_insert_with(|| {
+            let frames = source.trim.total_samples.unwrap_or_else(|| {
+                track.duration_ms as u64 * chunk.rate as u64 / 1000
+            });
+            PeakBuckets::new(STORED_POINTS, frames)
+        });
+        buckets.push(&chunk.samples, chunk.channels);
+    }
+    let (min, max) = buckets
+        .unwrap_or_else(|| PeakBuckets::new(STORED_POINTS, 1))
+        .finish();
+    Ok(Waveform {
+        duration_ms: track.duration_ms,
+        source_size: fingerprint.len,
+        source_modified_ns: fingerprint.modified_ns,
+        min,
+        max,
+    })
+}
+
+/// Folds the stored peaks into `points` buckets (at most the stored count).
+pub fn downsample(waveform: &Waveform, points: usize) -> (Vec<f32>, Vec<f32>) {
+    let len = waveform.min.len().min(waveform.max.len());
+    if len == 0 {
+        return (Vec::new(), Vec::new());
+    }
+    let points = points.clamp(1, len);
+    let mut min = Vec::with_capacity(points);
+    let mut max = Vec::with_capacity(points);
+    for index in 0..points {
+        let lo = index * len / points;
+        let hi = ((index + 1) * len / points).max(lo + 1);
+        min.push(waveform.min[lo..hi].iter().copied().fold(0.0, f32::min));
+        max.push(waveform.max[lo..hi].iter().copied().fold(0.0, f32::max));
+    }
+    (min, max)
+}
+
+/// Decodes peaks for every track that lacks them. Runs one track at a
+/// time behind streams in the transcode queue; a second call while a
+/// sweep is running does nothing.
+pub fn sweep(state: &AppState, library: &Library) {
+    if SWEEP_RUNNING.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    let priority = JobPriority::new(false);
+    let page_size = 200;
+    let mut offset = 0;
+    let mut count = 0;
+    loop {
+        let (tracks, total) = match library.list_tracks(None, page_size, offset) {
+            Ok(res) => res,
+            Err(err) => {
+                warn!("Waveform sweep failed to list tracks: {}", err);
+                break;
+            }
+        };
+        if tracks.is_empty() {
+            break;
+        }
+        for track in tracks {
+            let path = join_relpath(library.root(), &track.file_relpath);
+            let fresh = SourceFingerprint::of(&path)
+                .is_some_and(|source| matches!(stored(library, &track, source), Ok(Some(_))));
+            if fresh {
+                continue;
+            }
+            match load_or_compute(state, library, &track, &priority) {
+                Ok(_) => count += 1,
+                Err(err) => warn!("Waveform for track {} failed: {}", track.id, err),
+            }
+        }
+        if offset + page_size >= total {
+            break;
+        }
+        offset += page_size;
+    }
+    SWEEP_RUNNING.store(false, Ordering::Release);
+    if count > 0 {
+        info!("Waveform sweep completed: {} tracks processed", count);
+    }
+}
+
+/// Running min/max per bucket, with frames spread evenly over the
+/// expected length. Frames past the estimate land in the last bucket.
+struct PeakBuckets {
+    min: Vec<f32>,
+    max: Vec<f32>,
+    total_frames: u64,
+    frame: u64,
+}
+
+impl PeakBuckets {
+    fn new(points: usize, total_frames: u64) -> Self {
+        Self {
+            min: vec![0.0; points],
+            max: vec![0.0; points],
+            total_frames: total_frames.max(1),
+            frame: 0,
+        }
+    }
+
+    fn push(&mut self, samples: &[f32], channels: usize) {
+        let points = self.min.len() as u64;
+        for frame in samples.chunks_exact(channels.max(1)) {
+            let bucket = (self.frame * points / self.total_frames).min(points - 1) as usize;
+            for &sample in frame {
+                self.min[bucket] = self.min[bucket].min(sample);
+                self.max[bucket] = self.max[bucket].max(sample);
+            }
+            self.frame += 1;
+        }
+    }
+
+    fn finish(self) -> (Vec<f32>, Vec<f32>) {
+        let clamp = |peaks: Vec<f32>| peaks.into_iter().map(|peak| peak.clamp(-1.0, 1.0)).collect();
+        (clamp(self.min), clamp(self.max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_follow_the_expected_length() {
+        let mut buckets = PeakBuckets::new(4, 8);
+        // Stereo: one loud frame per bucket, on alternating channels.
+        let samples = [
+            0.5, 0.0, 0.0, 0.0, //
+            0.0, -0.25, 0.0, 0.0, //
+            0.0, 0.0, 0.0, 1.5, //
+            0.0, 0.0, -1.0, 0.1, //
+            0.2, 0.0, // past the estimate
+        ];
+        buckets.push(&samples, 2);
+        let (min, max) = buckets.finish();
+        assert_eq!(min, vec![0.0, -0.25, 0.0, -1.0]);
+        assert_eq!(max, vec![0.5, 0.0, 1.0, 0.2]);
+    }
+
+    #[test]
+    fn downsample_keeps_the_extremes() {
+        let waveform = Waveform {
+            duration_ms: 1000,
+            source_size: 0,
+            source_modified_ns: 0,
+            min: vec![-0.1, -0.9, -0.2, -0.3, -0.4],
+            max: vec![0.1, 0.2, 0.8, 0.3, 0.4],
+        };
+        assert_eq!(
+            downsample(&waveform, 2),
+            (vec![-0.9, -0.4], vec![0.2, 0.8])
+        );
+        assert_eq!(downsample(&waveform, 100).0.len(), 5);
+    }
+}
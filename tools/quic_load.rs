@@ -0,0 +1,238 @@
+//! QUIC load generator: opens many listeners against a running server and
+//! reports how many of them receive audio at least as fast as it plays.
+//!
+//! quic_load <addr> <token> <track_id> [listeners] [seconds] [kbps]
+
+use std::collections::HashMap;
+use std::env;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+use rand::RngCore;
+
+const ALPN_QUIC: &[&[u8]] = &[b"phonolite-quic"];
+const MAX_UDP_SIZE: usize = 65535;
+const MAX_QUIC_DATAGRAM: usize = 1350;
+const CONTROL_STREAM: u64 = 0;
+
+struct Listener {
+    socket: UdpSocket,
+    local_addr: SocketAddr,
+    conn: quiche::Connection,
+    timeout_at: Option<Instant>,
+    opened: bool,
+    control: Vec<u8>,
+    first_byte: Option<Instant>,
+    bytes: u64,
+    error: Option<String>,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = env::args().skip(1);
+    let usage = "usage: quic_load <addr> <token> <track_id> [listeners] [seconds] [kbps]";
+    let addr = args.next().ok_or(usage)?;
+    let token = args.next().ok_or(usage)?;
+    let track_id = args.next().ok_or(usage)?;
+    let listeners: usize = args.next().map_or(Ok(50), |value| value.parse())?;
+    let seconds: u64 = args.next().map_or(Ok(30), |value| value.parse())?;
+    // The fixed `high` quality the listeners ask for.
+    let kbps: u64 = args.next().map_or(Ok(160), |value| value.parse())?;
+
+    let server_addr = addr
+        .to_socket_addrs()?
+        .next()
+        .ok_or("server address did not resolve")?;
+    let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    config.set_application_protos(ALPN_QUIC)?;
+    config.verify_peer(false);
+    config.set_max_idle_timeout(30_000);
+    config.set_max_recv_udp_payload_size(MAX_QUIC_DATAGRAM);
+    config.set_max_send_udp_payload_size(MAX_QUIC_DATAGRAM);
+    config.set_initial_max_data(20_000_000);
+    config.set_initial_max_stream_data_bidi_local(10_000_000);
+    config.set_initial_max_stream_data_bidi_remote(10_000_000);
+    config.set_initial_max_stream_data_uni(10_000_000);
+    config.set_initial_max_streams_bidi(16);
+    config.set_initial_max_streams_uni(32);
+
+    let bind_addr = if server_addr.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let mut clients = Vec::with_capacity(listeners);
+    for _ in 0..listeners {
+        let socket = UdpSocket::bind(bind_addr)?;
+        socket.connect(server_addr)?;
+        socket.set_nonblocking(true)?;
+        let local_addr = socket.local_addr()?;
+        let mut scid = [0u8; 16];
+        rand::rng().fill_bytes(&mut scid);
+        let scid = quiche::ConnectionId::from_ref(&scid);
+        let conn = quiche::connect(None, &scid, local_addr, server_addr, &mut config)?;
+        clients.push(Listener {
+            socket,
+            local_addr,
+            conn,
+            timeout_at: None,
+            opened: false,
+            control: Vec::new(),
+            first_byte: None,
+            bytes: 0,
+            error: None,
+        });
+    }
+    let open = format!(
+        "{}\n{}\n{}\n",
+        serde_json::json!({"type": "hello", "protocol": 2, "client": "quic_load"}),
+        serde_json::json!({"type": "auth", "token": token}),
+        serde_json::json!({
+            "type": "open",
+            "track_id": track_id,
+            "mode": "fixed",
+            "quality": "high",
+        }),
+    );
+
+    println!(
+        "{} listeners -> {} for {}s, expecting {} kbps each",
+        listeners, server_addr, seconds, kbps
+    );
+    let started = Instant::now();
+    let deadline = started + Duration::from_secs(seconds);
+    let mut next_report = started + Duration::from_secs(1);
+    let mut last_bytes = 0u64;
+    let mut buf = vec![0u8; MAX_UDP_SIZE];
+    let mut out = vec![0u8; MAX_UDP_SIZE];
+    while Instant::now() < deadline {
+        let mut busy = false;
+        for client in clients.iter_mut() {
+            busy |= drive(client, &open, &mut buf, &mut out);
+        }
+        let now = Instant::now();
+        if now >= next_report {
+            next_report += Duration::from_secs(1);
+            let total: u64 = clients.iter().map(|client| client.bytes).sum();
+            let (keeping_up, streaming) = realtime_counts(&clients, kbps, now);
+            println!(
+                "{:>3}s  {:>8.1} Mbit/s  streaming {:>4}  realtime {:>4}",
+                now.duration_since(started).as_secs(),
+                (total - last_bytes) as f64 * 8.0 / 1_000_000.0,
+                streaming,
+                keeping_up,
+            );
+            last_bytes = total;
+        }
+        if !busy {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    let (keeping_up, streaming) = realtime_counts(&clients, kbps, Instant::now());
+    let mut errors: HashMap<&str, usize> = HashMap::new();
+    for client in &clients {
+        if let Some(error) = client.error.as_deref() {
+            *errors.entry(error).or_default() += 1;
+        }
+    }
+    println!(
+        "{} of {} listeners streamed, {} kept up with playback",
+        streaming, listeners, keeping_up
+    );
+    for (error, count) in errors {
+        println!("{} listeners failed: {}", count, error);
+    }
+    for client in clients.iter_mut() {
+        let _ = client.conn.close(true, 0, b"done");
+        flush(client, &mut out);
+    }
+    Ok(())
+}
+
+/// Returns (listeners that received at least real-time audio since their
+/// first byte, listeners that received anything).
+fn realtime_counts(clients: &[Listener], kbps: u64, now: Instant) -> (usize, usize) {
+    let mut keeping_up = 0;
+    let mut streaming = 0;
+    for client in clients {
+        let Some(first_byte) = client.first_byte else {
+            continue;
+        };
+        streaming += 1;
+        let played_ms = now.duration_since(first_byte).as_millis() as u64;
+        if client.bytes * 8 >= played_ms * kbps {
+            keeping_up += 1;
+        }
+    }
+    (keeping_up, streaming)
+}
+
+/// Runs one listener as far as it can go without blocking. Returns whether
+/// anything arrived.
+fn drive(client: &mut Listener, open: &str, buf: &mut [u8], out: &mut [u8]) -> bool {
+    let mut busy = false;
+    loop {
+        match client.socket.recv_from(buf) {
+            Ok((len, from)) => {
+                busy = true;
+                let info = quiche::RecvInfo {
+                    from,
+                    to: client.local_addr,
+                };
+                let _ = client.conn.recv(&mut buf[..len], info);
+            }
+            Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+            Err(err) => {
+                client.error.get_or_insert_with(|| err.to_string());
+                break;
+            }
+        }
+    }
+    if client.timeout_at.is_some_and(|at| at <= Instant::now()) {
+        client.conn.on_timeout();
+    }
+    if client.conn.is_established() && !client.opened {
+        client.opened = true;
+        let _ = client
+            .conn
+            .stream_send(CONTROL_STREAM, open.as_bytes(), false);
+    }
+    let readable: Vec<u64> = client.conn.readable().collect();
+    for stream_id in readable {
+        while let Ok((len, _fin)) = client.conn.stream_recv(stream_id, buf) {
+            if stream_id == CONTROL_STREAM {
+                client.control.extend_from_slice(&buf[..len]);
+            } else {
+                client.first_byte.get_or_insert_with(Instant::now);
+                client.bytes += len as u64;
+            }
+        }
+    }
+    while let Some(pos) = client.control.iter().position(|byte| *byte == b'\n') {
+        let line: Vec<u8> = client.control.drain(..=pos).collect();
+        let Ok(message) = serde_json::from_slice::<serde_json::Value>(&line) else {
+            continue;
+        };
+        if message["type"] == "error" {
+            let error = message["message"].as_str().unwrap_or("error").to_string();
+            client.error.get_or_insert(error);
+        }
+    }
+    if client.conn.is_closed() {
+        client
+            .error
+            .get_or_insert_with(|| "connection closed".to_string());
+    }
+    flush(client, out);
+    client.timeout_at = client.conn.timeout().map(|t| Instant::now() + t);
+    busy
+}
+
+fn flush(client: &mut Listener, out: &mut [u8]) {
+    while let Ok((len, _)) = client.conn.send(out) {
+        if client.socket.send(&out[..len]).is_err() {
+            break;
+        }
+    }
+}
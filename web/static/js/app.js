@@ -104,6 +104,46 @@
     });
   }
 
+  // One WebSocket per page carries server events to every listener.
+  const serverEvents = {
+    socket: null,
+    listeners: [],
+    fallbacks: [],
+  };
+
+  // Calls onMessage with each `event` or `resync_required` message. If the
+  // socket can't be opened or drops, onLost runs once instead.
+  function listenForEvents(onMessage, onLost) {
+    serverEvents.listeners.push(onMessage);
+    serverEvents.fallbacks.push(onLost);
+    if (serverEvents.socket) return;
+    if (!("WebSocket" in window)) {
+      eventsLost();
+      return;
+    }
+    const scheme = window.location.protocol === "https:" ? "wss:" : "ws:";
+    const socket = new WebSocket(`${scheme}//${window.location.host}/api/v1/events`);
+    serverEvents.socket = socket;
+    socket.addEventListener("message", (message) => {
+      let data;
+      try {
+        data = JSON.parse(message.data);
+      } catch (_) {
+        return;
+      }
+      if (data.type !== "event" && data.type !== "resync_required") return;
+      serverEvents.listeners.forEach((listener) => listener(data));
+    });
+    socket.addEventListener("close", eventsLost);
+  }
+
+  function eventsLost() {
+    const fallbacks = serverEvents.fallbacks;
+    serverEvents.listeners = [];
+    serverEvents.fallbacks = [];
+    fallbacks.forEach((fallback) => fallback());
+  }
+
   setupMessages();
   setupNavMenu();
   setupActivityBadge();
@@ -826,6 +866,19 @@
   function setupLibraryStatusPolling() {
     const scanning = document.querySelector('.status[data-status="scanning"]');
     if (!scanning) return;
+    listenForEvents((data) => {
+      if (data.type === "resync_required") {
+        pollLibraryStatus();
+        return;
+      }
+      if (data.topic !== "library" || data.kind !== "status") return;
+      if (data.data && data.data.status !== "scanning") {
+        window.location.reload();
+      }
+    }, pollLibraryStatus);
+  }
+
+  function pollLibraryStatus() {
     let retries = 0;
     const timer = setInterval(() => {
       fetch("/status/library", {
@@ -881,7 +934,18 @@
     }
 
     updateBadge();
-    setInterval(updateBadge, 10000);
+    let pending = null;
+    listenForEvents(
+      (data) => {
+        // The count only moves with activity entries and scan state.
+        if (data.type === "event" && data.topic !== "activity" && data.kind !== "status") {
+          return;
+        }
+        clearTimeout(pending);
+        pending = setTimeout(updateBadge, 500);
+      },
+      () => setInterval(updateBadge, 10000),
+    );
   }
 
   function setupActivityPage() {
@@ -890,6 +954,25 @@
     const count = Number(clearButton.dataset.count || 0);
     clearButton.disabled = count === 0;
 
+    const list = document.getElementById("activity-events");
+    if (list) {
+      listenForEvents((data) => {
+        if (data.topic !== "activity" || data.kind !== "added" || !data.data) return;
+        let items = list.querySelector("ul");
+        if (!items) {
+          list.innerHTML = "<ul></ul>";
+          items = list.querySelector("ul");
+        }
+        const item = document.createElement("li");
+        const kind = document.createElement("span");
+        kind.className = "muted";
+        kind.textContent = data.data.kind;
+        item.append(kind, ` ${data.data.message}`);
+        items.prepend(item);
+        clearButton.disabled = false;
+      }, () => {});
+    }
+
     clearButton.addEventListener("click", () => {
       fetch("/activity/clear", {
         method: "POST",